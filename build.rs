@@ -0,0 +1,48 @@
+use std::path::Path;
+
+use anyhow::Context;
+
+// Compiles every WGSL file in `shaders/` to SPIR-V in OUT_DIR, so that the renderer can
+// `include_bytes!` them and shader errors show up at build time.
+fn main() -> anyhow::Result<()> {
+    println!("cargo:rerun-if-changed=shaders");
+    let out_dir = std::env::var("OUT_DIR")?;
+
+    for entry in std::fs::read_dir("shaders")? {
+        let path = entry?.path();
+        if path.extension().is_none_or(|ext| ext != "wgsl") {
+            continue;
+        }
+        println!("cargo:rerun-if-changed={}", path.display());
+
+        let source = std::fs::read_to_string(&path)?;
+        let display_path = path.to_string_lossy();
+        let module = naga::front::wgsl::parse_str(&source)
+            .map_err(|e| anyhow::anyhow!(e.emit_to_string_with_path(&source, &path)))?;
+        let info = naga::valid::Validator::new(
+            naga::valid::ValidationFlags::all(),
+            naga::valid::Capabilities::all(),
+        )
+        .validate(&module)
+        .map_err(|e| anyhow::anyhow!(e.emit_to_string_with_path(&source, &display_path)))?;
+
+        // Shaders are written against Vulkan's conventions, so no Y flip
+        let mut flags = naga::back::spv::WriterFlags::LABEL_VARYINGS;
+        if cfg!(debug_assertions) {
+            flags |= naga::back::spv::WriterFlags::DEBUG;
+        }
+        let options = naga::back::spv::Options {
+            lang_version: (1, 5),
+            flags,
+            ..Default::default()
+        };
+        let words = naga::back::spv::write_vec(&module, &info, &options, None)
+            .with_context(|| format!("Failed to emit SPIR-V for {display_path}"))?;
+
+        let bytes: Vec<u8> = words.iter().flat_map(|word| word.to_le_bytes()).collect();
+        let name = path.file_stem().context("Shader without a name")?;
+        std::fs::write(Path::new(&out_dir).join(name).with_extension("spv"), bytes)?;
+    }
+
+    Ok(())
+}
@@ -0,0 +1,375 @@
+// Hillaire's physically based sky. The transmittance and multiple scattering LUTs only depend
+// on the atmosphere and are baked once, the sky-view LUT is marched every frame for the
+// camera's height and the sun, and the sky is drawn by looking view directions up in it.
+// Distances are in kilometres from the planet's centre, with the camera above its north pole
+
+const PI: f32 = 3.14159265359;
+
+// Earth's atmosphere, as in the paper
+const BOTTOM_RADIUS: f32 = 6360.0;
+const TOP_RADIUS: f32 = 6460.0;
+const RAYLEIGH_SCATTERING: vec3<f32> = vec3<f32>(5.802e-3, 13.558e-3, 33.1e-3);
+const RAYLEIGH_SCALE_HEIGHT: f32 = 8.0;
+const MIE_SCATTERING: f32 = 3.996e-3;
+const MIE_EXTINCTION: f32 = 4.44e-3;
+const MIE_SCALE_HEIGHT: f32 = 1.2;
+const MIE_ASYMMETRY: f32 = 0.8;
+const OZONE_ABSORPTION: vec3<f32> = vec3<f32>(0.650e-3, 1.881e-3, 0.085e-3);
+// Ozone is densest at this altitude and thins out linearly either side of it
+const OZONE_CENTRE: f32 = 25.0;
+const OZONE_HALF_WIDTH: f32 = 15.0;
+const GROUND_ALBEDO: vec3<f32> = vec3<f32>(0.3);
+
+// World units are metres, and the camera is kept at least this far above the ground
+const WORLD_TO_KM: f32 = 1e-3;
+const MIN_ALTITUDE: f32 = 0.01;
+
+const TRANSMITTANCE_STEPS: u32 = 40u;
+const MULTI_SCATTERING_STEPS: u32 = 20u;
+// Per side of the grid of directions integrated over the sphere
+const MULTI_SCATTERING_DIRECTIONS: u32 = 8u;
+const SKY_VIEW_STEPS: u32 = 32u;
+
+// A little larger than the real sun, and nowhere near as bright compared to its
+// illuminance, so it doesn't swamp exposure and bloom
+const SUN_ANGULAR_RADIUS: f32 = 0.0093;
+const SUN_DISK_LUMINANCE: f32 = 20.0;
+
+// The leading fields of mesh.wgsl's SceneData, nothing past them is read here
+struct SceneData {
+    view: mat4x4<f32>,
+    proj: mat4x4<f32>,
+    view_proj: mat4x4<f32>,
+    inv_view_proj: mat4x4<f32>,
+    camera_position: vec4<f32>,
+    sun_direction: vec4<f32>,
+    sun_color: vec4<f32>,
+    light_count: u32,
+    environment_intensity: f32,
+    debug_view: u32,
+    far_depth: f32,
+}
+
+@group(0) @binding(0) var<uniform> scene: SceneData;
+// The scene's environment sampler, linear and clamped
+@group(0) @binding(4) var lut_sampler: sampler;
+
+@group(1) @binding(0) var transmittance_lut: texture_2d<f32>;
+@group(1) @binding(1) var multi_scattering_lut: texture_2d<f32>;
+@group(1) @binding(2) var sky_view_lut: texture_2d<f32>;
+// Has to match atmosphere.rs's format
+@group(1) @binding(3) var dst: texture_storage_2d<rgba16float, write>;
+
+struct Medium {
+    scattering: vec3<f32>,
+    extinction: vec3<f32>,
+    rayleigh: vec3<f32>,
+    mie: vec3<f32>,
+}
+
+fn sample_medium(altitude: f32) -> Medium {
+    let rayleigh = RAYLEIGH_SCATTERING * exp(-altitude / RAYLEIGH_SCALE_HEIGHT);
+    let mie_density = exp(-altitude / MIE_SCALE_HEIGHT);
+    let mie = vec3<f32>(MIE_SCATTERING * mie_density);
+    let ozone_density = max(1.0 - abs(altitude - OZONE_CENTRE) / OZONE_HALF_WIDTH, 0.0);
+    let extinction = rayleigh + MIE_EXTINCTION * mie_density + OZONE_ABSORPTION * ozone_density;
+    return Medium(rayleigh + mie, extinction, rayleigh, mie);
+}
+
+fn rayleigh_phase(cos_theta: f32) -> f32 {
+    return 3.0 / (16.0 * PI) * (1.0 + cos_theta * cos_theta);
+}
+
+// Cornette-Shanks
+fn mie_phase(cos_theta: f32) -> f32 {
+    let g = MIE_ASYMMETRY;
+    let k = 3.0 / (8.0 * PI) * (1.0 - g * g) / (2.0 + g * g);
+    return k * (1.0 + cos_theta * cos_theta) / pow(1.0 + g * g - 2.0 * g * cos_theta, 1.5);
+}
+
+// Distances along the ray to where it enters and leaves a sphere around the planet's centre,
+// both negative when it misses
+fn ray_sphere(origin: vec3<f32>, dir: vec3<f32>, radius: f32) -> vec2<f32> {
+    let b = dot(origin, dir);
+    let c = dot(origin, origin) - radius * radius;
+    let discriminant = b * b - c;
+    if discriminant < 0.0 {
+        return vec2<f32>(-1.0);
+    }
+    let s = sqrt(discriminant);
+    return vec2<f32>(-b - s, -b + s);
+}
+
+// How far a ray from inside the atmosphere goes before hitting the ground or leaving it
+fn ray_end(origin: vec3<f32>, dir: vec3<f32>) -> f32 {
+    let ground = ray_sphere(origin, dir, BOTTOM_RADIUS);
+    if ground.x > 0.0 {
+        return ground.x;
+    }
+    return max(ray_sphere(origin, dir, TOP_RADIUS).y, 0.0);
+}
+
+// Whether the sun is above the horizon as seen from a point
+fn sun_visibility(position: vec3<f32>, sun: vec3<f32>) -> f32 {
+    return select(1.0, 0.0, ray_sphere(position, sun, BOTTOM_RADIUS).x > 0.0);
+}
+
+// Bruneton's parameterisation of radius and view zenith cosine, spending more of the LUT
+// near the horizon
+fn transmittance_uv(r: f32, mu: f32) -> vec2<f32> {
+    let h = sqrt(TOP_RADIUS * TOP_RADIUS - BOTTOM_RADIUS * BOTTOM_RADIUS);
+    let rho = sqrt(max(r * r - BOTTOM_RADIUS * BOTTOM_RADIUS, 0.0));
+    let discriminant = r * r * (mu * mu - 1.0) + TOP_RADIUS * TOP_RADIUS;
+    let d = max(-r * mu + sqrt(max(discriminant, 0.0)), 0.0);
+    let d_min = TOP_RADIUS - r;
+    let d_max = rho + h;
+    return vec2<f32>((d - d_min) / (d_max - d_min), rho / h);
+}
+
+fn transmittance_params(uv: vec2<f32>) -> vec2<f32> {
+    let h = sqrt(TOP_RADIUS * TOP_RADIUS - BOTTOM_RADIUS * BOTTOM_RADIUS);
+    let rho = h * uv.y;
+    let r = sqrt(rho * rho + BOTTOM_RADIUS * BOTTOM_RADIUS);
+    let d_min = TOP_RADIUS - r;
+    let d_max = rho + h;
+    let d = d_min + uv.x * (d_max - d_min);
+    var mu = 1.0;
+    if d > 0.0 {
+        mu = clamp((h * h - rho * rho - d * d) / (2.0 * r * d), -1.0, 1.0);
+    }
+    return vec2<f32>(r, mu);
+}
+
+fn transmittance(r: f32, mu: f32) -> vec3<f32> {
+    return textureSampleLevel(transmittance_lut, lut_sampler, transmittance_uv(r, mu), 0.0).rgb;
+}
+
+fn multi_scattering(r: f32, mu_sun: f32) -> vec3<f32> {
+    let uv = vec2<f32>(
+        mu_sun * 0.5 + 0.5,
+        (r - BOTTOM_RADIUS) / (TOP_RADIUS - BOTTOM_RADIUS),
+    );
+    return textureSampleLevel(multi_scattering_lut, lut_sampler, uv, 0.0).rgb;
+}
+
+fn camera_radius() -> f32 {
+    let altitude = max(scene.camera_position.y * WORLD_TO_KM, MIN_ALTITUDE);
+    return min(BOTTOM_RADIUS + altitude, TOP_RADIUS - MIN_ALTITUDE);
+}
+
+// Towards the sun, rather than the way its light travels
+fn sun_direction() -> vec3<f32> {
+    return -normalize(scene.sun_direction.xyz);
+}
+
+// The zenith angle of the horizon from a radius, and its angle up from straight down
+fn horizon(r: f32) -> vec2<f32> {
+    let beta = acos(sqrt(max(r * r - BOTTOM_RADIUS * BOTTOM_RADIUS, 0.0)) / r);
+    return vec2<f32>(PI - beta, beta);
+}
+
+// The sky-view LUT is laid out by azimuth from the sun across and zenith angle down, both
+// squeezed non-linearly to give the sun and the horizon more texels. The sky is symmetric
+// about the sun's azimuth, so only one half of it is stored
+fn sky_view_direction(uv: vec2<f32>, r: f32) -> vec3<f32> {
+    let angles = horizon(r);
+    var zenith: f32;
+    if uv.y < 0.5 {
+        let c = 1.0 - 2.0 * uv.y;
+        zenith = angles.x * (1.0 - c * c);
+    } else {
+        let c = uv.y * 2.0 - 1.0;
+        zenith = angles.x + angles.y * c * c;
+    }
+    let cos_azimuth = 1.0 - 2.0 * uv.x * uv.x;
+    let sin_azimuth = sqrt(max(1.0 - cos_azimuth * cos_azimuth, 0.0));
+    return vec3<f32>(sin(zenith) * cos_azimuth, cos(zenith), sin(zenith) * sin_azimuth);
+}
+
+fn sky_view_uv(r: f32, dir: vec3<f32>, sun: vec3<f32>) -> vec2<f32> {
+    let angles = horizon(r);
+    let zenith = acos(clamp(dir.y, -1.0, 1.0));
+    var v: f32;
+    if zenith < angles.x {
+        v = (1.0 - sqrt(1.0 - zenith / angles.x)) * 0.5;
+    } else {
+        v = sqrt((zenith - angles.x) / angles.y) * 0.5 + 0.5;
+    }
+
+    // Looking straight up or down, or the sun overhead, any azimuth will do
+    var cos_azimuth = 1.0;
+    let flat_dir = dir.xz;
+    let flat_sun = sun.xz;
+    if dot(flat_dir, flat_dir) > 1e-8 && dot(flat_sun, flat_sun) > 1e-8 {
+        cos_azimuth = dot(normalize(flat_dir), normalize(flat_sun));
+    }
+    let u = sqrt(clamp(0.5 - 0.5 * cos_azimuth, 0.0, 1.0));
+    return vec2<f32>(u, v);
+}
+
+@compute @workgroup_size(8, 8, 1)
+fn cs_transmittance(@builtin(global_invocation_id) id: vec3<u32>) {
+    let size = textureDimensions(dst);
+    if any(id.xy >= size) {
+        return;
+    }
+
+    let params = transmittance_params((vec2<f32>(id.xy) + 0.5) / vec2<f32>(size));
+    let origin = vec3<f32>(0.0, params.x, 0.0);
+    let dir = vec3<f32>(sqrt(1.0 - params.y * params.y), params.y, 0.0);
+    let dt = ray_end(origin, dir) / f32(TRANSMITTANCE_STEPS);
+    var optical_depth = vec3<f32>(0.0);
+    for (var i = 0u; i < TRANSMITTANCE_STEPS; i++) {
+        let p = origin + dir * ((f32(i) + 0.5) * dt);
+        optical_depth += sample_medium(length(p) - BOTTOM_RADIUS).extinction * dt;
+    }
+    textureStore(dst, id.xy, vec4<f32>(exp(-optical_depth), 1.0));
+}
+
+// Second order scattering from every direction around a point, and the fraction of light
+// scattered again on the way, summed as a geometric series for all higher orders
+@compute @workgroup_size(8, 8, 1)
+fn cs_multi_scattering(@builtin(global_invocation_id) id: vec3<u32>) {
+    let size = textureDimensions(dst);
+    if any(id.xy >= size) {
+        return;
+    }
+
+    let uv = (vec2<f32>(id.xy) + 0.5) / vec2<f32>(size);
+    let mu_sun = uv.x * 2.0 - 1.0;
+    let origin = vec3<f32>(0.0, BOTTOM_RADIUS + uv.y * (TOP_RADIUS - BOTTOM_RADIUS), 0.0);
+    let sun = vec3<f32>(sqrt(1.0 - mu_sun * mu_sun), mu_sun, 0.0);
+    let isotropic_phase = 1.0 / (4.0 * PI);
+
+    var luminance = vec3<f32>(0.0);
+    var transfer = vec3<f32>(0.0);
+    let n = MULTI_SCATTERING_DIRECTIONS;
+    for (var i = 0u; i < n; i++) {
+        for (var j = 0u; j < n; j++) {
+            // Spread evenly over the sphere
+            let theta = 2.0 * PI * (f32(i) + 0.5) / f32(n);
+            let cos_phi = 1.0 - 2.0 * (f32(j) + 0.5) / f32(n);
+            let sin_phi = sqrt(1.0 - cos_phi * cos_phi);
+            let dir = vec3<f32>(cos(theta) * sin_phi, cos_phi, sin(theta) * sin_phi);
+
+            let dt = ray_end(origin, dir) / f32(MULTI_SCATTERING_STEPS);
+            var throughput = vec3<f32>(1.0);
+            for (var k = 0u; k < MULTI_SCATTERING_STEPS; k++) {
+                let p = origin + dir * ((f32(k) + 0.5) * dt);
+                let r = length(p);
+                let medium = sample_medium(r - BOTTOM_RADIUS);
+                let step_transmittance = exp(-medium.extinction * dt);
+                let sunlight = transmittance(r, dot(p / r, sun)) * sun_visibility(p, sun);
+
+                // Integrated analytically over the step, as the paper does
+                let extinction = max(medium.extinction, vec3<f32>(1e-7));
+                let scattered = medium.scattering * sunlight * isotropic_phase;
+                let absorbed = 1.0 - step_transmittance;
+                luminance += throughput * scattered * absorbed / extinction;
+                transfer += throughput * medium.scattering * absorbed / extinction;
+                throughput *= step_transmittance;
+            }
+
+            // Sunlight bouncing off the ground
+            let ground = ray_sphere(origin, dir, BOTTOM_RADIUS).x;
+            if ground > 0.0 {
+                let normal = normalize(origin + dir * ground);
+                let mu = dot(normal, sun);
+                let sunlight = transmittance(BOTTOM_RADIUS, mu) * max(mu, 0.0);
+                luminance += throughput * sunlight * GROUND_ALBEDO / PI;
+            }
+        }
+    }
+
+    let count = f32(n * n);
+    let second_order = luminance / count;
+    let f_ms = transfer / count;
+    textureStore(dst, id.xy, vec4<f32>(second_order / (1.0 - f_ms), 1.0));
+}
+
+// Light scattered towards the camera along each view direction, for a sun of illuminance 1
+@compute @workgroup_size(8, 8, 1)
+fn cs_sky_view(@builtin(global_invocation_id) id: vec3<u32>) {
+    let size = textureDimensions(dst);
+    if any(id.xy >= size) {
+        return;
+    }
+
+    let r = camera_radius();
+    let origin = vec3<f32>(0.0, r, 0.0);
+    let mu_sun = sun_direction().y;
+    let sun = vec3<f32>(sqrt(max(1.0 - mu_sun * mu_sun, 0.0)), mu_sun, 0.0);
+    let dir = sky_view_direction((vec2<f32>(id.xy) + 0.5) / vec2<f32>(size), r);
+    let cos_theta = dot(dir, sun);
+    let phase_rayleigh = rayleigh_phase(cos_theta);
+    let phase_mie = mie_phase(cos_theta);
+
+    let end = ray_end(origin, dir);
+    var luminance = vec3<f32>(0.0);
+    var throughput = vec3<f32>(1.0);
+    for (var i = 0u; i < SKY_VIEW_STEPS; i++) {
+        // Steps grow quadratically, short near the camera where the air is densest
+        let t0 = f32(i) / f32(SKY_VIEW_STEPS);
+        let t1 = (f32(i) + 1.0) / f32(SKY_VIEW_STEPS);
+        let dt = (t1 * t1 - t0 * t0) * end;
+        let p = origin + dir * (t0 * t0 * end + dt * 0.3);
+        let pr = length(p);
+        let mu = dot(p / pr, sun);
+        let medium = sample_medium(pr - BOTTOM_RADIUS);
+        let step_transmittance = exp(-medium.extinction * dt);
+
+        let sunlight = transmittance(pr, mu) * sun_visibility(p, sun);
+        let single = sunlight * (medium.rayleigh * phase_rayleigh + medium.mie * phase_mie);
+        let scattered = single + multi_scattering(pr, mu) * medium.scattering;
+        let extinction = max(medium.extinction, vec3<f32>(1e-7));
+        luminance += throughput * (scattered - scattered * step_transmittance) / extinction;
+        throughput *= step_transmittance;
+    }
+    textureStore(dst, id.xy, vec4<f32>(luminance, 1.0));
+}
+
+struct VertexOutput {
+    @builtin(position) position: vec4<f32>,
+    @location(0) ndc: vec2<f32>,
+}
+
+// Like the skybox, on the far plane and depth tested for equality
+@vertex
+fn vs_sky(@builtin(vertex_index) index: u32) -> VertexOutput {
+    // One triangle covering the whole screen
+    let uv = vec2<f32>(f32((index << 1u) & 2u), f32(index & 2u));
+    let ndc = uv * 2.0 - 1.0;
+    return VertexOutput(vec4<f32>(ndc, scene.far_depth, 1.0), ndc);
+}
+
+fn unproject(ndc: vec2<f32>, depth: f32) -> vec3<f32> {
+    let p = scene.inv_view_proj * vec4<f32>(ndc, depth, 1.0);
+    return p.xyz / p.w;
+}
+
+@fragment
+fn fs_sky(in: VertexOutput) -> @location(0) vec4<f32> {
+    // Which of the depth planes is near depends on reverse-Z, so the ray between them is
+    // pointed away from the camera
+    let forward = -vec3<f32>(scene.view[0].z, scene.view[1].z, scene.view[2].z);
+    var dir = normalize(unproject(in.ndc, 1.0) - unproject(in.ndc, 0.0));
+    if dot(dir, forward) < 0.0 {
+        dir = -dir;
+    }
+
+    let r = camera_radius();
+    let sun = sun_direction();
+    let uv = sky_view_uv(r, dir, sun);
+    var color = textureSampleLevel(sky_view_lut, lut_sampler, uv, 0.0).rgb;
+
+    let origin = vec3<f32>(0.0, r, 0.0);
+    if ray_sphere(origin, dir, BOTTOM_RADIUS).x < 0.0 {
+        let edge = smoothstep(
+            cos(SUN_ANGULAR_RADIUS),
+            cos(SUN_ANGULAR_RADIUS * 0.8),
+            dot(dir, sun),
+        );
+        color += transmittance(r, dir.y) * SUN_DISK_LUMINANCE * edge;
+    }
+    return vec4<f32>(color * scene.sun_color.rgb, 1.0);
+}
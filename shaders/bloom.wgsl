@@ -0,0 +1,98 @@
+// Physically based bloom after Jimenez's "Next Generation Post Processing in Call of Duty:
+// Advanced Warfare". The scene is filtered down a chain of half sized mips with a 13 tap
+// filter, then each mip is tent filtered back up and added onto the one above it
+
+struct PushConstants {
+    // Brightness below which pixels start to fade out of the bloom, 0 keeps everything
+    threshold: f32,
+    // Upsample filter radius in UV
+    radius: f32,
+    // Set for the first downsample, reading the scene
+    prefilter: u32,
+}
+
+var<push_constant> pc: PushConstants;
+
+@group(0) @binding(0) var src: texture_2d<f32>;
+@group(0) @binding(1) var src_sampler: sampler;
+
+struct VertexOutput {
+    @builtin(position) position: vec4<f32>,
+    @location(0) uv: vec2<f32>,
+}
+
+@vertex
+fn vs_main(@builtin(vertex_index) index: u32) -> VertexOutput {
+    // One triangle covering the whole screen
+    let uv = vec2<f32>(f32((index << 1u) & 2u), f32(index & 2u));
+    return VertexOutput(vec4<f32>(uv * 2.0 - 1.0, 0.0, 1.0), uv);
+}
+
+fn luma(color: vec3<f32>) -> f32 {
+    return dot(color, vec3<f32>(0.2126, 0.7152, 0.0722));
+}
+
+// Averages four samples weighted down by brightness, so single very bright pixels don't
+// flicker into huge blobs
+fn karis_box(a: vec3<f32>, b: vec3<f32>, c: vec3<f32>, d: vec3<f32>) -> vec4<f32> {
+    let average = (a + b + c + d) * 0.25;
+    let weight = 1.0 / (1.0 + luma(average));
+    return vec4<f32>(average * weight, weight);
+}
+
+// Soft knee threshold, fading pixels out over half the threshold below it
+fn threshold(color: vec3<f32>) -> vec3<f32> {
+    let brightness = max(max(color.r, color.g), color.b);
+    let knee = pc.threshold * 0.5;
+    var soft = clamp(brightness - pc.threshold + knee, 0.0, 2.0 * knee);
+    soft = soft * soft / (4.0 * knee + 1e-4);
+    return color * (max(soft, brightness - pc.threshold) / max(brightness, 1e-4));
+}
+
+fn tap(uv: vec2<f32>, texel: vec2<f32>, x: f32, y: f32) -> vec3<f32> {
+    return textureSampleLevel(src, src_sampler, uv + vec2<f32>(x, y) * texel, 0.0).rgb;
+}
+
+// 13 taps in overlapping 2x2 boxes: one in the middle, and four around it sharing the centre
+@fragment
+fn fs_downsample(in: VertexOutput) -> @location(0) vec4<f32> {
+    let texel = 1.0 / vec2<f32>(textureDimensions(src));
+    let a = tap(in.uv, texel, -2.0, -2.0);
+    let b = tap(in.uv, texel, 0.0, -2.0);
+    let c = tap(in.uv, texel, 2.0, -2.0);
+    let d = tap(in.uv, texel, -2.0, 0.0);
+    let e = tap(in.uv, texel, 0.0, 0.0);
+    let f = tap(in.uv, texel, 2.0, 0.0);
+    let g = tap(in.uv, texel, -2.0, 2.0);
+    let h = tap(in.uv, texel, 0.0, 2.0);
+    let i = tap(in.uv, texel, 2.0, 2.0);
+    let j = tap(in.uv, texel, -1.0, -1.0);
+    let k = tap(in.uv, texel, 1.0, -1.0);
+    let l = tap(in.uv, texel, -1.0, 1.0);
+    let m = tap(in.uv, texel, 1.0, 1.0);
+
+    if pc.prefilter == 0u {
+        let color = e * 0.125 + (a + c + g + i) * 0.03125 + (b + d + f + h) * 0.0625
+            + (j + k + l + m) * 0.125;
+        return vec4<f32>(color, 1.0);
+    }
+
+    let sum = karis_box(j, k, l, m) * 0.5 + karis_box(a, b, d, e) * 0.125
+        + karis_box(b, c, e, f) * 0.125 + karis_box(d, e, g, h) * 0.125
+        + karis_box(e, f, h, i) * 0.125;
+    return vec4<f32>(threshold(sum.rgb / sum.a), 1.0);
+}
+
+// 3x3 tent, added onto the next larger mip by blending
+@fragment
+fn fs_upsample(in: VertexOutput) -> @location(0) vec4<f32> {
+    // Round on screen whatever the aspect ratio
+    let size = vec2<f32>(textureDimensions(src));
+    let r = vec2<f32>(pc.radius, pc.radius * size.x / size.y);
+    let color = tap(in.uv, r, 0.0, 0.0) * 4.0
+        + (tap(in.uv, r, 0.0, -1.0) + tap(in.uv, r, -1.0, 0.0) + tap(in.uv, r, 1.0, 0.0)
+        + tap(in.uv, r, 0.0, 1.0)) * 2.0
+        + tap(in.uv, r, -1.0, -1.0) + tap(in.uv, r, 1.0, -1.0) + tap(in.uv, r, -1.0, 1.0)
+        + tap(in.uv, r, 1.0, 1.0);
+    return vec4<f32>(color / 16.0, 1.0);
+}
@@ -0,0 +1,105 @@
+// Bins point and spot lights into a grid of froxels: screen tiles, cut into depth slices that
+// grow exponentially with distance. One invocation per cluster tests every light's sphere
+// against the cluster's view space bounding box
+
+// Has to match cluster.rs and mesh.wgsl
+const CLUSTERS_X: u32 = 16u;
+const CLUSTERS_Y: u32 = 9u;
+const CLUSTERS_Z: u32 = 24u;
+const MAX_LIGHTS_PER_CLUSTER: u32 = 128u;
+const CASCADE_COUNT: u32 = 4u;
+
+struct PunctualLight {
+    position_range: vec4<f32>,
+    color_spot_scale: vec4<f32>,
+    direction_spot_offset: vec4<f32>,
+}
+
+// Has to match mesh.wgsl
+struct SceneData {
+    view: mat4x4<f32>,
+    proj: mat4x4<f32>,
+    view_proj: mat4x4<f32>,
+    inv_view_proj: mat4x4<f32>,
+    camera_position: vec4<f32>,
+    sun_direction: vec4<f32>,
+    sun_color: vec4<f32>,
+    light_count: u32,
+    environment_intensity: f32,
+    debug_view: u32,
+    far_depth: f32,
+    cascade_view_proj: array<mat4x4<f32>, CASCADE_COUNT>,
+    cascade_splits: vec4<f32>,
+    cascade_texel_sizes: vec4<f32>,
+    inv_proj: mat4x4<f32>,
+    screen_size: vec2<f32>,
+    cluster_near: f32,
+    cluster_far: f32,
+}
+
+@group(0) @binding(0) var<uniform> scene: SceneData;
+@group(0) @binding(8) var<storage, read> lights: array<PunctualLight>;
+@group(0) @binding(9) var<storage, read_write> cluster_counts: array<u32>;
+@group(0) @binding(10) var<storage, read_write> cluster_lights: array<u32>;
+
+// View space point along the ray through `ndc` at a view depth, which works for perspective and
+// orthographic projections alike
+fn point_at_depth(ndc: vec2<f32>, depth: f32) -> vec3<f32> {
+    let a4 = scene.inv_proj * vec4<f32>(ndc, 0.0, 1.0);
+    let b4 = scene.inv_proj * vec4<f32>(ndc, 1.0, 1.0);
+    let a = a4.xyz / a4.w;
+    let b = b4.xyz / b4.w;
+    return mix(a, b, (-depth - a.z) / (b.z - a.z));
+}
+
+fn slice_depth(slice: u32) -> f32 {
+    let t = f32(slice) / f32(CLUSTERS_Z);
+    return scene.cluster_near * pow(scene.cluster_far / scene.cluster_near, t);
+}
+
+@compute @workgroup_size(64, 1, 1)
+fn cs_main(@builtin(global_invocation_id) id: vec3<u32>) {
+    let cluster = id.x;
+    if cluster >= CLUSTERS_X * CLUSTERS_Y * CLUSTERS_Z {
+        return;
+    }
+    let x = cluster % CLUSTERS_X;
+    let y = (cluster / CLUSTERS_X) % CLUSTERS_Y;
+    let z = cluster / (CLUSTERS_X * CLUSTERS_Y);
+
+    let tile_min = vec2<f32>(f32(x), f32(y)) / vec2<f32>(f32(CLUSTERS_X), f32(CLUSTERS_Y)) * 2.0
+        - 1.0;
+    let tile_max = vec2<f32>(f32(x + 1u), f32(y + 1u))
+        / vec2<f32>(f32(CLUSTERS_X), f32(CLUSTERS_Y)) * 2.0 - 1.0;
+    let near = slice_depth(z);
+    let far = slice_depth(z + 1u);
+
+    var box_min = vec3<f32>(3.4e38);
+    var box_max = vec3<f32>(-3.4e38);
+    for (var i = 0u; i < 4u; i++) {
+        let ndc = vec2<f32>(
+            select(tile_min.x, tile_max.x, (i & 1u) != 0u),
+            select(tile_min.y, tile_max.y, (i & 2u) != 0u),
+        );
+        let p_near = point_at_depth(ndc, near);
+        let p_far = point_at_depth(ndc, far);
+        box_min = min(box_min, min(p_near, p_far));
+        box_max = max(box_max, max(p_near, p_far));
+    }
+
+    // Spot lights are tested as their whole sphere, which is conservative
+    var count = 0u;
+    let base = cluster * MAX_LIGHTS_PER_CLUSTER;
+    for (var i = 0u; i < scene.light_count && count < MAX_LIGHTS_PER_CLUSTER; i++) {
+        let light = lights[i];
+        let center = (scene.view * vec4<f32>(light.position_range.xyz, 1.0)).xyz;
+        let closest = clamp(center, box_min, box_max);
+        let d = closest - center;
+        let range = light.position_range.w;
+        if dot(d, d) <= range * range {
+            cluster_lights[base + count] = i;
+            count++;
+        }
+    }
+    cluster_counts[cluster] = count;
+}
@@ -0,0 +1,94 @@
+// Automatic exposure: bins the draw image's log luminance into a histogram, then averages it
+// and eases the scene's adapted luminance towards the result
+
+// Has to match exposure.rs
+const BIN_COUNT: u32 = 256u;
+// Middle grey, what the average luminance is exposed to
+const KEY_VALUE: f32 = 0.18;
+
+struct PushConstants {
+    // log2 luminance of the second bin, the first one only counts black pixels
+    min_log_luminance: f32,
+    log_luminance_range: f32,
+    // How far to move towards this frame's average, from 0 to 1
+    adaptation: f32,
+    // Jumps straight to this frame's average when the state is stale
+    reset: u32,
+}
+
+struct ExposureState {
+    luminance: f32,
+    exposure: f32,
+}
+
+var<push_constant> pc: PushConstants;
+
+@group(0) @binding(0) var scene: texture_2d<f32>;
+@group(0) @binding(1) var<storage, read_write> histogram: array<atomic<u32>, BIN_COUNT>;
+@group(0) @binding(2) var<storage, read_write> state: ExposureState;
+
+var<workgroup> local_bins: array<atomic<u32>, BIN_COUNT>;
+var<workgroup> weighted: array<f32, BIN_COUNT>;
+
+fn bin(color: vec3<f32>) -> u32 {
+    let luminance = dot(color, vec3<f32>(0.2126, 0.7152, 0.0722));
+    if luminance < 1e-5 {
+        return 0u;
+    }
+    let t = clamp((log2(luminance) - pc.min_log_luminance) / pc.log_luminance_range, 0.0, 1.0);
+    return u32(t * f32(BIN_COUNT - 2u)) + 1u;
+}
+
+// Counts into shared memory first, so the global histogram only sees one add per bin per group
+@compute @workgroup_size(16, 16, 1)
+fn cs_histogram(
+    @builtin(global_invocation_id) id: vec3<u32>,
+    @builtin(local_invocation_index) index: u32,
+) {
+    atomicStore(&local_bins[index], 0u);
+    workgroupBarrier();
+
+    let size = textureDimensions(scene);
+    if all(id.xy < size) {
+        atomicAdd(&local_bins[bin(textureLoad(scene, id.xy, 0).rgb)], 1u);
+    }
+    workgroupBarrier();
+
+    let count = atomicLoad(&local_bins[index]);
+    if count > 0u {
+        atomicAdd(&histogram[index], count);
+    }
+}
+
+// One group with one invocation per bin
+@compute @workgroup_size(256, 1, 1)
+fn cs_average(@builtin(local_invocation_index) index: u32) {
+    let count = atomicLoad(&histogram[index]);
+    weighted[index] = f32(count) * f32(index);
+    workgroupBarrier();
+
+    for (var stride = BIN_COUNT / 2u; stride > 0u; stride >>= 1u) {
+        if index < stride {
+            weighted[index] += weighted[index + stride];
+        }
+        workgroupBarrier();
+    }
+
+    if index != 0u {
+        return;
+    }
+    // Black pixels are left out of the average, `count` is the first bin's here
+    let size = textureDimensions(scene);
+    let lit = max(f32(size.x * size.y) - f32(count), 1.0);
+    let average_bin = weighted[0] / lit - 1.0;
+    let log_luminance = average_bin / f32(BIN_COUNT - 2u) * pc.log_luminance_range
+        + pc.min_log_luminance;
+    let target_luminance = exp2(log_luminance);
+
+    var luminance = target_luminance;
+    if pc.reset == 0u {
+        luminance = mix(state.luminance, target_luminance, pc.adaptation);
+    }
+    state.luminance = luminance;
+    state.exposure = KEY_VALUE / luminance;
+}
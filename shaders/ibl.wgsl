@@ -0,0 +1,211 @@
+// Bakes image based lighting: an equirectangular image or six face images into a cubemap, the
+// cubemap into diffuse irradiance and GGX prefiltered radiance, and the split-sum BRDF lookup
+// table.
+// Cube faces are dispatched as the Z dimension, in Vulkan's +X, -X, +Y, -Y, +Z, -Z order
+
+const PI: f32 = 3.14159265359;
+
+struct PushConstants {
+    // Mip of the source to read for `cs_equirect_to_cube`, `cs_face_to_cube` and
+    // `cs_irradiance`, roughness for `cs_prefilter`
+    param: f32,
+    // The face being written for `cs_face_to_cube`
+    sample_count: u32,
+}
+
+var<push_constant> pc: PushConstants;
+
+@group(0) @binding(0) var equirect: texture_2d<f32>;
+@group(0) @binding(1) var environment: texture_cube<f32>;
+@group(0) @binding(2) var linear_sampler: sampler;
+@group(0) @binding(3) var cube_dst: texture_storage_2d_array<rgba16float, write>;
+@group(0) @binding(4) var lut_dst: texture_storage_2d<rgba16float, write>;
+
+// Direction through the centre of a texel of a cube face
+fn cube_direction(id: vec3<u32>, size: u32) -> vec3<f32> {
+    let uv = (vec2<f32>(id.xy) + 0.5) / f32(size) * 2.0 - 1.0;
+    var dir: vec3<f32>;
+    switch id.z {
+        case 0u: { dir = vec3<f32>(1.0, -uv.y, -uv.x); }
+        case 1u: { dir = vec3<f32>(-1.0, -uv.y, uv.x); }
+        case 2u: { dir = vec3<f32>(uv.x, 1.0, uv.y); }
+        case 3u: { dir = vec3<f32>(uv.x, -1.0, -uv.y); }
+        case 4u: { dir = vec3<f32>(uv.x, -uv.y, 1.0); }
+        default: { dir = vec3<f32>(-uv.x, -uv.y, -1.0); }
+    }
+    return normalize(dir);
+}
+
+// Any orthonormal basis around `n` will do, the integrals are symmetric around it
+fn tangent_to_world(v: vec3<f32>, n: vec3<f32>) -> vec3<f32> {
+    let up = select(vec3<f32>(1.0, 0.0, 0.0), vec3<f32>(0.0, 0.0, 1.0), abs(n.z) < 0.999);
+    let t = normalize(cross(up, n));
+    let b = cross(n, t);
+    return t * v.x + b * v.y + n * v.z;
+}
+
+fn hammersley(i: u32, count: u32) -> vec2<f32> {
+    return vec2<f32>(f32(i) / f32(count), f32(reverseBits(i)) * 2.3283064365386963e-10);
+}
+
+// Half vector around +Z distributed like GGX's normal distribution
+fn importance_sample_ggx(xi: vec2<f32>, alpha: f32) -> vec3<f32> {
+    let phi = 2.0 * PI * xi.x;
+    let cos_theta = sqrt((1.0 - xi.y) / (1.0 + (alpha * alpha - 1.0) * xi.y));
+    let sin_theta = sqrt(1.0 - cos_theta * cos_theta);
+    return vec3<f32>(sin_theta * cos(phi), sin_theta * sin(phi), cos_theta);
+}
+
+fn distribution_ggx(n_dot_h: f32, alpha: f32) -> f32 {
+    let a2 = alpha * alpha;
+    let d = n_dot_h * n_dot_h * (a2 - 1.0) + 1.0;
+    return a2 / (PI * d * d);
+}
+
+// Matches the direct lighting in mesh.wgsl
+fn visibility_smith_ggx(n_dot_v: f32, n_dot_l: f32, alpha: f32) -> f32 {
+    let a2 = alpha * alpha;
+    let ggx_v = n_dot_l * sqrt(n_dot_v * n_dot_v * (1.0 - a2) + a2);
+    let ggx_l = n_dot_v * sqrt(n_dot_l * n_dot_l * (1.0 - a2) + a2);
+    return 0.5 / max(ggx_v + ggx_l, 1e-5);
+}
+
+@compute @workgroup_size(8, 8, 1)
+fn cs_equirect_to_cube(@builtin(global_invocation_id) id: vec3<u32>) {
+    let size = textureDimensions(cube_dst).x;
+    if any(id.xy >= vec2<u32>(size)) {
+        return;
+    }
+
+    let dir = cube_direction(id, size);
+    let uv = vec2<f32>(atan2(dir.z, dir.x) / (2.0 * PI) + 0.5, acos(clamp(dir.y, -1.0, 1.0)) / PI);
+    let color = textureSampleLevel(equirect, linear_sampler, uv, pc.param);
+    textureStore(cube_dst, id.xy, id.z, vec4<f32>(color.rgb, 1.0));
+}
+
+// One face image resampled into the same face of the cube, dispatched once per face
+@compute @workgroup_size(8, 8, 1)
+fn cs_face_to_cube(@builtin(global_invocation_id) id: vec3<u32>) {
+    let size = textureDimensions(cube_dst).x;
+    if any(id.xy >= vec2<u32>(size)) {
+        return;
+    }
+
+    let uv = (vec2<f32>(id.xy) + 0.5) / f32(size);
+    let color = textureSampleLevel(equirect, linear_sampler, uv, pc.param);
+    textureStore(cube_dst, id.xy, pc.sample_count, vec4<f32>(color.rgb, 1.0));
+}
+
+// Cosine weighted integral over the hemisphere, stored already multiplied by albedo's 1/pi
+// so shading only multiplies by the diffuse colour
+@compute @workgroup_size(8, 8, 1)
+fn cs_irradiance(@builtin(global_invocation_id) id: vec3<u32>) {
+    let size = textureDimensions(cube_dst).x;
+    if any(id.xy >= vec2<u32>(size)) {
+        return;
+    }
+
+    let n = cube_direction(id, size);
+    // Steps in both angles, so sample_count^2 samples in total
+    let steps = pc.sample_count;
+    var sum = vec3<f32>(0.0);
+    for (var i = 0u; i < steps; i++) {
+        let phi = 2.0 * PI * (f32(i) + 0.5) / f32(steps);
+        for (var j = 0u; j < steps; j++) {
+            let theta = 0.5 * PI * (f32(j) + 0.5) / f32(steps);
+            let local = vec3<f32>(sin(theta) * cos(phi), sin(theta) * sin(phi), cos(theta));
+            let radiance = textureSampleLevel(
+                environment,
+                linear_sampler,
+                tangent_to_world(local, n),
+                pc.param,
+            ).rgb;
+            sum += radiance * cos(theta) * sin(theta);
+        }
+    }
+
+    let irradiance = PI * sum / f32(steps * steps);
+    textureStore(cube_dst, id.xy, id.z, vec4<f32>(irradiance, 1.0));
+}
+
+// GGX importance sampling assuming the view direction is the normal. Samples read from a mip
+// matching their solid angle, which keeps bright spots from turning into fireflies
+@compute @workgroup_size(8, 8, 1)
+fn cs_prefilter(@builtin(global_invocation_id) id: vec3<u32>) {
+    let size = textureDimensions(cube_dst).x;
+    if any(id.xy >= vec2<u32>(size)) {
+        return;
+    }
+
+    let n = cube_direction(id, size);
+    let roughness = pc.param;
+    if roughness == 0.0 {
+        textureStore(cube_dst, id.xy, id.z, textureSampleLevel(environment, linear_sampler, n, 0.0));
+        return;
+    }
+
+    let alpha = roughness * roughness;
+    let source_size = f32(textureDimensions(environment).x);
+    let texel_solid_angle = 4.0 * PI / (6.0 * source_size * source_size);
+    let max_lod = f32(textureNumLevels(environment) - 1u);
+
+    var sum = vec3<f32>(0.0);
+    var weight = 0.0;
+    for (var i = 0u; i < pc.sample_count; i++) {
+        let h = tangent_to_world(importance_sample_ggx(hammersley(i, pc.sample_count), alpha), n);
+        let n_dot_h = dot(n, h);
+        let l = 2.0 * n_dot_h * h - n;
+        let n_dot_l = dot(n, l);
+        if n_dot_l <= 0.0 {
+            continue;
+        }
+
+        // With v == n, the pdf of l is D / 4
+        let pdf = distribution_ggx(max(n_dot_h, 0.0), alpha) * 0.25;
+        let sample_solid_angle = 1.0 / (f32(pc.sample_count) * pdf + 1e-4);
+        let lod = clamp(0.5 * log2(sample_solid_angle / texel_solid_angle) + 1.0, 0.0, max_lod);
+
+        sum += textureSampleLevel(environment, linear_sampler, l, lod).rgb * n_dot_l;
+        weight += n_dot_l;
+    }
+
+    textureStore(cube_dst, id.xy, id.z, vec4<f32>(sum / max(weight, 1e-4), 1.0));
+}
+
+// Scale and bias applied to F0 by the split-sum approximation, indexed by n_dot_v across and
+// roughness down
+@compute @workgroup_size(8, 8, 1)
+fn cs_brdf_lut(@builtin(global_invocation_id) id: vec3<u32>) {
+    let size = textureDimensions(lut_dst);
+    if any(id.xy >= size) {
+        return;
+    }
+
+    let n_dot_v = (f32(id.x) + 0.5) / f32(size.x);
+    let roughness = (f32(id.y) + 0.5) / f32(size.y);
+    let alpha = roughness * roughness;
+    let v = vec3<f32>(sqrt(1.0 - n_dot_v * n_dot_v), 0.0, n_dot_v);
+
+    var scale = 0.0;
+    var bias = 0.0;
+    for (var i = 0u; i < pc.sample_count; i++) {
+        let h = importance_sample_ggx(hammersley(i, pc.sample_count), alpha);
+        let l = 2.0 * dot(v, h) * h - v;
+        let n_dot_l = l.z;
+        if n_dot_l <= 0.0 {
+            continue;
+        }
+        let n_dot_h = max(h.z, 0.0);
+        let v_dot_h = max(dot(v, h), 0.0);
+
+        // BRDF * n_dot_l / pdf, with the Fresnel term factored out
+        let g_vis = visibility_smith_ggx(n_dot_v, n_dot_l, alpha) * 4.0 * n_dot_l * v_dot_h
+            / max(n_dot_h, 1e-4);
+        let fc = pow(1.0 - v_dot_h, 5.0);
+        scale += (1.0 - fc) * g_vis;
+        bias += fc * g_vis;
+    }
+
+    let count = f32(pc.sample_count);
+    textureStore(lut_dst, id.xy, vec4<f32>(scale / count, bias / count, 0.0, 1.0));
+}
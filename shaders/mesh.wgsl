@@ -0,0 +1,634 @@
+// Forward and deferred shading of glTF's metallic-roughness materials
+
+const PI: f32 = 3.14159265359;
+// Keeps highlights from collapsing to nothing on perfectly smooth surfaces
+const MIN_ROUGHNESS: f32 = 0.045;
+// Mip of the prefiltered environment holding roughness 1, has to match ibl.rs
+const PREFILTERED_MAX_LOD: f32 = 5.0;
+// Has to match shadow.rs
+const CASCADE_COUNT: u32 = 4u;
+// Shadow lookups are pushed off the surface by this many shadow map texels
+const SHADOW_NORMAL_OFFSET: f32 = 1.5;
+// Has to match cluster.rs and cluster.wgsl
+const CLUSTERS_X: u32 = 16u;
+const CLUSTERS_Y: u32 = 9u;
+const CLUSTERS_Z: u32 = 24u;
+const MAX_LIGHTS_PER_CLUSTER: u32 = 128u;
+// Light count the heatmap turns fully red at
+const HEATMAP_MAX_LIGHTS: f32 = 32.0;
+// Has to match the renderer's DebugView
+const DEBUG_VIEW_CASCADES: u32 = 1u;
+const DEBUG_VIEW_LIGHT_COUNT: u32 = 2u;
+const DEBUG_VIEW_AMBIENT_OCCLUSION: u32 = 3u;
+// Has to match atmosphere.wgsl, in kilometres
+const BOTTOM_RADIUS: f32 = 6360.0;
+const TOP_RADIUS: f32 = 6460.0;
+const WORLD_TO_KM: f32 = 1e-3;
+const MIN_ALTITUDE: f32 = 0.01;
+
+// Point lights have a spot scale of 0 and offset of 1, so the cone never attenuates them
+struct PunctualLight {
+    position_range: vec4<f32>,
+    color_spot_scale: vec4<f32>,
+    // Direction the light shines in
+    direction_spot_offset: vec4<f32>,
+}
+
+struct SceneData {
+    view: mat4x4<f32>,
+    proj: mat4x4<f32>,
+    view_proj: mat4x4<f32>,
+    inv_view_proj: mat4x4<f32>,
+    camera_position: vec4<f32>,
+    // Direction the sunlight travels in
+    sun_direction: vec4<f32>,
+    sun_color: vec4<f32>,
+    light_count: u32,
+    // Scales all light coming from the environment, skybox included
+    environment_intensity: f32,
+    debug_view: u32,
+    // Depth buffer value at the far plane, 0 with reverse-Z
+    far_depth: f32,
+    cascade_view_proj: array<mat4x4<f32>, CASCADE_COUNT>,
+    // View space depth each cascade ends at
+    cascade_splits: vec4<f32>,
+    // World space size of a shadow map texel in each cascade
+    cascade_texel_sizes: vec4<f32>,
+    inv_proj: mat4x4<f32>,
+    screen_size: vec2<f32>,
+    // View space depths the first cluster slice starts and the last one ends at
+    cluster_near: f32,
+    cluster_far: f32,
+    // Whether ambient_occlusion_map holds this frame's screen-space occlusion
+    ambient_occlusion: u32,
+    // Whether the sun shines through the procedural atmosphere, dimming and reddening it
+    atmosphere: u32,
+    // Turns world space directions into the environment's, for the skybox's rotation
+    environment_rotation: mat4x4<f32>,
+    // Both without jitter, for motion vectors
+    unjittered_view_proj: mat4x4<f32>,
+    previous_view_proj: mat4x4<f32>,
+}
+
+struct MaterialConstants {
+    base_color_factor: vec4<f32>,
+    emissive_factor: vec4<f32>,
+    metallic_factor: f32,
+    roughness_factor: f32,
+    normal_scale: f32,
+    occlusion_strength: f32,
+    alpha_cutoff: f32,
+}
+
+struct PushConstants {
+    model: mat4x4<f32>,
+    // What the model was drawn with last frame
+    previous_model: mat4x4<f32>,
+}
+
+@group(0) @binding(0) var<uniform> scene: SceneData;
+@group(0) @binding(1) var irradiance_map: texture_cube<f32>;
+@group(0) @binding(2) var prefiltered_map: texture_cube<f32>;
+@group(0) @binding(3) var brdf_lut: texture_2d<f32>;
+@group(0) @binding(4) var environment_sampler: sampler;
+@group(0) @binding(6) var shadow_map: texture_depth_2d_array;
+@group(0) @binding(7) var shadow_sampler: sampler_comparison;
+@group(0) @binding(8) var<storage, read> lights: array<PunctualLight>;
+@group(0) @binding(9) var<storage, read> cluster_counts: array<u32>;
+@group(0) @binding(10) var<storage, read> cluster_lights: array<u32>;
+@group(0) @binding(11) var ambient_occlusion_map: texture_2d<f32>;
+@group(0) @binding(12) var transmittance_lut: texture_2d<f32>;
+
+@group(1) @binding(0) var<uniform> material: MaterialConstants;
+@group(1) @binding(1) var base_color_texture: texture_2d<f32>;
+@group(1) @binding(2) var metallic_roughness_texture: texture_2d<f32>;
+@group(1) @binding(3) var normal_texture: texture_2d<f32>;
+@group(1) @binding(4) var occlusion_texture: texture_2d<f32>;
+@group(1) @binding(5) var emissive_texture: texture_2d<f32>;
+@group(1) @binding(6) var material_sampler: sampler;
+
+// Written by the G-buffer entry points, read back by the deferred lighting pass
+@group(2) @binding(0) var gbuffer_albedo: texture_2d<f32>;
+@group(2) @binding(1) var gbuffer_normal: texture_2d<f32>;
+@group(2) @binding(2) var gbuffer_material: texture_2d<f32>;
+@group(2) @binding(3) var gbuffer_emissive: texture_2d<f32>;
+@group(2) @binding(4) var gbuffer_depth: texture_depth_2d;
+
+var<push_constant> pc: PushConstants;
+
+struct VertexInput {
+    @location(0) position: vec3<f32>,
+    @location(1) normal: vec3<f32>,
+    @location(2) uv: vec2<f32>,
+    @location(3) color: vec4<f32>,
+    @location(4) tangent: vec4<f32>,
+}
+
+struct VertexOutput {
+    @builtin(position) position: vec4<f32>,
+    @location(0) normal: vec3<f32>,
+    @location(1) uv: vec2<f32>,
+    @location(2) color: vec4<f32>,
+    @location(3) world_position: vec3<f32>,
+    @location(4) tangent: vec4<f32>,
+    // Where the vertex is this frame and was last frame, without jitter
+    @location(5) clip_position: vec4<f32>,
+    @location(6) previous_clip_position: vec4<f32>,
+}
+
+@vertex
+fn vs_main(in: VertexInput) -> VertexOutput {
+    let world = pc.model * vec4<f32>(in.position, 1.0);
+    // Fine as long as the model matrix has no non-uniform scale
+    let normal = (pc.model * vec4<f32>(in.normal, 0.0)).xyz;
+    let tangent = vec4<f32>((pc.model * vec4<f32>(in.tangent.xyz, 0.0)).xyz, in.tangent.w);
+    let previous_world = pc.previous_model * vec4<f32>(in.position, 1.0);
+    return VertexOutput(
+        scene.view_proj * world,
+        normal,
+        in.uv,
+        in.color,
+        world.xyz,
+        tangent,
+        scene.unjittered_view_proj * world,
+        scene.previous_view_proj * previous_world,
+    );
+}
+
+// How far the surface moved in UV since the last frame, and that it covers the pixel. Blending
+// weights both by alpha, so TAA can fill in the rest from what's behind
+fn motion(in: VertexOutput, alpha: f32) -> vec4<f32> {
+    let current = in.clip_position.xy / in.clip_position.w;
+    let previous = in.previous_clip_position.xy / in.previous_clip_position.w;
+    return vec4<f32>((current - previous) * 0.5, 1.0, alpha);
+}
+
+// Every texture is sampled up front, while control flow is still uniform
+struct MaterialSample {
+    base_color: vec4<f32>,
+    metallic: f32,
+    roughness: f32,
+    // Tangent space
+    normal: vec3<f32>,
+    occlusion: f32,
+    emissive: vec3<f32>,
+}
+
+fn sample_material(in: VertexOutput) -> MaterialSample {
+    let base_color = textureSample(base_color_texture, material_sampler, in.uv)
+        * material.base_color_factor * in.color;
+    let metallic_roughness = textureSample(metallic_roughness_texture, material_sampler, in.uv);
+    let normal = textureSample(normal_texture, material_sampler, in.uv).xyz * 2.0 - 1.0;
+    let occlusion = textureSample(occlusion_texture, material_sampler, in.uv).r;
+    let emissive = textureSample(emissive_texture, material_sampler, in.uv).rgb;
+
+    return MaterialSample(
+        base_color,
+        clamp(material.metallic_factor * metallic_roughness.b, 0.0, 1.0),
+        clamp(material.roughness_factor * metallic_roughness.g, MIN_ROUGHNESS, 1.0),
+        normalize(normal * vec3<f32>(material.normal_scale, material.normal_scale, 1.0)),
+        mix(1.0, occlusion, material.occlusion_strength),
+        material.emissive_factor.rgb * emissive,
+    );
+}
+
+// Back faces of double sided materials are lit as if they were front faces
+fn shading_normal(
+    in: VertexOutput,
+    front_facing: bool,
+    face_normal: vec3<f32>,
+    tangent_normal: vec3<f32>,
+) -> vec3<f32> {
+    let side = select(-1.0, 1.0, front_facing);
+    // Meshes without normals get faceted shading
+    var n = face_normal;
+    if dot(in.normal, in.normal) > 0.0 {
+        n = normalize(in.normal) * side;
+    }
+
+    if dot(in.tangent.xyz, in.tangent.xyz) == 0.0 {
+        return n;
+    }
+    let t = normalize(in.tangent.xyz - n * dot(n, in.tangent.xyz)) * side;
+    let b = cross(n, t) * in.tangent.w * side;
+    return normalize(t * tangent_normal.x + b * tangent_normal.y + n * tangent_normal.z);
+}
+
+fn distribution_ggx(n_dot_h: f32, alpha: f32) -> f32 {
+    let a2 = alpha * alpha;
+    let d = n_dot_h * n_dot_h * (a2 - 1.0) + 1.0;
+    return a2 / (PI * d * d);
+}
+
+// Height correlated Smith, already divided by 4 * n_dot_l * n_dot_v
+fn visibility_smith_ggx(n_dot_v: f32, n_dot_l: f32, alpha: f32) -> f32 {
+    let a2 = alpha * alpha;
+    let ggx_v = n_dot_l * sqrt(n_dot_v * n_dot_v * (1.0 - a2) + a2);
+    let ggx_l = n_dot_v * sqrt(n_dot_l * n_dot_l * (1.0 - a2) + a2);
+    return 0.5 / max(ggx_v + ggx_l, 1e-5);
+}
+
+fn fresnel_schlick(f0: vec3<f32>, v_dot_h: f32) -> vec3<f32> {
+    return f0 + (1.0 - f0) * pow(1.0 - v_dot_h, 5.0);
+}
+
+struct Surface {
+    n: vec3<f32>,
+    v: vec3<f32>,
+    diffuse_color: vec3<f32>,
+    f0: vec3<f32>,
+    roughness: f32,
+    alpha: f32,
+}
+
+// Lambert diffuse plus a GGX specular lobe, times the cosine term
+fn brdf(surface: Surface, l: vec3<f32>) -> vec3<f32> {
+    let n_dot_l = dot(surface.n, l);
+    if n_dot_l <= 0.0 {
+        return vec3<f32>(0.0);
+    }
+    let h = normalize(l + surface.v);
+    let n_dot_v = max(dot(surface.n, surface.v), 1e-4);
+    let n_dot_h = max(dot(surface.n, h), 0.0);
+    let v_dot_h = max(dot(surface.v, h), 0.0);
+
+    let f = fresnel_schlick(surface.f0, v_dot_h);
+    let specular = f * distribution_ggx(n_dot_h, surface.alpha)
+        * visibility_smith_ggx(n_dot_v, n_dot_l, surface.alpha);
+    let diffuse = (1.0 - f) * surface.diffuse_color / PI;
+    return (diffuse + specular) * n_dot_l;
+}
+
+// Inverse square falloff, windowed to reach zero at the range as glTF suggests
+fn distance_attenuation(distance_sq: f32, range: f32) -> f32 {
+    let ratio = distance_sq / (range * range);
+    let window = clamp(1.0 - ratio * ratio, 0.0, 1.0);
+    return window * window / max(distance_sq, 1e-4);
+}
+
+fn punctual_light(light: PunctualLight, surface: Surface, position: vec3<f32>) -> vec3<f32> {
+    let to_light = light.position_range.xyz - position;
+    let distance_sq = dot(to_light, to_light);
+    let l = to_light * inverseSqrt(max(distance_sq, 1e-8));
+
+    let cd = dot(light.direction_spot_offset.xyz, -l);
+    let spot = clamp(cd * light.color_spot_scale.w + light.direction_spot_offset.w, 0.0, 1.0);
+
+    let radiance = light.color_spot_scale.rgb * spot * spot
+        * distance_attenuation(distance_sq, light.position_range.w);
+    return brdf(surface, l) * radiance;
+}
+
+// Diffuse irradiance plus the split-sum approximation of the specular lobe. Explicit LODs keep
+// this fine to call after a discard
+fn image_based_light(surface: Surface) -> vec3<f32> {
+    let n_dot_v = clamp(dot(surface.n, surface.v), 1e-4, 1.0);
+    let r = (scene.environment_rotation * vec4<f32>(reflect(-surface.v, surface.n), 0.0)).xyz;
+    let n = (scene.environment_rotation * vec4<f32>(surface.n, 0.0)).xyz;
+
+    let lut = textureSampleLevel(
+        brdf_lut,
+        environment_sampler,
+        vec2<f32>(n_dot_v, surface.roughness),
+        0.0,
+    ).rg;
+    let specular_color = surface.f0 * lut.x + lut.y;
+    let prefiltered = textureSampleLevel(
+        prefiltered_map,
+        environment_sampler,
+        r,
+        surface.roughness * PREFILTERED_MAX_LOD,
+    ).rgb;
+    let irradiance = textureSampleLevel(irradiance_map, environment_sampler, n, 0.0).rgb;
+
+    let diffuse = irradiance * surface.diffuse_color * (1.0 - specular_color);
+    return (diffuse + prefiltered * specular_color) * scene.environment_intensity;
+}
+
+// Index of the cascade covering a point, or CASCADE_COUNT past the last one
+fn cascade_index(world_position: vec3<f32>) -> u32 {
+    let depth = -(scene.view * vec4<f32>(world_position, 1.0)).z;
+    for (var i = 0u; i < CASCADE_COUNT; i++) {
+        if depth < scene.cascade_splits[i] {
+            return i;
+        }
+    }
+    return CASCADE_COUNT;
+}
+
+// Fraction of the sun reaching a point, 3x3 taps of bilinear comparisons
+fn sun_shadow(world_position: vec3<f32>, n: vec3<f32>) -> f32 {
+    let cascade = cascade_index(world_position);
+    if cascade == CASCADE_COUNT {
+        return 1.0;
+    }
+
+    let offset = n * scene.cascade_texel_sizes[cascade] * SHADOW_NORMAL_OFFSET;
+    let clip = scene.cascade_view_proj[cascade] * vec4<f32>(world_position + offset, 1.0);
+    let uv = clip.xy * 0.5 + 0.5;
+    let texel = 1.0 / vec2<f32>(textureDimensions(shadow_map));
+
+    var lit = 0.0;
+    for (var y = -1; y <= 1; y++) {
+        for (var x = -1; x <= 1; x++) {
+            lit += textureSampleCompareLevel(
+                shadow_map,
+                shadow_sampler,
+                uv + vec2<f32>(f32(x), f32(y)) * texel,
+                cascade,
+                clip.z,
+            );
+        }
+    }
+    return lit / 9.0;
+}
+
+fn cascade_tint(world_position: vec3<f32>) -> vec3<f32> {
+    var tints = array<vec3<f32>, 5>(
+        vec3<f32>(1.0, 0.3, 0.3),
+        vec3<f32>(0.3, 1.0, 0.3),
+        vec3<f32>(0.3, 0.3, 1.0),
+        vec3<f32>(1.0, 1.0, 0.3),
+        // Beyond the shadow distance
+        vec3<f32>(1.0),
+    );
+    return tints[cascade_index(world_position)];
+}
+
+// Inverse of cluster.wgsl's slicing
+fn cluster_index(frag_coord: vec4<f32>, world_position: vec3<f32>) -> u32 {
+    let depth = -(scene.view * vec4<f32>(world_position, 1.0)).z;
+    let slices = log(max(depth, scene.cluster_near) / scene.cluster_near)
+        / log(scene.cluster_far / scene.cluster_near) * f32(CLUSTERS_Z);
+    let tile = frag_coord.xy / scene.screen_size
+        * vec2<f32>(f32(CLUSTERS_X), f32(CLUSTERS_Y));
+    let x = min(u32(tile.x), CLUSTERS_X - 1u);
+    let y = min(u32(tile.y), CLUSTERS_Y - 1u);
+    let z = min(u32(slices), CLUSTERS_Z - 1u);
+    return x + CLUSTERS_X * (y + CLUSTERS_Y * z);
+}
+
+// Blue through green to red as more lights pile into a cluster
+fn heatmap(count: u32) -> vec3<f32> {
+    let t = clamp(f32(count) / HEATMAP_MAX_LIGHTS, 0.0, 1.0);
+    return clamp(
+        vec3<f32>(2.0 * t - 1.0, 1.0 - abs(2.0 * t - 1.0), 1.0 - 2.0 * t),
+        vec3<f32>(0.0),
+        vec3<f32>(1.0),
+    );
+}
+
+// Screen-space occlusion of the opaque surface at this pixel, filtered up from however many
+// pixels it was computed at
+fn screen_occlusion(frag_coord: vec4<f32>) -> f32 {
+    if scene.ambient_occlusion == 0u {
+        return 1.0;
+    }
+    let uv = frag_coord.xy / scene.screen_size;
+    return textureSampleLevel(ambient_occlusion_map, environment_sampler, uv, 0.0).r;
+}
+
+// The sun's light after passing through the atmosphere to a point, none once it's set. Looked
+// up the same way as in atmosphere.wgsl
+fn sun_light(world_position: vec3<f32>) -> vec3<f32> {
+    if scene.atmosphere == 0u {
+        return scene.sun_color.rgb;
+    }
+    let altitude = max(world_position.y * WORLD_TO_KM, MIN_ALTITUDE);
+    let r = min(BOTTOM_RADIUS + altitude, TOP_RADIUS - MIN_ALTITUDE);
+    let mu = -normalize(scene.sun_direction.xyz).y;
+    let below_horizon = -sqrt(max(1.0 - BOTTOM_RADIUS * BOTTOM_RADIUS / (r * r), 0.0));
+    if mu < below_horizon {
+        return vec3<f32>(0.0);
+    }
+
+    let h = sqrt(TOP_RADIUS * TOP_RADIUS - BOTTOM_RADIUS * BOTTOM_RADIUS);
+    let rho = sqrt(max(r * r - BOTTOM_RADIUS * BOTTOM_RADIUS, 0.0));
+    let discriminant = r * r * (mu * mu - 1.0) + TOP_RADIUS * TOP_RADIUS;
+    let d = max(-r * mu + sqrt(max(discriminant, 0.0)), 0.0);
+    let d_min = TOP_RADIUS - r;
+    let uv = vec2<f32>((d - d_min) / (rho + h - d_min), rho / h);
+    let transmittance = textureSampleLevel(transmittance_lut, environment_sampler, uv, 0.0).rgb;
+    return scene.sun_color.rgb * transmittance;
+}
+
+fn make_surface(
+    world_position: vec3<f32>,
+    n: vec3<f32>,
+    base_color: vec3<f32>,
+    metallic: f32,
+    roughness: f32,
+) -> Surface {
+    return Surface(
+        n,
+        normalize(scene.camera_position.xyz - world_position),
+        base_color * (1.0 - metallic),
+        mix(vec3<f32>(0.04), base_color, metallic),
+        roughness,
+        roughness * roughness,
+    );
+}
+
+// Shared by both paths, so forward and deferred come out the same
+fn shade(
+    frag_coord: vec4<f32>,
+    world_position: vec3<f32>,
+    surface: Surface,
+    occlusion: f32,
+    emissive: vec3<f32>,
+) -> vec3<f32> {
+    let shadow = sun_shadow(world_position, surface.n);
+    let sun = sun_light(world_position);
+    var color = brdf(surface, -scene.sun_direction.xyz) * sun * shadow;
+    let cluster = cluster_index(frag_coord, world_position);
+    let count = min(cluster_counts[cluster], MAX_LIGHTS_PER_CLUSTER);
+    for (var i = 0u; i < count; i++) {
+        let light = lights[cluster_lights[cluster * MAX_LIGHTS_PER_CLUSTER + i]];
+        color += punctual_light(light, surface, world_position);
+    }
+
+    // Occlusion only applies to indirect light
+    color += image_based_light(surface) * occlusion;
+    color += emissive;
+
+    if scene.debug_view == DEBUG_VIEW_CASCADES {
+        color *= cascade_tint(world_position);
+    } else if scene.debug_view == DEBUG_VIEW_LIGHT_COUNT {
+        color = heatmap(count);
+    } else if scene.debug_view == DEBUG_VIEW_AMBIENT_OCCLUSION {
+        color = vec3<f32>(screen_occlusion(frag_coord));
+    }
+    return color;
+}
+
+fn shade_forward(
+    in: VertexOutput,
+    front_facing: bool,
+    face_normal: vec3<f32>,
+    m: MaterialSample,
+) -> vec3<f32> {
+    let n = shading_normal(in, front_facing, face_normal, m.normal);
+    let surface = make_surface(in.world_position, n, m.base_color.rgb, m.metallic, m.roughness);
+    return shade(in.position, in.world_position, surface, m.occlusion, m.emissive);
+}
+
+// Facing the camera, with derivatives taken before anything can diverge
+fn face_normal(in: VertexOutput) -> vec3<f32> {
+    let n = normalize(cross(dpdx(in.world_position), dpdy(in.world_position)));
+    let to_camera = scene.camera_position.xyz - in.world_position;
+    return select(-n, n, dot(n, to_camera) >= 0.0);
+}
+
+struct ForwardOutput {
+    @location(0) color: vec4<f32>,
+    @location(1) motion: vec4<f32>,
+}
+
+@fragment
+fn fs_opaque(
+    in: VertexOutput,
+    @builtin(front_facing) front_facing: bool,
+) -> ForwardOutput {
+    let face = face_normal(in);
+    var m = sample_material(in);
+    m.occlusion *= screen_occlusion(in.position);
+    return ForwardOutput(
+        vec4<f32>(shade_forward(in, front_facing, face, m), 1.0),
+        motion(in, 1.0),
+    );
+}
+
+@fragment
+fn fs_mask(
+    in: VertexOutput,
+    @builtin(front_facing) front_facing: bool,
+) -> ForwardOutput {
+    let face = face_normal(in);
+    var m = sample_material(in);
+    if m.base_color.a < material.alpha_cutoff {
+        discard;
+    }
+    m.occlusion *= screen_occlusion(in.position);
+    return ForwardOutput(
+        vec4<f32>(shade_forward(in, front_facing, face, m), 1.0),
+        motion(in, 1.0),
+    );
+}
+
+@fragment
+fn fs_blend(
+    in: VertexOutput,
+    @builtin(front_facing) front_facing: bool,
+) -> ForwardOutput {
+    let face = face_normal(in);
+    let m = sample_material(in);
+    let alpha = m.base_color.a;
+    return ForwardOutput(
+        vec4<f32>(shade_forward(in, front_facing, face, m), alpha),
+        motion(in, alpha),
+    );
+}
+
+// Base colour and occlusion, with the base colour stored as sRGB
+struct GBufferOutput {
+    @location(0) albedo: vec4<f32>,
+    // Packed into 0 to 1
+    @location(1) normal: vec4<f32>,
+    // Metallic and roughness
+    @location(2) material: vec4<f32>,
+    @location(3) emissive: vec4<f32>,
+    // Not read back by lighting, TAA reads it
+    @location(4) motion: vec4<f32>,
+}
+
+fn gbuffer(
+    in: VertexOutput,
+    front_facing: bool,
+    face_normal: vec3<f32>,
+    m: MaterialSample,
+) -> GBufferOutput {
+    let n = shading_normal(in, front_facing, face_normal, m.normal);
+    return GBufferOutput(
+        vec4<f32>(m.base_color.rgb, m.occlusion),
+        vec4<f32>(n * 0.5 + 0.5, 0.0),
+        vec4<f32>(m.metallic, m.roughness, 0.0, 0.0),
+        vec4<f32>(m.emissive, 0.0),
+        motion(in, 1.0),
+    );
+}
+
+@fragment
+fn fs_gbuffer_opaque(
+    in: VertexOutput,
+    @builtin(front_facing) front_facing: bool,
+) -> GBufferOutput {
+    let face = face_normal(in);
+    let m = sample_material(in);
+    return gbuffer(in, front_facing, face, m);
+}
+
+@fragment
+fn fs_gbuffer_mask(
+    in: VertexOutput,
+    @builtin(front_facing) front_facing: bool,
+) -> GBufferOutput {
+    let face = face_normal(in);
+    let m = sample_material(in);
+    if m.base_color.a < material.alpha_cutoff {
+        discard;
+    }
+    return gbuffer(in, front_facing, face, m);
+}
+
+// World space normals, packed like the G-buffer's, for screen-space occlusion to read
+@fragment
+fn fs_prepass_opaque(
+    in: VertexOutput,
+    @builtin(front_facing) front_facing: bool,
+) -> @location(0) vec4<f32> {
+    let face = face_normal(in);
+    let m = sample_material(in);
+    return vec4<f32>(shading_normal(in, front_facing, face, m.normal) * 0.5 + 0.5, 0.0);
+}
+
+@fragment
+fn fs_prepass_mask(
+    in: VertexOutput,
+    @builtin(front_facing) front_facing: bool,
+) -> @location(0) vec4<f32> {
+    let face = face_normal(in);
+    let m = sample_material(in);
+    if m.base_color.a < material.alpha_cutoff {
+        discard;
+    }
+    return vec4<f32>(shading_normal(in, front_facing, face, m.normal) * 0.5 + 0.5, 0.0);
+}
+
+@vertex
+fn vs_fullscreen(@builtin(vertex_index) index: u32) -> @builtin(position) vec4<f32> {
+    let uv = vec2<f32>(f32((index << 1u) & 2u), f32(index & 2u));
+    return vec4<f32>(uv * 2.0 - 1.0, 0.0, 1.0);
+}
+
+// Lights whatever the G-buffer pass left at this pixel, leaving the background alone
+@fragment
+fn fs_deferred(@builtin(position) frag_coord: vec4<f32>) -> @location(0) vec4<f32> {
+    let coord = vec2<i32>(frag_coord.xy);
+    let depth = textureLoad(gbuffer_depth, coord, 0);
+    if depth == scene.far_depth {
+        discard;
+    }
+
+    let albedo = textureLoad(gbuffer_albedo, coord, 0);
+    let n = normalize(textureLoad(gbuffer_normal, coord, 0).xyz * 2.0 - 1.0);
+    let params = textureLoad(gbuffer_material, coord, 0);
+    let emissive = textureLoad(gbuffer_emissive, coord, 0).rgb;
+
+    let ndc = frag_coord.xy / scene.screen_size * 2.0 - 1.0;
+    let world = scene.inv_view_proj * vec4<f32>(ndc, depth, 1.0);
+    let world_position = world.xyz / world.w;
+
+    let surface = make_surface(world_position, n, albedo.rgb, params.r, params.g);
+    let occlusion = albedo.a * screen_occlusion(frag_coord);
+    return vec4<f32>(shade(frag_coord, world_position, surface, occlusion, emissive), 1.0);
+}
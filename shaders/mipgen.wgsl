@@ -0,0 +1,24 @@
+// Box filters one mip level into the next, for formats that can't be blitted with linear filtering
+
+@group(0) @binding(0) var src: texture_2d<f32>;
+@group(0) @binding(1) var dst: texture_storage_2d<rgba32float, write>;
+
+@compute @workgroup_size(8, 8)
+fn cs_main(@builtin(global_invocation_id) id: vec3<u32>) {
+    let dst_size = textureDimensions(dst);
+    if any(id.xy >= dst_size) {
+        return;
+    }
+
+    // Odd sized levels clamp instead of reading past the edge
+    let src_max = textureDimensions(src) - vec2<u32>(1u);
+    let base = id.xy * 2u;
+    var sum = vec4<f32>(0.0);
+    for (var y = 0u; y < 2u; y++) {
+        for (var x = 0u; x < 2u; x++) {
+            sum += textureLoad(src, min(base + vec2<u32>(x, y), src_max), 0);
+        }
+    }
+
+    textureStore(dst, id.xy, sum * 0.25);
+}
@@ -0,0 +1,340 @@
+// Fullscreen pass that copies the HDR draw image into the swapchain, blending in bloom and
+// exposing and tonemapping it for the display's range and applying whatever encoding the
+// swapchain format/colour space needs. With FXAA on, tonemapping and encoding are split around it.
+
+const TRANSFER_HARDWARE: u32 = 0u;
+const TRANSFER_SRGB: u32 = 1u;
+const TRANSFER_PQ: u32 = 2u;
+const TRANSFER_SCRGB: u32 = 3u;
+
+// Has to match renderer.rs's Tonemapper
+const TONEMAPPER_REINHARD: u32 = 0u;
+const TONEMAPPER_ACES: u32 = 1u;
+const TONEMAPPER_AGX: u32 = 2u;
+const TONEMAPPER_UNCHARTED2: u32 = 3u;
+
+// Scene value that ends up at the display's peak brightness with Reinhard
+const WHITE_POINT: f32 = 4.0;
+
+struct PushConstants {
+    transfer: u32,
+    // Brightness of scene value 1.0 in nits, only used for HDR outputs
+    paper_white_nits: f32,
+    max_luminance_nits: f32,
+    tonemapper: u32,
+    // Scales the scene by the adapted exposure instead of leaving it as is
+    auto_exposure: u32,
+    // How much of the bloom is blended over the scene, 0 when it's off
+    bloom_intensity: f32,
+}
+
+// Has to match exposure.wgsl
+struct ExposureState {
+    luminance: f32,
+    exposure: f32,
+}
+
+var<push_constant> pc: PushConstants;
+
+@group(0) @binding(0) var scene: texture_2d<f32>;
+@group(0) @binding(1) var scene_sampler: sampler;
+@group(0) @binding(2) var<storage, read> exposure: ExposureState;
+@group(0) @binding(3) var bloom: texture_2d<f32>;
+
+struct VertexOutput {
+    @builtin(position) position: vec4<f32>,
+    @location(0) uv: vec2<f32>,
+}
+
+@vertex
+fn vs_main(@builtin(vertex_index) index: u32) -> VertexOutput {
+    // One triangle covering the whole screen
+    let uv = vec2<f32>(f32((index << 1u) & 2u), f32(index & 2u));
+    return VertexOutput(vec4<f32>(uv * 2.0 - 1.0, 0.0, 1.0), uv);
+}
+
+// Extended Reinhard on the max channel so hue is preserved, `peak` maps to the brightest
+// value the output can show
+fn reinhard(color: vec3<f32>, peak: f32) -> vec3<f32> {
+    let m = max(max(color.r, color.g), color.b);
+    if m <= 0.0 {
+        return vec3<f32>(0.0);
+    }
+    let x = m / peak;
+    let mapped = x * (1.0 + x / (WHITE_POINT * WHITE_POINT)) / (1.0 + x);
+    return color * (min(mapped, 1.0) * peak / m);
+}
+
+// Stephen Hill's fit of the ACES reference rendering and SDR output transforms
+fn aces(color: vec3<f32>) -> vec3<f32> {
+    let input = mat3x3<f32>(
+        vec3<f32>(0.59719, 0.07600, 0.02840),
+        vec3<f32>(0.35458, 0.90834, 0.13383),
+        vec3<f32>(0.04823, 0.01566, 0.83777),
+    );
+    let output = mat3x3<f32>(
+        vec3<f32>(1.60475, -0.10208, -0.00327),
+        vec3<f32>(-0.53108, 1.10813, -0.07276),
+        vec3<f32>(-0.07367, -0.00605, 1.07602),
+    );
+    let v = input * color;
+    let a = v * (v + 0.0245786) - 0.000090537;
+    let b = v * (0.983729 * v + 0.4329510) + 0.238081;
+    return clamp(output * (a / b), vec3<f32>(0.0), vec3<f32>(1.0));
+}
+
+// Troy Sobotka's AgX with its default look, using Benjamin Wrensch's polynomial fit of the
+// contrast curve
+fn agx(color: vec3<f32>) -> vec3<f32> {
+    let inset = mat3x3<f32>(
+        vec3<f32>(0.842479062253094, 0.0423282422610123, 0.0423756549057051),
+        vec3<f32>(0.0784335999999992, 0.878468636469772, 0.0784336),
+        vec3<f32>(0.0792237451477643, 0.0791661274605434, 0.879142973793104),
+    );
+    let outset = mat3x3<f32>(
+        vec3<f32>(1.19687900512017, -0.0528968517574562, -0.0529716355144438),
+        vec3<f32>(-0.0980208811401368, 1.15190312990417, -0.0980434501171241),
+        vec3<f32>(-0.0990297440797205, -0.0989611768448433, 1.15107367264116),
+    );
+    let min_ev = -12.47393;
+    let max_ev = 4.026069;
+
+    let log_color = clamp(log2(max(inset * color, vec3<f32>(1e-10))), vec3<f32>(min_ev), vec3<f32>(max_ev));
+    let x = (log_color - min_ev) / (max_ev - min_ev);
+    let x2 = x * x;
+    let x4 = x2 * x2;
+    let curve = 15.5 * x4 * x2 - 40.14 * x4 * x + 31.96 * x4 - 6.868 * x2 * x + 0.4298 * x2
+        + 0.1191 * x - 0.00232;
+    // The curve's output is display encoded
+    return pow(max(outset * curve, vec3<f32>(0.0)), vec3<f32>(2.2));
+}
+
+fn hable(x: vec3<f32>) -> vec3<f32> {
+    let a = 0.15;
+    let b = 0.50;
+    let c = 0.10;
+    let d = 0.20;
+    let e = 0.02;
+    let f = 0.30;
+    return (x * (a * x + c * b) + d * e) / (x * (a * x + b) + d * f) - e / f;
+}
+
+// John Hable's filmic curve from Uncharted 2, with its usual exposure bias
+fn uncharted2(color: vec3<f32>) -> vec3<f32> {
+    let white = 11.2;
+    return hable(color * 2.0) / hable(vec3<f32>(white));
+}
+
+// The SDR curves are stretched to `peak` for HDR outputs
+fn tonemap(color: vec3<f32>, peak: f32) -> vec3<f32> {
+    switch pc.tonemapper {
+        case TONEMAPPER_ACES: {
+            return aces(color / peak) * peak;
+        }
+        case TONEMAPPER_AGX: {
+            return agx(color / peak) * peak;
+        }
+        case TONEMAPPER_UNCHARTED2: {
+            return uncharted2(color / peak) * peak;
+        }
+        case TONEMAPPER_REINHARD, default: {
+            return reinhard(color, peak);
+        }
+    }
+}
+
+fn with_bloom(uv: vec2<f32>) -> vec3<f32> {
+    let color = textureSampleLevel(scene, scene_sampler, uv, 0.0).rgb;
+    if pc.bloom_intensity <= 0.0 {
+        return color;
+    }
+    return mix(color, textureSampleLevel(bloom, scene_sampler, uv, 0.0).rgb, pc.bloom_intensity);
+}
+
+fn expose(color: vec3<f32>) -> vec3<f32> {
+    if pc.auto_exposure == 0u {
+        return color;
+    }
+    return color * exposure.exposure;
+}
+
+fn srgb_oetf(linear: vec3<f32>) -> vec3<f32> {
+    let c = clamp(linear, vec3<f32>(0.0), vec3<f32>(1.0));
+    let low = c * 12.92;
+    let high = 1.055 * pow(c, vec3<f32>(1.0 / 2.4)) - 0.055;
+    return select(high, low, c <= vec3<f32>(0.0031308));
+}
+
+fn rec709_to_rec2020(color: vec3<f32>) -> vec3<f32> {
+    let m = mat3x3<f32>(
+        vec3<f32>(0.6274, 0.0691, 0.0164),
+        vec3<f32>(0.3293, 0.9195, 0.0880),
+        vec3<f32>(0.0433, 0.0114, 0.8956),
+    );
+    return m * color;
+}
+
+// SMPTE ST 2084, `nits` is absolute luminance
+fn pq_oetf(nits: vec3<f32>) -> vec3<f32> {
+    let m1 = 0.1593017578125;
+    let m2 = 78.84375;
+    let c1 = 0.8359375;
+    let c2 = 18.8515625;
+    let c3 = 18.6875;
+    let y = pow(clamp(nits / 10000.0, vec3<f32>(0.0), vec3<f32>(1.0)), vec3<f32>(m1));
+    return pow((c1 + c2 * y) / (1.0 + c3 * y), vec3<f32>(m2));
+}
+
+// Brightest value the tonemapper maps to, above 1.0 for HDR outputs
+fn output_peak() -> f32 {
+    if pc.transfer == TRANSFER_PQ || pc.transfer == TRANSFER_SCRGB {
+        return max(pc.max_luminance_nits / pc.paper_white_nits, 1.0);
+    }
+    return 1.0;
+}
+
+// Applies the swapchain's encoding to an already tonemapped colour
+fn encode(mapped: vec3<f32>) -> vec3<f32> {
+    switch pc.transfer {
+        case TRANSFER_SRGB: {
+            return srgb_oetf(mapped);
+        }
+        case TRANSFER_PQ: {
+            return pq_oetf(rec709_to_rec2020(mapped) * pc.paper_white_nits);
+        }
+        case TRANSFER_SCRGB: {
+            return mapped * (pc.paper_white_nits / 80.0);
+        }
+        case TRANSFER_HARDWARE, default: {
+            // The swapchain is an _SRGB format, the hardware encodes on write
+            return mapped;
+        }
+    }
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    return vec4<f32>(encode(tonemap(expose(with_bloom(in.uv)), output_peak())), 1.0);
+}
+
+// Tonemapped but not encoded yet, for FXAA to run on. Alpha holds perceptual luma, relative to
+// the output's peak so the same thresholds work for HDR outputs
+@fragment
+fn fs_tonemap(in: VertexOutput) -> @location(0) vec4<f32> {
+    let peak = output_peak();
+    let mapped = tonemap(expose(with_bloom(in.uv)), peak);
+    let luma = sqrt(dot(mapped / peak, vec3<f32>(0.299, 0.587, 0.114)));
+    return vec4<f32>(mapped, luma);
+}
+
+// FXAA 3.11's quality path: finds the direction of the edge through a pixel, walks along it
+// to both ends, and resamples the pixel towards the neighbour on the other side of the edge
+// depending on how far along it the pixel sits
+
+// Contrast needed to count as an edge, relative to the brightest neighbour and absolute
+const FXAA_EDGE_THRESHOLD: f32 = 0.166;
+const FXAA_EDGE_THRESHOLD_MIN: f32 = 0.0833;
+// How much single pixel features get smoothed
+const FXAA_SUBPIX: f32 = 0.75;
+const FXAA_SEARCH_STEPS: u32 = 10u;
+
+fn fxaa_luma(uv: vec2<f32>) -> f32 {
+    return textureSampleLevel(scene, scene_sampler, uv, 0.0).a;
+}
+
+@fragment
+fn fs_fxaa(in: VertexOutput) -> @location(0) vec4<f32> {
+    let texel = 1.0 / vec2<f32>(textureDimensions(scene));
+    let center = textureSampleLevel(scene, scene_sampler, in.uv, 0.0);
+    let luma_m = center.a;
+    let luma_n = fxaa_luma(in.uv + vec2<f32>(0.0, -texel.y));
+    let luma_s = fxaa_luma(in.uv + vec2<f32>(0.0, texel.y));
+    let luma_w = fxaa_luma(in.uv + vec2<f32>(-texel.x, 0.0));
+    let luma_e = fxaa_luma(in.uv + vec2<f32>(texel.x, 0.0));
+
+    let luma_min = min(luma_m, min(min(luma_n, luma_s), min(luma_w, luma_e)));
+    let luma_max = max(luma_m, max(max(luma_n, luma_s), max(luma_w, luma_e)));
+    let range = luma_max - luma_min;
+    if range < max(FXAA_EDGE_THRESHOLD_MIN, luma_max * FXAA_EDGE_THRESHOLD) {
+        return vec4<f32>(encode(center.rgb), 1.0);
+    }
+
+    let luma_nw = fxaa_luma(in.uv - texel);
+    let luma_se = fxaa_luma(in.uv + texel);
+    let luma_ne = fxaa_luma(in.uv + vec2<f32>(texel.x, -texel.y));
+    let luma_sw = fxaa_luma(in.uv + vec2<f32>(-texel.x, texel.y));
+
+    // A horizontal edge changes the most going up or down
+    let edge_horizontal = abs(luma_nw + luma_sw - 2.0 * luma_w)
+        + 2.0 * abs(luma_n + luma_s - 2.0 * luma_m)
+        + abs(luma_ne + luma_se - 2.0 * luma_e);
+    let edge_vertical = abs(luma_nw + luma_ne - 2.0 * luma_n)
+        + 2.0 * abs(luma_w + luma_e - 2.0 * luma_m)
+        + abs(luma_sw + luma_se - 2.0 * luma_s);
+    let horizontal = edge_horizontal >= edge_vertical;
+
+    // Which side of the pixel the edge is on
+    let luma_before = select(luma_w, luma_n, horizontal);
+    let luma_after = select(luma_e, luma_s, horizontal);
+    let gradient_before = luma_before - luma_m;
+    let gradient_after = luma_after - luma_m;
+    let before_steeper = abs(gradient_before) >= abs(gradient_after);
+    let gradient_scaled = 0.25 * max(abs(gradient_before), abs(gradient_after));
+    var step_length = select(texel.x, texel.y, horizontal);
+    var luma_local_average = 0.5 * (luma_after + luma_m);
+    if before_steeper {
+        step_length = -step_length;
+        luma_local_average = 0.5 * (luma_before + luma_m);
+    }
+
+    // Walk along the edge, half a pixel across so bilinear filtering averages both sides
+    let across = select(vec2<f32>(1.0, 0.0), vec2<f32>(0.0, 1.0), horizontal);
+    let along = select(vec2<f32>(0.0, texel.y), vec2<f32>(texel.x, 0.0), horizontal);
+    let start = in.uv + across * step_length * 0.5;
+    var steps = array<f32, FXAA_SEARCH_STEPS>(1.0, 1.0, 1.0, 1.0, 1.5, 2.0, 2.0, 2.0, 4.0, 8.0);
+    var uv_before = start;
+    var uv_after = start;
+    var end_before = 0.0;
+    var end_after = 0.0;
+    var reached_before = false;
+    var reached_after = false;
+    for (var i = 0u; i < FXAA_SEARCH_STEPS; i++) {
+        if !reached_before {
+            uv_before -= along * steps[i];
+            end_before = fxaa_luma(uv_before) - luma_local_average;
+            reached_before = abs(end_before) >= gradient_scaled;
+        }
+        if !reached_after {
+            uv_after += along * steps[i];
+            end_after = fxaa_luma(uv_after) - luma_local_average;
+            reached_after = abs(end_after) >= gradient_scaled;
+        }
+        if reached_before && reached_after {
+            break;
+        }
+    }
+
+    let distance_before = select(in.uv.y - uv_before.y, in.uv.x - uv_before.x, horizontal);
+    let distance_after = select(uv_after.y - in.uv.y, uv_after.x - in.uv.x, horizontal);
+    let before_closer = distance_before < distance_after;
+    let closest = min(distance_before, distance_after);
+    let edge_length = distance_before + distance_after;
+    // Only blend if the closer end varies the opposite way to the centre, otherwise the
+    // pixel is past the end of the edge
+    let end_luma = select(end_after, end_before, before_closer);
+    let center_darker = luma_m < luma_local_average;
+    var pixel_offset = 0.0;
+    if (end_luma < 0.0) != center_darker {
+        pixel_offset = 0.5 - closest / edge_length;
+    }
+
+    // Isolated pixels are blended by how far they are from their neighbourhood's average
+    let average = (2.0 * (luma_n + luma_s + luma_w + luma_e) + luma_nw + luma_ne + luma_sw
+        + luma_se) / 12.0;
+    let subpix = smoothstep(0.0, 1.0, clamp(abs(average - luma_m) / range, 0.0, 1.0));
+    pixel_offset = max(pixel_offset, subpix * subpix * FXAA_SUBPIX);
+
+    let uv = in.uv + across * pixel_offset * step_length;
+    let color = textureSampleLevel(scene, scene_sampler, uv, 0.0).rgb;
+    return vec4<f32>(encode(color), 1.0);
+}
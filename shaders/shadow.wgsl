@@ -0,0 +1,13 @@
+// Depth-only pass rendering shadow casters into one cascade of the sun's shadow map
+
+struct PushConstants {
+    light_view_proj: mat4x4<f32>,
+    model: mat4x4<f32>,
+}
+
+var<push_constant> pc: PushConstants;
+
+@vertex
+fn vs_main(@location(0) position: vec3<f32>) -> @builtin(position) vec4<f32> {
+    return pc.light_view_proj * pc.model * vec4<f32>(position, 1.0);
+}
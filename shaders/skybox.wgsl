@@ -0,0 +1,68 @@
+// Fills the background with the environment cubemap. The triangle sits on the far plane and
+// is depth tested for equality, so it's drawn after opaque geometry and only shades pixels
+// nothing else covered
+
+// Has to match mesh.wgsl's SceneData
+struct SceneData {
+    view: mat4x4<f32>,
+    proj: mat4x4<f32>,
+    view_proj: mat4x4<f32>,
+    inv_view_proj: mat4x4<f32>,
+    camera_position: vec4<f32>,
+    sun_direction: vec4<f32>,
+    sun_color: vec4<f32>,
+    light_count: u32,
+    environment_intensity: f32,
+    debug_view: u32,
+    far_depth: f32,
+    cascade_view_proj: array<mat4x4<f32>, 4>,
+    cascade_splits: vec4<f32>,
+    cascade_texel_sizes: vec4<f32>,
+    inv_proj: mat4x4<f32>,
+    screen_size: vec2<f32>,
+    cluster_near: f32,
+    cluster_far: f32,
+    ambient_occlusion: u32,
+    environment_rotation: mat4x4<f32>,
+}
+
+@group(0) @binding(0) var<uniform> scene: SceneData;
+@group(0) @binding(4) var environment_sampler: sampler;
+@group(0) @binding(5) var skybox: texture_cube<f32>;
+
+struct VertexOutput {
+    @builtin(position) position: vec4<f32>,
+    @location(0) ndc: vec2<f32>,
+}
+
+@vertex
+fn vs_main(@builtin(vertex_index) index: u32) -> VertexOutput {
+    // One triangle covering the whole screen
+    let uv = vec2<f32>(f32((index << 1u) & 2u), f32(index & 2u));
+    let ndc = uv * 2.0 - 1.0;
+    return VertexOutput(vec4<f32>(ndc, scene.far_depth, 1.0), ndc);
+}
+
+fn unproject(ndc: vec2<f32>, depth: f32) -> vec3<f32> {
+    let p = scene.inv_view_proj * vec4<f32>(ndc, depth, 1.0);
+    return p.xyz / p.w;
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    // Which of the depth planes is near depends on reverse-Z, so the ray between them is
+    // pointed away from the camera
+    let forward = -vec3<f32>(scene.view[0].z, scene.view[1].z, scene.view[2].z);
+    var dir = normalize(unproject(in.ndc, 1.0) - unproject(in.ndc, 0.0));
+    if dot(dir, forward) < 0.0 {
+        dir = -dir;
+    }
+
+    let color = textureSampleLevel(
+        skybox,
+        environment_sampler,
+        (scene.environment_rotation * vec4<f32>(dir, 0.0)).xyz,
+        0.0,
+    ).rgb;
+    return vec4<f32>(color * scene.environment_intensity, 1.0);
+}
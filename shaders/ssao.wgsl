@@ -0,0 +1,144 @@
+// Screen-space ambient occlusion: a hemisphere of samples around each pixel's surface is tested
+// against the depth buffer, then blurred in two passes that stop at depth discontinuities
+
+const SAMPLE_COUNT: u32 = 16u;
+const BLUR_RADIUS: i32 = 4;
+// How far apart in depth, relative to the centre's, neighbours still get blurred together
+const BLUR_DEPTH_TOLERANCE: f32 = 0.05;
+const GOLDEN_ANGLE: f32 = 2.39996323;
+
+// The leading fields of mesh.wgsl's SceneData, nothing past them is read here
+struct SceneData {
+    view: mat4x4<f32>,
+    proj: mat4x4<f32>,
+    view_proj: mat4x4<f32>,
+    inv_view_proj: mat4x4<f32>,
+    camera_position: vec4<f32>,
+    sun_direction: vec4<f32>,
+    sun_color: vec4<f32>,
+    light_count: u32,
+    environment_intensity: f32,
+    debug_view: u32,
+    far_depth: f32,
+    cascade_view_proj: array<mat4x4<f32>, 4>,
+    cascade_splits: vec4<f32>,
+    cascade_texel_sizes: vec4<f32>,
+    inv_proj: mat4x4<f32>,
+}
+
+struct PushConstants {
+    // View space radius of the sampled hemisphere
+    radius: f32,
+    // Exponent sharpening the result
+    power: f32,
+    // Depth buffer pixels per occlusion pixel along each axis
+    scale: u32,
+    // 0 blurs horizontally, 1 vertically
+    direction: u32,
+}
+
+var<push_constant> pc: PushConstants;
+
+@group(0) @binding(0) var<uniform> scene: SceneData;
+
+@group(1) @binding(0) var depth: texture_depth_2d;
+// World space, packed into 0 to 1
+@group(1) @binding(1) var normals: texture_2d<f32>;
+// Occlusion and view depth, where a depth of 0 marks the background
+@group(1) @binding(2) var src: texture_2d<f32>;
+// Has to match ssao.rs's format
+@group(1) @binding(3) var dst: texture_storage_2d<rg16float, write>;
+
+fn view_position(uv: vec2<f32>, d: f32) -> vec3<f32> {
+    let p = scene.inv_proj * vec4<f32>(uv * 2.0 - 1.0, d, 1.0);
+    return p.xyz / p.w;
+}
+
+// Jimenez's interleaved gradient noise, so neighbouring pixels rotate their samples differently
+fn noise(pixel: vec2<u32>) -> f32 {
+    return fract(52.9829189 * fract(dot(vec2<f32>(pixel), vec2<f32>(0.06711056, 0.00583715))));
+}
+
+@compute @workgroup_size(8, 8, 1)
+fn cs_ssao(@builtin(global_invocation_id) id: vec3<u32>) {
+    let size = textureDimensions(dst);
+    if any(id.xy >= size) {
+        return;
+    }
+    let full = textureDimensions(depth);
+    let pixel = min(id.xy * pc.scale + pc.scale / 2u, full - 1u);
+    let d = textureLoad(depth, pixel, 0);
+    if d == scene.far_depth {
+        textureStore(dst, id.xy, vec4<f32>(1.0, 0.0, 0.0, 0.0));
+        return;
+    }
+
+    let position = view_position((vec2<f32>(pixel) + 0.5) / vec2<f32>(full), d);
+    let world_normal = textureLoad(normals, pixel, 0).xyz * 2.0 - 1.0;
+    let n = normalize((scene.view * vec4<f32>(world_normal, 0.0)).xyz);
+    let angle = noise(id.xy) * 6.28318531;
+    let random = vec3<f32>(cos(angle), sin(angle), 0.0);
+    // Falls back to another axis when the normal faces the camera head on
+    var t = random - n * dot(random, n);
+    if dot(t, t) < 1e-4 {
+        t = vec3<f32>(0.0, 1.0, 0.0) - n * n.y;
+    }
+    t = normalize(t);
+    let b = cross(n, t);
+
+    let bias = pc.radius * 0.025;
+    var occlusion = 0.0;
+    for (var i = 0u; i < SAMPLE_COUNT; i++) {
+        // Spiralling out over the hemisphere, bunched up towards the centre
+        let fi = f32(i);
+        let z = 1.0 - (fi + 0.5) / f32(SAMPLE_COUNT);
+        let r = sqrt(1.0 - z * z);
+        let phi = fi * GOLDEN_ANGLE;
+        let progress = (fi + 1.0) / f32(SAMPLE_COUNT);
+        let offset = t * (cos(phi) * r) + b * (sin(phi) * r) + n * z;
+        let probe = position + offset * pc.radius * mix(0.1, 1.0, progress * progress);
+
+        let clip = scene.proj * vec4<f32>(probe, 1.0);
+        let uv = clip.xy / clip.w * 0.5 + 0.5;
+        if any(uv < vec2<f32>(0.0)) || any(uv >= vec2<f32>(1.0)) {
+            continue;
+        }
+        let occluder_pixel = vec2<u32>(uv * vec2<f32>(full));
+        let occluder = view_position(uv, textureLoad(depth, occluder_pixel, 0));
+        // Surfaces far in front of the sample are unrelated geometry, they fade out
+        let range = smoothstep(0.0, 1.0, pc.radius / abs(position.z - occluder.z));
+        occlusion += select(0.0, range, occluder.z >= probe.z + bias);
+    }
+
+    let ao = pow(1.0 - occlusion / f32(SAMPLE_COUNT), pc.power);
+    textureStore(dst, id.xy, vec4<f32>(ao, -position.z, 0.0, 0.0));
+}
+
+// One direction of a Gaussian, skipping neighbours on other surfaces
+@compute @workgroup_size(8, 8, 1)
+fn cs_blur(@builtin(global_invocation_id) id: vec3<u32>) {
+    let size = textureDimensions(dst);
+    if any(id.xy >= size) {
+        return;
+    }
+    let centre = textureLoad(src, id.xy, 0).rg;
+    if centre.y == 0.0 {
+        textureStore(dst, id.xy, vec4<f32>(centre, 0.0, 0.0));
+        return;
+    }
+
+    let stride = select(vec2<i32>(1, 0), vec2<i32>(0, 1), pc.direction == 1u);
+    let sigma = f32(BLUR_RADIUS) * 0.5;
+    var sum = 0.0;
+    var total = 0.0;
+    for (var i = -BLUR_RADIUS; i <= BLUR_RADIUS; i++) {
+        let pixel = clamp(vec2<i32>(id.xy) + stride * i, vec2<i32>(0), vec2<i32>(size) - 1);
+        let neighbour = textureLoad(src, pixel, 0).rg;
+        let difference = abs(neighbour.y - centre.y) / (centre.y * BLUR_DEPTH_TOLERANCE);
+        let weight = exp(-f32(i * i) / (2.0 * sigma * sigma)) * max(1.0 - difference, 0.0);
+        sum += neighbour.x * weight;
+        total += weight;
+    }
+
+    textureStore(dst, id.xy, vec4<f32>(sum / total, centre.y, 0.0, 0.0));
+}
@@ -0,0 +1,69 @@
+// Temporal anti-aliasing: the projection is jittered by a different sub-pixel offset every
+// frame, and each frame is blended into a history reprojected from the last one. Geometry
+// writes its own motion, static background is reprojected from depth
+
+struct PushConstants {
+    // From this frame's clip space to last frame's, both without jitter
+    reprojection: mat4x4<f32>,
+    // Ignores the history, after a resize or camera cut
+    reset: u32,
+    // How much of the history is kept each frame
+    feedback: f32,
+}
+
+var<push_constant> pc: PushConstants;
+
+@group(0) @binding(0) var current: texture_2d<f32>;
+@group(0) @binding(1) var depth: texture_depth_2d;
+@group(0) @binding(2) var history: texture_2d<f32>;
+@group(0) @binding(3) var output: texture_storage_2d<rgba16float, write>;
+@group(0) @binding(4) var history_sampler: sampler;
+// Motion in UV weighted by how much of the pixel geometry covered, with the coverage in z
+@group(0) @binding(5) var motion: texture_2d<f32>;
+
+fn pixel_uv(coord: vec2<u32>, size: vec2<u32>) -> vec2<f32> {
+    return (vec2<f32>(coord) + 0.5) / vec2<f32>(size);
+}
+
+// How far the pixel moved in UV since the last frame. Whatever geometry didn't cover only
+// moved with the camera, which depth is enough to undo
+fn pixel_motion(coord: vec2<u32>, size: vec2<u32>) -> vec2<f32> {
+    let uv = pixel_uv(coord, size);
+    let d = textureLoad(depth, coord, 0);
+    let previous = pc.reprojection * vec4<f32>(uv * 2.0 - 1.0, d, 1.0);
+    let camera_motion = uv - (previous.xy / previous.w * 0.5 + 0.5);
+
+    let object_motion = textureLoad(motion, coord, 0);
+    return object_motion.xy + (1.0 - object_motion.z) * camera_motion;
+}
+
+@compute @workgroup_size(8, 8, 1)
+fn cs_resolve(@builtin(global_invocation_id) id: vec3<u32>) {
+    let size = textureDimensions(current);
+    if any(id.xy >= size) {
+        return;
+    }
+
+    let color = textureLoad(current, id.xy, 0).rgb;
+    // The history is clamped to what the pixel's neighbours could blend into, which rejects
+    // anything disoccluded or stale
+    var low = color;
+    var high = color;
+    for (var y = -1; y <= 1; y++) {
+        for (var x = -1; x <= 1; x++) {
+            let coord = clamp(vec2<i32>(id.xy) + vec2<i32>(x, y), vec2<i32>(0), vec2<i32>(size) - 1);
+            let neighbour = textureLoad(current, coord, 0).rgb;
+            low = min(low, neighbour);
+            high = max(high, neighbour);
+        }
+    }
+
+    let previous_uv = pixel_uv(id.xy, size) - pixel_motion(id.xy, size);
+    let offscreen = any(previous_uv < vec2<f32>(0.0)) || any(previous_uv > vec2<f32>(1.0));
+    var result = color;
+    if pc.reset == 0u && !offscreen {
+        let previous = textureSampleLevel(history, history_sampler, previous_uv, 0.0).rgb;
+        result = mix(color, clamp(previous, low, high), pc.feedback);
+    }
+    textureStore(output, id.xy, vec4<f32>(result, 1.0));
+}
@@ -1,30 +1,440 @@
-use crate::gfx;
+use std::{path::PathBuf, time::Instant};
+
+use crate::{
+    assets::{self, Light, LightKind},
+    camera::{self, Camera, CameraController, FlyController, OrbitController, Projection},
+    gfx,
+    input::{Action, Axis, Bindings, Input},
+    scene::{NodeId, Scene, Transform},
+};
+use glam::{Quat, Vec3};
 use winit::{
     application::ApplicationHandler,
-    event::{ElementState, KeyEvent, WindowEvent},
-    keyboard::{KeyCode, PhysicalKey},
-    window::{Window, WindowAttributes},
+    event::{DeviceEvent, WindowEvent},
+    window::{CursorGrabMode, Window, WindowAttributes},
 };
 
+// Frame times above this are treated as a hitch rather than movement to catch up on
+const MAX_FRAME_TIME: f32 = 0.1;
+// Radians per second
+const SUN_TURN_SPEED: f32 = 1.0;
+// Radians per second the sun climbs while the day cycles, a day takes about a minute
+const DAY_CYCLE_SPEED: f32 = 0.1;
+// Bloom intensity and radius scale by this much per second, threshold moves by it
+const BLOOM_ADJUST_SPEED: f32 = 1.0;
+// Radians per second the environment turns, its intensity scales by this much per second
+const SKYBOX_ADJUST_SPEED: f32 = 1.0;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ControllerKind {
+    Fly,
+    Orbit,
+}
+
 pub struct App {
     window: Option<Window>,
     window_attribs: WindowAttributes,
     renderer: Option<gfx::Renderer>,
+    renderer_config: gfx::RendererConfig,
+    model_path: Option<PathBuf>,
+    environment_path: Option<PathBuf>,
+    load_options: assets::LoadOptions,
+    input: Input,
+    camera: Camera,
+    fly: FlyController,
+    orbit: OrbitController,
+    controller: ControllerKind,
+    cursor_grabbed: bool,
+    last_frame: Option<Instant>,
+    scene: Scene,
+    sun: NodeId,
+    /// A camera node to view the scene through instead of the free camera
+    active_camera: Option<NodeId>,
+    /// Set while a bloom axis is held, the new settings are printed once it's let go
+    adjusting_bloom: bool,
+    /// Same as `adjusting_bloom`, for the skybox's rotation and intensity
+    adjusting_skybox: bool,
+    day_cycle: bool,
 }
 
 impl App {
-    pub fn new(window_attribs: WindowAttributes) -> Self {
+    pub fn new(
+        window_attribs: WindowAttributes,
+        renderer_config: gfx::RendererConfig,
+        model_path: Option<PathBuf>,
+        environment_path: Option<PathBuf>,
+        load_options: assets::LoadOptions,
+        bindings: Bindings,
+    ) -> Self {
+        let camera = Camera::new(renderer_config.reverse_z);
+        let mut orbit = OrbitController::default();
+        orbit.focus(&camera, Vec3::ZERO);
+        let mut scene = Scene::default();
+        let sun = find_or_add_sun(&mut scene);
+
         Self {
             window: None,
             window_attribs,
             renderer: None,
+            renderer_config,
+            model_path,
+            environment_path,
+            load_options,
+            input: Input::new(bindings),
+            camera,
+            fly: FlyController::default(),
+            orbit,
+            controller: ControllerKind::Orbit,
+            cursor_grabbed: false,
+            last_frame: None,
+            scene,
+            sun,
+            active_camera: None,
+            adjusting_bloom: false,
+            adjusting_skybox: false,
+            day_cycle: false,
+        }
+    }
+
+    fn controller(&mut self) -> &mut dyn CameraController {
+        match self.controller {
+            ControllerKind::Fly => &mut self.fly,
+            ControllerKind::Orbit => &mut self.orbit,
+        }
+    }
+
+    // A model that fails to load is reported but doesn't bring the app down
+    fn load_model(&mut self, path: &std::path::Path) {
+        let result = assets::load_model(path, &self.load_options).and_then(|model| {
+            self.renderer.as_mut().unwrap().set_model(&model)?;
+            Ok(model)
+        });
+        match result {
+            Ok(model) => {
+                self.scene.clear();
+                self.scene.instantiate(&model);
+                self.sun = find_or_add_sun(&mut self.scene);
+                self.active_camera = None;
+                if let Some((min, max)) = model.bounds() {
+                    self.frame_bounds(min, max);
+                }
+                self.renderer.as_mut().unwrap().reset_history();
+            }
+            Err(err) => eprintln!("Failed to load {}: {err:#}", path.display()),
+        }
+    }
+
+    fn load_environment(&mut self, path: &std::path::Path) {
+        if let Err(err) = self.renderer.as_mut().unwrap().set_environment(path) {
+            eprintln!("Failed to load {}: {err:#}", path.display());
         }
     }
-    pub fn handle_input(&mut self, event_loop: &winit::event_loop::ActiveEventLoop, key: KeyCode) {
-        if key == KeyCode::Escape {
+
+    fn frame_bounds(&mut self, min: Vec3, max: Vec3) {
+        let center = (min + max) * 0.5;
+        let radius = ((max - min).length() * 0.5).max(0.01);
+        let eye = center + Vec3::new(0.0, 0.5, 1.0).normalize() * radius * 2.5;
+        self.camera.look_at(eye, center);
+        self.orbit.focus(&self.camera, center);
+        self.fly.speed = radius;
+    }
+
+    fn handle_actions(&mut self, event_loop: &winit::event_loop::ActiveEventLoop) {
+        if self.input.action(Action::Exit).just_pressed() {
             event_loop.exit();
         }
+        if self.input.action(Action::ToggleController).just_pressed() {
+            self.controller = match self.controller {
+                ControllerKind::Fly => ControllerKind::Orbit,
+                ControllerKind::Orbit => ControllerKind::Fly,
+            };
+            let camera = self.camera.clone();
+            self.controller().attach(&camera);
+        }
+        if self.input.action(Action::ToggleProjection).just_pressed() {
+            self.toggle_projection();
+        }
+        if self.input.action(Action::NextCamera).just_pressed() {
+            self.next_camera();
+        }
+        if self.input.action(Action::ToggleRenderPath).just_pressed() {
+            match self.renderer.as_mut().unwrap().toggle_render_path() {
+                Ok(path) => println!("Render path: {path:?}"),
+                Err(err) => eprintln!("Failed to switch render path: {err:#}"),
+            }
+        }
+        if self.input.action(Action::CycleMsaa).just_pressed() {
+            let renderer = self.renderer.as_mut().unwrap();
+            let next = match renderer.msaa_samples() {
+                samples @ 1..=4 => samples * 2,
+                _ => 1,
+            };
+            match renderer.set_msaa_samples(next) {
+                Ok(samples) => println!("MSAA: {samples}x"),
+                Err(err) => eprintln!("Failed to change MSAA: {err:#}"),
+            }
+        }
+        if self.input.action(Action::ToggleTaa).just_pressed() {
+            let enabled = self.renderer.as_mut().unwrap().toggle_taa();
+            println!("TAA: {}", if enabled { "on" } else { "off" });
+        }
+        if self.input.action(Action::ToggleFxaa).just_pressed() {
+            let enabled = self.renderer.as_mut().unwrap().toggle_fxaa();
+            println!("FXAA: {}", if enabled { "on" } else { "off" });
+        }
+        if self.input.action(Action::CycleTonemapper).just_pressed() {
+            let tonemapper = self.renderer.as_mut().unwrap().cycle_tonemapper();
+            println!("Tonemapper: {tonemapper:?}");
+        }
+        if self.input.action(Action::ToggleAutoExposure).just_pressed() {
+            let enabled = self.renderer.as_mut().unwrap().toggle_auto_exposure();
+            println!("Auto-exposure: {}", if enabled { "on" } else { "off" });
+        }
+        if self.input.action(Action::ToggleBloom).just_pressed() {
+            let bloom = self.renderer.as_mut().unwrap().bloom_mut();
+            bloom.enabled = !bloom.enabled;
+            println!("Bloom: {}", if bloom.enabled { "on" } else { "off" });
+        }
+        if self.input.action(Action::ToggleDayCycle).just_pressed() {
+            self.day_cycle = !self.day_cycle;
+            println!("Day cycle: {}", if self.day_cycle { "on" } else { "off" });
+        }
+        if self.input.action(Action::ToggleSsao).just_pressed() {
+            let enabled = self.renderer.as_mut().unwrap().toggle_ssao();
+            println!("SSAO: {}", if enabled { "on" } else { "off" });
+        }
+        if self
+            .input
+            .action(Action::CycleSsaoResolution)
+            .just_pressed()
+        {
+            match self.renderer.as_mut().unwrap().cycle_ssao_resolution() {
+                Ok(resolution) => println!("SSAO resolution: {resolution:?}"),
+                Err(err) => eprintln!("Failed to change SSAO resolution: {err:#}"),
+            }
+        }
+        if self.input.action(Action::ToggleCascadeDebug).just_pressed() {
+            self.renderer
+                .as_mut()
+                .unwrap()
+                .toggle_debug_view(gfx::DebugView::Cascades);
+        }
+        if self.input.action(Action::ToggleLightHeatmap).just_pressed() {
+            self.renderer
+                .as_mut()
+                .unwrap()
+                .toggle_debug_view(gfx::DebugView::LightCount);
+        }
+        if self
+            .input
+            .action(Action::ToggleOcclusionDebug)
+            .just_pressed()
+        {
+            self.renderer
+                .as_mut()
+                .unwrap()
+                .toggle_debug_view(gfx::DebugView::AmbientOcclusion);
+        }
+    }
+
+    fn next_camera(&mut self) {
+        let mut cameras = self
+            .scene
+            .nodes()
+            .filter(|(_, node)| node.camera.is_some())
+            .map(|(id, _)| id);
+        self.active_camera = match self.active_camera {
+            Some(current) => cameras.skip_while(|&id| id != current).nth(1),
+            None => cameras.next(),
+        };
+        self.renderer.as_mut().unwrap().reset_history();
+    }
+
+    // Cameras can't roll, so a scene camera is rebuilt from where its node sits and looks
+    fn view_camera(&self) -> Camera {
+        let Some(id) = self.active_camera else {
+            return self.camera.clone();
+        };
+        let node = self.scene.node(id);
+        let world = node.world();
+        let eye = world.transform_point3(Vec3::ZERO);
+
+        let mut camera = Camera::new(self.renderer_config.reverse_z);
+        camera.look_at(eye, eye + world.transform_vector3(Vec3::NEG_Z));
+        if let Some(projection) = node.camera {
+            camera.projection = projection;
+        }
+        camera
+    }
+
+    fn toggle_projection(&mut self) {
+        self.camera.projection = match self.camera.projection {
+            Projection::Perspective { far, .. } => Projection::Orthographic {
+                height: self.ortho_height(),
+                near: -far,
+                far,
+            },
+            Projection::Orthographic { far, .. } => Projection::Perspective {
+                fov_y: camera::DEFAULT_FOV_Y,
+                near: 0.01,
+                far,
+            },
+        };
+        self.renderer.as_mut().unwrap().reset_history();
+    }
+
+    // Sized so whatever the orbit controller is looking at stays about the same size on screen
+    fn ortho_height(&self) -> f32 {
+        2.0 * self.orbit.distance * (camera::DEFAULT_FOV_Y * 0.5).tan()
+    }
+
+    fn update_cursor_grab(&mut self) {
+        let grab = match self.controller {
+            ControllerKind::Fly => self.fly.wants_cursor_grab(&self.input),
+            ControllerKind::Orbit => self.orbit.wants_cursor_grab(&self.input),
+        };
+        if grab == self.cursor_grabbed {
+            return;
+        }
+        let Some(window) = &self.window else {
+            return;
+        };
+
+        // Not every platform can lock the cursor, confining it is the next best thing
+        let result = if grab {
+            window
+                .set_cursor_grab(CursorGrabMode::Locked)
+                .or_else(|_| window.set_cursor_grab(CursorGrabMode::Confined))
+        } else {
+            window.set_cursor_grab(CursorGrabMode::None)
+        };
+        if let Err(err) = result {
+            eprintln!("Failed to grab the cursor: {err}");
+        }
+        window.set_cursor_visible(!grab);
+        self.cursor_grabbed = grab;
+    }
+
+    fn adjust_bloom(&mut self, dt: f32) {
+        let intensity = self.input.axis(Axis::BloomIntensity);
+        let threshold = self.input.axis(Axis::BloomThreshold);
+        let radius = self.input.axis(Axis::BloomRadius);
+        let adjusting = intensity != 0.0 || threshold != 0.0 || radius != 0.0;
+        let Some(renderer) = &mut self.renderer else {
+            return;
+        };
+
+        let bloom = renderer.bloom_mut();
+        let step = BLOOM_ADJUST_SPEED * dt;
+        bloom.intensity = (bloom.intensity * step.mul_add(intensity, 1.0)).clamp(0.001, 1.0);
+        bloom.threshold = step.mul_add(threshold, bloom.threshold).max(0.0);
+        bloom.radius = (bloom.radius * step.mul_add(radius, 1.0)).clamp(0.001, 0.05);
+        if self.adjusting_bloom && !adjusting {
+            println!(
+                "Bloom: intensity {:.3}, threshold {:.2}, radius {:.4}",
+                bloom.intensity, bloom.threshold, bloom.radius
+            );
+        }
+        self.adjusting_bloom = adjusting;
+    }
+
+    fn adjust_skybox(&mut self, dt: f32) {
+        let rotation = self.input.axis(Axis::SkyboxRotation);
+        let intensity = self.input.axis(Axis::SkyboxIntensity);
+        let adjusting = rotation != 0.0 || intensity != 0.0;
+        let Some(renderer) = &mut self.renderer else {
+            return;
+        };
+
+        let skybox = renderer.skybox_mut();
+        let step = SKYBOX_ADJUST_SPEED * dt;
+        skybox.rotation = step
+            .mul_add(rotation, skybox.rotation)
+            .rem_euclid(std::f32::consts::TAU);
+        skybox.intensity = (skybox.intensity * step.mul_add(intensity, 1.0)).clamp(0.01, 100.0);
+        if self.adjusting_skybox && !adjusting {
+            println!(
+                "Skybox: rotation {:.1}°, intensity {:.2}",
+                skybox.rotation.to_degrees(),
+                skybox.intensity
+            );
+        }
+        self.adjusting_skybox = adjusting;
+    }
+
+    fn update(&mut self, event_loop: &winit::event_loop::ActiveEventLoop) {
+        self.handle_actions(event_loop);
+
+        let now = Instant::now();
+        let dt = self
+            .last_frame
+            .map_or(0.0, |last| (now - last).as_secs_f32().min(MAX_FRAME_TIME));
+        self.last_frame = Some(now);
+
+        // The free camera stays put while looking through a scene camera
+        if self.active_camera.is_none() {
+            match self.controller {
+                ControllerKind::Fly => self.fly.update(&mut self.camera, &self.input, dt),
+                ControllerKind::Orbit => self.orbit.update(&mut self.camera, &self.input, dt),
+            }
+        }
+        self.update_cursor_grab();
+
+        // Moving closer doesn't change an orthographic view, so zooming resizes it instead
+        if self.controller == ControllerKind::Orbit {
+            let ortho_height = self.ortho_height();
+            if let Projection::Orthographic { height, .. } = &mut self.camera.projection {
+                *height = ortho_height;
+            }
+        }
+
+        let sun_turn = self.input.axis(Axis::Sun) * SUN_TURN_SPEED * dt;
+        let mut sun_climb = self.input.axis(Axis::SunElevation) * SUN_TURN_SPEED * dt;
+        if self.day_cycle {
+            sun_climb += DAY_CYCLE_SPEED * dt;
+        }
+        if sun_turn != 0.0 || sun_climb != 0.0 {
+            // Tilting about the light's own X axis carries it along a great circle, so it
+            // keeps going over the top and under the horizon rather than stopping overhead
+            let mut local = *self.scene.node(self.sun).local();
+            local.rotation = Quat::from_rotation_y(sun_turn)
+                * local.rotation
+                * Quat::from_rotation_x(-sun_climb);
+            self.scene.set_local(self.sun, local);
+        }
+        self.scene.update_transforms();
+        self.adjust_bloom(dt);
+        self.adjust_skybox(dt);
+
+        self.input.end_frame();
+    }
+}
+
+// Scenes without a directional light of their own get a default sun
+fn find_or_add_sun(scene: &mut Scene) -> NodeId {
+    let existing = scene.nodes().find(|(_, node)| {
+        node.light
+            .is_some_and(|light| light.kind == LightKind::Directional)
+    });
+    if let Some((id, _)) = existing {
+        return id;
     }
+
+    let direction = Vec3::new(-0.3, -1.0, -0.4).normalize();
+    let id = scene.add_node(
+        None,
+        Transform {
+            rotation: Quat::from_rotation_arc(Vec3::NEG_Z, direction),
+            ..Default::default()
+        },
+    );
+    scene.node_mut(id).light = Some(Light {
+        kind: LightKind::Directional,
+        color: Vec3::new(1.0, 0.95, 0.9),
+        intensity: 3.0,
+        range: None,
+    });
+    id
 }
 
 impl ApplicationHandler for App {
@@ -33,10 +443,17 @@ impl ApplicationHandler for App {
             .create_window(self.window_attribs.clone())
             .unwrap();
 
-        let renderer = gfx::Renderer::new(&window).unwrap();
+        let renderer = gfx::Renderer::new(&window, self.renderer_config.clone()).unwrap();
 
         self.renderer = Some(renderer);
         self.window = Some(window);
+
+        if let Some(path) = self.model_path.clone() {
+            self.load_model(&path);
+        }
+        if let Some(path) = self.environment_path.clone() {
+            self.load_environment(&path);
+        }
     }
 
     fn window_event(
@@ -47,23 +464,49 @@ impl ApplicationHandler for App {
     ) {
         let window = self.window.as_ref().unwrap();
         if window_id == window.id() {
+            self.input.window_event(&event);
             match event {
                 WindowEvent::CloseRequested => event_loop.exit(),
+                WindowEvent::Resized(size) => {
+                    self.renderer
+                        .as_mut()
+                        .unwrap()
+                        .resize(size.width, size.height);
+                }
                 WindowEvent::RedrawRequested => {
                     window.request_redraw();
-                    self.renderer.as_mut().unwrap().draw().unwrap();
+                    self.update(event_loop);
+                    let camera = self.view_camera();
+                    self.renderer
+                        .as_mut()
+                        .unwrap()
+                        .draw(&self.scene, &camera)
+                        .unwrap();
+                }
+                // Equirectangular HDR images, KTX2 cubemaps and directories of cube faces light
+                // the scene, anything else is a model
+                WindowEvent::DroppedFile(path) => {
+                    let is_environment = path.is_dir()
+                        || path.extension().is_some_and(|ext| {
+                            ext.eq_ignore_ascii_case("hdr") || ext.eq_ignore_ascii_case("ktx2")
+                        });
+                    if is_environment {
+                        self.load_environment(&path);
+                    } else {
+                        self.load_model(&path);
+                    }
                 }
-                WindowEvent::KeyboardInput {
-                    event:
-                        KeyEvent {
-                            physical_key: PhysicalKey::Code(key),
-                            state: ElementState::Pressed,
-                            ..
-                        },
-                    ..
-                } => self.handle_input(event_loop, key),
                 _ => {}
             }
         }
     }
+
+    fn device_event(
+        &mut self,
+        _event_loop: &winit::event_loop::ActiveEventLoop,
+        _device_id: winit::event::DeviceId,
+        event: DeviceEvent,
+    ) {
+        self.input.device_event(&event);
+    }
 }
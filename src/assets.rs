@@ -0,0 +1,378 @@
+// CPU side representation of models, shared by every loader and uploaded by the renderer
+mod gltf;
+mod ktx2;
+mod obj;
+mod transcode;
+
+use std::path::Path;
+
+use anyhow::Context;
+use ash::vk;
+use glam::{Mat4, Vec2, Vec3, Vec4};
+
+use crate::camera::Projection;
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default, PartialEq, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct Vertex {
+    pub position: [f32; 3],
+    pub normal: [f32; 3],
+    pub uv: [f32; 2],
+    pub color: [f32; 4],
+    /// W is the handedness of the bitangent, `cross(normal, tangent) * w`
+    pub tangent: [f32; 4],
+}
+
+/// A range of a mesh's index buffer drawn with one material
+#[derive(Debug, Clone)]
+pub struct Primitive {
+    pub first_index: u32,
+    pub index_count: u32,
+    pub material: Option<usize>,
+}
+
+#[derive(Debug, Default)]
+pub struct Mesh {
+    pub vertices: Vec<Vertex>,
+    pub indices: Vec<u32>,
+    pub primitives: Vec<Primitive>,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum AlphaMode {
+    /// Alpha is ignored
+    #[default]
+    Opaque,
+    /// Fully transparent below the cutoff, fully opaque otherwise
+    Mask,
+    /// Blended over whatever is behind it
+    Blend,
+}
+
+/// glTF's metallic-roughness material, texture indices point into `Model::images`
+#[derive(Debug, Clone)]
+pub struct Material {
+    pub base_color_factor: Vec4,
+    pub base_color_texture: Option<usize>,
+    pub metallic_factor: f32,
+    pub roughness_factor: f32,
+    /// Roughness in green, metalness in blue
+    pub metallic_roughness_texture: Option<usize>,
+    /// Tangent space normals
+    pub normal_texture: Option<usize>,
+    pub normal_scale: f32,
+    /// Ambient occlusion in red
+    pub occlusion_texture: Option<usize>,
+    pub occlusion_strength: f32,
+    pub emissive_factor: Vec3,
+    pub emissive_texture: Option<usize>,
+    pub alpha_mode: AlphaMode,
+    pub alpha_cutoff: f32,
+    pub double_sided: bool,
+}
+
+impl Default for Material {
+    fn default() -> Self {
+        Self {
+            base_color_factor: Vec4::ONE,
+            base_color_texture: None,
+            metallic_factor: 1.0,
+            roughness_factor: 1.0,
+            metallic_roughness_texture: None,
+            normal_texture: None,
+            normal_scale: 1.0,
+            occlusion_texture: None,
+            occlusion_strength: 1.0,
+            emissive_factor: Vec3::ZERO,
+            emissive_texture: None,
+            alpha_mode: AlphaMode::Opaque,
+            alpha_cutoff: 0.5,
+            double_sided: false,
+        }
+    }
+}
+
+impl Material {
+    /// Images holding colour, which have to be sampled through sRGB views
+    pub fn srgb_textures(&self) -> impl Iterator<Item = usize> {
+        [self.base_color_texture, self.emissive_texture]
+            .into_iter()
+            .flatten()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PixelFormat {
+    /// `srgb` marks colour data that has to be sampled through an sRGB format
+    Rgba8 { srgb: bool },
+    /// Linear HDR data
+    Rgba32F,
+    /// Uploaded as is, usually block compressed data from a KTX2 file
+    Native(vk::Format),
+    /// `pixels` holds a whole .basis file with every level, transcoded once the device's
+    /// formats are known
+    Basis { srgb: bool },
+}
+
+/// Decoded, tightly packed pixels
+#[derive(Debug)]
+pub struct ImageData {
+    pub width: u32,
+    pub height: u32,
+    pub format: PixelFormat,
+    pub pixels: Vec<u8>,
+    /// Levels below the base one when the file already has them, otherwise they get generated
+    pub mips: Vec<Vec<u8>>,
+}
+
+/// Decodes a PNG, JPEG or Radiance HDR file, or reads a KTX2 one. `srgb` only matters for
+/// 8 bit images, KTX2 files carry their own format
+pub fn load_image(path: &Path, srgb: bool) -> anyhow::Result<ImageData> {
+    if path
+        .extension()
+        .is_some_and(|ext| ext.eq_ignore_ascii_case("ktx2"))
+    {
+        return ktx2::load(path);
+    }
+
+    let image =
+        image::open(path).with_context(|| format!("Failed to load image {}", path.display()))?;
+    let (width, height) = (image.width(), image.height());
+
+    let (format, pixels) = match image {
+        image::DynamicImage::ImageRgb32F(_) | image::DynamicImage::ImageRgba32F(_) => (
+            PixelFormat::Rgba32F,
+            bytemuck::cast_slice(&image.into_rgba32f().into_raw()).to_vec(),
+        ),
+        _ => (PixelFormat::Rgba8 { srgb }, image.into_rgba8().into_raw()),
+    };
+
+    Ok(ImageData {
+        width,
+        height,
+        format,
+        pixels,
+        mips: Vec::new(),
+    })
+}
+
+/// What an environment is made from
+#[derive(Debug)]
+pub enum EnvironmentImage {
+    /// A latitude-longitude panorama
+    Equirect(ImageData),
+    /// Square faces of one size and format, in +X, -X, +Y, -Y, +Z, -Z order
+    Cube(Vec<ImageData>),
+}
+
+// Names a directory's face images can go by, in face order
+const CUBE_FACE_NAMES: [[&str; 6]; 2] = [
+    ["px", "nx", "py", "ny", "pz", "nz"],
+    ["right", "left", "top", "bottom", "front", "back"],
+];
+
+/// Reads an equirectangular image, a KTX2 cubemap, or a directory holding six face images
+/// named px, nx, py, ny, pz and nz, or right, left, top, bottom, front and back
+pub fn load_environment(path: &Path) -> anyhow::Result<EnvironmentImage> {
+    let faces = if path.is_dir() {
+        load_cube_faces(path)?
+    } else if path
+        .extension()
+        .is_some_and(|ext| ext.eq_ignore_ascii_case("ktx2"))
+    {
+        ktx2::load_faces(path)?
+    } else {
+        return Ok(EnvironmentImage::Equirect(load_image(path, true)?));
+    };
+    if faces.len() == 1 {
+        return Ok(EnvironmentImage::Equirect(
+            faces.into_iter().next().unwrap(),
+        ));
+    }
+
+    let first = &faces[0];
+    if first.width != first.height
+        || faces.iter().any(|face| {
+            (face.width, face.height, face.format) != (first.width, first.height, first.format)
+        })
+    {
+        return Err(anyhow::anyhow!(
+            "{}'s faces have to be square and all the same size and format",
+            path.display()
+        ));
+    }
+    Ok(EnvironmentImage::Cube(faces))
+}
+
+fn load_cube_faces(dir: &Path) -> anyhow::Result<Vec<ImageData>> {
+    let entries = std::fs::read_dir(dir)
+        .with_context(|| format!("Failed to read {}", dir.display()))?
+        .map(|entry| entry.map(|entry| entry.path()))
+        .collect::<Result<Vec<_>, _>>()?;
+    let find = |name: &str| {
+        entries.iter().find(|path| {
+            path.file_stem()
+                .is_some_and(|stem| stem.eq_ignore_ascii_case(name))
+        })
+    };
+
+    let paths = CUBE_FACE_NAMES
+        .iter()
+        .find_map(|names| {
+            names
+                .iter()
+                .map(|name| find(name))
+                .collect::<Option<Vec<_>>>()
+        })
+        .with_context(|| format!("{} doesn't hold six cube face images", dir.display()))?;
+    paths
+        .into_iter()
+        .map(|path| load_image(path, true))
+        .collect()
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum LightKind {
+    /// Infinitely far away, shining down the node's -Z axis
+    Directional,
+    Point,
+    /// A point light limited to a cone around the node's -Z axis, angles are in radians from
+    /// its centre
+    Spot {
+        inner_cone_angle: f32,
+        outer_cone_angle: f32,
+    },
+}
+
+/// A glTF style punctual light
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Light {
+    pub kind: LightKind,
+    pub color: Vec3,
+    pub intensity: f32,
+    /// Distance at which a point or spot light's contribution reaches zero, unbounded if `None`
+    pub range: Option<f32>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Node {
+    pub transform: Mat4,
+    pub children: Vec<usize>,
+    pub mesh: Option<usize>,
+    pub light: Option<Light>,
+    pub camera: Option<Projection>,
+}
+
+#[derive(Debug, Default)]
+pub struct Model {
+    pub meshes: Vec<Mesh>,
+    pub materials: Vec<Material>,
+    pub images: Vec<ImageData>,
+    pub nodes: Vec<Node>,
+    pub roots: Vec<usize>,
+}
+
+impl Model {
+    /// Calls `f` with every node that has a mesh and its world transform
+    pub fn visit_meshes(&self, mut f: impl FnMut(usize, Mat4)) {
+        let mut stack: Vec<(usize, Mat4)> = self
+            .roots
+            .iter()
+            .map(|&root| (root, Mat4::IDENTITY))
+            .collect();
+        while let Some((idx, parent)) = stack.pop() {
+            let node = &self.nodes[idx];
+            let world = parent * node.transform;
+            if let Some(mesh) = node.mesh {
+                f(mesh, world);
+            }
+            stack.extend(node.children.iter().map(|&child| (child, world)));
+        }
+    }
+
+    /// World space bounding box of every mesh instance
+    pub fn bounds(&self) -> Option<(Vec3, Vec3)> {
+        let mut bounds: Option<(Vec3, Vec3)> = None;
+        self.visit_meshes(|mesh, world| {
+            for vertex in &self.meshes[mesh].vertices {
+                let p = world.transform_point3(Vec3::from(vertex.position));
+                bounds = Some(match bounds {
+                    Some((min, max)) => (min.min(p), max.max(p)),
+                    None => (p, p),
+                });
+            }
+        });
+        bounds
+    }
+}
+
+/// Fills in the tangents of the vertices used by `indices` from their UVs, which tangent
+/// space normal maps need. Tangents of shared vertices are averaged
+fn generate_tangents(vertices: &mut [Vertex], indices: &[u32]) {
+    let mut tangents = vec![Vec3::ZERO; vertices.len()];
+    let mut bitangents = vec![Vec3::ZERO; vertices.len()];
+    for face in indices.chunks_exact(3) {
+        let [a, b, c] = [face[0], face[1], face[2]].map(|idx| &vertices[idx as usize]);
+        let edge1 = Vec3::from(b.position) - Vec3::from(a.position);
+        let edge2 = Vec3::from(c.position) - Vec3::from(a.position);
+        let duv1 = Vec2::from(b.uv) - Vec2::from(a.uv);
+        let duv2 = Vec2::from(c.uv) - Vec2::from(a.uv);
+
+        let det = duv1.x * duv2.y - duv2.x * duv1.y;
+        if det.abs() < f32::EPSILON {
+            continue;
+        }
+        let tangent = (edge1 * duv2.y - edge2 * duv1.y) / det;
+        let bitangent = (edge2 * duv1.x - edge1 * duv2.x) / det;
+        for &idx in face {
+            tangents[idx as usize] += tangent;
+            bitangents[idx as usize] += bitangent;
+        }
+    }
+
+    for &idx in indices {
+        let idx = idx as usize;
+        let normal = Vec3::from(vertices[idx].normal);
+        // Gram-Schmidt, falling back to any perpendicular vector for degenerate UVs
+        let tangent = (tangents[idx] - normal * normal.dot(tangents[idx]))
+            .try_normalize()
+            .unwrap_or_else(|| normal.any_orthonormal_vector());
+        let handedness = if normal.cross(tangent).dot(bitangents[idx]) < 0.0 {
+            -1.0
+        } else {
+            1.0
+        };
+        vertices[idx].tangent = tangent.extend(handedness).to_array();
+    }
+}
+
+/// How normals are generated for meshes that don't have them
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Normals {
+    /// One normal per face, giving a faceted look
+    Flat,
+    /// Face normals averaged over every face sharing a position
+    #[default]
+    Smooth,
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct LoadOptions {
+    pub normals: Normals,
+}
+
+pub fn load_model(path: &Path, options: &LoadOptions) -> anyhow::Result<Model> {
+    let extension = path
+        .extension()
+        .and_then(|ext| ext.to_str())
+        .map(|ext| ext.to_ascii_lowercase());
+
+    match extension.as_deref() {
+        Some("gltf" | "glb") => gltf::load(path),
+        Some("obj") => obj::load(path, options),
+        _ => Err(anyhow::anyhow!(
+            "Don't know how to load {}, expected a .gltf, .glb or .obj file",
+            path.display()
+        )),
+    }
+}
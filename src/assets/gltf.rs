@@ -0,0 +1,318 @@
+use std::path::Path;
+
+use anyhow::Context;
+use glam::{Mat4, Vec3, Vec4};
+
+use crate::camera::Projection;
+
+use super::{
+    generate_tangents, AlphaMode, ImageData, Light, LightKind, Material, Mesh, Model, Node,
+    PixelFormat, Primitive, Vertex,
+};
+
+// Extensions a file may list as required that the loader understands
+const SUPPORTED_EXTENSIONS: &[&str] = &["KHR_lights_punctual"];
+
+pub fn load(path: &Path) -> anyhow::Result<Model> {
+    let gltf::Gltf { document, blob } =
+        gltf::Gltf::open(path).with_context(|| format!("Failed to parse {}", path.display()))?;
+
+    let unsupported: Vec<&str> = document
+        .extensions_required()
+        .filter(|extension| !SUPPORTED_EXTENSIONS.contains(extension))
+        .collect();
+    if !unsupported.is_empty() {
+        return Err(anyhow::anyhow!(
+            "{} requires unsupported glTF extensions: {}",
+            path.display(),
+            unsupported.join(", ")
+        ));
+    }
+
+    let base = path.parent();
+    let buffers = gltf::import_buffers(&document, base, blob)
+        .with_context(|| format!("Failed to load buffers of {}", path.display()))?;
+    let images = gltf::import_images(&document, base, &buffers)
+        .with_context(|| format!("Failed to load images of {}", path.display()))?;
+
+    let materials: Vec<Material> = document.materials().map(load_material).collect();
+
+    // Only colour textures are sampled through sRGB views
+    let mut srgb_images = vec![false; images.len()];
+    for image in materials.iter().flat_map(Material::srgb_textures) {
+        srgb_images[image] = true;
+    }
+    let images = images
+        .into_iter()
+        .zip(srgb_images)
+        .map(|(image, srgb)| convert_image(image, srgb))
+        .collect::<anyhow::Result<Vec<_>>>()?;
+
+    let meshes = document
+        .meshes()
+        .map(|mesh| load_mesh(&mesh, &buffers))
+        .collect::<anyhow::Result<Vec<_>>>()?;
+
+    let nodes = document
+        .nodes()
+        .map(|node| Node {
+            transform: Mat4::from_cols_array_2d(&node.transform().matrix()),
+            children: node.children().map(|child| child.index()).collect(),
+            mesh: node.mesh().map(|mesh| mesh.index()),
+            light: node.light().map(|light| load_light(&light)),
+            camera: node.camera().map(|camera| load_camera(&camera)),
+        })
+        .collect();
+
+    let roots = document
+        .default_scene()
+        .or_else(|| document.scenes().next())
+        .map(|scene| scene.nodes().map(|node| node.index()).collect())
+        .unwrap_or_default();
+
+    Ok(Model {
+        meshes,
+        materials,
+        images,
+        nodes,
+        roots,
+    })
+}
+
+// Every texture is read with the first UV set and the renderer's sampler
+fn load_material(material: gltf::Material) -> Material {
+    let pbr = material.pbr_metallic_roughness();
+    let normal = material.normal_texture();
+    let occlusion = material.occlusion_texture();
+    Material {
+        base_color_factor: Vec4::from(pbr.base_color_factor()),
+        base_color_texture: pbr
+            .base_color_texture()
+            .map(|info| info.texture().source().index()),
+        metallic_factor: pbr.metallic_factor(),
+        roughness_factor: pbr.roughness_factor(),
+        metallic_roughness_texture: pbr
+            .metallic_roughness_texture()
+            .map(|info| info.texture().source().index()),
+        normal_texture: normal.as_ref().map(|info| info.texture().source().index()),
+        normal_scale: normal.as_ref().map_or(1.0, |info| info.scale()),
+        occlusion_texture: occlusion
+            .as_ref()
+            .map(|info| info.texture().source().index()),
+        occlusion_strength: occlusion.as_ref().map_or(1.0, |info| info.strength()),
+        emissive_factor: Vec3::from(material.emissive_factor()),
+        emissive_texture: material
+            .emissive_texture()
+            .map(|info| info.texture().source().index()),
+        alpha_mode: match material.alpha_mode() {
+            gltf::material::AlphaMode::Opaque => AlphaMode::Opaque,
+            gltf::material::AlphaMode::Mask => AlphaMode::Mask,
+            gltf::material::AlphaMode::Blend => AlphaMode::Blend,
+        },
+        alpha_cutoff: material.alpha_cutoff().unwrap_or(0.5),
+        double_sided: material.double_sided(),
+    }
+}
+
+fn load_light(light: &gltf::khr_lights_punctual::Light) -> Light {
+    use gltf::khr_lights_punctual::Kind;
+
+    Light {
+        kind: match light.kind() {
+            Kind::Directional => LightKind::Directional,
+            Kind::Point => LightKind::Point,
+            Kind::Spot {
+                inner_cone_angle,
+                outer_cone_angle,
+            } => LightKind::Spot {
+                inner_cone_angle,
+                outer_cone_angle,
+            },
+        },
+        color: Vec3::from(light.color()),
+        intensity: light.intensity(),
+        range: light.range(),
+    }
+}
+
+// The aspect ratio is left to the window
+fn load_camera(camera: &gltf::Camera) -> Projection {
+    match camera.projection() {
+        gltf::camera::Projection::Perspective(perspective) => Projection::Perspective {
+            fov_y: perspective.yfov(),
+            near: perspective.znear(),
+            far: perspective.zfar().unwrap_or(1000.0),
+        },
+        gltf::camera::Projection::Orthographic(orthographic) => Projection::Orthographic {
+            height: orthographic.ymag() * 2.0,
+            near: orthographic.znear(),
+            far: orthographic.zfar(),
+        },
+    }
+}
+
+fn load_mesh(mesh: &gltf::Mesh, buffers: &[gltf::buffer::Data]) -> anyhow::Result<Mesh> {
+    let mut out = Mesh::default();
+
+    for primitive in mesh.primitives() {
+        if primitive.mode() != gltf::mesh::Mode::Triangles {
+            return Err(anyhow::anyhow!(
+                "Mesh {} uses {:?} primitives, only triangle lists are supported",
+                mesh.index(),
+                primitive.mode()
+            ));
+        }
+
+        let reader = primitive.reader(|buffer| Some(&buffers[buffer.index()]));
+        let positions: Vec<[f32; 3]> = reader
+            .read_positions()
+            .with_context(|| format!("Mesh {} has a primitive without positions", mesh.index()))?
+            .collect();
+
+        let base_vertex = out.vertices.len() as u32;
+        let first_index = out.indices.len() as u32;
+
+        let mut vertices: Vec<Vertex> = positions
+            .iter()
+            .map(|&position| Vertex {
+                position,
+                color: [1.0; 4],
+                ..Default::default()
+            })
+            .collect();
+        let normals = reader.read_normals();
+        let has_normals = normals.is_some();
+        if let Some(normals) = normals {
+            for (vertex, normal) in vertices.iter_mut().zip(normals) {
+                vertex.normal = normal;
+            }
+        }
+        let uvs = reader.read_tex_coords(0);
+        let has_uvs = uvs.is_some();
+        if let Some(uvs) = uvs {
+            for (vertex, uv) in vertices.iter_mut().zip(uvs.into_f32()) {
+                vertex.uv = uv;
+            }
+        }
+        if let Some(colors) = reader.read_colors(0) {
+            for (vertex, color) in vertices.iter_mut().zip(colors.into_rgba_f32()) {
+                vertex.color = color;
+            }
+        }
+
+        let mut indices: Vec<u32> = match reader.read_indices() {
+            Some(indices) => indices.into_u32().collect(),
+            None => (0..positions.len() as u32).collect(),
+        };
+        if let Some(&idx) = indices.iter().find(|&&idx| idx as usize >= vertices.len()) {
+            return Err(anyhow::anyhow!(
+                "Mesh {} primitive {} has index {idx}, but only {} vertices",
+                mesh.index(),
+                primitive.index(),
+                vertices.len()
+            ));
+        }
+        // Tangents are meant for the normals they came with, not generated flat ones
+        let tangents = reader.read_tangents().filter(|_| has_normals);
+        let has_tangents = tangents.is_some();
+        if let Some(tangents) = tangents {
+            for (vertex, tangent) in vertices.iter_mut().zip(tangents) {
+                vertex.tangent = tangent;
+            }
+        }
+
+        // The spec asks for flat normals when there are none, so every face gets its own
+        // vertices
+        if !has_normals {
+            vertices = indices.iter().map(|&idx| vertices[idx as usize]).collect();
+            indices = (0..vertices.len() as u32).collect();
+            for face in vertices.chunks_exact_mut(3) {
+                let [a, b, c] = [&face[0], &face[1], &face[2]].map(|v| Vec3::from(v.position));
+                let normal = (b - a).cross(c - a).normalize_or_zero().to_array();
+                for vertex in face {
+                    vertex.normal = normal;
+                }
+            }
+        }
+
+        // Without UVs there's nothing to derive tangents from, and nothing to normal map
+        if !has_tangents && has_uvs {
+            generate_tangents(&mut vertices, &indices);
+        }
+
+        out.vertices.extend(vertices);
+        out.indices
+            .extend(indices.into_iter().map(|idx| idx + base_vertex));
+
+        out.primitives.push(Primitive {
+            first_index,
+            index_count: out.indices.len() as u32 - first_index,
+            material: primitive.material().index(),
+        });
+    }
+
+    Ok(out)
+}
+
+fn convert_image(image: gltf::image::Data, srgb: bool) -> anyhow::Result<ImageData> {
+    use gltf::image::Format;
+
+    let (channels, channel_size) = match image.format {
+        Format::R8 => (1, 1),
+        Format::R8G8 => (2, 1),
+        Format::R8G8B8 => (3, 1),
+        Format::R8G8B8A8 => (4, 1),
+        Format::R16 => (1, 2),
+        Format::R16G16 => (2, 2),
+        Format::R16G16B16 => (3, 2),
+        Format::R16G16B16A16 => (4, 2),
+        Format::R32G32B32FLOAT => (3, 4),
+        Format::R32G32B32A32FLOAT => (4, 4),
+    };
+
+    let pixels = if channels == 4 && channel_size == 1 {
+        image.pixels
+    } else {
+        let to_u8 = |bytes: &[u8]| -> u8 {
+            match bytes.len() {
+                1 => bytes[0],
+                2 => bytes[1], // High byte of a little endian u16
+                _ => {
+                    let value = f32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]);
+                    (value.clamp(0.0, 1.0) * 255.0).round() as u8
+                }
+            }
+        };
+
+        image
+            .pixels
+            .chunks_exact(channels * channel_size)
+            .flat_map(|pixel| {
+                let channel = |i: usize| to_u8(&pixel[i * channel_size..(i + 1) * channel_size]);
+                match channels {
+                    1 => [channel(0), channel(0), channel(0), 255],
+                    2 => [channel(0), channel(1), 0, 255],
+                    3 => [channel(0), channel(1), channel(2), 255],
+                    _ => [channel(0), channel(1), channel(2), channel(3)],
+                }
+            })
+            .collect()
+    };
+
+    if pixels.len() != (image.width * image.height * 4) as usize {
+        return Err(anyhow::anyhow!(
+            "glTF image has {} bytes of pixel data for {}x{}",
+            pixels.len(),
+            image.width,
+            image.height
+        ));
+    }
+
+    Ok(ImageData {
+        width: image.width,
+        height: image.height,
+        format: PixelFormat::Rgba8 { srgb },
+        pixels,
+        mips: Vec::new(),
+    })
+}
@@ -0,0 +1,361 @@
+use std::path::Path;
+
+use anyhow::Context;
+use ash::vk;
+use ktx2::{ColorModel, DfdBlockBasic, SupercompressionScheme, TransferFunction};
+
+use super::{ImageData, PixelFormat};
+
+pub fn load(path: &Path) -> anyhow::Result<ImageData> {
+    let mut faces = load_faces(path)?;
+    if faces.len() > 1 {
+        return Err(anyhow::anyhow!(
+            "{} is a cubemap, where a plain 2D texture was expected",
+            path.display()
+        ));
+    }
+    Ok(faces.remove(0))
+}
+
+/// One image for a plain 2D texture, or six in +X, -X, +Y, -Y, +Z, -Z order for a cubemap
+pub fn load_faces(path: &Path) -> anyhow::Result<Vec<ImageData>> {
+    let bytes =
+        std::fs::read(path).with_context(|| format!("Failed to read {}", path.display()))?;
+    let reader = ktx2::Reader::new(&bytes[..])
+        .with_context(|| format!("Failed to parse {}", path.display()))?;
+    let header = reader.header();
+
+    if header.pixel_depth > 1 || header.layer_count > 1 {
+        return Err(anyhow::anyhow!(
+            "{} is a 3D texture or an array, neither of which are supported",
+            path.display()
+        ));
+    }
+
+    let levels = reader
+        .levels()
+        .map(|level| match header.supercompression_scheme {
+            // BasisLZ levels are ETC1S slices, left for the transcoder to decode
+            None | Some(SupercompressionScheme::BasisLZ) => Ok(level.data.to_vec()),
+            Some(SupercompressionScheme::ZLIB) => {
+                miniz_oxide::inflate::decompress_to_vec_zlib(level.data).map_err(|err| {
+                    anyhow::anyhow!("Failed to inflate a level of {}: {err}", path.display())
+                })
+            }
+            Some(SupercompressionScheme::Zstandard) => {
+                let mut data = Vec::with_capacity(level.uncompressed_byte_length as usize);
+                ruzstd::decoding::FrameDecoder::new()
+                    .decode_all_to_vec(level.data, &mut data)
+                    .map_err(|err| {
+                        anyhow::anyhow!("Failed to decompress a level of {}: {err}", path.display())
+                    })?;
+                Ok(data)
+            }
+            Some(scheme) => Err(anyhow::anyhow!(
+                "{} uses unsupported {scheme:?} supercompression",
+                path.display()
+            )),
+        })
+        .collect::<anyhow::Result<Vec<_>>>()?;
+    if levels.is_empty() {
+        return Err(anyhow::anyhow!("{} has no image data", path.display()));
+    }
+
+    // Basis Universal payloads leave the format undefined, they're transcoded once the
+    // device's formats are known
+    let Some(format) = header.format else {
+        return basis_faces(&reader, &levels).with_context(|| {
+            format!(
+                "Failed to read the {} data of {}",
+                payload_name(&reader),
+                path.display()
+            )
+        });
+    };
+
+    // Plain formats go through the same path as decoded images, so missing mips get generated
+    let format = match vk::Format::from_raw(format.value() as i32) {
+        vk::Format::R8G8B8A8_SRGB => PixelFormat::Rgba8 { srgb: true },
+        vk::Format::R8G8B8A8_UNORM => PixelFormat::Rgba8 { srgb: false },
+        vk::Format::R32G32B32A32_SFLOAT => PixelFormat::Rgba32F,
+        format => PixelFormat::Native(format),
+    };
+
+    // Each level holds every face back to back
+    let face_count = header.face_count.max(1) as usize;
+    let mut faces = (0..face_count)
+        .map(|_| Vec::with_capacity(levels.len()))
+        .collect::<Vec<_>>();
+    for level in levels {
+        if level.len() % face_count != 0 {
+            return Err(anyhow::anyhow!(
+                "{} has a level that doesn't split into its faces",
+                path.display()
+            ));
+        }
+        for (face, data) in faces
+            .iter_mut()
+            .zip(level.chunks_exact(level.len() / face_count))
+        {
+            face.push(data.to_vec());
+        }
+    }
+
+    Ok(faces
+        .into_iter()
+        .map(|mut levels| ImageData {
+            width: header.pixel_width,
+            height: header.pixel_height.max(1),
+            format,
+            pixels: levels.remove(0),
+            mips: levels,
+        })
+        .collect())
+}
+
+fn payload_name(reader: &ktx2::Reader<&[u8]>) -> &'static str {
+    if reader.header().supercompression_scheme == Some(SupercompressionScheme::BasisLZ) {
+        return "ETC1S";
+    }
+
+    let color_model = reader
+        .dfd_blocks()
+        .find_map(|block| DfdBlockBasic::parse(block.data).ok())
+        .and_then(|block| block.header.color_model);
+    match color_model {
+        Some(ColorModel::UASTC) => "UASTC",
+        Some(ColorModel::ETC1S) => "ETC1S",
+        _ => "untyped",
+    }
+}
+
+// Channel ids in UASTC's data format descriptor, the ones with alpha
+const UASTC_RGBA: u8 = 3;
+const UASTC_RRRG: u8 = 5;
+
+// Layout of the .basis files the transcoder reads, all little endian without padding
+const BASIS_SIGNATURE: u32 = 0x4273;
+const BASIS_VERSION: u32 = 0x13;
+const BASIS_HEADER_SIZE: usize = 77;
+const BASIS_SLICE_DESC_SIZE: usize = 23;
+const BASIS_TEX_FORMAT_UASTC: u32 = 1;
+const BASIS_FLAG_ETC1S: u32 = 1;
+const BASIS_FLAG_HAS_ALPHA_SLICES: u32 = 4;
+const BASIS_FLAG_SRGB: u32 = 16;
+const BASIS_SLICE_HAS_ALPHA: u32 = 1;
+
+// One compressed slice of a .basis file, the colour or the alpha of one level
+struct BasisSlice<'a> {
+    level: u32,
+    alpha: bool,
+    data: &'a [u8],
+}
+
+/// Repackages every face as a .basis file, which is what the transcoder reads
+fn basis_faces(reader: &ktx2::Reader<&[u8]>, levels: &[Vec<u8>]) -> anyhow::Result<Vec<ImageData>> {
+    let header = reader.header();
+    let face_count = header.face_count.max(1) as usize;
+    let dfd = reader
+        .dfd_blocks()
+        .find_map(|block| DfdBlockBasic::parse(block.data).ok());
+    let srgb = dfd
+        .as_ref()
+        .is_some_and(|dfd| dfd.header.transfer_function == Some(TransferFunction::SRGB));
+    let etc1s = header.supercompression_scheme == Some(SupercompressionScheme::BasisLZ);
+    let level_extent = |level: u32| {
+        let width = (header.pixel_width >> level).max(1);
+        let height = (header.pixel_height.max(1) >> level).max(1);
+        (width, height, width.div_ceil(4), height.div_ceil(4))
+    };
+
+    // ETC1S shares its codebooks and Huffman tables between every image, and describes where
+    // each one's slices are in the global data
+    let mut codebooks = None;
+    let mut face_slices: Vec<Vec<BasisSlice>> = (0..face_count).map(|_| Vec::new()).collect();
+    if etc1s {
+        let global = reader.supercompression_global_data();
+        let read = |offset: usize, size: usize| -> anyhow::Result<u32> {
+            let bytes = global.get(offset..offset + size).ok_or(anyhow::anyhow!(
+                "The supercompression global data is truncated"
+            ))?;
+            Ok(bytes
+                .iter()
+                .rev()
+                .fold(0, |value, &byte| value << 8 | byte as u32))
+        };
+        let endpoint_count = read(0, 2)?;
+        let selector_count = read(2, 2)?;
+        let lengths = [read(4, 4)?, read(8, 4)?, read(12, 4)?].map(|length| length as usize);
+
+        let descs_start = 20;
+        let descs_end = descs_start + levels.len() * face_count * 20;
+        let mut start = descs_end;
+        let [endpoints, selectors, tables] = lengths.map(|length| {
+            let range = start..start + length;
+            start += length;
+            range
+        });
+        if global.len() < start {
+            return Err(anyhow::anyhow!(
+                "The supercompression global data is truncated"
+            ));
+        }
+        codebooks = Some((endpoint_count, selector_count, endpoints, selectors, tables));
+
+        // Image descriptions go by level, then face
+        for (level, data) in levels.iter().enumerate() {
+            for (face, slices) in face_slices.iter_mut().enumerate() {
+                let desc = descs_start + (level * face_count + face) * 20;
+                let [rgb_offset, rgb_length, alpha_offset, alpha_length] =
+                    [4, 8, 12, 16].map(|field| read(desc + field, 4));
+                let slice = |offset: anyhow::Result<u32>, length: u32| {
+                    let (offset, length) = (offset? as usize, length as usize);
+                    data.get(offset..offset + length)
+                        .ok_or(anyhow::anyhow!("Level {level} is truncated"))
+                };
+                slices.push(BasisSlice {
+                    level: level as u32,
+                    alpha: false,
+                    data: slice(rgb_offset, rgb_length?)?,
+                });
+                let alpha_length = alpha_length?;
+                if alpha_length > 0 {
+                    slices.push(BasisSlice {
+                        level: level as u32,
+                        alpha: true,
+                        data: slice(alpha_offset, alpha_length)?,
+                    });
+                }
+            }
+        }
+    } else {
+        let alpha = dfd.as_ref().is_some_and(|dfd| {
+            dfd.sample_information()
+                .any(|sample| matches!(sample.channel_type, UASTC_RGBA | UASTC_RRRG))
+        });
+        // Each level holds every face back to back, in 16 byte blocks
+        for (level, data) in levels.iter().enumerate() {
+            let (_, _, blocks_x, blocks_y) = level_extent(level as u32);
+            let face_size = (blocks_x * blocks_y) as usize * 16;
+            if data.len() != face_size * face_count {
+                return Err(anyhow::anyhow!(
+                    "Level {level} isn't the size its blocks need"
+                ));
+            }
+            for (slices, data) in face_slices.iter_mut().zip(data.chunks_exact(face_size)) {
+                slices.push(BasisSlice {
+                    level: level as u32,
+                    alpha,
+                    data,
+                });
+            }
+        }
+    }
+
+    let global = reader.supercompression_global_data();
+    Ok(face_slices
+        .iter()
+        .map(|slices| {
+            let has_alpha = slices.iter().any(|slice| slice.alpha);
+            let mut flags = if srgb { BASIS_FLAG_SRGB } else { 0 };
+            if has_alpha {
+                flags |= BASIS_FLAG_HAS_ALPHA_SLICES;
+            }
+
+            // Header, slice descriptions, then the codebooks and tables, then the slices
+            let slices_start = BASIS_HEADER_SIZE + slices.len() * BASIS_SLICE_DESC_SIZE;
+            let mut file = vec![0; slices_start];
+            let mut fields = BasisWriter(&mut file[..BASIS_HEADER_SIZE]);
+            fields.put(BASIS_SIGNATURE, 2);
+            fields.put(BASIS_VERSION, 2);
+            fields.put(BASIS_HEADER_SIZE as u32, 2);
+            // Checksums are only checked when asked to, which never happens
+            fields.put(0, 2);
+            // The data size is filled in once the slices are in
+            fields.skip(4 + 2);
+            fields.put(slices.len() as u32, 3);
+            fields.put(1, 3);
+            match &codebooks {
+                Some((endpoint_count, selector_count, endpoints, selectors, tables)) => {
+                    fields.put(0, 1);
+                    fields.put(flags | BASIS_FLAG_ETC1S, 2);
+                    // Texture type, frame time, reserved and user data stay zero
+                    fields.skip(1 + 3 + 4 + 4 + 4);
+                    let mut offset = slices_start;
+                    for (count, range) in [
+                        (Some(*endpoint_count), endpoints),
+                        (Some(*selector_count), selectors),
+                        (None, tables),
+                    ] {
+                        // Tables have no count, and a four byte size where the codebooks have three
+                        if let Some(count) = count {
+                            fields.put(count, 2);
+                        }
+                        fields.put(offset as u32, 4);
+                        fields.put(range.len() as u32, if count.is_some() { 3 } else { 4 });
+                        offset += range.len();
+                    }
+                    fields.put(BASIS_HEADER_SIZE as u32, 4);
+                    for range in [endpoints, selectors, tables] {
+                        file.extend_from_slice(&global[range.clone()]);
+                    }
+                }
+                None => {
+                    fields.put(BASIS_TEX_FORMAT_UASTC, 1);
+                    fields.put(flags, 2);
+                    fields.skip(1 + 3 + 4 + 4 + 4 + 2 + 4 + 3 + 2 + 4 + 3 + 4 + 4);
+                    fields.put(BASIS_HEADER_SIZE as u32, 4);
+                }
+            }
+
+            for (index, slice) in slices.iter().enumerate() {
+                let offset = file.len() as u32;
+                file.extend_from_slice(slice.data);
+                let (width, height, blocks_x, blocks_y) = level_extent(slice.level);
+                let desc = BASIS_HEADER_SIZE + index * BASIS_SLICE_DESC_SIZE;
+                let mut desc = BasisWriter(&mut file[desc..desc + BASIS_SLICE_DESC_SIZE]);
+                desc.put(0, 3);
+                desc.put(slice.level, 1);
+                desc.put(
+                    if slice.alpha {
+                        BASIS_SLICE_HAS_ALPHA
+                    } else {
+                        0
+                    },
+                    1,
+                );
+                for value in [width, height, blocks_x, blocks_y] {
+                    desc.put(value, 2);
+                }
+                desc.put(offset, 4);
+                desc.put(slice.data.len() as u32, 4);
+            }
+
+            let data_size = (file.len() - BASIS_HEADER_SIZE) as u32;
+            BasisWriter(&mut file[8..12]).put(data_size, 4);
+
+            ImageData {
+                width: header.pixel_width,
+                height: header.pixel_height.max(1),
+                format: PixelFormat::Basis { srgb },
+                pixels: file,
+                mips: Vec::new(),
+            }
+        })
+        .collect())
+}
+
+// Fills in packed little endian fields one after the other
+struct BasisWriter<'a>(&'a mut [u8]);
+
+impl BasisWriter<'_> {
+    fn put(&mut self, value: u32, size: usize) {
+        let (field, rest) = std::mem::take(&mut self.0).split_at_mut(size);
+        field.copy_from_slice(&value.to_le_bytes()[..size]);
+        self.0 = rest;
+    }
+
+    fn skip(&mut self, size: usize) {
+        self.0 = &mut std::mem::take(&mut self.0)[size..];
+    }
+}
@@ -0,0 +1,251 @@
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+};
+
+use anyhow::Context;
+use glam::{Mat4, Vec3, Vec4};
+
+use super::{
+    generate_tangents, load_image, AlphaMode, LoadOptions, Material, Mesh, Model, Node, Normals,
+    Primitive, Vertex,
+};
+
+// Marks an index the file didn't provide
+const MISSING: u32 = u32::MAX;
+
+pub fn load(path: &Path, options: &LoadOptions) -> anyhow::Result<Model> {
+    let load_options = tobj::LoadOptions {
+        triangulate: true,
+        ignore_points: true,
+        ignore_lines: true,
+        ..Default::default()
+    };
+    let (objects, materials) = tobj::load_obj(path, &load_options)
+        .with_context(|| format!("Failed to parse {}", path.display()))?;
+
+    // A missing or broken MTL file shouldn't keep the geometry from showing up
+    let materials = materials.unwrap_or_else(|err| {
+        eprintln!("Failed to load materials of {}: {err}", path.display());
+        Vec::new()
+    });
+
+    let base = path.parent().unwrap_or(Path::new("."));
+    let mut images = Vec::new();
+    let mut image_indices: HashMap<PathBuf, Option<usize>> = HashMap::new();
+    let mut load_texture = |texture: &str, srgb: bool| {
+        let texture_path = base.join(texture.replace('\\', "/"));
+        *image_indices
+            .entry(texture_path.clone())
+            .or_insert_with(|| match load_image(&texture_path, srgb) {
+                Ok(image) => {
+                    images.push(image);
+                    Some(images.len() - 1)
+                }
+                Err(err) => {
+                    eprintln!("{err:#}");
+                    None
+                }
+            })
+    };
+    let materials = materials
+        .iter()
+        .map(|material| convert_material(material, &mut load_texture))
+        .collect::<Vec<_>>();
+
+    let mut mesh = Mesh::default();
+    for object in &objects {
+        let first_index = mesh.indices.len() as u32;
+        append_object(&mut mesh, &object.mesh, options.normals);
+        let index_count = mesh.indices.len() as u32 - first_index;
+        if index_count > 0 {
+            mesh.primitives.push(Primitive {
+                first_index,
+                index_count,
+                material: object.mesh.material_id.filter(|&id| id < materials.len()),
+            });
+        }
+    }
+
+    generate_tangents(&mut mesh.vertices, &mesh.indices);
+
+    Ok(Model {
+        meshes: vec![mesh],
+        materials,
+        images,
+        nodes: vec![Node {
+            transform: Mat4::IDENTITY,
+            children: Vec::new(),
+            mesh: Some(0),
+            light: None,
+            camera: None,
+        }],
+        roots: vec![0],
+    })
+}
+
+// Reads the PBR extension to MTL where it's there (Pr, Pm, norm, map_Ke), otherwise the
+// Phong parameters are approximated. Separate roughness and metalness maps aren't supported,
+// glTF expects them packed into one texture
+fn convert_material(
+    material: &tobj::Material,
+    load_texture: &mut impl FnMut(&str, bool) -> Option<usize>,
+) -> Material {
+    let param = |name: &str| material.unknown_param.get(name).map(String::as_str);
+    let diffuse = material.diffuse.map_or(Vec3::ONE, Vec3::from);
+    let dissolve = material.dissolve.unwrap_or(1.0);
+
+    // Roughly matches the width of a Blinn-Phong highlight
+    let roughness = param("Pr")
+        .and_then(|value| value.trim().parse().ok())
+        .or_else(|| material.shininess.map(|ns| (2.0 / (ns + 2.0)).sqrt()))
+        .unwrap_or(1.0);
+
+    let (normal_texture, normal_scale) = match param("norm").or(material.normal_texture.as_deref())
+    {
+        Some(value) => {
+            let (texture, scale) = strip_bump_multiplier(value);
+            (load_texture(texture, false), scale)
+        }
+        None => (None, 1.0),
+    };
+
+    Material {
+        base_color_factor: diffuse.extend(dissolve),
+        // Diffuse and emissive maps hold colour, so they're sampled as sRGB
+        base_color_texture: material
+            .diffuse_texture
+            .as_deref()
+            .and_then(|texture| load_texture(texture, true)),
+        metallic_factor: param("Pm")
+            .and_then(|value| value.trim().parse().ok())
+            .unwrap_or(0.0),
+        roughness_factor: roughness,
+        normal_texture,
+        normal_scale,
+        emissive_factor: param("Ke").and_then(parse_vec3).unwrap_or(Vec3::ZERO),
+        emissive_texture: param("map_Ke").and_then(|texture| load_texture(texture, true)),
+        alpha_mode: if dissolve < 1.0 {
+            AlphaMode::Blend
+        } else {
+            AlphaMode::Opaque
+        },
+        // OBJ files don't say which side is the front, and often wind faces inconsistently
+        double_sided: true,
+        ..Default::default()
+    }
+}
+
+// Bump maps may start with a `-bm <multiplier>` option, other options aren't supported
+fn strip_bump_multiplier(value: &str) -> (&str, f32) {
+    let value = value.trim();
+    let Some(rest) = value.strip_prefix("-bm") else {
+        return (value, 1.0);
+    };
+    let rest = rest.trim_start();
+    let (multiplier, texture) = rest.split_once(char::is_whitespace).unwrap_or((rest, ""));
+    (texture.trim(), multiplier.parse().unwrap_or(1.0))
+}
+
+fn parse_vec3(value: &str) -> Option<Vec3> {
+    let components: Vec<f32> = value
+        .split_whitespace()
+        .map(str::parse)
+        .collect::<Result<_, _>>()
+        .ok()?;
+    match components[..] {
+        [x, y, z] => Some(Vec3::new(x, y, z)),
+        [v] => Some(Vec3::splat(v)),
+        _ => None,
+    }
+}
+
+// OBJ indexes positions, normals and texcoords separately, so every distinct
+// combination becomes one vertex
+fn append_object(mesh: &mut Mesh, object: &tobj::Mesh, normals: Normals) {
+    let position = |idx: u32| Vec3::from_slice(&object.positions[idx as usize * 3..][..3]);
+    let has_normals = !object.normal_indices.is_empty();
+    let has_uvs = !object.texcoord_indices.is_empty();
+    let has_colors = !object.vertex_color.is_empty();
+
+    // Area weighted, since the cross product's length is twice the triangle's area
+    let smooth_normals = if !has_normals && normals == Normals::Smooth {
+        let mut accumulated = vec![Vec3::ZERO; object.positions.len() / 3];
+        for face in object.indices.chunks_exact(3) {
+            let normal = face_normal(position(face[0]), position(face[1]), position(face[2]));
+            for &idx in face {
+                accumulated[idx as usize] += normal;
+            }
+        }
+        accumulated
+    } else {
+        Vec::new()
+    };
+
+    // Flat normals are part of the key so coplanar faces can still share vertices
+    let mut unique: HashMap<(u32, u32, u32, [u32; 3]), u32> = HashMap::new();
+    for (face_idx, face) in object.indices.chunks_exact(3).enumerate() {
+        let flat_normal = if !has_normals && normals == Normals::Flat {
+            face_normal(position(face[0]), position(face[1]), position(face[2])).normalize_or_zero()
+        } else {
+            Vec3::ZERO
+        };
+
+        for (corner, &position_idx) in face.iter().enumerate() {
+            let idx = face_idx * 3 + corner;
+            let normal_idx = if has_normals {
+                object.normal_indices[idx]
+            } else {
+                MISSING
+            };
+            let uv_idx = if has_uvs {
+                object.texcoord_indices[idx]
+            } else {
+                MISSING
+            };
+
+            let key = (
+                position_idx,
+                normal_idx,
+                uv_idx,
+                flat_normal.to_array().map(f32::to_bits),
+            );
+            let vertex_idx = *unique.entry(key).or_insert_with(|| {
+                let normal = if has_normals {
+                    Vec3::from_slice(&object.normals[normal_idx as usize * 3..][..3])
+                } else if normals == Normals::Smooth {
+                    smooth_normals[position_idx as usize].normalize_or_zero()
+                } else {
+                    flat_normal
+                };
+                // OBJ puts the texture origin at the bottom left
+                let uv = if has_uvs {
+                    let uv = &object.texcoords[uv_idx as usize * 2..][..2];
+                    [uv[0], 1.0 - uv[1]]
+                } else {
+                    [0.0; 2]
+                };
+                let color = if has_colors {
+                    Vec3::from_slice(&object.vertex_color[position_idx as usize * 3..][..3])
+                        .extend(1.0)
+                } else {
+                    Vec4::ONE
+                };
+
+                mesh.vertices.push(Vertex {
+                    position: position(position_idx).to_array(),
+                    normal: normal.to_array(),
+                    uv,
+                    color: color.to_array(),
+                    ..Default::default()
+                });
+                mesh.vertices.len() as u32 - 1
+            });
+            mesh.indices.push(vertex_idx);
+        }
+    }
+}
+
+fn face_normal(a: Vec3, b: Vec3, c: Vec3) -> Vec3 {
+    (b - a).cross(c - a)
+}
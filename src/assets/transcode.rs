@@ -0,0 +1,218 @@
+// Turns what the device can't sample as is into something it can. Basis Universal goes to the
+// best block compressed format on offer, anything else is decoded to plain RGBA8
+use ash::vk;
+use basis_universal::{TranscodeParameters, Transcoder, TranscoderTextureFormat};
+
+use super::{ImageData, PixelFormat};
+
+// What a decoder from texture2ddecoder looks like
+type Decoder = fn(&[u8], usize, usize, &mut [u32]) -> Result<(), &'static str>;
+
+impl ImageData {
+    /// Returns the image in a format `can_sample` accepts, or `None` if it already is in one
+    pub fn transcode(
+        &self,
+        can_sample: impl Fn(vk::Format) -> bool,
+    ) -> anyhow::Result<Option<ImageData>> {
+        match self.format {
+            PixelFormat::Basis { srgb } => self.transcode_basis(srgb, can_sample).map(Some),
+            PixelFormat::Native(format) if !can_sample(format) => self.decode(format).map(Some),
+            _ => Ok(None),
+        }
+    }
+
+    fn transcode_basis(
+        &self,
+        srgb: bool,
+        can_sample: impl Fn(vk::Format) -> bool,
+    ) -> anyhow::Result<ImageData> {
+        let data = &self.pixels;
+        let mut transcoder = Transcoder::new();
+        transcoder
+            .prepare_transcoding(data)
+            .map_err(|()| anyhow::anyhow!("The Basis Universal data is malformed"))?;
+        let alpha = transcoder
+            .image_info(data, 0)
+            .is_some_and(|info| info.m_alpha_flag);
+
+        let pick = |srgb_format, unorm_format| if srgb { srgb_format } else { unorm_format };
+        let targets = [
+            (
+                TranscoderTextureFormat::BC7_RGBA,
+                pick(vk::Format::BC7_SRGB_BLOCK, vk::Format::BC7_UNORM_BLOCK),
+            ),
+            if alpha {
+                (
+                    TranscoderTextureFormat::BC3_RGBA,
+                    pick(vk::Format::BC3_SRGB_BLOCK, vk::Format::BC3_UNORM_BLOCK),
+                )
+            } else {
+                (
+                    TranscoderTextureFormat::BC1_RGB,
+                    pick(
+                        vk::Format::BC1_RGB_SRGB_BLOCK,
+                        vk::Format::BC1_RGB_UNORM_BLOCK,
+                    ),
+                )
+            },
+            (
+                TranscoderTextureFormat::ASTC_4x4_RGBA,
+                pick(
+                    vk::Format::ASTC_4X4_SRGB_BLOCK,
+                    vk::Format::ASTC_4X4_UNORM_BLOCK,
+                ),
+            ),
+            if alpha {
+                (
+                    TranscoderTextureFormat::ETC2_RGBA,
+                    pick(
+                        vk::Format::ETC2_R8G8B8A8_SRGB_BLOCK,
+                        vk::Format::ETC2_R8G8B8A8_UNORM_BLOCK,
+                    ),
+                )
+            } else {
+                (
+                    TranscoderTextureFormat::ETC1_RGB,
+                    pick(
+                        vk::Format::ETC2_R8G8B8_SRGB_BLOCK,
+                        vk::Format::ETC2_R8G8B8_UNORM_BLOCK,
+                    ),
+                )
+            },
+        ];
+        let (target, format) = targets
+            .into_iter()
+            .find(|&(_, format)| can_sample(format))
+            .map_or(
+                (TranscoderTextureFormat::RGBA32, PixelFormat::Rgba8 { srgb }),
+                |(target, format)| (target, PixelFormat::Native(format)),
+            );
+
+        let mut levels = (0..transcoder.image_level_count(data, 0))
+            .map(|level_index| {
+                transcoder
+                    .transcode_image_level(
+                        data,
+                        target,
+                        TranscodeParameters {
+                            image_index: 0,
+                            level_index,
+                            ..Default::default()
+                        },
+                    )
+                    .map_err(|err| {
+                        anyhow::anyhow!("Failed to transcode level {level_index}: {err:?}")
+                    })
+            })
+            .collect::<anyhow::Result<Vec<_>>>()?;
+        transcoder.end_transcoding();
+        if levels.is_empty() {
+            return Err(anyhow::anyhow!("The Basis Universal data has no levels"));
+        }
+
+        Ok(ImageData {
+            width: self.width,
+            height: self.height,
+            format,
+            pixels: levels.remove(0),
+            mips: levels,
+        })
+    }
+
+    fn decode(&self, format: vk::Format) -> anyhow::Result<ImageData> {
+        let (decoder, srgb): (Decoder, bool) = match format {
+            vk::Format::BC1_RGB_UNORM_BLOCK => (texture2ddecoder::decode_bc1, false),
+            vk::Format::BC1_RGB_SRGB_BLOCK => (texture2ddecoder::decode_bc1, true),
+            vk::Format::BC1_RGBA_UNORM_BLOCK => (texture2ddecoder::decode_bc1a, false),
+            vk::Format::BC1_RGBA_SRGB_BLOCK => (texture2ddecoder::decode_bc1a, true),
+            vk::Format::BC2_UNORM_BLOCK => (texture2ddecoder::decode_bc2, false),
+            vk::Format::BC2_SRGB_BLOCK => (texture2ddecoder::decode_bc2, true),
+            vk::Format::BC3_UNORM_BLOCK => (texture2ddecoder::decode_bc3, false),
+            vk::Format::BC3_SRGB_BLOCK => (texture2ddecoder::decode_bc3, true),
+            vk::Format::BC4_UNORM_BLOCK => (texture2ddecoder::decode_bc4, false),
+            vk::Format::BC5_UNORM_BLOCK => (texture2ddecoder::decode_bc5, false),
+            vk::Format::BC7_UNORM_BLOCK => (texture2ddecoder::decode_bc7, false),
+            vk::Format::BC7_SRGB_BLOCK => (texture2ddecoder::decode_bc7, true),
+            vk::Format::ETC2_R8G8B8_UNORM_BLOCK => (texture2ddecoder::decode_etc2_rgb, false),
+            vk::Format::ETC2_R8G8B8_SRGB_BLOCK => (texture2ddecoder::decode_etc2_rgb, true),
+            vk::Format::ETC2_R8G8B8A1_UNORM_BLOCK => (texture2ddecoder::decode_etc2_rgba1, false),
+            vk::Format::ETC2_R8G8B8A1_SRGB_BLOCK => (texture2ddecoder::decode_etc2_rgba1, true),
+            vk::Format::ETC2_R8G8B8A8_UNORM_BLOCK => (texture2ddecoder::decode_etc2_rgba8, false),
+            vk::Format::ETC2_R8G8B8A8_SRGB_BLOCK => (texture2ddecoder::decode_etc2_rgba8, true),
+            vk::Format::EAC_R11_UNORM_BLOCK => (texture2ddecoder::decode_eacr, false),
+            vk::Format::EAC_R11G11_UNORM_BLOCK => (texture2ddecoder::decode_eacrg, false),
+            vk::Format::ASTC_4X4_UNORM_BLOCK => (astc::<4, 4>, false),
+            vk::Format::ASTC_4X4_SRGB_BLOCK => (astc::<4, 4>, true),
+            vk::Format::ASTC_5X4_UNORM_BLOCK => (astc::<5, 4>, false),
+            vk::Format::ASTC_5X4_SRGB_BLOCK => (astc::<5, 4>, true),
+            vk::Format::ASTC_5X5_UNORM_BLOCK => (astc::<5, 5>, false),
+            vk::Format::ASTC_5X5_SRGB_BLOCK => (astc::<5, 5>, true),
+            vk::Format::ASTC_6X5_UNORM_BLOCK => (astc::<6, 5>, false),
+            vk::Format::ASTC_6X5_SRGB_BLOCK => (astc::<6, 5>, true),
+            vk::Format::ASTC_6X6_UNORM_BLOCK => (astc::<6, 6>, false),
+            vk::Format::ASTC_6X6_SRGB_BLOCK => (astc::<6, 6>, true),
+            vk::Format::ASTC_8X5_UNORM_BLOCK => (astc::<8, 5>, false),
+            vk::Format::ASTC_8X5_SRGB_BLOCK => (astc::<8, 5>, true),
+            vk::Format::ASTC_8X6_UNORM_BLOCK => (astc::<8, 6>, false),
+            vk::Format::ASTC_8X6_SRGB_BLOCK => (astc::<8, 6>, true),
+            vk::Format::ASTC_8X8_UNORM_BLOCK => (astc::<8, 8>, false),
+            vk::Format::ASTC_8X8_SRGB_BLOCK => (astc::<8, 8>, true),
+            vk::Format::ASTC_10X5_UNORM_BLOCK => (astc::<10, 5>, false),
+            vk::Format::ASTC_10X5_SRGB_BLOCK => (astc::<10, 5>, true),
+            vk::Format::ASTC_10X6_UNORM_BLOCK => (astc::<10, 6>, false),
+            vk::Format::ASTC_10X6_SRGB_BLOCK => (astc::<10, 6>, true),
+            vk::Format::ASTC_10X8_UNORM_BLOCK => (astc::<10, 8>, false),
+            vk::Format::ASTC_10X8_SRGB_BLOCK => (astc::<10, 8>, true),
+            vk::Format::ASTC_10X10_UNORM_BLOCK => (astc::<10, 10>, false),
+            vk::Format::ASTC_10X10_SRGB_BLOCK => (astc::<10, 10>, true),
+            vk::Format::ASTC_12X10_UNORM_BLOCK => (astc::<12, 10>, false),
+            vk::Format::ASTC_12X10_SRGB_BLOCK => (astc::<12, 10>, true),
+            vk::Format::ASTC_12X12_UNORM_BLOCK => (astc::<12, 12>, false),
+            vk::Format::ASTC_12X12_SRGB_BLOCK => (astc::<12, 12>, true),
+            // Signed and HDR formats don't fit in RGBA8
+            format => {
+                return Err(anyhow::anyhow!(
+                    "{format:?} can't be sampled by this device, or decoded to RGBA8"
+                ))
+            }
+        };
+
+        let mut levels = std::iter::once(&self.pixels)
+            .chain(&self.mips)
+            .enumerate()
+            .map(|(level, data)| {
+                let width = (self.width >> level).max(1) as usize;
+                let height = (self.height >> level).max(1) as usize;
+                // Single and two channel formats leave the rest alone, so they read as opaque
+                let mut texels = vec![u32::from_le_bytes([0, 0, 0, 255]); width * height];
+                decoder(data, width, height, &mut texels)
+                    .map_err(|err| anyhow::anyhow!("Failed to decode level {level}: {err}"))?;
+                // Texels come out as BGRA
+                Ok(texels
+                    .into_iter()
+                    .flat_map(|texel| {
+                        let [b, g, r, a] = texel.to_le_bytes();
+                        [r, g, b, a]
+                    })
+                    .collect())
+            })
+            .collect::<anyhow::Result<Vec<Vec<u8>>>>()?;
+
+        Ok(ImageData {
+            width: self.width,
+            height: self.height,
+            format: PixelFormat::Rgba8 { srgb },
+            pixels: levels.remove(0),
+            mips: levels,
+        })
+    }
+}
+
+fn astc<const W: usize, const H: usize>(
+    data: &[u8],
+    width: usize,
+    height: usize,
+    image: &mut [u32],
+) -> Result<(), &'static str> {
+    texture2ddecoder::decode_astc(data, width, height, W, H, image)
+}
@@ -0,0 +1,125 @@
+// Camera state and the controllers that move it around
+mod fly;
+mod orbit;
+
+pub use fly::FlyController;
+pub use orbit::OrbitController;
+
+use glam::{Mat4, Vec3};
+
+use crate::input::Input;
+
+pub const DEFAULT_FOV_Y: f32 = 70.0 * std::f32::consts::PI / 180.0;
+
+// Keeps the view from flipping over when looking straight up or down
+const MAX_PITCH: f32 = 89.0 * std::f32::consts::PI / 180.0;
+
+#[derive(Debug, Clone, Copy)]
+pub enum Projection {
+    Perspective {
+        fov_y: f32,
+        near: f32,
+        far: f32,
+    },
+    /// `height` is the world space height of the view volume
+    Orthographic {
+        height: f32,
+        near: f32,
+        far: f32,
+    },
+}
+
+impl Projection {
+    pub fn near_far(&self) -> (f32, f32) {
+        match *self {
+            Self::Perspective { near, far, .. } | Self::Orthographic { near, far, .. } => {
+                (near, far)
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Camera {
+    pub position: Vec3,
+    /// Radians around +Y, zero looks down +X
+    pub yaw: f32,
+    /// Radians above the horizon
+    pub pitch: f32,
+    pub projection: Projection,
+    /// Maps the near plane to depth 1 and the far plane to 0, has to match the depth test
+    pub reverse_z: bool,
+}
+
+impl Camera {
+    pub fn new(reverse_z: bool) -> Self {
+        Self {
+            position: Vec3::new(0.0, 0.0, 3.0),
+            yaw: -std::f32::consts::FRAC_PI_2,
+            pitch: 0.0,
+            projection: Projection::Perspective {
+                fov_y: DEFAULT_FOV_Y,
+                near: 0.01,
+                far: 1000.0,
+            },
+            reverse_z,
+        }
+    }
+
+    pub fn forward(&self) -> Vec3 {
+        let (sin_yaw, cos_yaw) = self.yaw.sin_cos();
+        let (sin_pitch, cos_pitch) = self.pitch.sin_cos();
+        Vec3::new(cos_pitch * cos_yaw, sin_pitch, cos_pitch * sin_yaw)
+    }
+
+    pub fn right(&self) -> Vec3 {
+        self.forward().cross(Vec3::Y).normalize()
+    }
+
+    pub fn look_at(&mut self, eye: Vec3, target: Vec3) {
+        self.position = eye;
+        let dir = (target - eye).normalize_or(Vec3::NEG_Z);
+        self.yaw = dir.z.atan2(dir.x);
+        self.pitch = dir.y.asin().clamp(-MAX_PITCH, MAX_PITCH);
+    }
+
+    pub fn view(&self) -> Mat4 {
+        Mat4::look_to_rh(self.position, self.forward(), Vec3::Y)
+    }
+
+    /// Vulkan clip space: Y points down and depth goes from 0 to 1, or 1 to 0 with reverse-Z
+    pub fn projection(&self, aspect: f32) -> Mat4 {
+        let mut proj = match self.projection {
+            Projection::Perspective { fov_y, near, far } => {
+                let (near, far) = self.depth_range(near, far);
+                Mat4::perspective_rh(fov_y, aspect, near, far)
+            }
+            Projection::Orthographic { height, near, far } => {
+                let (near, far) = self.depth_range(near, far);
+                let (half_w, half_h) = (height * aspect * 0.5, height * 0.5);
+                Mat4::orthographic_rh(-half_w, half_w, -half_h, half_h, near, far)
+            }
+        };
+        proj.y_axis.y *= -1.0;
+        proj
+    }
+
+    fn depth_range(&self, near: f32, far: f32) -> (f32, f32) {
+        if self.reverse_z {
+            (far, near)
+        } else {
+            (near, far)
+        }
+    }
+}
+
+/// Turns input into camera movement, scaled by frame time where it's continuous
+pub trait CameraController {
+    /// Picks up from wherever another controller left the camera
+    fn attach(&mut self, _camera: &Camera) {}
+    fn update(&mut self, camera: &mut Camera, input: &Input, dt: f32);
+    /// Whether the cursor should be hidden and locked in place
+    fn wants_cursor_grab(&self, _input: &Input) -> bool {
+        false
+    }
+}
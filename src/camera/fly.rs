@@ -0,0 +1,50 @@
+use glam::Vec3;
+
+use crate::input::{Action, Axis, Input};
+
+use super::{Camera, CameraController, MAX_PITCH};
+
+/// Moves along the camera's axes and looks around while `Action::Look` is held, scrolling
+/// changes speed
+pub struct FlyController {
+    /// Units per second
+    pub speed: f32,
+    /// Radians per pixel
+    pub sensitivity: f32,
+}
+
+impl Default for FlyController {
+    fn default() -> Self {
+        Self {
+            speed: 2.0,
+            sensitivity: 0.003,
+        }
+    }
+}
+
+impl CameraController for FlyController {
+    fn update(&mut self, camera: &mut Camera, input: &Input, dt: f32) {
+        // Mouse deltas are already per frame, so they aren't scaled by dt
+        if input.action(Action::Look).is_down() {
+            let look = input.mouse_delta() * self.sensitivity;
+            camera.yaw += look.x;
+            camera.pitch = (camera.pitch - look.y).clamp(-MAX_PITCH, MAX_PITCH);
+        }
+
+        self.speed = (self.speed * 1.2f32.powf(input.scroll_delta())).clamp(0.01, 1000.0);
+        let speed = if input.action(Action::Boost).is_down() {
+            self.speed * 4.0
+        } else {
+            self.speed
+        };
+
+        let direction = camera.forward() * input.axis(Axis::Forward)
+            + camera.right() * input.axis(Axis::Right)
+            + Vec3::Y * input.axis(Axis::Up);
+        camera.position += direction.normalize_or_zero() * speed * dt;
+    }
+
+    fn wants_cursor_grab(&self, input: &Input) -> bool {
+        input.action(Action::Look).is_down()
+    }
+}
@@ -0,0 +1,68 @@
+use glam::Vec3;
+
+use crate::input::{Action, Input};
+
+use super::{Camera, CameraController, MAX_PITCH};
+
+/// Circles a target while dragging with `Action::Orbit` held, scrolling zooms in and out
+pub struct OrbitController {
+    pub target: Vec3,
+    pub distance: f32,
+    /// Radians per pixel
+    pub sensitivity: f32,
+    yaw: f32,
+    pitch: f32,
+}
+
+impl Default for OrbitController {
+    fn default() -> Self {
+        Self {
+            target: Vec3::ZERO,
+            distance: 3.0,
+            sensitivity: 0.005,
+            yaw: std::f32::consts::FRAC_PI_2,
+            pitch: 0.0,
+        }
+    }
+}
+
+impl OrbitController {
+    /// Orbits `target` from wherever the camera currently is
+    pub fn focus(&mut self, camera: &Camera, target: Vec3) {
+        self.target = target;
+        let offset = camera.position - target;
+        self.distance = offset.length().max(1e-3);
+        self.yaw = offset.z.atan2(offset.x);
+        self.pitch = (offset.y / self.distance)
+            .asin()
+            .clamp(-MAX_PITCH, MAX_PITCH);
+    }
+}
+
+impl CameraController for OrbitController {
+    // Keeps orbiting whatever is in front of the camera, at the previous distance
+    fn attach(&mut self, camera: &Camera) {
+        let target = camera.position + camera.forward() * self.distance;
+        self.focus(camera, target);
+    }
+
+    fn update(&mut self, camera: &mut Camera, input: &Input, _dt: f32) {
+        if input.action(Action::Orbit).is_down() {
+            let drag = input.mouse_delta() * self.sensitivity;
+            self.yaw += drag.x;
+            self.pitch = (self.pitch + drag.y).clamp(-MAX_PITCH, MAX_PITCH);
+        }
+
+        // Zooming is proportional to the distance so it feels the same at any scale
+        self.distance = (self.distance * 0.9f32.powf(input.scroll_delta())).max(1e-3);
+
+        let (sin_yaw, cos_yaw) = self.yaw.sin_cos();
+        let (sin_pitch, cos_pitch) = self.pitch.sin_cos();
+        let offset = Vec3::new(cos_pitch * cos_yaw, sin_pitch, cos_pitch * sin_yaw) * self.distance;
+        camera.look_at(self.target + offset, self.target);
+    }
+
+    fn wants_cursor_grab(&self, input: &Input) -> bool {
+        input.action(Action::Orbit).is_down()
+    }
+}
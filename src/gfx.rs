@@ -1,7 +1,29 @@
+mod atmosphere;
+mod barrier;
+mod bloom;
+mod buffer;
+mod cluster;
+mod deferred;
+mod depth;
+mod descriptors;
+mod exposure;
+mod ibl;
+mod image;
 mod init;
+mod mesh;
+mod mipmap;
+mod model;
+mod pipeline;
 mod renderer;
+mod shadow;
+mod skybox;
+mod ssao;
 mod surface;
 mod swapchain;
+mod taa;
+mod texture;
+mod tracker;
+mod upload;
 mod util;
 
 pub use renderer::*;
@@ -0,0 +1,244 @@
+// Procedural sky from Hillaire's atmosphere model, lit by the sun. Transmittance and multiple
+// scattering only depend on the atmosphere and are baked once, the sky-view LUT follows the
+// camera and the sun every frame
+use ash::vk;
+use gpu_allocator::vulkan::Allocator;
+
+use super::{
+    barrier::Access,
+    descriptors::{DescriptorAllocator, DescriptorLayoutBuilder, DescriptorWriter, PoolSizeRatio},
+    image::AllocatedImage,
+    init,
+    pipeline::{self, include_shader},
+    tracker::ResourceTracker,
+};
+
+/// Has to match atmosphere.wgsl's storage format
+const FORMAT: vk::Format = vk::Format::R16G16B16A16_SFLOAT;
+const TRANSMITTANCE_SIZE: vk::Extent2D = vk::Extent2D {
+    width: 256,
+    height: 64,
+};
+const MULTI_SCATTERING_SIZE: vk::Extent2D = vk::Extent2D {
+    width: 32,
+    height: 32,
+};
+const SKY_VIEW_SIZE: vk::Extent2D = vk::Extent2D {
+    width: 192,
+    height: 108,
+};
+
+pub struct AtmospherePass {
+    descriptor_allocator: DescriptorAllocator,
+    set_layout: vk::DescriptorSetLayout,
+    /// Writing the transmittance, multiple scattering and sky-view LUTs in turn. The last one
+    /// is also what the sky is drawn with
+    sets: [vk::DescriptorSet; 3],
+    layout: vk::PipelineLayout,
+    transmittance_pipeline: vk::Pipeline,
+    multi_scattering_pipeline: vk::Pipeline,
+    sky_view_pipeline: vk::Pipeline,
+    /// Also read by shading, to filter the sun's light through the atmosphere
+    pub transmittance: AllocatedImage,
+    pub multi_scattering: AllocatedImage,
+    pub sky_view: AllocatedImage,
+}
+
+impl AtmospherePass {
+    pub fn new(
+        device: &ash::Device,
+        allocator: &mut Allocator,
+        scene_set_layout: vk::DescriptorSetLayout,
+    ) -> anyhow::Result<Self> {
+        let descriptor_allocator = DescriptorAllocator::new(
+            device,
+            3,
+            &[
+                PoolSizeRatio {
+                    ty: vk::DescriptorType::SAMPLED_IMAGE,
+                    ratio: 3.0,
+                },
+                PoolSizeRatio {
+                    ty: vk::DescriptorType::STORAGE_IMAGE,
+                    ratio: 1.0,
+                },
+            ],
+        )?;
+        let set_layout = DescriptorLayoutBuilder::default()
+            .add_binding(0, vk::DescriptorType::SAMPLED_IMAGE)
+            .add_binding(1, vk::DescriptorType::SAMPLED_IMAGE)
+            .add_binding(2, vk::DescriptorType::SAMPLED_IMAGE)
+            .add_binding(3, vk::DescriptorType::STORAGE_IMAGE)
+            .build(
+                device,
+                vk::ShaderStageFlags::COMPUTE | vk::ShaderStageFlags::FRAGMENT,
+            )?;
+        let sets = [
+            descriptor_allocator.allocate(device, set_layout)?,
+            descriptor_allocator.allocate(device, set_layout)?,
+            descriptor_allocator.allocate(device, set_layout)?,
+        ];
+
+        let layout =
+            pipeline::create_pipeline_layout(device, &[scene_set_layout, set_layout], &[])?;
+        let module = pipeline::create_shader_module(device, include_shader!("atmosphere"))?;
+        let pipelines = [c"cs_transmittance", c"cs_multi_scattering", c"cs_sky_view"]
+            .map(|entry| pipeline::create_compute_pipeline(device, layout, module, entry));
+        unsafe { device.destroy_shader_module(module, None) };
+        let [transmittance_pipeline, multi_scattering_pipeline, sky_view_pipeline] = pipelines;
+
+        let image = |allocator: &mut Allocator, name, extent: vk::Extent2D| {
+            AllocatedImage::new(
+                device,
+                allocator,
+                name,
+                &init::image_create_info(
+                    FORMAT,
+                    vk::ImageUsageFlags::STORAGE | vk::ImageUsageFlags::SAMPLED,
+                    extent.into(),
+                ),
+                vk::ImageAspectFlags::COLOR,
+            )
+        };
+        let mut transmittance = image(allocator, "transmittance LUT", TRANSMITTANCE_SIZE)?;
+        let mut multi_scattering =
+            match image(allocator, "multiple scattering LUT", MULTI_SCATTERING_SIZE) {
+                Ok(image) => image,
+                Err(err) => {
+                    transmittance.destroy(device, allocator);
+                    return Err(err);
+                }
+            };
+        let sky_view = match image(allocator, "sky-view LUT", SKY_VIEW_SIZE) {
+            Ok(image) => image,
+            Err(err) => {
+                transmittance.destroy(device, allocator);
+                multi_scattering.destroy(device, allocator);
+                return Err(err);
+            }
+        };
+
+        // Each set reads all three, only the ones written before it are ever sampled
+        let views = [transmittance.view, multi_scattering.view, sky_view.view];
+        for (&set, &dst) in sets.iter().zip(&views) {
+            let mut writer = DescriptorWriter::default();
+            for (binding, &view) in (0..).zip(&views) {
+                writer = writer.write_image(
+                    binding,
+                    view,
+                    vk::Sampler::null(),
+                    vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+                    vk::DescriptorType::SAMPLED_IMAGE,
+                );
+            }
+            writer
+                .write_image(
+                    3,
+                    dst,
+                    vk::Sampler::null(),
+                    vk::ImageLayout::GENERAL,
+                    vk::DescriptorType::STORAGE_IMAGE,
+                )
+                .update_set(device, set);
+        }
+
+        Ok(Self {
+            descriptor_allocator,
+            set_layout,
+            sets,
+            layout,
+            transmittance_pipeline: transmittance_pipeline?,
+            multi_scattering_pipeline: multi_scattering_pipeline?,
+            sky_view_pipeline: sky_view_pipeline?,
+            transmittance,
+            multi_scattering,
+            sky_view,
+        })
+    }
+
+    pub fn set_layout(&self) -> vk::DescriptorSetLayout {
+        self.set_layout
+    }
+
+    /// Reads the sky-view and transmittance LUTs
+    pub fn sky_set(&self) -> vk::DescriptorSet {
+        self.sets[2]
+    }
+
+    pub fn track(&self, tracker: &mut ResourceTracker) {
+        for image in [&self.transmittance, &self.multi_scattering, &self.sky_view] {
+            tracker.register_image(image.image, FORMAT, 1, 1, Access::Undefined);
+        }
+    }
+
+    /// Expects the transmittance LUT ready for compute writes
+    pub fn dispatch_transmittance(
+        &self,
+        device: &ash::Device,
+        cmd: vk::CommandBuffer,
+        scene_set: vk::DescriptorSet,
+    ) {
+        let pipeline = self.transmittance_pipeline;
+        self.dispatch(device, cmd, pipeline, 0, scene_set, TRANSMITTANCE_SIZE);
+    }
+
+    /// Expects the transmittance LUT sampled and the multiple scattering one ready for compute
+    /// writes
+    pub fn dispatch_multi_scattering(
+        &self,
+        device: &ash::Device,
+        cmd: vk::CommandBuffer,
+        scene_set: vk::DescriptorSet,
+    ) {
+        let pipeline = self.multi_scattering_pipeline;
+        self.dispatch(device, cmd, pipeline, 1, scene_set, MULTI_SCATTERING_SIZE);
+    }
+
+    /// Expects the other two LUTs sampled and the sky-view one ready for compute writes
+    pub fn dispatch_sky_view(
+        &self,
+        device: &ash::Device,
+        cmd: vk::CommandBuffer,
+        scene_set: vk::DescriptorSet,
+    ) {
+        let pipeline = self.sky_view_pipeline;
+        self.dispatch(device, cmd, pipeline, 2, scene_set, SKY_VIEW_SIZE);
+    }
+
+    fn dispatch(
+        &self,
+        device: &ash::Device,
+        cmd: vk::CommandBuffer,
+        pipeline: vk::Pipeline,
+        set: usize,
+        scene_set: vk::DescriptorSet,
+        extent: vk::Extent2D,
+    ) {
+        unsafe {
+            device.cmd_bind_pipeline(cmd, vk::PipelineBindPoint::COMPUTE, pipeline);
+            device.cmd_bind_descriptor_sets(
+                cmd,
+                vk::PipelineBindPoint::COMPUTE,
+                self.layout,
+                0,
+                &[scene_set, self.sets[set]],
+                &[],
+            );
+            device.cmd_dispatch(cmd, extent.width.div_ceil(8), extent.height.div_ceil(8), 1);
+        }
+    }
+
+    pub fn destroy(&mut self, device: &ash::Device, allocator: &mut Allocator) {
+        unsafe {
+            device.destroy_pipeline(self.transmittance_pipeline, None);
+            device.destroy_pipeline(self.multi_scattering_pipeline, None);
+            device.destroy_pipeline(self.sky_view_pipeline, None);
+            device.destroy_pipeline_layout(self.layout, None);
+            device.destroy_descriptor_set_layout(self.set_layout, None);
+        }
+        self.descriptor_allocator.destroy(device);
+        self.transmittance.destroy(device, allocator);
+        self.multi_scattering.destroy(device, allocator);
+        self.sky_view.destroy(device, allocator);
+    }
+}
@@ -0,0 +1,741 @@
+use ash::vk;
+
+/// How a resource is used by a command, from which the stage, access mask and (for images)
+/// layout of a barrier are derived
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Access {
+    /// Contents don't matter, transitions from this discard the image
+    Undefined,
+    TransferSrc,
+    TransferDst,
+    HostRead,
+    ComputeRead,
+    ComputeWrite,
+    ComputeReadWrite,
+    ComputeSampled,
+    FragmentSampled,
+    FragmentStorageRead,
+    UniformRead,
+    VertexBuffer,
+    IndexBuffer,
+    ColorAttachmentWrite,
+    DepthAttachmentWrite,
+    /// Depth tested against and sampled in the same pass, e.g. deferred lighting
+    DepthAttachmentReadSampled,
+    /// The target of a depth resolve, which happens in the colour output stage as a colour
+    /// attachment write
+    DepthResolveWrite,
+    Present,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct AccessInfo {
+    pub stage: vk::PipelineStageFlags2,
+    pub access: vk::AccessFlags2,
+    pub layout: vk::ImageLayout,
+}
+
+impl AccessInfo {
+    pub fn scope(&self) -> Scope {
+        Scope {
+            stage: self.stage,
+            access: self.access,
+        }
+    }
+}
+
+/// One side of a dependency: the stages waited on or made to wait, and the accesses in them
+/// whose writes are made available or visible
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Scope {
+    pub stage: vk::PipelineStageFlags2,
+    pub access: vk::AccessFlags2,
+}
+
+impl Scope {
+    pub fn contains(self, other: Self) -> bool {
+        self.stage.contains(other.stage) && self.access.contains(other.access)
+    }
+
+    /// Only the stages, for waiting on accesses that didn't write anything
+    pub fn execution_only(self) -> Self {
+        Self {
+            stage: self.stage,
+            access: vk::AccessFlags2::NONE,
+        }
+    }
+}
+
+impl std::ops::BitOr for Scope {
+    type Output = Self;
+
+    fn bitor(self, rhs: Self) -> Self {
+        Self {
+            stage: self.stage | rhs.stage,
+            access: self.access | rhs.access,
+        }
+    }
+}
+
+impl Access {
+    pub fn is_write(self) -> bool {
+        matches!(
+            self,
+            Self::TransferDst
+                | Self::ComputeWrite
+                | Self::ComputeReadWrite
+                | Self::ColorAttachmentWrite
+                | Self::DepthAttachmentWrite
+                | Self::DepthResolveWrite
+        )
+    }
+
+    /// `aspect_mask` picks between depth-only and depth-stencil layouts
+    pub fn info(self, aspect_mask: vk::ImageAspectFlags) -> AccessInfo {
+        type Stage = vk::PipelineStageFlags2;
+        type Flags = vk::AccessFlags2;
+        type Layout = vk::ImageLayout;
+
+        let stencil = aspect_mask.contains(vk::ImageAspectFlags::STENCIL);
+        let depth = aspect_mask.contains(vk::ImageAspectFlags::DEPTH);
+        let read_only_layout = match (depth, stencil) {
+            (true, true) => Layout::DEPTH_STENCIL_READ_ONLY_OPTIMAL,
+            (true, false) => Layout::DEPTH_READ_ONLY_OPTIMAL,
+            _ => Layout::SHADER_READ_ONLY_OPTIMAL,
+        };
+        let fragment_tests = Stage::EARLY_FRAGMENT_TESTS | Stage::LATE_FRAGMENT_TESTS;
+        let attachment_layout = if stencil {
+            Layout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL
+        } else {
+            Layout::DEPTH_ATTACHMENT_OPTIMAL
+        };
+
+        let (stage, access, layout) = match self {
+            Self::Undefined => (Stage::NONE, Flags::NONE, Layout::UNDEFINED),
+            Self::TransferSrc => (
+                Stage::ALL_TRANSFER,
+                Flags::TRANSFER_READ,
+                Layout::TRANSFER_SRC_OPTIMAL,
+            ),
+            Self::TransferDst => (
+                Stage::ALL_TRANSFER,
+                Flags::TRANSFER_WRITE,
+                Layout::TRANSFER_DST_OPTIMAL,
+            ),
+            Self::HostRead => (Stage::HOST, Flags::HOST_READ, Layout::GENERAL),
+            Self::ComputeRead => (
+                Stage::COMPUTE_SHADER,
+                Flags::SHADER_STORAGE_READ,
+                Layout::GENERAL,
+            ),
+            Self::ComputeWrite => (
+                Stage::COMPUTE_SHADER,
+                Flags::SHADER_STORAGE_WRITE,
+                Layout::GENERAL,
+            ),
+            Self::ComputeReadWrite => (
+                Stage::COMPUTE_SHADER,
+                Flags::SHADER_STORAGE_READ | Flags::SHADER_STORAGE_WRITE,
+                Layout::GENERAL,
+            ),
+            Self::ComputeSampled => (
+                Stage::COMPUTE_SHADER,
+                Flags::SHADER_SAMPLED_READ,
+                read_only_layout,
+            ),
+            Self::FragmentSampled => (
+                Stage::FRAGMENT_SHADER,
+                Flags::SHADER_SAMPLED_READ,
+                read_only_layout,
+            ),
+            Self::FragmentStorageRead => (
+                Stage::FRAGMENT_SHADER,
+                Flags::SHADER_STORAGE_READ,
+                Layout::GENERAL,
+            ),
+            Self::UniformRead => (
+                Stage::VERTEX_SHADER | Stage::FRAGMENT_SHADER | Stage::COMPUTE_SHADER,
+                Flags::UNIFORM_READ,
+                Layout::UNDEFINED,
+            ),
+            Self::VertexBuffer => (
+                Stage::VERTEX_ATTRIBUTE_INPUT,
+                Flags::VERTEX_ATTRIBUTE_READ,
+                Layout::UNDEFINED,
+            ),
+            Self::IndexBuffer => (Stage::INDEX_INPUT, Flags::INDEX_READ, Layout::UNDEFINED),
+            // Attachments are loaded as well as stored
+            Self::ColorAttachmentWrite => (
+                Stage::COLOR_ATTACHMENT_OUTPUT,
+                Flags::COLOR_ATTACHMENT_READ | Flags::COLOR_ATTACHMENT_WRITE,
+                Layout::COLOR_ATTACHMENT_OPTIMAL,
+            ),
+            Self::DepthAttachmentWrite => (
+                fragment_tests,
+                Flags::DEPTH_STENCIL_ATTACHMENT_READ | Flags::DEPTH_STENCIL_ATTACHMENT_WRITE,
+                attachment_layout,
+            ),
+            Self::DepthAttachmentReadSampled => (
+                fragment_tests | Stage::FRAGMENT_SHADER,
+                Flags::DEPTH_STENCIL_ATTACHMENT_READ | Flags::SHADER_SAMPLED_READ,
+                read_only_layout,
+            ),
+            Self::DepthResolveWrite => (
+                Stage::COLOR_ATTACHMENT_OUTPUT,
+                Flags::COLOR_ATTACHMENT_WRITE,
+                attachment_layout,
+            ),
+            // Matches the stage the acquire semaphore is waited on, so the transition after
+            // acquiring chains with it
+            Self::Present => (
+                Stage::COLOR_ATTACHMENT_OUTPUT,
+                Flags::NONE,
+                Layout::PRESENT_SRC_KHR,
+            ),
+        };
+
+        AccessInfo {
+            stage,
+            access,
+            layout,
+        }
+    }
+}
+
+pub fn aspect_mask_for_format(format: vk::Format) -> vk::ImageAspectFlags {
+    match format {
+        vk::Format::D16_UNORM | vk::Format::D32_SFLOAT | vk::Format::X8_D24_UNORM_PACK32 => {
+            vk::ImageAspectFlags::DEPTH
+        }
+        vk::Format::D16_UNORM_S8_UINT
+        | vk::Format::D24_UNORM_S8_UINT
+        | vk::Format::D32_SFLOAT_S8_UINT => {
+            vk::ImageAspectFlags::DEPTH | vk::ImageAspectFlags::STENCIL
+        }
+        vk::Format::S8_UINT => vk::ImageAspectFlags::STENCIL,
+        _ => vk::ImageAspectFlags::COLOR,
+    }
+}
+
+/// Mips and array layers a barrier covers
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ImageRange {
+    pub base_mip: u32,
+    pub mip_count: u32,
+    pub base_layer: u32,
+    pub layer_count: u32,
+}
+
+impl ImageRange {
+    pub const ALL: Self = Self {
+        base_mip: 0,
+        mip_count: vk::REMAINING_MIP_LEVELS,
+        base_layer: 0,
+        layer_count: vk::REMAINING_ARRAY_LAYERS,
+    };
+
+    pub fn subresource_range(
+        &self,
+        aspect_mask: vk::ImageAspectFlags,
+    ) -> vk::ImageSubresourceRange {
+        vk::ImageSubresourceRange::default()
+            .aspect_mask(aspect_mask)
+            .base_mip_level(self.base_mip)
+            .level_count(self.mip_count)
+            .base_array_layer(self.base_layer)
+            .layer_count(self.layer_count)
+    }
+}
+
+pub struct ImageBarrier {
+    pub image: vk::Image,
+    pub format: vk::Format,
+    pub range: ImageRange,
+    pub prev: Access,
+    pub next: Access,
+    pub discard: bool,
+}
+
+impl ImageBarrier {
+    pub fn new(image: vk::Image, format: vk::Format, prev: Access, next: Access) -> Self {
+        Self {
+            image,
+            format,
+            range: ImageRange::ALL,
+            prev,
+            next,
+            discard: prev == Access::Undefined,
+        }
+    }
+
+    pub fn range(mut self, range: ImageRange) -> Self {
+        self.range = range;
+        self
+    }
+
+    /// Transition from UNDEFINED, the previous contents are thrown away
+    pub fn discard(mut self) -> Self {
+        self.discard = true;
+        self
+    }
+}
+
+/// Batches barriers so that they can all be recorded with a single `cmd_pipeline_barrier2`
+#[derive(Default)]
+pub struct Barriers {
+    buffers: Vec<vk::BufferMemoryBarrier2<'static>>,
+    images: Vec<vk::ImageMemoryBarrier2<'static>>,
+}
+
+impl Barriers {
+    pub fn image(&mut self, barrier: ImageBarrier) -> &mut Self {
+        let aspect_mask = aspect_mask_for_format(barrier.format);
+        let prev = barrier.prev.info(aspect_mask);
+        let next = barrier.next.info(aspect_mask);
+        let src = if barrier.prev.is_write() {
+            prev.scope()
+        } else {
+            prev.scope().execution_only()
+        };
+        let old_layout = if barrier.discard {
+            vk::ImageLayout::UNDEFINED
+        } else {
+            prev.layout
+        };
+
+        self.image_dependency(
+            barrier.image,
+            aspect_mask,
+            barrier.range,
+            src,
+            next.scope(),
+            old_layout,
+            next.layout,
+        )
+    }
+
+    /// Queues a barrier over `range` of `image` from `src` to `dst`. One already queued over
+    /// the same subresources and ending in the same layout is widened instead, since both
+    /// accesses follow this batch anyway, and one over neighbouring subresources with the same
+    /// scopes and layouts grows to cover these as well.
+    #[allow(clippy::too_many_arguments)]
+    pub fn image_dependency(
+        &mut self,
+        image: vk::Image,
+        aspect_mask: vk::ImageAspectFlags,
+        range: ImageRange,
+        src: Scope,
+        dst: Scope,
+        old_layout: vk::ImageLayout,
+        new_layout: vk::ImageLayout,
+    ) -> &mut Self {
+        let subresources = range.subresource_range(aspect_mask);
+        for queued in self
+            .images
+            .iter_mut()
+            .filter(|queued| queued.image == image)
+        {
+            if same_range(&queued.subresource_range, &subresources)
+                && queued.new_layout == new_layout
+            {
+                queued.src_stage_mask |= src.stage;
+                queued.src_access_mask |= src.access;
+                queued.dst_stage_mask |= dst.stage;
+                queued.dst_access_mask |= dst.access;
+                return self;
+            }
+
+            let same_barrier = queued.src_stage_mask == src.stage
+                && queued.src_access_mask == src.access
+                && queued.dst_stage_mask == dst.stage
+                && queued.dst_access_mask == dst.access
+                && queued.old_layout == old_layout
+                && queued.new_layout == new_layout;
+            if same_barrier {
+                if let Some(merged) = merge_adjacent(&queued.subresource_range, &subresources) {
+                    queued.subresource_range = merged;
+                    return self;
+                }
+            }
+        }
+
+        self.images.push(
+            vk::ImageMemoryBarrier2::default()
+                .src_stage_mask(src.stage)
+                .src_access_mask(src.access)
+                .dst_stage_mask(dst.stage)
+                .dst_access_mask(dst.access)
+                .old_layout(old_layout)
+                .new_layout(new_layout)
+                .subresource_range(subresources)
+                .image(image),
+        );
+        self
+    }
+
+    pub fn buffer(
+        &mut self,
+        buffer: vk::Buffer,
+        offset: vk::DeviceSize,
+        size: vk::DeviceSize,
+        prev: Access,
+        next: Access,
+    ) -> &mut Self {
+        let prev_info = prev.info(vk::ImageAspectFlags::empty());
+        let next_info = next.info(vk::ImageAspectFlags::empty());
+        self.buffers.push(
+            vk::BufferMemoryBarrier2::default()
+                .src_stage_mask(prev_info.stage)
+                .src_access_mask(if prev.is_write() {
+                    prev_info.access
+                } else {
+                    vk::AccessFlags2::NONE
+                })
+                .dst_stage_mask(next_info.stage)
+                .dst_access_mask(next_info.access)
+                .buffer(buffer)
+                .offset(offset)
+                .size(size),
+        );
+        self
+    }
+
+    /// Queues a barrier over all of `buffer` from `src` to `dst`, widening one already queued
+    /// for the whole of it
+    pub fn buffer_dependency(&mut self, buffer: vk::Buffer, src: Scope, dst: Scope) -> &mut Self {
+        let queued = self.buffers.iter_mut().find(|queued| {
+            queued.buffer == buffer && queued.offset == 0 && queued.size == vk::WHOLE_SIZE
+        });
+        if let Some(queued) = queued {
+            queued.src_stage_mask |= src.stage;
+            queued.src_access_mask |= src.access;
+            queued.dst_stage_mask |= dst.stage;
+            queued.dst_access_mask |= dst.access;
+            return self;
+        }
+
+        self.buffers.push(
+            vk::BufferMemoryBarrier2::default()
+                .src_stage_mask(src.stage)
+                .src_access_mask(src.access)
+                .dst_stage_mask(dst.stage)
+                .dst_access_mask(dst.access)
+                .buffer(buffer)
+                .offset(0)
+                .size(vk::WHOLE_SIZE),
+        );
+        self
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.buffers.is_empty() && self.images.is_empty()
+    }
+
+    #[cfg(test)]
+    pub fn queued_images(&self) -> &[vk::ImageMemoryBarrier2<'static>] {
+        &self.images
+    }
+
+    #[cfg(test)]
+    pub fn queued_buffers(&self) -> &[vk::BufferMemoryBarrier2<'static>] {
+        &self.buffers
+    }
+
+    pub fn flush(&mut self, device: &ash::Device, cmd: vk::CommandBuffer) {
+        if self.is_empty() {
+            return;
+        }
+
+        let dep_info = vk::DependencyInfo::default()
+            .buffer_memory_barriers(&self.buffers)
+            .image_memory_barriers(&self.images);
+        unsafe { device.cmd_pipeline_barrier2(cmd, &dep_info) };
+
+        self.buffers.clear();
+        self.images.clear();
+    }
+}
+
+fn same_range(a: &vk::ImageSubresourceRange, b: &vk::ImageSubresourceRange) -> bool {
+    (
+        a.aspect_mask,
+        a.base_mip_level,
+        a.level_count,
+        a.base_array_layer,
+        a.layer_count,
+    ) == (
+        b.aspect_mask,
+        b.base_mip_level,
+        b.level_count,
+        b.base_array_layer,
+        b.layer_count,
+    )
+}
+
+// The range covering both when one continues the other along its mips or its layers
+fn merge_adjacent(
+    a: &vk::ImageSubresourceRange,
+    b: &vk::ImageSubresourceRange,
+) -> Option<vk::ImageSubresourceRange> {
+    // Both REMAINING_ values are the same
+    let remaining = [a.level_count, a.layer_count, b.level_count, b.layer_count]
+        .contains(&vk::REMAINING_MIP_LEVELS);
+    if remaining || a.aspect_mask != b.aspect_mask {
+        return None;
+    }
+
+    let same_mips = a.base_mip_level == b.base_mip_level && a.level_count == b.level_count;
+    let same_layers = a.base_array_layer == b.base_array_layer && a.layer_count == b.layer_count;
+    let (first, second) =
+        if a.base_mip_level <= b.base_mip_level && a.base_array_layer <= b.base_array_layer {
+            (a, b)
+        } else {
+            (b, a)
+        };
+
+    if same_mips && first.base_array_layer + first.layer_count == second.base_array_layer {
+        Some(vk::ImageSubresourceRange {
+            layer_count: first.layer_count + second.layer_count,
+            ..*first
+        })
+    } else if same_layers && first.base_mip_level + first.level_count == second.base_mip_level {
+        Some(vk::ImageSubresourceRange {
+            level_count: first.level_count + second.level_count,
+            ..*first
+        })
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ash::vk::Handle;
+
+    use super::*;
+
+    const COLOR: vk::Format = vk::Format::R8G8B8A8_UNORM;
+
+    fn image(raw: u64) -> vk::Image {
+        vk::Image::from_raw(raw)
+    }
+
+    fn range(base_mip: u32, mip_count: u32, base_layer: u32, layer_count: u32) -> ImageRange {
+        ImageRange {
+            base_mip,
+            mip_count,
+            base_layer,
+            layer_count,
+        }
+    }
+
+    fn scope(access: Access) -> Scope {
+        access.info(vk::ImageAspectFlags::COLOR).scope()
+    }
+
+    // A compute write read by a fragment shader, the same one every test queues
+    fn queue(barriers: &mut Barriers, image: vk::Image, range: ImageRange) {
+        barriers.image_dependency(
+            image,
+            vk::ImageAspectFlags::COLOR,
+            range,
+            scope(Access::ComputeWrite),
+            scope(Access::FragmentSampled),
+            vk::ImageLayout::GENERAL,
+            vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+        );
+    }
+
+    #[test]
+    fn write_is_made_available() {
+        let mut barriers = Barriers::default();
+        barriers.image(ImageBarrier::new(
+            image(1),
+            COLOR,
+            Access::ComputeWrite,
+            Access::FragmentSampled,
+        ));
+
+        let [queued] = barriers.queued_images() else {
+            panic!("expected one barrier");
+        };
+        assert_eq!(
+            queued.src_stage_mask,
+            vk::PipelineStageFlags2::COMPUTE_SHADER
+        );
+        assert_eq!(
+            queued.src_access_mask,
+            vk::AccessFlags2::SHADER_STORAGE_WRITE
+        );
+        assert_eq!(
+            queued.dst_access_mask,
+            vk::AccessFlags2::SHADER_SAMPLED_READ
+        );
+        assert_eq!(queued.old_layout, vk::ImageLayout::GENERAL);
+        assert_eq!(queued.new_layout, vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL);
+    }
+
+    #[test]
+    fn read_only_waits_for_execution() {
+        let mut barriers = Barriers::default();
+        barriers.image(ImageBarrier::new(
+            image(1),
+            COLOR,
+            Access::ComputeSampled,
+            Access::ComputeWrite,
+        ));
+
+        let [queued] = barriers.queued_images() else {
+            panic!("expected one barrier");
+        };
+        assert_eq!(
+            queued.src_stage_mask,
+            vk::PipelineStageFlags2::COMPUTE_SHADER
+        );
+        assert_eq!(queued.src_access_mask, vk::AccessFlags2::NONE);
+    }
+
+    #[test]
+    fn discard_transitions_from_undefined() {
+        let mut barriers = Barriers::default();
+        barriers.image(
+            ImageBarrier::new(
+                image(1),
+                COLOR,
+                Access::FragmentSampled,
+                Access::ColorAttachmentWrite,
+            )
+            .discard(),
+        );
+
+        assert_eq!(
+            barriers.queued_images()[0].old_layout,
+            vk::ImageLayout::UNDEFINED
+        );
+    }
+
+    #[test]
+    fn same_range_and_layout_widens() {
+        let mut barriers = Barriers::default();
+        queue(&mut barriers, image(1), range(0, 1, 0, 1));
+        barriers.image_dependency(
+            image(1),
+            vk::ImageAspectFlags::COLOR,
+            range(0, 1, 0, 1),
+            scope(Access::ComputeWrite),
+            scope(Access::ComputeSampled),
+            vk::ImageLayout::GENERAL,
+            vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+        );
+
+        let [queued] = barriers.queued_images() else {
+            panic!("expected the barriers to merge");
+        };
+        assert_eq!(
+            queued.dst_stage_mask,
+            vk::PipelineStageFlags2::FRAGMENT_SHADER | vk::PipelineStageFlags2::COMPUTE_SHADER
+        );
+    }
+
+    #[test]
+    fn neighbouring_layers_and_mips_merge() {
+        let mut barriers = Barriers::default();
+        queue(&mut barriers, image(1), range(0, 2, 1, 1));
+        queue(&mut barriers, image(1), range(0, 2, 0, 1));
+        queue(&mut barriers, image(2), range(0, 1, 0, 3));
+        queue(&mut barriers, image(2), range(1, 3, 0, 3));
+
+        let ranges: Vec<_> = barriers
+            .queued_images()
+            .iter()
+            .map(|queued| {
+                let range = queued.subresource_range;
+                (
+                    range.base_mip_level,
+                    range.level_count,
+                    range.base_array_layer,
+                    range.layer_count,
+                )
+            })
+            .collect();
+        assert_eq!(ranges, [(0, 2, 0, 2), (0, 4, 0, 3)]);
+    }
+
+    #[test]
+    fn separate_ranges_stay_separate() {
+        let mut barriers = Barriers::default();
+        // A gap between the layers
+        queue(&mut barriers, image(1), range(0, 1, 0, 1));
+        queue(&mut barriers, image(1), range(0, 1, 2, 1));
+        // Neighbouring, but a different image
+        queue(&mut barriers, image(2), range(0, 1, 1, 1));
+        // Neighbouring, but not the same mips
+        queue(&mut barriers, image(1), range(0, 2, 1, 1));
+        // Neighbouring, but from another layout
+        barriers.image_dependency(
+            image(1),
+            vk::ImageAspectFlags::COLOR,
+            range(1, 1, 0, 1),
+            scope(Access::ComputeWrite),
+            scope(Access::FragmentSampled),
+            vk::ImageLayout::UNDEFINED,
+            vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+        );
+
+        assert_eq!(barriers.queued_images().len(), 5);
+    }
+
+    #[test]
+    fn remaining_ranges_never_merge() {
+        let all = ImageRange::ALL.subresource_range(vk::ImageAspectFlags::COLOR);
+        let first = range(0, 1, 0, 1).subresource_range(vk::ImageAspectFlags::COLOR);
+        assert!(merge_adjacent(&all, &first).is_none());
+        assert!(merge_adjacent(&first, &all).is_none());
+    }
+
+    #[test]
+    fn merge_is_order_independent() {
+        let aspect_mask = vk::ImageAspectFlags::COLOR;
+        let a = range(2, 3, 0, 1).subresource_range(aspect_mask);
+        let b = range(0, 2, 0, 1).subresource_range(aspect_mask);
+        let merged = merge_adjacent(&a, &b).expect("mips 0-1 continue into 2-4");
+        assert!(same_range(
+            &merged,
+            &range(0, 5, 0, 1).subresource_range(aspect_mask)
+        ));
+        assert!(same_range(&merged, &merge_adjacent(&b, &a).unwrap()));
+    }
+
+    #[test]
+    fn whole_buffer_barriers_widen() {
+        let buffer = vk::Buffer::from_raw(1);
+        let mut barriers = Barriers::default();
+        barriers
+            .buffer_dependency(
+                buffer,
+                scope(Access::ComputeWrite),
+                scope(Access::UniformRead),
+            )
+            .buffer_dependency(
+                buffer,
+                scope(Access::TransferDst),
+                scope(Access::IndexBuffer),
+            )
+            .buffer_dependency(
+                vk::Buffer::from_raw(2),
+                scope(Access::ComputeWrite),
+                scope(Access::UniformRead),
+            );
+
+        let [first, second] = barriers.queued_buffers() else {
+            panic!("expected one barrier per buffer");
+        };
+        assert_eq!(first.buffer, buffer);
+        assert_eq!(
+            first.src_access_mask,
+            vk::AccessFlags2::SHADER_STORAGE_WRITE | vk::AccessFlags2::TRANSFER_WRITE
+        );
+        assert!(first.dst_access_mask.contains(vk::AccessFlags2::INDEX_READ));
+        assert_eq!(second.buffer, vk::Buffer::from_raw(2));
+    }
+}
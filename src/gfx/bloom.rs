@@ -0,0 +1,351 @@
+// Physically based bloom: the draw image is filtered down a chain of mips and back up again,
+// leaving a wide blur in the first mip for the present pass to blend over the scene
+use ash::vk;
+use gpu_allocator::vulkan::Allocator;
+
+use super::{
+    barrier::Access,
+    descriptors::{DescriptorAllocator, DescriptorLayoutBuilder, DescriptorWriter, PoolSizeRatio},
+    image::AllocatedImage,
+    init,
+    pipeline::{self, include_shader, PipelineBuilder},
+    tracker::ResourceTracker,
+    util,
+};
+
+const FORMAT: vk::Format = vk::Format::R16G16B16A16_SFLOAT;
+// Fewer on small windows, the last mip is still a few pixels across
+const MAX_MIPS: u32 = 6;
+
+#[derive(Debug, Clone, Copy)]
+pub struct BloomSettings {
+    pub enabled: bool,
+    /// Brightness pixels have to reach to bloom, 0 lets everything through
+    pub threshold: f32,
+    /// How much of the blurred image is blended over the scene
+    pub intensity: f32,
+    /// Upsampling filter radius, as a fraction of the screen's width
+    pub radius: f32,
+}
+
+impl Default for BloomSettings {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            threshold: 0.0,
+            intensity: 0.04,
+            radius: 0.005,
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy, bytemuck::Pod, bytemuck::Zeroable)]
+struct BloomPushConstants {
+    threshold: f32,
+    radius: f32,
+    prefilter: u32,
+}
+
+// Sized to half the swapchain, recreated with it
+pub struct BloomImages {
+    image: AllocatedImage,
+    mip_views: Vec<vk::ImageView>,
+    extents: Vec<vk::Extent2D>,
+}
+
+impl BloomImages {
+    pub fn new(
+        device: &ash::Device,
+        allocator: &mut Allocator,
+        extent: vk::Extent2D,
+    ) -> anyhow::Result<Self> {
+        let base = vk::Extent2D {
+            width: (extent.width / 2).max(1),
+            height: (extent.height / 2).max(1),
+        };
+        let mip_levels = MAX_MIPS.min(base.width.min(base.height).max(1).ilog2() + 1);
+        let mut image = AllocatedImage::new(
+            device,
+            allocator,
+            "bloom mips",
+            &init::image_create_info(
+                FORMAT,
+                vk::ImageUsageFlags::COLOR_ATTACHMENT | vk::ImageUsageFlags::SAMPLED,
+                base.into(),
+            )
+            .mip_levels(mip_levels),
+            vk::ImageAspectFlags::COLOR,
+        )?;
+
+        let mut mip_views = Vec::with_capacity(mip_levels as usize);
+        for level in 0..mip_levels {
+            let mut info =
+                init::imageview_create_info(FORMAT, image.image, vk::ImageAspectFlags::COLOR);
+            info.subresource_range.base_mip_level = level;
+            match unsafe { device.create_image_view(&info, None) } {
+                Ok(view) => mip_views.push(view),
+                Err(err) => {
+                    for view in mip_views {
+                        unsafe { device.destroy_image_view(view, None) };
+                    }
+                    image.destroy(device, allocator);
+                    return Err(err.into());
+                }
+            }
+        }
+        let extents = (0..mip_levels)
+            .map(|level| vk::Extent2D {
+                width: (base.width >> level).max(1),
+                height: (base.height >> level).max(1),
+            })
+            .collect();
+
+        Ok(Self {
+            image,
+            mip_views,
+            extents,
+        })
+    }
+
+    pub fn image(&self) -> vk::Image {
+        self.image.image
+    }
+
+    pub fn mip_count(&self) -> u32 {
+        self.mip_views.len() as u32
+    }
+
+    /// The finished bloom, once every upsample has run
+    pub fn result_view(&self) -> vk::ImageView {
+        self.mip_views[0]
+    }
+
+    pub fn track(&self, tracker: &mut ResourceTracker) {
+        tracker.register_image(
+            self.image.image,
+            FORMAT,
+            self.mip_count(),
+            1,
+            Access::Undefined,
+        );
+    }
+
+    pub fn untrack(&self, tracker: &mut ResourceTracker) {
+        tracker.forget_image(self.image.image);
+    }
+
+    pub fn destroy(&mut self, device: &ash::Device, allocator: &mut Allocator) {
+        for &view in &self.mip_views {
+            unsafe { device.destroy_image_view(view, None) };
+        }
+        self.image.destroy(device, allocator);
+    }
+}
+
+pub struct BloomPass {
+    // Owns its own pool, it needs a set per mip
+    descriptor_allocator: DescriptorAllocator,
+    set_layout: vk::DescriptorSetLayout,
+    /// Reading the draw image, then each mip in turn
+    sets: Vec<vk::DescriptorSet>,
+    sampler: vk::Sampler,
+    layout: vk::PipelineLayout,
+    downsample_pipeline: vk::Pipeline,
+    upsample_pipeline: vk::Pipeline,
+}
+
+impl BloomPass {
+    pub fn new(device: &ash::Device) -> anyhow::Result<Self> {
+        let set_count = MAX_MIPS + 1;
+        let descriptor_allocator = DescriptorAllocator::new(
+            device,
+            set_count,
+            &[
+                PoolSizeRatio {
+                    ty: vk::DescriptorType::SAMPLED_IMAGE,
+                    ratio: 1.0,
+                },
+                PoolSizeRatio {
+                    ty: vk::DescriptorType::SAMPLER,
+                    ratio: 1.0,
+                },
+            ],
+        )?;
+        let set_layout = DescriptorLayoutBuilder::default()
+            .add_binding(0, vk::DescriptorType::SAMPLED_IMAGE)
+            .add_binding(1, vk::DescriptorType::SAMPLER)
+            .build(device, vk::ShaderStageFlags::FRAGMENT)?;
+        let sets = (0..set_count)
+            .map(|_| descriptor_allocator.allocate(device, set_layout))
+            .collect::<anyhow::Result<Vec<_>>>()?;
+
+        let sampler_info = vk::SamplerCreateInfo::default()
+            .mag_filter(vk::Filter::LINEAR)
+            .min_filter(vk::Filter::LINEAR)
+            .address_mode_u(vk::SamplerAddressMode::CLAMP_TO_EDGE)
+            .address_mode_v(vk::SamplerAddressMode::CLAMP_TO_EDGE)
+            .address_mode_w(vk::SamplerAddressMode::CLAMP_TO_EDGE);
+        let sampler = unsafe { device.create_sampler(&sampler_info, None) }?;
+
+        let layout = pipeline::create_pipeline_layout(
+            device,
+            &[set_layout],
+            &[pipeline::push_constant_range::<BloomPushConstants>(
+                vk::ShaderStageFlags::FRAGMENT,
+            )],
+        )?;
+        let module = pipeline::create_shader_module(device, include_shader!("bloom"))?;
+        let builder = PipelineBuilder::default()
+            .layout(layout)
+            .color_attachment_format(FORMAT);
+        let downsample_pipeline = builder
+            .clone()
+            .shaders(module, c"vs_main", module, c"fs_downsample")
+            .build(device);
+        let upsample_pipeline = builder
+            .shaders(module, c"vs_main", module, c"fs_upsample")
+            .additive_blending()
+            .build(device);
+        unsafe { device.destroy_shader_module(module, None) };
+
+        Ok(Self {
+            descriptor_allocator,
+            set_layout,
+            sets,
+            sampler,
+            layout,
+            downsample_pipeline: downsample_pipeline?,
+            upsample_pipeline: upsample_pipeline?,
+        })
+    }
+
+    /// Has to be called again whenever the images are recreated
+    pub fn write_sources(&self, device: &ash::Device, images: &BloomImages, draw: vk::ImageView) {
+        let sources = std::iter::once(draw).chain(images.mip_views.iter().copied());
+        for (&set, view) in self.sets.iter().zip(sources) {
+            DescriptorWriter::default()
+                .write_image(
+                    0,
+                    view,
+                    vk::Sampler::null(),
+                    vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+                    vk::DescriptorType::SAMPLED_IMAGE,
+                )
+                .write_image(
+                    1,
+                    vk::ImageView::null(),
+                    self.sampler,
+                    vk::ImageLayout::UNDEFINED,
+                    vk::DescriptorType::SAMPLER,
+                )
+                .update_set(device, set);
+        }
+    }
+
+    /// Filters the mip above `mip`, or the draw image for the first, into it. Expects the
+    /// source sampled and `mip` ready for attachment writes
+    pub fn downsample(
+        &self,
+        device: &ash::Device,
+        cmd: vk::CommandBuffer,
+        images: &BloomImages,
+        mip: u32,
+        settings: &BloomSettings,
+    ) {
+        let push_constants = BloomPushConstants {
+            threshold: settings.threshold,
+            radius: settings.radius,
+            prefilter: (mip == 0) as u32,
+        };
+        self.draw(
+            device,
+            cmd,
+            self.downsample_pipeline,
+            self.sets[mip as usize],
+            images,
+            mip,
+            push_constants,
+        );
+    }
+
+    /// Adds the mip below `mip` onto it. Expects the source sampled and `mip` ready for
+    /// attachment writes
+    pub fn upsample(
+        &self,
+        device: &ash::Device,
+        cmd: vk::CommandBuffer,
+        images: &BloomImages,
+        mip: u32,
+        settings: &BloomSettings,
+    ) {
+        let push_constants = BloomPushConstants {
+            threshold: settings.threshold,
+            radius: settings.radius,
+            prefilter: 0,
+        };
+        self.draw(
+            device,
+            cmd,
+            self.upsample_pipeline,
+            self.sets[mip as usize + 2],
+            images,
+            mip,
+            push_constants,
+        );
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn draw(
+        &self,
+        device: &ash::Device,
+        cmd: vk::CommandBuffer,
+        pipeline: vk::Pipeline,
+        set: vk::DescriptorSet,
+        images: &BloomImages,
+        mip: u32,
+        push_constants: BloomPushConstants,
+    ) {
+        let extent = images.extents[mip as usize];
+        let color_attachments = [init::color_attachment_info(
+            images.mip_views[mip as usize],
+            None,
+            vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
+        )];
+        let rendering_info = init::rendering_info(extent, &color_attachments, None);
+
+        unsafe {
+            device.cmd_begin_rendering(cmd, &rendering_info);
+            device.cmd_bind_pipeline(cmd, vk::PipelineBindPoint::GRAPHICS, pipeline);
+            device.cmd_bind_descriptor_sets(
+                cmd,
+                vk::PipelineBindPoint::GRAPHICS,
+                self.layout,
+                0,
+                &[set],
+                &[],
+            );
+            device.cmd_push_constants(
+                cmd,
+                self.layout,
+                vk::ShaderStageFlags::FRAGMENT,
+                0,
+                bytemuck::bytes_of(&push_constants),
+            );
+            util::set_viewport_and_scissor(device, cmd, extent);
+            device.cmd_draw(cmd, 3, 1, 0, 0);
+            device.cmd_end_rendering(cmd);
+        }
+    }
+
+    pub fn destroy(&self, device: &ash::Device) {
+        unsafe {
+            device.destroy_pipeline(self.downsample_pipeline, None);
+            device.destroy_pipeline(self.upsample_pipeline, None);
+            device.destroy_pipeline_layout(self.layout, None);
+            device.destroy_sampler(self.sampler, None);
+            device.destroy_descriptor_set_layout(self.set_layout, None);
+        }
+        self.descriptor_allocator.destroy(device);
+    }
+}
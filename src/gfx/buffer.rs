@@ -0,0 +1,78 @@
+use anyhow::Context;
+use ash::vk;
+use gpu_allocator::{
+    vulkan::{Allocation, AllocationCreateDesc, AllocationScheme, Allocator},
+    MemoryLocation,
+};
+
+pub struct AllocatedBuffer {
+    pub buffer: vk::Buffer,
+    pub allocation: Option<Allocation>,
+    pub size: vk::DeviceSize,
+}
+
+impl AllocatedBuffer {
+    pub fn new(
+        device: &ash::Device,
+        allocator: &mut Allocator,
+        name: &str,
+        size: vk::DeviceSize,
+        usage: vk::BufferUsageFlags,
+        location: MemoryLocation,
+    ) -> anyhow::Result<Self> {
+        let info = vk::BufferCreateInfo::default()
+            .size(size)
+            .usage(usage)
+            .sharing_mode(vk::SharingMode::EXCLUSIVE);
+        let buffer = unsafe { device.create_buffer(&info, None) }?;
+        let requirements = unsafe { device.get_buffer_memory_requirements(buffer) };
+
+        let allocation = allocator
+            .allocate(&AllocationCreateDesc {
+                name,
+                requirements,
+                location,
+                linear: true,
+                allocation_scheme: AllocationScheme::GpuAllocatorManaged,
+            })
+            .with_context(|| format!("Failed to allocate memory for {name}"))?;
+
+        unsafe { device.bind_buffer_memory(buffer, allocation.memory(), allocation.offset()) }?;
+
+        Ok(Self {
+            buffer,
+            allocation: Some(allocation),
+            size,
+        })
+    }
+
+    /// Copies `data` to `offset` of a host visible buffer
+    pub fn write(&mut self, offset: usize, data: &[u8]) -> anyhow::Result<()> {
+        let mapped = self
+            .allocation
+            .as_mut()
+            .and_then(|allocation| allocation.mapped_slice_mut())
+            .context("Buffer is not host visible")?;
+        mapped
+            .get_mut(offset..offset + data.len())
+            .context("Write past the end of the buffer")?
+            .copy_from_slice(data);
+        Ok(())
+    }
+
+    /// The contents of a host visible buffer
+    pub fn read(&self) -> anyhow::Result<&[u8]> {
+        self.allocation
+            .as_ref()
+            .and_then(|allocation| allocation.mapped_slice())
+            .map(|mapped| &mapped[..self.size as usize])
+            .context("Buffer is not host visible")
+    }
+
+    pub fn destroy(&mut self, device: &ash::Device, allocator: &mut Allocator) {
+        unsafe { device.destroy_buffer(self.buffer, None) };
+        if let Some(allocation) = self.allocation.take() {
+            allocator.free(allocation).unwrap();
+        }
+    }
+}
@@ -0,0 +1,93 @@
+// Clustered light culling: bins point and spot lights into froxels on the GPU each frame, so
+// shading only loops over the lights that can reach a fragment
+use ash::vk;
+use gpu_allocator::{vulkan::Allocator, MemoryLocation};
+
+use super::{
+    buffer::AllocatedBuffer,
+    pipeline::{self, include_shader},
+};
+
+// Has to match cluster.wgsl and mesh.wgsl
+const CLUSTERS_X: u32 = 16;
+const CLUSTERS_Y: u32 = 9;
+const CLUSTERS_Z: u32 = 24;
+const MAX_LIGHTS_PER_CLUSTER: u32 = 128;
+const CLUSTER_COUNT: u32 = CLUSTERS_X * CLUSTERS_Y * CLUSTERS_Z;
+
+pub struct ClusterPass {
+    layout: vk::PipelineLayout,
+    pipeline: vk::Pipeline,
+    /// Lights in each cluster
+    pub counts: AllocatedBuffer,
+    /// `MAX_LIGHTS_PER_CLUSTER` light indices per cluster, the first `counts` of them used
+    pub indices: AllocatedBuffer,
+}
+
+impl ClusterPass {
+    /// Culls against whatever's in the scene set's uniforms and light buffer
+    pub fn new(
+        device: &ash::Device,
+        allocator: &mut Allocator,
+        scene_set_layout: vk::DescriptorSetLayout,
+    ) -> anyhow::Result<Self> {
+        let layout = pipeline::create_pipeline_layout(device, &[scene_set_layout], &[])?;
+        let module = pipeline::create_shader_module(device, include_shader!("cluster"))?;
+        let pipeline = pipeline::create_compute_pipeline(device, layout, module, c"cs_main");
+        unsafe { device.destroy_shader_module(module, None) };
+
+        let counts = AllocatedBuffer::new(
+            device,
+            allocator,
+            "cluster light counts",
+            CLUSTER_COUNT as vk::DeviceSize * 4,
+            vk::BufferUsageFlags::STORAGE_BUFFER,
+            MemoryLocation::GpuOnly,
+        )?;
+        let indices = AllocatedBuffer::new(
+            device,
+            allocator,
+            "cluster light indices",
+            (CLUSTER_COUNT * MAX_LIGHTS_PER_CLUSTER) as vk::DeviceSize * 4,
+            vk::BufferUsageFlags::STORAGE_BUFFER,
+            MemoryLocation::GpuOnly,
+        )?;
+
+        Ok(Self {
+            layout,
+            pipeline: pipeline?,
+            counts,
+            indices,
+        })
+    }
+
+    /// Expects both buffers ready for compute writes
+    pub fn dispatch(
+        &self,
+        device: &ash::Device,
+        cmd: vk::CommandBuffer,
+        scene_set: vk::DescriptorSet,
+    ) {
+        unsafe {
+            device.cmd_bind_pipeline(cmd, vk::PipelineBindPoint::COMPUTE, self.pipeline);
+            device.cmd_bind_descriptor_sets(
+                cmd,
+                vk::PipelineBindPoint::COMPUTE,
+                self.layout,
+                0,
+                &[scene_set],
+                &[],
+            );
+            device.cmd_dispatch(cmd, CLUSTER_COUNT.div_ceil(64), 1, 1);
+        }
+    }
+
+    pub fn destroy(&mut self, device: &ash::Device, allocator: &mut Allocator) {
+        unsafe {
+            device.destroy_pipeline(self.pipeline, None);
+            device.destroy_pipeline_layout(self.layout, None);
+        }
+        self.counts.destroy(device, allocator);
+        self.indices.destroy(device, allocator);
+    }
+}
@@ -0,0 +1,214 @@
+// Deferred shading: opaque geometry writes its surface parameters to a G-buffer, then one
+// fullscreen pass lights every pixel using the same shading as the forward path
+use ash::vk;
+use gpu_allocator::vulkan::Allocator;
+
+use super::{
+    barrier::Access,
+    depth::DepthBuffer,
+    descriptors::{DescriptorAllocator, DescriptorLayoutBuilder, DescriptorWriter},
+    image::AllocatedImage,
+    init,
+    pipeline::{self, include_shader, PipelineBuilder},
+    tracker::ResourceTracker,
+    util,
+};
+
+/// Albedo and occlusion, normal, metallic-roughness and emissive, has to match mesh.wgsl
+pub const GBUFFER_FORMATS: [vk::Format; 4] = [
+    vk::Format::R8G8B8A8_SRGB,
+    vk::Format::A2B10G10R10_UNORM_PACK32,
+    vk::Format::R8G8B8A8_UNORM,
+    vk::Format::R16G16B16A16_SFLOAT,
+];
+
+// Sized to the swapchain, recreated with it
+pub struct GBuffer {
+    pub images: [AllocatedImage; 4],
+}
+
+impl GBuffer {
+    pub fn new(
+        device: &ash::Device,
+        allocator: &mut Allocator,
+        extent: vk::Extent2D,
+    ) -> anyhow::Result<Self> {
+        let names = [
+            "gbuffer albedo",
+            "gbuffer normal",
+            "gbuffer material",
+            "gbuffer emissive",
+        ];
+        let mut images = Vec::with_capacity(GBUFFER_FORMATS.len());
+        for (name, format) in names.into_iter().zip(GBUFFER_FORMATS) {
+            let image = AllocatedImage::new(
+                device,
+                allocator,
+                name,
+                &init::image_create_info(
+                    format,
+                    vk::ImageUsageFlags::COLOR_ATTACHMENT | vk::ImageUsageFlags::SAMPLED,
+                    extent.into(),
+                ),
+                vk::ImageAspectFlags::COLOR,
+            );
+            match image {
+                Ok(image) => images.push(image),
+                Err(err) => {
+                    for mut image in images {
+                        image.destroy(device, allocator);
+                    }
+                    return Err(err);
+                }
+            }
+        }
+
+        Ok(Self {
+            images: images.try_into().ok().unwrap(),
+        })
+    }
+
+    /// Every attachment cleared to zero
+    pub fn attachment_infos(&self) -> [vk::RenderingAttachmentInfo<'static>; 4] {
+        self.images.each_ref().map(|image| {
+            init::color_attachment_info(
+                image.view,
+                Some(vk::ClearValue::default()),
+                vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
+            )
+        })
+    }
+
+    pub fn track(&self, tracker: &mut ResourceTracker) {
+        for (image, format) in self.images.iter().zip(GBUFFER_FORMATS) {
+            tracker.register_image(image.image, format, 1, 1, Access::Undefined);
+        }
+    }
+
+    pub fn untrack(&self, tracker: &mut ResourceTracker) {
+        for image in &self.images {
+            tracker.forget_image(image.image);
+        }
+    }
+
+    pub fn destroy(&mut self, device: &ash::Device, allocator: &mut Allocator) {
+        for image in &mut self.images {
+            image.destroy(device, allocator);
+        }
+    }
+}
+
+// Fullscreen pass reading the G-buffer and depth, drawn with the depth buffer attached
+// read-only so the skybox and blended geometry can share its render pass
+pub struct LightingPass {
+    set_layout: vk::DescriptorSetLayout,
+    set: vk::DescriptorSet,
+    layout: vk::PipelineLayout,
+    pipeline: vk::Pipeline,
+}
+
+impl LightingPass {
+    /// The material set layout only fills the slot between the scene and G-buffer sets. Only the
+    /// first of `color_formats` is written, the rest are left to what shares the render pass
+    pub fn new(
+        device: &ash::Device,
+        descriptor_allocator: &DescriptorAllocator,
+        scene_set_layout: vk::DescriptorSetLayout,
+        material_set_layout: vk::DescriptorSetLayout,
+        color_formats: &[vk::Format],
+        depth_format: vk::Format,
+    ) -> anyhow::Result<Self> {
+        let set_layout = DescriptorLayoutBuilder::default()
+            .add_binding(0, vk::DescriptorType::SAMPLED_IMAGE)
+            .add_binding(1, vk::DescriptorType::SAMPLED_IMAGE)
+            .add_binding(2, vk::DescriptorType::SAMPLED_IMAGE)
+            .add_binding(3, vk::DescriptorType::SAMPLED_IMAGE)
+            .add_binding(4, vk::DescriptorType::SAMPLED_IMAGE)
+            .build(device, vk::ShaderStageFlags::FRAGMENT)?;
+        let set = descriptor_allocator.allocate(device, set_layout)?;
+
+        let layout = pipeline::create_pipeline_layout(
+            device,
+            &[scene_set_layout, material_set_layout, set_layout],
+            &[],
+        )?;
+
+        let module = pipeline::create_shader_module(device, include_shader!("mesh"))?;
+        let pipeline = PipelineBuilder::default()
+            .shaders(module, c"vs_fullscreen", module, c"fs_deferred")
+            .layout(layout)
+            .color_attachment_formats(color_formats)
+            .first_attachment_only()
+            .depth_format(depth_format)
+            .build(device);
+        unsafe { device.destroy_shader_module(module, None) };
+
+        Ok(Self {
+            set_layout,
+            set,
+            layout,
+            pipeline: pipeline?,
+        })
+    }
+
+    /// Has to be called again whenever the targets are recreated
+    pub fn write_gbuffer(&self, device: &ash::Device, gbuffer: &GBuffer, depth: &DepthBuffer) {
+        let mut writer = DescriptorWriter::default();
+        for (binding, image) in gbuffer.images.iter().enumerate() {
+            writer = writer.write_image(
+                binding as u32,
+                image.view,
+                vk::Sampler::null(),
+                vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+                vk::DescriptorType::SAMPLED_IMAGE,
+            );
+        }
+        writer
+            .write_image(
+                4,
+                depth.sampled_view,
+                vk::Sampler::null(),
+                depth.read_only_layout(),
+                vk::DescriptorType::SAMPLED_IMAGE,
+            )
+            .update_set(device, self.set);
+    }
+
+    pub fn draw(
+        &self,
+        device: &ash::Device,
+        cmd: vk::CommandBuffer,
+        extent: vk::Extent2D,
+        scene_set: vk::DescriptorSet,
+    ) {
+        unsafe {
+            device.cmd_bind_pipeline(cmd, vk::PipelineBindPoint::GRAPHICS, self.pipeline);
+            device.cmd_bind_descriptor_sets(
+                cmd,
+                vk::PipelineBindPoint::GRAPHICS,
+                self.layout,
+                0,
+                &[scene_set],
+                &[],
+            );
+            device.cmd_bind_descriptor_sets(
+                cmd,
+                vk::PipelineBindPoint::GRAPHICS,
+                self.layout,
+                2,
+                &[self.set],
+                &[],
+            );
+            util::set_viewport_and_scissor(device, cmd, extent);
+            device.cmd_draw(cmd, 3, 1, 0, 0);
+        }
+    }
+
+    pub fn destroy(&self, device: &ash::Device) {
+        unsafe {
+            device.destroy_pipeline(self.pipeline, None);
+            device.destroy_pipeline_layout(self.layout, None);
+            device.destroy_descriptor_set_layout(self.set_layout, None);
+        }
+    }
+}
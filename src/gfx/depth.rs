@@ -0,0 +1,151 @@
+use ash::vk::{self, PhysicalDevice};
+use gpu_allocator::vulkan::Allocator;
+
+use super::{barrier, image::AllocatedImage, init};
+
+// Candidates in order of preference, the first one usable as a depth attachment wins
+pub const DEPTH_FORMATS: &[vk::Format] = &[
+    vk::Format::D32_SFLOAT,
+    vk::Format::D32_SFLOAT_S8_UINT,
+    vk::Format::D24_UNORM_S8_UINT,
+    vk::Format::D16_UNORM,
+];
+pub const DEPTH_STENCIL_FORMATS: &[vk::Format] = &[
+    vk::Format::D32_SFLOAT_S8_UINT,
+    vk::Format::D24_UNORM_S8_UINT,
+    vk::Format::D16_UNORM_S8_UINT,
+];
+
+pub fn has_stencil(format: vk::Format) -> bool {
+    matches!(
+        format,
+        vk::Format::D32_SFLOAT_S8_UINT
+            | vk::Format::D24_UNORM_S8_UINT
+            | vk::Format::D16_UNORM_S8_UINT
+            | vk::Format::S8_UINT
+    )
+}
+
+pub fn choose_depth_format(
+    instance: &ash::Instance,
+    physical_device: PhysicalDevice,
+    candidates: &[vk::Format],
+) -> anyhow::Result<vk::Format> {
+    candidates
+        .iter()
+        .copied()
+        .find(|&format| {
+            let properties =
+                unsafe { instance.get_physical_device_format_properties(physical_device, format) };
+            properties
+                .optimal_tiling_features
+                .contains(vk::FormatFeatureFlags::DEPTH_STENCIL_ATTACHMENT)
+        })
+        .ok_or(anyhow::anyhow!(
+            "None of the depth formats {:?} are supported",
+            candidates
+        ))
+}
+
+pub struct DepthBuffer {
+    pub image: AllocatedImage,
+    /// Depth aspect only, sampled views can't include stencil
+    pub sampled_view: vk::ImageView,
+    pub format: vk::Format,
+    /// Near plane at 1.0 and far plane at 0.0, which spreads float precision far better
+    pub reverse_z: bool,
+}
+
+impl DepthBuffer {
+    pub fn new(
+        device: &ash::Device,
+        allocator: &mut Allocator,
+        format: vk::Format,
+        extent: vk::Extent2D,
+        reverse_z: bool,
+        samples: vk::SampleCountFlags,
+    ) -> anyhow::Result<Self> {
+        // Multisampled depth is only ever resolved, and sampling it may not be supported
+        let mut usage = vk::ImageUsageFlags::DEPTH_STENCIL_ATTACHMENT;
+        if samples == vk::SampleCountFlags::TYPE_1 {
+            usage |= vk::ImageUsageFlags::SAMPLED;
+        }
+        let image = AllocatedImage::new(
+            device,
+            allocator,
+            "depth image",
+            &init::image_create_info(format, usage, extent.into()).samples(samples),
+            barrier::aspect_mask_for_format(format),
+        )?;
+        let sampled_view = unsafe {
+            device.create_image_view(
+                &init::imageview_create_info(format, image.image, vk::ImageAspectFlags::DEPTH),
+                None,
+            )
+        }?;
+
+        Ok(Self {
+            image,
+            sampled_view,
+            format,
+            reverse_z,
+        })
+    }
+
+    pub fn has_stencil(&self) -> bool {
+        has_stencil(self.format)
+    }
+
+    pub fn layout(&self) -> vk::ImageLayout {
+        if self.has_stencil() {
+            vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL
+        } else {
+            vk::ImageLayout::DEPTH_ATTACHMENT_OPTIMAL
+        }
+    }
+
+    /// For passes that test against depth and sample it without writing
+    pub fn read_only_layout(&self) -> vk::ImageLayout {
+        if self.has_stencil() {
+            vk::ImageLayout::DEPTH_STENCIL_READ_ONLY_OPTIMAL
+        } else {
+            vk::ImageLayout::DEPTH_READ_ONLY_OPTIMAL
+        }
+    }
+
+    pub fn compare_op(&self) -> vk::CompareOp {
+        if self.reverse_z {
+            vk::CompareOp::GREATER_OR_EQUAL
+        } else {
+            vk::CompareOp::LESS_OR_EQUAL
+        }
+    }
+
+    /// The value of the far plane
+    pub fn clear_value(&self) -> vk::ClearValue {
+        vk::ClearValue {
+            depth_stencil: vk::ClearDepthStencilValue {
+                depth: if self.reverse_z { 0.0 } else { 1.0 },
+                stencil: 0,
+            },
+        }
+    }
+
+    pub fn attachment_info(&self, clear: bool) -> vk::RenderingAttachmentInfo<'static> {
+        init::depth_attachment_info(
+            self.image.view,
+            clear.then(|| self.clear_value()),
+            self.layout(),
+        )
+    }
+
+    /// Loaded and kept, for passes that only test against it
+    pub fn read_only_attachment_info(&self) -> vk::RenderingAttachmentInfo<'static> {
+        init::depth_attachment_info(self.image.view, None, self.read_only_layout())
+    }
+
+    pub fn destroy(&mut self, device: &ash::Device, allocator: &mut Allocator) {
+        unsafe { device.destroy_image_view(self.sampled_view, None) };
+        self.image.destroy(device, allocator);
+    }
+}
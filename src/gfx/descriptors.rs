@@ -0,0 +1,144 @@
+use ash::vk;
+
+#[derive(Default)]
+pub struct DescriptorLayoutBuilder {
+    bindings: Vec<vk::DescriptorSetLayoutBinding<'static>>,
+}
+
+impl DescriptorLayoutBuilder {
+    pub fn add_binding(mut self, binding: u32, ty: vk::DescriptorType) -> Self {
+        self.bindings.push(
+            vk::DescriptorSetLayoutBinding::default()
+                .binding(binding)
+                .descriptor_count(1)
+                .descriptor_type(ty),
+        );
+        self
+    }
+
+    pub fn build(
+        mut self,
+        device: &ash::Device,
+        stages: vk::ShaderStageFlags,
+    ) -> anyhow::Result<vk::DescriptorSetLayout> {
+        for binding in &mut self.bindings {
+            binding.stage_flags |= stages;
+        }
+
+        let info = vk::DescriptorSetLayoutCreateInfo::default().bindings(&self.bindings);
+        Ok(unsafe { device.create_descriptor_set_layout(&info, None) }?)
+    }
+}
+
+pub struct PoolSizeRatio {
+    pub ty: vk::DescriptorType,
+    pub ratio: f32,
+}
+
+pub struct DescriptorAllocator {
+    pub pool: vk::DescriptorPool,
+}
+
+impl DescriptorAllocator {
+    pub fn new(
+        device: &ash::Device,
+        max_sets: u32,
+        ratios: &[PoolSizeRatio],
+    ) -> anyhow::Result<Self> {
+        let pool_sizes: Vec<vk::DescriptorPoolSize> = ratios
+            .iter()
+            .map(|ratio| vk::DescriptorPoolSize {
+                ty: ratio.ty,
+                descriptor_count: (ratio.ratio * max_sets as f32).ceil() as u32,
+            })
+            .collect();
+
+        let info = vk::DescriptorPoolCreateInfo::default()
+            .max_sets(max_sets)
+            .pool_sizes(&pool_sizes);
+        let pool = unsafe { device.create_descriptor_pool(&info, None) }?;
+
+        Ok(Self { pool })
+    }
+
+    pub fn allocate(
+        &self,
+        device: &ash::Device,
+        layout: vk::DescriptorSetLayout,
+    ) -> anyhow::Result<vk::DescriptorSet> {
+        let layouts = [layout];
+        let info = vk::DescriptorSetAllocateInfo::default()
+            .descriptor_pool(self.pool)
+            .set_layouts(&layouts);
+
+        Ok(unsafe { device.allocate_descriptor_sets(&info) }?[0])
+    }
+
+    pub fn destroy(&self, device: &ash::Device) {
+        unsafe { device.destroy_descriptor_pool(self.pool, None) };
+    }
+}
+
+// Collects descriptor writes so a whole set can be updated with one call
+#[derive(Default)]
+pub struct DescriptorWriter {
+    image_infos: Vec<(u32, vk::DescriptorType, vk::DescriptorImageInfo)>,
+    buffer_infos: Vec<(u32, vk::DescriptorType, vk::DescriptorBufferInfo)>,
+}
+
+impl DescriptorWriter {
+    pub fn write_image(
+        mut self,
+        binding: u32,
+        view: vk::ImageView,
+        sampler: vk::Sampler,
+        layout: vk::ImageLayout,
+        ty: vk::DescriptorType,
+    ) -> Self {
+        let info = vk::DescriptorImageInfo::default()
+            .image_view(view)
+            .sampler(sampler)
+            .image_layout(layout);
+        self.image_infos.push((binding, ty, info));
+        self
+    }
+
+    pub fn write_buffer(
+        mut self,
+        binding: u32,
+        buffer: vk::Buffer,
+        offset: vk::DeviceSize,
+        size: vk::DeviceSize,
+        ty: vk::DescriptorType,
+    ) -> Self {
+        let info = vk::DescriptorBufferInfo::default()
+            .buffer(buffer)
+            .offset(offset)
+            .range(size);
+        self.buffer_infos.push((binding, ty, info));
+        self
+    }
+
+    pub fn update_set(&self, device: &ash::Device, set: vk::DescriptorSet) {
+        let writes: Vec<vk::WriteDescriptorSet> = self
+            .image_infos
+            .iter()
+            .map(|(binding, ty, info)| {
+                vk::WriteDescriptorSet::default()
+                    .dst_set(set)
+                    .dst_binding(*binding)
+                    .descriptor_type(*ty)
+                    .image_info(std::slice::from_ref(info))
+            })
+            .chain(self.buffer_infos.iter().map(|(binding, ty, info)| {
+                vk::WriteDescriptorSet::default()
+                    .dst_set(set)
+                    .dst_binding(*binding)
+                    .descriptor_type(*ty)
+                    .buffer_info(std::slice::from_ref(info))
+            }))
+            .collect();
+
+        unsafe { device.update_descriptor_sets(&writes, &[]) };
+    }
+}
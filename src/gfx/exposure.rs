@@ -0,0 +1,199 @@
+// Histogram based auto-exposure, adapting to the draw image's average luminance over time
+use ash::vk;
+use gpu_allocator::{vulkan::Allocator, MemoryLocation};
+
+use super::{
+    buffer::AllocatedBuffer,
+    descriptors::{DescriptorAllocator, DescriptorLayoutBuilder, DescriptorWriter},
+    pipeline::{self, include_shader},
+};
+
+// Has to match exposure.wgsl
+const BIN_COUNT: u64 = 256;
+// The histogram covers luminances from 2^-10 to 2^12
+const MIN_LOG_LUMINANCE: f32 = -10.0;
+const LOG_LUMINANCE_RANGE: f32 = 22.0;
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy, bytemuck::Pod, bytemuck::Zeroable)]
+struct ExposurePushConstants {
+    min_log_luminance: f32,
+    log_luminance_range: f32,
+    adaptation: f32,
+    reset: u32,
+}
+
+pub struct ExposurePass {
+    set_layout: vk::DescriptorSetLayout,
+    set: vk::DescriptorSet,
+    layout: vk::PipelineLayout,
+    histogram_pipeline: vk::Pipeline,
+    average_pipeline: vk::Pipeline,
+    /// Pixels per luminance bin, cleared with a transfer before each frame's histogram
+    pub histogram: AllocatedBuffer,
+    /// The adapted luminance and the exposure it gives, as two floats
+    pub state: AllocatedBuffer,
+}
+
+impl ExposurePass {
+    pub fn new(
+        device: &ash::Device,
+        allocator: &mut Allocator,
+        descriptor_allocator: &DescriptorAllocator,
+    ) -> anyhow::Result<Self> {
+        let set_layout = DescriptorLayoutBuilder::default()
+            .add_binding(0, vk::DescriptorType::SAMPLED_IMAGE)
+            .add_binding(1, vk::DescriptorType::STORAGE_BUFFER)
+            .add_binding(2, vk::DescriptorType::STORAGE_BUFFER)
+            .build(device, vk::ShaderStageFlags::COMPUTE)?;
+        let set = descriptor_allocator.allocate(device, set_layout)?;
+
+        let layout = pipeline::create_pipeline_layout(
+            device,
+            &[set_layout],
+            &[pipeline::push_constant_range::<ExposurePushConstants>(
+                vk::ShaderStageFlags::COMPUTE,
+            )],
+        )?;
+        let module = pipeline::create_shader_module(device, include_shader!("exposure"))?;
+        let histogram_pipeline =
+            pipeline::create_compute_pipeline(device, layout, module, c"cs_histogram");
+        let average_pipeline =
+            pipeline::create_compute_pipeline(device, layout, module, c"cs_average");
+        unsafe { device.destroy_shader_module(module, None) };
+
+        let histogram = AllocatedBuffer::new(
+            device,
+            allocator,
+            "luminance histogram",
+            BIN_COUNT * 4,
+            vk::BufferUsageFlags::STORAGE_BUFFER | vk::BufferUsageFlags::TRANSFER_DST,
+            MemoryLocation::GpuOnly,
+        )?;
+        let state = AllocatedBuffer::new(
+            device,
+            allocator,
+            "exposure state",
+            8,
+            vk::BufferUsageFlags::STORAGE_BUFFER,
+            MemoryLocation::GpuOnly,
+        )?;
+        let storage = |writer: DescriptorWriter, binding, buffer: &AllocatedBuffer| {
+            writer.write_buffer(
+                binding,
+                buffer.buffer,
+                0,
+                buffer.size,
+                vk::DescriptorType::STORAGE_BUFFER,
+            )
+        };
+        let writer = storage(DescriptorWriter::default(), 1, &histogram);
+        storage(writer, 2, &state).update_set(device, set);
+
+        Ok(Self {
+            set_layout,
+            set,
+            layout,
+            histogram_pipeline: histogram_pipeline?,
+            average_pipeline: average_pipeline?,
+            histogram,
+            state,
+        })
+    }
+
+    /// Has to be called again whenever the draw image is recreated
+    pub fn write_source(&self, device: &ash::Device, view: vk::ImageView) {
+        DescriptorWriter::default()
+            .write_image(
+                0,
+                view,
+                vk::Sampler::null(),
+                vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+                vk::DescriptorType::SAMPLED_IMAGE,
+            )
+            .update_set(device, self.set);
+    }
+
+    /// Expects the histogram ready for transfer writes
+    pub fn clear_histogram(&self, device: &ash::Device, cmd: vk::CommandBuffer) {
+        unsafe { device.cmd_fill_buffer(cmd, self.histogram.buffer, 0, vk::WHOLE_SIZE, 0) };
+    }
+
+    /// Expects the draw image sampled and the histogram ready for compute reads and writes
+    pub fn dispatch_histogram(
+        &self,
+        device: &ash::Device,
+        cmd: vk::CommandBuffer,
+        extent: vk::Extent2D,
+    ) {
+        let groups = [extent.width.div_ceil(16), extent.height.div_ceil(16), 1];
+        self.dispatch(device, cmd, self.histogram_pipeline, groups, 0.0, false);
+    }
+
+    /// Expects the histogram ready for compute reads and the state for reads and writes.
+    /// `adaptation` is how far to move towards this frame's luminance, `reset` skips straight
+    /// to it
+    pub fn dispatch_average(
+        &self,
+        device: &ash::Device,
+        cmd: vk::CommandBuffer,
+        adaptation: f32,
+        reset: bool,
+    ) {
+        self.dispatch(
+            device,
+            cmd,
+            self.average_pipeline,
+            [1; 3],
+            adaptation,
+            reset,
+        );
+    }
+
+    fn dispatch(
+        &self,
+        device: &ash::Device,
+        cmd: vk::CommandBuffer,
+        pipeline: vk::Pipeline,
+        groups: [u32; 3],
+        adaptation: f32,
+        reset: bool,
+    ) {
+        let push_constants = ExposurePushConstants {
+            min_log_luminance: MIN_LOG_LUMINANCE,
+            log_luminance_range: LOG_LUMINANCE_RANGE,
+            adaptation,
+            reset: reset as u32,
+        };
+        unsafe {
+            device.cmd_bind_pipeline(cmd, vk::PipelineBindPoint::COMPUTE, pipeline);
+            device.cmd_bind_descriptor_sets(
+                cmd,
+                vk::PipelineBindPoint::COMPUTE,
+                self.layout,
+                0,
+                &[self.set],
+                &[],
+            );
+            device.cmd_push_constants(
+                cmd,
+                self.layout,
+                vk::ShaderStageFlags::COMPUTE,
+                0,
+                bytemuck::bytes_of(&push_constants),
+            );
+            device.cmd_dispatch(cmd, groups[0], groups[1], groups[2]);
+        }
+    }
+
+    pub fn destroy(&mut self, device: &ash::Device, allocator: &mut Allocator) {
+        unsafe {
+            device.destroy_pipeline(self.histogram_pipeline, None);
+            device.destroy_pipeline(self.average_pipeline, None);
+            device.destroy_pipeline_layout(self.layout, None);
+            device.destroy_descriptor_set_layout(self.set_layout, None);
+        }
+        self.histogram.destroy(device, allocator);
+        self.state.destroy(device, allocator);
+    }
+}
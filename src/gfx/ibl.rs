@@ -0,0 +1,960 @@
+// Image based lighting: an equirectangular or cube environment baked into a skybox cubemap,
+// diffuse irradiance and GGX prefiltered radiance, plus the split-sum BRDF lookup table
+use std::{
+    path::{Path, PathBuf},
+    time::UNIX_EPOCH,
+};
+
+use anyhow::Context;
+use ash::vk;
+use gpu_allocator::{vulkan::Allocator, MemoryLocation};
+
+use crate::assets::EnvironmentImage;
+
+use super::{
+    barrier::{Access, Barriers, ImageBarrier, ImageRange},
+    buffer::AllocatedBuffer,
+    descriptors::{DescriptorAllocator, DescriptorLayoutBuilder, DescriptorWriter, PoolSizeRatio},
+    image::AllocatedImage,
+    init,
+    pipeline::{self, include_shader},
+    texture::Texture,
+    upload::UploadContext,
+};
+
+const FORMAT: vk::Format = vk::Format::R16G16B16A16_SFLOAT;
+const SKYBOX_SIZE: u32 = 512;
+const IRRADIANCE_SIZE: u32 = 32;
+const PREFILTERED_SIZE: u32 = 256;
+/// Roughness goes from 0 at the base level to 1 at the last, has to match mesh.wgsl
+pub const PREFILTERED_MIPS: u32 = 6;
+const BRDF_LUT_SIZE: u32 = 256;
+
+// Steps per angle, so the square of this is the number of samples
+const IRRADIANCE_STEPS: u32 = 64;
+const PREFILTER_SAMPLES: u32 = 1024;
+const BRDF_LUT_SAMPLES: u32 = 1024;
+
+const CACHE_MAGIC: [u8; 4] = *b"IBLC";
+// Bumped whenever baking changes, so stale cache files get rebuilt
+const CACHE_VERSION: u32 = 1;
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy, bytemuck::Pod, bytemuck::Zeroable)]
+struct BakePushConstants {
+    param: f32,
+    sample_count: u32,
+}
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, bytemuck::Pod, bytemuck::Zeroable)]
+struct CacheHeader {
+    magic: [u8; 4],
+    version: u32,
+    /// Size and mip count of each cube, in `Environment::cubes` order
+    cubes: [[u32; 2]; 3],
+}
+
+impl CacheHeader {
+    fn current() -> Self {
+        Self {
+            magic: CACHE_MAGIC,
+            version: CACHE_VERSION,
+            cubes: Environment::LAYOUT.map(|(_, size, mips)| [size, mips]),
+        }
+    }
+}
+
+/// Cubemaps baked from one environment, all `R16G16B16A16_SFLOAT` and ready to be sampled
+pub struct Environment {
+    pub skybox: AllocatedImage,
+    pub irradiance: AllocatedImage,
+    pub prefiltered: AllocatedImage,
+}
+
+impl Environment {
+    // Name, size and mip count of each cube
+    const LAYOUT: [(&'static str, u32, u32); 3] = [
+        (
+            "environment skybox",
+            SKYBOX_SIZE,
+            u32::BITS - SKYBOX_SIZE.leading_zeros(),
+        ),
+        ("environment irradiance", IRRADIANCE_SIZE, 1),
+        (
+            "environment prefiltered",
+            PREFILTERED_SIZE,
+            PREFILTERED_MIPS,
+        ),
+    ];
+
+    fn new(ctx: &mut UploadContext) -> anyhow::Result<Self> {
+        let mut cubes = Vec::with_capacity(3);
+        for (name, size, mips) in Self::LAYOUT {
+            match cube_image(ctx, name, size, mips) {
+                Ok(image) => cubes.push(image),
+                Err(err) => {
+                    for mut image in cubes {
+                        image.destroy(ctx.device, ctx.allocator);
+                    }
+                    return Err(err);
+                }
+            }
+        }
+        let mut cubes = cubes.into_iter();
+        Ok(Self {
+            skybox: cubes.next().unwrap(),
+            irradiance: cubes.next().unwrap(),
+            prefiltered: cubes.next().unwrap(),
+        })
+    }
+
+    fn cubes(&self) -> [&AllocatedImage; 3] {
+        [&self.skybox, &self.irradiance, &self.prefiltered]
+    }
+
+    /// Reads an environment written by `save`
+    pub fn load(ctx: &mut UploadContext, path: &Path) -> anyhow::Result<Self> {
+        let data =
+            std::fs::read(path).with_context(|| format!("Failed to read {}", path.display()))?;
+        let header_size = size_of::<CacheHeader>();
+        let header: CacheHeader = data
+            .get(..header_size)
+            .map(bytemuck::pod_read_unaligned)
+            .context("Truncated header")?;
+        if header != CacheHeader::current() {
+            return Err(anyhow::anyhow!("Baked by a different version"));
+        }
+        let pixels = &data[header_size..];
+        if pixels.len() as vk::DeviceSize != cache_size() {
+            return Err(anyhow::anyhow!("Expected {} bytes of pixels", cache_size()));
+        }
+
+        let mut environment = Self::new(ctx)?;
+        let mut staging = match ctx.staging_buffer(pixels) {
+            Ok(staging) => staging,
+            Err(err) => {
+                environment.destroy(ctx.device, ctx.allocator);
+                return Err(err);
+            }
+        };
+
+        let device = ctx.device;
+        let result = ctx.immediate.submit(device, ctx.queue, |cmd| {
+            let mut barriers = Barriers::default();
+            let mut offset = 0;
+            for (image, (_, size, mips)) in environment.cubes().into_iter().zip(Self::LAYOUT) {
+                barriers
+                    .image(
+                        ImageBarrier::new(
+                            image.image,
+                            FORMAT,
+                            Access::Undefined,
+                            Access::TransferDst,
+                        )
+                        .range(cube_range(0, mips)),
+                    )
+                    .flush(device, cmd);
+                let regions = cube_copy_regions(size, mips, &mut offset);
+                unsafe {
+                    device.cmd_copy_buffer_to_image(
+                        cmd,
+                        staging.buffer,
+                        image.image,
+                        vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                        &regions,
+                    )
+                };
+                barriers.image(
+                    ImageBarrier::new(
+                        image.image,
+                        FORMAT,
+                        Access::TransferDst,
+                        Access::FragmentSampled,
+                    )
+                    .range(cube_range(0, mips)),
+                );
+            }
+            barriers.flush(device, cmd);
+        });
+        staging.destroy(ctx.device, ctx.allocator);
+        if let Err(err) = result {
+            environment.destroy(ctx.device, ctx.allocator);
+            return Err(err);
+        }
+
+        Ok(environment)
+    }
+
+    /// Reads every level of every cube back and writes them to `path`
+    pub fn save(&self, ctx: &mut UploadContext, path: &Path) -> anyhow::Result<()> {
+        let mut readback = AllocatedBuffer::new(
+            ctx.device,
+            ctx.allocator,
+            "environment readback",
+            cache_size(),
+            vk::BufferUsageFlags::TRANSFER_DST,
+            MemoryLocation::GpuToCpu,
+        )?;
+
+        let device = ctx.device;
+        let result = ctx
+            .immediate
+            .submit(device, ctx.queue, |cmd| {
+                let mut barriers = Barriers::default();
+                let mut offset = 0;
+                for (image, (_, size, mips)) in self.cubes().into_iter().zip(Self::LAYOUT) {
+                    barriers
+                        .image(
+                            ImageBarrier::new(
+                                image.image,
+                                FORMAT,
+                                Access::FragmentSampled,
+                                Access::TransferSrc,
+                            )
+                            .range(cube_range(0, mips)),
+                        )
+                        .flush(device, cmd);
+                    let regions = cube_copy_regions(size, mips, &mut offset);
+                    unsafe {
+                        device.cmd_copy_image_to_buffer(
+                            cmd,
+                            image.image,
+                            vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+                            readback.buffer,
+                            &regions,
+                        )
+                    };
+                    barriers.image(
+                        ImageBarrier::new(
+                            image.image,
+                            FORMAT,
+                            Access::TransferSrc,
+                            Access::FragmentSampled,
+                        )
+                        .range(cube_range(0, mips)),
+                    );
+                }
+                barriers
+                    .buffer(
+                        readback.buffer,
+                        0,
+                        vk::WHOLE_SIZE,
+                        Access::TransferDst,
+                        Access::HostRead,
+                    )
+                    .flush(device, cmd);
+            })
+            .and_then(|()| {
+                let mut data = bytemuck::bytes_of(&CacheHeader::current()).to_vec();
+                data.extend_from_slice(readback.read()?);
+                if let Some(dir) = path.parent() {
+                    std::fs::create_dir_all(dir)?;
+                }
+                std::fs::write(path, data)
+                    .with_context(|| format!("Failed to write {}", path.display()))
+            });
+        readback.destroy(ctx.device, ctx.allocator);
+        result
+    }
+
+    pub fn destroy(&mut self, device: &ash::Device, allocator: &mut Allocator) {
+        self.skybox.destroy(device, allocator);
+        self.irradiance.destroy(device, allocator);
+        self.prefiltered.destroy(device, allocator);
+    }
+}
+
+/// Where the environment baked from `source` is cached in `dir`. The name changes with the
+/// source's path, size and modification time, or those of the files in it for a directory of
+/// cube faces, so edited files get baked again. It changes with the bake settings too, and
+/// stays the same across toolchains
+pub fn cache_path(dir: &Path, source: &Path) -> anyhow::Result<PathBuf> {
+    let mut files = vec![source.to_path_buf()];
+    if source.is_dir() {
+        files = std::fs::read_dir(source)
+            .with_context(|| format!("Failed to read {}", source.display()))?
+            .map(|entry| entry.map(|entry| entry.path()))
+            .collect::<Result<_, _>>()?;
+        files.sort();
+    }
+
+    let mut hash = Fnv1a::default();
+    hash.write(bytemuck::bytes_of(&CacheHeader::current()));
+    for setting in [IRRADIANCE_STEPS, PREFILTER_SAMPLES, FORMAT.as_raw() as u32] {
+        hash.write(&setting.to_le_bytes());
+    }
+    hash.write_path(&std::fs::canonicalize(source)?);
+    for file in &files {
+        let metadata = std::fs::metadata(file)
+            .with_context(|| format!("Failed to read {}", file.display()))?;
+        hash.write_path(file);
+        hash.write(&metadata.len().to_le_bytes());
+        // Sources without a modification time are only told apart by size
+        let modified = metadata
+            .modified()
+            .ok()
+            .and_then(|time| time.duration_since(UNIX_EPOCH).ok())
+            .unwrap_or_default();
+        hash.write(&modified.as_secs().to_le_bytes());
+        hash.write(&modified.subsec_nanos().to_le_bytes());
+    }
+
+    let stem = source
+        .file_stem()
+        .map_or("environment".into(), |stem| stem.to_string_lossy());
+    Ok(dir.join(format!("{stem}-{:016x}.ibl", hash.0)))
+}
+
+// 64-bit FNV-1a, fixed unlike the standard library's hashers so cache names survive toolchain
+// updates
+struct Fnv1a(u64);
+
+impl Default for Fnv1a {
+    fn default() -> Self {
+        Self(0xcbf2_9ce4_8422_2325)
+    }
+}
+
+impl Fnv1a {
+    fn write(&mut self, bytes: &[u8]) {
+        for &byte in bytes {
+            self.0 = (self.0 ^ byte as u64).wrapping_mul(0x0000_0100_0000_01b3);
+        }
+    }
+
+    // Length first, so one path running into the next can't collide with a different split
+    fn write_path(&mut self, path: &Path) {
+        let bytes = path.as_os_str().as_encoded_bytes();
+        self.write(&(bytes.len() as u64).to_le_bytes());
+        self.write(bytes);
+    }
+}
+
+/// Compute pipelines for everything image based lighting needs precomputed
+pub struct IblBaker {
+    set_layout: vk::DescriptorSetLayout,
+    layout: vk::PipelineLayout,
+    equirect_to_cube: vk::Pipeline,
+    face_to_cube: vk::Pipeline,
+    irradiance: vk::Pipeline,
+    prefilter: vk::Pipeline,
+    brdf_lut: vk::Pipeline,
+    sampler: vk::Sampler,
+}
+
+impl IblBaker {
+    pub fn new(device: &ash::Device) -> anyhow::Result<Self> {
+        let set_layout = DescriptorLayoutBuilder::default()
+            .add_binding(0, vk::DescriptorType::SAMPLED_IMAGE)
+            .add_binding(1, vk::DescriptorType::SAMPLED_IMAGE)
+            .add_binding(2, vk::DescriptorType::SAMPLER)
+            .add_binding(3, vk::DescriptorType::STORAGE_IMAGE)
+            .add_binding(4, vk::DescriptorType::STORAGE_IMAGE)
+            .build(device, vk::ShaderStageFlags::COMPUTE)?;
+        let layout = pipeline::create_pipeline_layout(
+            device,
+            &[set_layout],
+            &[pipeline::push_constant_range::<BakePushConstants>(
+                vk::ShaderStageFlags::COMPUTE,
+            )],
+        )?;
+
+        let module = pipeline::create_shader_module(device, include_shader!("ibl"))?;
+        let pipelines = [
+            c"cs_equirect_to_cube",
+            c"cs_face_to_cube",
+            c"cs_irradiance",
+            c"cs_prefilter",
+            c"cs_brdf_lut",
+        ]
+        .map(|entry| pipeline::create_compute_pipeline(device, layout, module, entry));
+        unsafe { device.destroy_shader_module(module, None) };
+        let [equirect_to_cube, face_to_cube, irradiance, prefilter, brdf_lut] = pipelines;
+
+        let sampler_info = vk::SamplerCreateInfo::default()
+            .mag_filter(vk::Filter::LINEAR)
+            .min_filter(vk::Filter::LINEAR)
+            .mipmap_mode(vk::SamplerMipmapMode::LINEAR)
+            .address_mode_u(vk::SamplerAddressMode::REPEAT)
+            .address_mode_v(vk::SamplerAddressMode::CLAMP_TO_EDGE)
+            .address_mode_w(vk::SamplerAddressMode::CLAMP_TO_EDGE)
+            .max_lod(vk::LOD_CLAMP_NONE);
+        let sampler = unsafe { device.create_sampler(&sampler_info, None) }?;
+
+        Ok(Self {
+            set_layout,
+            layout,
+            equirect_to_cube: equirect_to_cube?,
+            face_to_cube: face_to_cube?,
+            irradiance: irradiance?,
+            prefilter: prefilter?,
+            brdf_lut: brdf_lut?,
+            sampler,
+        })
+    }
+
+    /// Independent of the environment, so only ever needs baking once
+    pub fn bake_brdf_lut(&self, ctx: &mut UploadContext) -> anyhow::Result<AllocatedImage> {
+        let mut lut = AllocatedImage::new(
+            ctx.device,
+            ctx.allocator,
+            "BRDF LUT",
+            &init::image_create_info(
+                FORMAT,
+                vk::ImageUsageFlags::STORAGE | vk::ImageUsageFlags::SAMPLED,
+                vk::Extent3D {
+                    width: BRDF_LUT_SIZE,
+                    height: BRDF_LUT_SIZE,
+                    depth: 1,
+                },
+            ),
+            vk::ImageAspectFlags::COLOR,
+        )?;
+
+        let device = ctx.device;
+        let descriptor_allocator = self.descriptor_allocator(device, 1)?;
+        let result = descriptor_allocator
+            .allocate(device, self.set_layout)
+            .and_then(|set| {
+                DescriptorWriter::default()
+                    .write_image(
+                        4,
+                        lut.view,
+                        vk::Sampler::null(),
+                        vk::ImageLayout::GENERAL,
+                        vk::DescriptorType::STORAGE_IMAGE,
+                    )
+                    .update_set(device, set);
+
+                ctx.immediate.submit(device, ctx.queue, |cmd| {
+                    let mut barriers = Barriers::default();
+                    barriers
+                        .image(ImageBarrier::new(
+                            lut.image,
+                            FORMAT,
+                            Access::Undefined,
+                            Access::ComputeWrite,
+                        ))
+                        .flush(device, cmd);
+                    self.dispatch(
+                        device,
+                        cmd,
+                        self.brdf_lut,
+                        set,
+                        BakePushConstants {
+                            param: 0.0,
+                            sample_count: BRDF_LUT_SAMPLES,
+                        },
+                        BRDF_LUT_SIZE,
+                        1,
+                    );
+                    barriers
+                        .image(ImageBarrier::new(
+                            lut.image,
+                            FORMAT,
+                            Access::ComputeWrite,
+                            Access::FragmentSampled,
+                        ))
+                        .flush(device, cmd);
+                })
+            });
+        descriptor_allocator.destroy(device);
+        if let Err(err) = result {
+            lut.destroy(ctx.device, ctx.allocator);
+            return Err(err);
+        }
+
+        Ok(lut)
+    }
+
+    /// Converts an equirectangular image to a cubemap and prefilters it for diffuse and
+    /// specular lighting
+    pub fn bake(
+        &self,
+        ctx: &mut UploadContext,
+        image: &EnvironmentImage,
+    ) -> anyhow::Result<Environment> {
+        // An equirect is read whole, cube faces are each resampled into the matching face
+        let (images, pipeline, name) = match image {
+            EnvironmentImage::Equirect(image) => (
+                std::slice::from_ref(image),
+                self.equirect_to_cube,
+                "environment equirect",
+            ),
+            EnvironmentImage::Cube(faces) => (&faces[..], self.face_to_cube, "environment face"),
+        };
+        let mut sources: Vec<Texture> = Vec::with_capacity(images.len());
+        for image in images {
+            match Texture::from_image(ctx, name, image) {
+                Ok(texture) => sources.push(texture),
+                Err(err) => {
+                    for mut texture in sources {
+                        texture.destroy(ctx.device, ctx.allocator);
+                    }
+                    return Err(err);
+                }
+            }
+        }
+        let mut environment = match Environment::new(ctx) {
+            Ok(environment) => environment,
+            Err(err) => {
+                for mut texture in sources {
+                    texture.destroy(ctx.device, ctx.allocator);
+                }
+                return Err(err);
+            }
+        };
+
+        let device = ctx.device;
+        let mut views = Vec::new();
+        let set_count = sources.len() as u32 + 1 + PREFILTERED_MIPS;
+        let descriptor_allocator = match self.descriptor_allocator(device, set_count) {
+            Ok(descriptor_allocator) => descriptor_allocator,
+            Err(err) => {
+                for mut texture in sources {
+                    texture.destroy(ctx.device, ctx.allocator);
+                }
+                environment.destroy(ctx.device, ctx.allocator);
+                return Err(err);
+            }
+        };
+        let result = (|| {
+            let mut storage_view = |image: &AllocatedImage, mip| {
+                let mut info =
+                    init::imageview_create_info(FORMAT, image.image, vk::ImageAspectFlags::COLOR);
+                info.view_type = vk::ImageViewType::TYPE_2D_ARRAY;
+                info.subresource_range.base_mip_level = mip;
+                info.subresource_range.layer_count = 6;
+                let view = unsafe { device.create_image_view(&info, None) }?;
+                views.push(view);
+                anyhow::Ok(view)
+            };
+            let skybox_view = storage_view(&environment.skybox, 0)?;
+            let irradiance_view = storage_view(&environment.irradiance, 0)?;
+            let prefiltered_views = (0..PREFILTERED_MIPS)
+                .map(|mip| storage_view(&environment.prefiltered, mip))
+                .collect::<anyhow::Result<Vec<_>>>()?;
+
+            let set = |source: vk::ImageView, binding: u32, dst: vk::ImageView| {
+                let set = descriptor_allocator.allocate(device, self.set_layout)?;
+                DescriptorWriter::default()
+                    .write_image(
+                        binding,
+                        source,
+                        vk::Sampler::null(),
+                        vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+                        vk::DescriptorType::SAMPLED_IMAGE,
+                    )
+                    .write_image(
+                        2,
+                        vk::ImageView::null(),
+                        self.sampler,
+                        vk::ImageLayout::UNDEFINED,
+                        vk::DescriptorType::SAMPLER,
+                    )
+                    .write_image(
+                        3,
+                        dst,
+                        vk::Sampler::null(),
+                        vk::ImageLayout::GENERAL,
+                        vk::DescriptorType::STORAGE_IMAGE,
+                    )
+                    .update_set(device, set);
+                anyhow::Ok(set)
+            };
+            let source_sets = sources
+                .iter()
+                .map(|source| set(source.image.view, 0, skybox_view))
+                .collect::<anyhow::Result<Vec<_>>>()?;
+            let irradiance_set = set(environment.skybox.view, 1, irradiance_view)?;
+            let prefilter_sets = prefiltered_views
+                .iter()
+                .map(|&view| set(environment.skybox.view, 1, view))
+                .collect::<anyhow::Result<Vec<_>>>()?;
+
+            // Reads from a mip about as dense as the cube faces, to keep it from aliasing. An
+            // equirect spans four faces across
+            let texels_per_face = match image {
+                EnvironmentImage::Equirect(_) => images[0].width as f32 / 4.0,
+                EnvironmentImage::Cube(_) => images[0].width as f32,
+            };
+            let source_lod = (texels_per_face / SKYBOX_SIZE as f32).log2().max(0.0);
+            let skybox_mips = Environment::LAYOUT[0].2;
+
+            ctx.immediate.submit(device, ctx.queue, |cmd| {
+                let mut barriers = Barriers::default();
+                barriers
+                    .image(
+                        ImageBarrier::new(
+                            environment.skybox.image,
+                            FORMAT,
+                            Access::Undefined,
+                            Access::ComputeWrite,
+                        )
+                        .range(cube_range(0, 1)),
+                    )
+                    .flush(device, cmd);
+                // The equirect covers every face in one dispatch, each face image only its own
+                let layers = match image {
+                    EnvironmentImage::Equirect(_) => 6,
+                    EnvironmentImage::Cube(_) => 1,
+                };
+                for (face, &set) in (0..).zip(&source_sets) {
+                    self.dispatch(
+                        device,
+                        cmd,
+                        pipeline,
+                        set,
+                        BakePushConstants {
+                            param: source_lod,
+                            sample_count: face,
+                        },
+                        SKYBOX_SIZE,
+                        layers,
+                    );
+                }
+
+                record_cube_mips(
+                    device,
+                    cmd,
+                    environment.skybox.image,
+                    SKYBOX_SIZE,
+                    skybox_mips,
+                );
+
+                barriers
+                    .image(
+                        ImageBarrier::new(
+                            environment.irradiance.image,
+                            FORMAT,
+                            Access::Undefined,
+                            Access::ComputeWrite,
+                        )
+                        .range(cube_range(0, 1)),
+                    )
+                    .image(
+                        ImageBarrier::new(
+                            environment.prefiltered.image,
+                            FORMAT,
+                            Access::Undefined,
+                            Access::ComputeWrite,
+                        )
+                        .range(cube_range(0, PREFILTERED_MIPS)),
+                    )
+                    .flush(device, cmd);
+
+                self.dispatch(
+                    device,
+                    cmd,
+                    self.irradiance,
+                    irradiance_set,
+                    BakePushConstants {
+                        param: (SKYBOX_SIZE / IRRADIANCE_SIZE).ilog2() as f32,
+                        sample_count: IRRADIANCE_STEPS,
+                    },
+                    IRRADIANCE_SIZE,
+                    6,
+                );
+                for (mip, &set) in (0..).zip(&prefilter_sets) {
+                    self.dispatch(
+                        device,
+                        cmd,
+                        self.prefilter,
+                        set,
+                        BakePushConstants {
+                            param: mip as f32 / (PREFILTERED_MIPS - 1) as f32,
+                            sample_count: PREFILTER_SAMPLES,
+                        },
+                        PREFILTERED_SIZE >> mip,
+                        6,
+                    );
+                }
+
+                barriers
+                    .image(
+                        ImageBarrier::new(
+                            environment.skybox.image,
+                            FORMAT,
+                            Access::ComputeSampled,
+                            Access::FragmentSampled,
+                        )
+                        .range(cube_range(0, skybox_mips)),
+                    )
+                    .image(
+                        ImageBarrier::new(
+                            environment.irradiance.image,
+                            FORMAT,
+                            Access::ComputeWrite,
+                            Access::FragmentSampled,
+                        )
+                        .range(cube_range(0, 1)),
+                    )
+                    .image(
+                        ImageBarrier::new(
+                            environment.prefiltered.image,
+                            FORMAT,
+                            Access::ComputeWrite,
+                            Access::FragmentSampled,
+                        )
+                        .range(cube_range(0, PREFILTERED_MIPS)),
+                    )
+                    .flush(device, cmd);
+            })
+        })();
+
+        for view in views {
+            unsafe { device.destroy_image_view(view, None) };
+        }
+        descriptor_allocator.destroy(device);
+        for mut texture in sources {
+            texture.destroy(ctx.device, ctx.allocator);
+        }
+        if let Err(err) = result {
+            environment.destroy(ctx.device, ctx.allocator);
+            return Err(err);
+        }
+
+        Ok(environment)
+    }
+
+    fn descriptor_allocator(
+        &self,
+        device: &ash::Device,
+        max_sets: u32,
+    ) -> anyhow::Result<DescriptorAllocator> {
+        DescriptorAllocator::new(
+            device,
+            max_sets,
+            &[
+                PoolSizeRatio {
+                    ty: vk::DescriptorType::SAMPLED_IMAGE,
+                    ratio: 1.0,
+                },
+                PoolSizeRatio {
+                    ty: vk::DescriptorType::SAMPLER,
+                    ratio: 1.0,
+                },
+                PoolSizeRatio {
+                    ty: vk::DescriptorType::STORAGE_IMAGE,
+                    ratio: 1.0,
+                },
+            ],
+        )
+    }
+
+    // Covers a square of `size` texels in each of `layers` array layers or cube faces
+    #[allow(clippy::too_many_arguments)]
+    fn dispatch(
+        &self,
+        device: &ash::Device,
+        cmd: vk::CommandBuffer,
+        pipeline: vk::Pipeline,
+        set: vk::DescriptorSet,
+        push_constants: BakePushConstants,
+        size: u32,
+        layers: u32,
+    ) {
+        unsafe {
+            device.cmd_bind_pipeline(cmd, vk::PipelineBindPoint::COMPUTE, pipeline);
+            device.cmd_bind_descriptor_sets(
+                cmd,
+                vk::PipelineBindPoint::COMPUTE,
+                self.layout,
+                0,
+                &[set],
+                &[],
+            );
+            device.cmd_push_constants(
+                cmd,
+                self.layout,
+                vk::ShaderStageFlags::COMPUTE,
+                0,
+                bytemuck::bytes_of(&push_constants),
+            );
+            let groups = size.max(1).div_ceil(8);
+            device.cmd_dispatch(cmd, groups, groups, layers);
+        }
+    }
+
+    pub fn destroy(&self, device: &ash::Device) {
+        unsafe {
+            for pipeline in [
+                self.equirect_to_cube,
+                self.face_to_cube,
+                self.irradiance,
+                self.prefilter,
+                self.brdf_lut,
+            ] {
+                device.destroy_pipeline(pipeline, None);
+            }
+            device.destroy_sampler(self.sampler, None);
+            device.destroy_pipeline_layout(self.layout, None);
+            device.destroy_descriptor_set_layout(self.set_layout, None);
+        }
+    }
+}
+
+fn cube_image(
+    ctx: &mut UploadContext,
+    name: &str,
+    size: u32,
+    mip_levels: u32,
+) -> anyhow::Result<AllocatedImage> {
+    let info = init::image_create_info(
+        FORMAT,
+        vk::ImageUsageFlags::STORAGE
+            | vk::ImageUsageFlags::SAMPLED
+            | vk::ImageUsageFlags::TRANSFER_SRC
+            | vk::ImageUsageFlags::TRANSFER_DST,
+        vk::Extent3D {
+            width: size,
+            height: size,
+            depth: 1,
+        },
+    )
+    .mip_levels(mip_levels)
+    .array_layers(6)
+    .flags(vk::ImageCreateFlags::CUBE_COMPATIBLE);
+    AllocatedImage::new(
+        ctx.device,
+        ctx.allocator,
+        name,
+        &info,
+        vk::ImageAspectFlags::COLOR,
+    )
+}
+
+fn cube_range(base_mip: u32, mip_count: u32) -> ImageRange {
+    ImageRange {
+        base_mip,
+        mip_count,
+        base_layer: 0,
+        layer_count: 6,
+    }
+}
+
+// Every face of every level tightly packed, advancing `offset` past them
+fn cube_copy_regions(
+    size: u32,
+    mips: u32,
+    offset: &mut vk::DeviceSize,
+) -> Vec<vk::BufferImageCopy> {
+    (0..mips)
+        .map(|mip| {
+            let mip_size = (size >> mip).max(1);
+            let region = vk::BufferImageCopy::default()
+                .buffer_offset(*offset)
+                .image_subresource(
+                    vk::ImageSubresourceLayers::default()
+                        .aspect_mask(vk::ImageAspectFlags::COLOR)
+                        .mip_level(mip)
+                        .layer_count(6),
+                )
+                .image_extent(vk::Extent3D {
+                    width: mip_size,
+                    height: mip_size,
+                    depth: 1,
+                });
+            *offset += cube_level_size(mip_size);
+            region
+        })
+        .collect()
+}
+
+fn cube_level_size(size: u32) -> vk::DeviceSize {
+    // 8 bytes per RGBA16F texel
+    size as vk::DeviceSize * size as vk::DeviceSize * 6 * 8
+}
+
+// Bytes of pixels in a cache file
+fn cache_size() -> vk::DeviceSize {
+    Environment::LAYOUT
+        .iter()
+        .flat_map(|&(_, size, mips)| {
+            (0..mips).map(move |mip| cube_level_size((size >> mip).max(1)))
+        })
+        .sum()
+}
+
+// Box filters the base level of a cube written by a compute shader down the chain, every
+// level ends up ready to be sampled by compute shaders
+fn record_cube_mips(
+    device: &ash::Device,
+    cmd: vk::CommandBuffer,
+    image: vk::Image,
+    size: u32,
+    mip_levels: u32,
+) {
+    let mut barriers = Barriers::default();
+    barriers
+        .image(
+            ImageBarrier::new(image, FORMAT, Access::ComputeWrite, Access::TransferSrc)
+                .range(cube_range(0, 1)),
+        )
+        .image(
+            ImageBarrier::new(image, FORMAT, Access::Undefined, Access::TransferDst)
+                .range(cube_range(1, mip_levels - 1)),
+        )
+        .flush(device, cmd);
+
+    let subresource = |mip| {
+        vk::ImageSubresourceLayers::default()
+            .aspect_mask(vk::ImageAspectFlags::COLOR)
+            .mip_level(mip)
+            .layer_count(6)
+    };
+    let corner = |mip: u32| {
+        let size = (size >> mip).max(1) as i32;
+        vk::Offset3D {
+            x: size,
+            y: size,
+            z: 1,
+        }
+    };
+    for mip in 1..mip_levels {
+        let regions = [vk::ImageBlit2::default()
+            .src_subresource(subresource(mip - 1))
+            .src_offsets([vk::Offset3D::default(), corner(mip - 1)])
+            .dst_subresource(subresource(mip))
+            .dst_offsets([vk::Offset3D::default(), corner(mip)])];
+        let info = vk::BlitImageInfo2::default()
+            .src_image(image)
+            .src_image_layout(vk::ImageLayout::TRANSFER_SRC_OPTIMAL)
+            .dst_image(image)
+            .dst_image_layout(vk::ImageLayout::TRANSFER_DST_OPTIMAL)
+            .regions(&regions)
+            .filter(vk::Filter::LINEAR);
+        unsafe { device.cmd_blit_image2(cmd, &info) };
+        barriers
+            .image(
+                ImageBarrier::new(image, FORMAT, Access::TransferDst, Access::TransferSrc)
+                    .range(cube_range(mip, 1)),
+            )
+            .flush(device, cmd);
+    }
+
+    barriers
+        .image(
+            ImageBarrier::new(image, FORMAT, Access::TransferSrc, Access::ComputeSampled)
+                .range(cube_range(0, mip_levels)),
+        )
+        .flush(device, cmd);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fnv1a_matches_reference_values() {
+        let hash = |bytes: &[u8]| {
+            let mut hash = Fnv1a::default();
+            hash.write(bytes);
+            hash.0
+        };
+        assert_eq!(hash(b""), 0xcbf2_9ce4_8422_2325);
+        assert_eq!(hash(b"a"), 0xaf63_dc4c_8601_ec8c);
+        assert_eq!(hash(b"foobar"), 0x8594_4171_f739_67e8);
+    }
+}
@@ -0,0 +1,66 @@
+use anyhow::Context;
+use ash::vk;
+use gpu_allocator::{
+    vulkan::{Allocation, AllocationCreateDesc, AllocationScheme, Allocator},
+    MemoryLocation,
+};
+
+use super::init;
+
+pub struct AllocatedImage {
+    pub image: vk::Image,
+    pub view: vk::ImageView,
+    pub allocation: Option<Allocation>,
+}
+
+impl AllocatedImage {
+    pub fn new(
+        device: &ash::Device,
+        allocator: &mut Allocator,
+        name: &str,
+        info: &vk::ImageCreateInfo,
+        aspect_mask: vk::ImageAspectFlags,
+    ) -> anyhow::Result<Self> {
+        let image = unsafe { device.create_image(info, None) }?;
+        let requirements = unsafe { device.get_image_memory_requirements(image) };
+
+        let allocation = allocator
+            .allocate(&AllocationCreateDesc {
+                name,
+                requirements,
+                location: MemoryLocation::GpuOnly,
+                linear: false,
+                allocation_scheme: AllocationScheme::GpuAllocatorManaged,
+            })
+            .with_context(|| format!("Failed to allocate memory for {name}"))?;
+
+        unsafe { device.bind_image_memory(image, allocation.memory(), allocation.offset()) }?;
+
+        // The view covers every mip level, and every face of a cubemap or layer of an array
+        let mut view_info = init::imageview_create_info(info.format, image, aspect_mask);
+        view_info.subresource_range.level_count = info.mip_levels;
+        view_info.subresource_range.layer_count = info.array_layers;
+        if info.flags.contains(vk::ImageCreateFlags::CUBE_COMPATIBLE) {
+            view_info.view_type = vk::ImageViewType::CUBE;
+        } else if info.array_layers > 1 {
+            view_info.view_type = vk::ImageViewType::TYPE_2D_ARRAY;
+        }
+        let view = unsafe { device.create_image_view(&view_info, None) }?;
+
+        Ok(Self {
+            image,
+            view,
+            allocation: Some(allocation),
+        })
+    }
+
+    pub fn destroy(&mut self, device: &ash::Device, allocator: &mut Allocator) {
+        unsafe {
+            device.destroy_image_view(self.view, None);
+            device.destroy_image(self.image, None);
+        }
+        if let Some(allocation) = self.allocation.take() {
+            allocator.free(allocation).unwrap();
+        }
+    }
+}
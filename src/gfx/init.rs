@@ -74,13 +74,6 @@ pub fn choose_physical_device(instance: &ash::Instance) -> anyhow::Result<Physic
         .context("No Graphics!")
 }
 
-pub fn image_subresource_range(aspect_mask: vk::ImageAspectFlags) -> vk::ImageSubresourceRange {
-    vk::ImageSubresourceRange::default()
-        .aspect_mask(aspect_mask)
-        .level_count(vk::REMAINING_MIP_LEVELS)
-        .layer_count(vk::REMAINING_ARRAY_LAYERS)
-}
-
 pub fn sem_submit_info(
     stage_mask: vk::PipelineStageFlags2,
     sem: vk::Semaphore,
@@ -110,3 +103,119 @@ pub fn submit_info<'a>(
         ..Default::default()
     }
 }
+
+pub fn image_create_info(
+    format: vk::Format,
+    usage: vk::ImageUsageFlags,
+    extent: vk::Extent3D,
+) -> vk::ImageCreateInfo<'static> {
+    vk::ImageCreateInfo::default()
+        .image_type(vk::ImageType::TYPE_2D)
+        .format(format)
+        .extent(extent)
+        .mip_levels(1)
+        .array_layers(1)
+        .samples(vk::SampleCountFlags::TYPE_1)
+        .tiling(vk::ImageTiling::OPTIMAL)
+        .usage(usage)
+}
+
+pub fn imageview_create_info(
+    format: vk::Format,
+    image: vk::Image,
+    aspect_mask: vk::ImageAspectFlags,
+) -> vk::ImageViewCreateInfo<'static> {
+    vk::ImageViewCreateInfo::default()
+        .view_type(vk::ImageViewType::TYPE_2D)
+        .image(image)
+        .format(format)
+        .subresource_range(
+            vk::ImageSubresourceRange::default()
+                .aspect_mask(aspect_mask)
+                .level_count(1)
+                .layer_count(1),
+        )
+}
+
+pub fn color_attachment_info(
+    view: vk::ImageView,
+    clear: Option<vk::ClearValue>,
+    layout: vk::ImageLayout,
+) -> vk::RenderingAttachmentInfo<'static> {
+    vk::RenderingAttachmentInfo::default()
+        .image_view(view)
+        .image_layout(layout)
+        .load_op(if clear.is_some() {
+            vk::AttachmentLoadOp::CLEAR
+        } else {
+            vk::AttachmentLoadOp::LOAD
+        })
+        .store_op(vk::AttachmentStoreOp::STORE)
+        .clear_value(clear.unwrap_or_default())
+}
+
+pub fn depth_attachment_info(
+    view: vk::ImageView,
+    clear: Option<vk::ClearValue>,
+    layout: vk::ImageLayout,
+) -> vk::RenderingAttachmentInfo<'static> {
+    color_attachment_info(view, clear, layout)
+}
+
+// The stencil attachment shares the depth view when the layout says it has one
+pub fn rendering_info<'a>(
+    extent: vk::Extent2D,
+    color_attachments: &'a [vk::RenderingAttachmentInfo<'a>],
+    depth_attachment: Option<&'a vk::RenderingAttachmentInfo<'a>>,
+) -> vk::RenderingInfo<'a> {
+    let info = vk::RenderingInfo::default()
+        .render_area(vk::Rect2D {
+            offset: vk::Offset2D::default(),
+            extent,
+        })
+        .layer_count(1)
+        .color_attachments(color_attachments);
+
+    match depth_attachment {
+        Some(depth)
+            if matches!(
+                depth.image_layout,
+                vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL
+                    | vk::ImageLayout::DEPTH_STENCIL_READ_ONLY_OPTIMAL
+            ) =>
+        {
+            info.depth_attachment(depth).stencil_attachment(depth)
+        }
+        Some(depth) => info.depth_attachment(depth),
+        None => info,
+    }
+}
+
+pub fn shader_stage_create_info(
+    stage: vk::ShaderStageFlags,
+    module: vk::ShaderModule,
+    entry_point: &'static std::ffi::CStr,
+) -> vk::PipelineShaderStageCreateInfo<'static> {
+    vk::PipelineShaderStageCreateInfo::default()
+        .stage(stage)
+        .module(module)
+        .name(entry_point)
+}
+
+pub fn instance_extension_supported(entry: &ash::Entry, name: &std::ffi::CStr) -> bool {
+    unsafe { entry.enumerate_instance_extension_properties(None) }
+        .unwrap_or_default()
+        .iter()
+        .any(|extension| extension.extension_name_as_c_str() == Ok(name))
+}
+
+pub fn device_extension_supported(
+    instance: &ash::Instance,
+    physical_device: PhysicalDevice,
+    name: &std::ffi::CStr,
+) -> bool {
+    unsafe { instance.enumerate_device_extension_properties(physical_device) }
+        .unwrap_or_default()
+        .iter()
+        .any(|extension| extension.extension_name_as_c_str() == Ok(name))
+}
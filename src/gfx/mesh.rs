@@ -0,0 +1,352 @@
+use std::mem::offset_of;
+
+use ash::vk;
+use glam::Mat4;
+use gpu_allocator::vulkan::Allocator;
+
+use crate::assets::{self, AlphaMode};
+
+use super::{
+    barrier::Access,
+    buffer::AllocatedBuffer,
+    descriptors::DescriptorLayoutBuilder,
+    model::{DrawItem, GpuModel},
+    pipeline::{self, include_shader, PipelineBuilder},
+    upload::UploadContext,
+    util,
+};
+
+pub struct GpuMesh {
+    pub vertex_buffer: AllocatedBuffer,
+    pub index_buffer: AllocatedBuffer,
+    pub primitives: Vec<assets::Primitive>,
+}
+
+impl GpuMesh {
+    pub fn upload(ctx: &mut UploadContext, mesh: &assets::Mesh) -> anyhow::Result<Self> {
+        let mut vertex_buffer = ctx.create_buffer(
+            "vertex buffer",
+            vk::BufferUsageFlags::VERTEX_BUFFER,
+            bytemuck::cast_slice(&mesh.vertices),
+            Access::VertexBuffer,
+        )?;
+        let index_buffer = match ctx.create_buffer(
+            "index buffer",
+            vk::BufferUsageFlags::INDEX_BUFFER,
+            bytemuck::cast_slice(&mesh.indices),
+            Access::IndexBuffer,
+        ) {
+            Ok(buffer) => buffer,
+            Err(err) => {
+                vertex_buffer.destroy(ctx.device, ctx.allocator);
+                return Err(err);
+            }
+        };
+
+        Ok(Self {
+            vertex_buffer,
+            index_buffer,
+            primitives: mesh.primitives.clone(),
+        })
+    }
+
+    pub fn destroy(&mut self, device: &ash::Device, allocator: &mut Allocator) {
+        self.vertex_buffer.destroy(device, allocator);
+        self.index_buffer.destroy(device, allocator);
+    }
+}
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct MeshPushConstants {
+    pub model: Mat4,
+    pub previous_model: Mat4,
+}
+
+/// Which primitives a draw covers and where they end up
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MeshPhase {
+    /// Opaque and masked primitives, lit straight into the colour attachment
+    Opaque,
+    /// Opaque and masked primitives, written to the G-buffer's attachments
+    GBuffer,
+    /// Blended primitives only, lit forward over what's already been drawn
+    Blended,
+    /// Opaque and masked primitives' depth and normals, for screen-space effects that have to
+    /// run before forward shading
+    Prepass,
+}
+
+/// Base colour, metallic-roughness, normal, occlusion and emissive textures, after the
+/// material's constants
+pub fn create_material_set_layout(device: &ash::Device) -> anyhow::Result<vk::DescriptorSetLayout> {
+    DescriptorLayoutBuilder::default()
+        .add_binding(0, vk::DescriptorType::UNIFORM_BUFFER)
+        .add_binding(1, vk::DescriptorType::SAMPLED_IMAGE)
+        .add_binding(2, vk::DescriptorType::SAMPLED_IMAGE)
+        .add_binding(3, vk::DescriptorType::SAMPLED_IMAGE)
+        .add_binding(4, vk::DescriptorType::SAMPLED_IMAGE)
+        .add_binding(5, vk::DescriptorType::SAMPLED_IMAGE)
+        .add_binding(6, vk::DescriptorType::SAMPLER)
+        .build(device, vk::ShaderStageFlags::FRAGMENT)
+}
+
+// Opaque and masked materials are drawn first, in any order, then blended ones back to front
+pub struct MeshPass {
+    layout: vk::PipelineLayout,
+    // One per alpha mode and sidedness, see `pipeline_index`
+    pipelines: [vk::Pipeline; 6],
+    // The same for opaque and masked materials, without blending there's no third
+    gbuffer_pipelines: [vk::Pipeline; 4],
+    prepass_pipelines: [vk::Pipeline; 4],
+}
+
+fn pipeline_index(alpha_mode: AlphaMode, double_sided: bool) -> usize {
+    alpha_mode as usize * 2 + double_sided as usize
+}
+
+impl MeshPass {
+    /// `set_layouts` are the scene's then the material's. Forward and G-buffer formats both end
+    /// with motion vectors. Only the forward pipelines are multisampled, the G-buffer and prepass
+    /// always have one sample. The prepass writes to the G-buffer's normal attachment
+    pub fn new(
+        device: &ash::Device,
+        set_layouts: [vk::DescriptorSetLayout; 2],
+        color_formats: &[vk::Format],
+        gbuffer_formats: &[vk::Format],
+        depth_format: vk::Format,
+        depth_compare_op: vk::CompareOp,
+        samples: vk::SampleCountFlags,
+    ) -> anyhow::Result<Self> {
+        let layout = pipeline::create_pipeline_layout(
+            device,
+            &set_layouts,
+            &[pipeline::push_constant_range::<MeshPushConstants>(
+                vk::ShaderStageFlags::VERTEX,
+            )],
+        )?;
+
+        let bindings = [vk::VertexInputBindingDescription {
+            binding: 0,
+            stride: size_of::<assets::Vertex>() as u32,
+            input_rate: vk::VertexInputRate::VERTEX,
+        }];
+        let attribute = |location, format, offset: usize| vk::VertexInputAttributeDescription {
+            location,
+            binding: 0,
+            format,
+            offset: offset as u32,
+        };
+        let attributes = [
+            attribute(
+                0,
+                vk::Format::R32G32B32_SFLOAT,
+                offset_of!(assets::Vertex, position),
+            ),
+            attribute(
+                1,
+                vk::Format::R32G32B32_SFLOAT,
+                offset_of!(assets::Vertex, normal),
+            ),
+            attribute(2, vk::Format::R32G32_SFLOAT, offset_of!(assets::Vertex, uv)),
+            attribute(
+                3,
+                vk::Format::R32G32B32A32_SFLOAT,
+                offset_of!(assets::Vertex, color),
+            ),
+            attribute(
+                4,
+                vk::Format::R32G32B32A32_SFLOAT,
+                offset_of!(assets::Vertex, tangent),
+            ),
+        ];
+
+        let module = pipeline::create_shader_module(device, include_shader!("mesh"))?;
+        let base = PipelineBuilder::default()
+            .layout(layout)
+            .vertex_input(&bindings, &attributes)
+            .depth_format(depth_format);
+        let forward = base
+            .clone()
+            .color_attachment_formats(color_formats)
+            .samples(samples);
+        let prepass = base
+            .clone()
+            .color_attachment_formats(&gbuffer_formats[1..2]);
+        let gbuffer = base.color_attachment_formats(gbuffer_formats);
+
+        let mut pipelines = [vk::Pipeline::null(); 6];
+        let mut gbuffer_pipelines = [vk::Pipeline::null(); 4];
+        let mut prepass_pipelines = [vk::Pipeline::null(); 4];
+        let mut result = Ok(());
+        let mut build = |builder: PipelineBuilder, set: &mut [vk::Pipeline], alpha_mode| {
+            for double_sided in [false, true] {
+                let cull_mode = if double_sided {
+                    vk::CullModeFlags::NONE
+                } else {
+                    vk::CullModeFlags::BACK
+                };
+                match builder.clone().cull_mode(cull_mode).build(device) {
+                    Ok(pipeline) => set[pipeline_index(alpha_mode, double_sided)] = pipeline,
+                    Err(err) => result = Err(err),
+                }
+            }
+        };
+        for alpha_mode in [AlphaMode::Opaque, AlphaMode::Mask, AlphaMode::Blend] {
+            let (entry, gbuffer_entries, builder) = match alpha_mode {
+                AlphaMode::Opaque => (
+                    c"fs_opaque",
+                    Some((c"fs_gbuffer_opaque", c"fs_prepass_opaque")),
+                    forward.clone(),
+                ),
+                AlphaMode::Mask => (
+                    c"fs_mask",
+                    Some((c"fs_gbuffer_mask", c"fs_prepass_mask")),
+                    forward.clone(),
+                ),
+                // Blended surfaces are tested against what's opaque but don't hide each other
+                AlphaMode::Blend => (c"fs_blend", None, forward.clone().alpha_blending()),
+            };
+            let write_depth = alpha_mode != AlphaMode::Blend;
+            build(
+                builder
+                    .shaders(module, c"vs_main", module, entry)
+                    .depth_test(write_depth, depth_compare_op),
+                &mut pipelines,
+                alpha_mode,
+            );
+            if let Some((gbuffer_entry, prepass_entry)) = gbuffer_entries {
+                build(
+                    gbuffer
+                        .clone()
+                        .shaders(module, c"vs_main", module, gbuffer_entry)
+                        .depth_test(write_depth, depth_compare_op),
+                    &mut gbuffer_pipelines,
+                    alpha_mode,
+                );
+                build(
+                    prepass
+                        .clone()
+                        .shaders(module, c"vs_main", module, prepass_entry)
+                        .depth_test(write_depth, depth_compare_op),
+                    &mut prepass_pipelines,
+                    alpha_mode,
+                );
+            }
+        }
+        unsafe { device.destroy_shader_module(module, None) };
+
+        let pass = Self {
+            layout,
+            pipelines,
+            gbuffer_pipelines,
+            prepass_pipelines,
+        };
+        if let Err(err) = result {
+            pass.destroy(device);
+            return Err(err);
+        }
+        Ok(pass)
+    }
+
+    /// `draws` has to be sorted back to front for blending to come out right
+    #[allow(clippy::too_many_arguments)]
+    pub fn draw(
+        &self,
+        device: &ash::Device,
+        cmd: vk::CommandBuffer,
+        extent: vk::Extent2D,
+        scene_set: vk::DescriptorSet,
+        model: &GpuModel,
+        draws: &[DrawItem],
+        phase: MeshPhase,
+    ) {
+        unsafe {
+            device.cmd_bind_descriptor_sets(
+                cmd,
+                vk::PipelineBindPoint::GRAPHICS,
+                self.layout,
+                0,
+                &[scene_set],
+                &[],
+            );
+            util::set_viewport_and_scissor(device, cmd, extent);
+        }
+
+        let mut bound_pipeline = vk::Pipeline::null();
+        let mut draw_primitive = |draw: &DrawItem, primitive: &assets::Primitive| {
+            let mesh = &model.meshes[draw.mesh];
+            let material = model.material(primitive.material);
+            let index = pipeline_index(material.alpha_mode, material.double_sided);
+            let pipeline = match phase {
+                MeshPhase::GBuffer => self.gbuffer_pipelines[index],
+                MeshPhase::Prepass => self.prepass_pipelines[index],
+                MeshPhase::Opaque | MeshPhase::Blended => self.pipelines[index],
+            };
+            let push_constants = MeshPushConstants {
+                model: draw.transform,
+                previous_model: draw.previous_transform,
+            };
+
+            unsafe {
+                if pipeline != bound_pipeline {
+                    device.cmd_bind_pipeline(cmd, vk::PipelineBindPoint::GRAPHICS, pipeline);
+                    bound_pipeline = pipeline;
+                }
+                device.cmd_push_constants(
+                    cmd,
+                    self.layout,
+                    vk::ShaderStageFlags::VERTEX,
+                    0,
+                    bytemuck::bytes_of(&push_constants),
+                );
+                device.cmd_bind_vertex_buffers(cmd, 0, &[mesh.vertex_buffer.buffer], &[0]);
+                device.cmd_bind_index_buffer(
+                    cmd,
+                    mesh.index_buffer.buffer,
+                    0,
+                    vk::IndexType::UINT32,
+                );
+                device.cmd_bind_descriptor_sets(
+                    cmd,
+                    vk::PipelineBindPoint::GRAPHICS,
+                    self.layout,
+                    1,
+                    &[material.set],
+                    &[],
+                );
+                device.cmd_draw_indexed(cmd, primitive.index_count, 1, primitive.first_index, 0, 0);
+            }
+        };
+
+        let is_blended = |primitive: &assets::Primitive| {
+            model.material(primitive.material).alpha_mode == AlphaMode::Blend
+        };
+        let mut blended = Vec::new();
+        for draw in draws {
+            for primitive in &model.meshes[draw.mesh].primitives {
+                if is_blended(primitive) {
+                    blended.push((draw, primitive));
+                } else if phase != MeshPhase::Blended {
+                    draw_primitive(draw, primitive);
+                }
+            }
+        }
+        if phase != MeshPhase::Blended {
+            return;
+        }
+        for (draw, primitive) in blended {
+            draw_primitive(draw, primitive);
+        }
+    }
+
+    pub fn destroy(&self, device: &ash::Device) {
+        unsafe {
+            let pipelines = self.pipelines.iter().chain(&self.gbuffer_pipelines);
+            for &pipeline in pipelines.chain(&self.prepass_pipelines) {
+                device.destroy_pipeline(pipeline, None);
+            }
+            device.destroy_pipeline_layout(self.layout, None);
+        }
+    }
+}
@@ -0,0 +1,339 @@
+use ash::vk;
+
+use super::{
+    barrier::{Access, Barriers, ImageBarrier, ImageRange},
+    descriptors::{DescriptorAllocator, DescriptorLayoutBuilder, DescriptorWriter, PoolSizeRatio},
+    init,
+    pipeline::{self, include_shader},
+    upload::UploadContext,
+};
+
+// Formats textures get uploaded as
+const TEXTURE_FORMATS: &[vk::Format] = &[
+    vk::Format::R8G8B8A8_UNORM,
+    vk::Format::R8G8B8A8_SRGB,
+    vk::Format::R32G32B32A32_SFLOAT,
+];
+
+// The only format `mipgen.wgsl` can write
+const COMPUTE_FORMAT: vk::Format = vk::Format::R32G32B32A32_SFLOAT;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Method {
+    Blit,
+    Compute,
+}
+
+/// Fills mip chains on the GPU, blitting where the format allows linear filtering
+pub struct MipGenerator {
+    blit_formats: Vec<vk::Format>,
+    compute_formats: Vec<vk::Format>,
+    set_layout: vk::DescriptorSetLayout,
+    layout: vk::PipelineLayout,
+    pipeline: vk::Pipeline,
+}
+
+impl MipGenerator {
+    pub fn new(
+        instance: &ash::Instance,
+        physical_device: vk::PhysicalDevice,
+        device: &ash::Device,
+    ) -> anyhow::Result<Self> {
+        let features = |format| unsafe {
+            instance
+                .get_physical_device_format_properties(physical_device, format)
+                .optimal_tiling_features
+        };
+        let blit_features = vk::FormatFeatureFlags::BLIT_SRC
+            | vk::FormatFeatureFlags::BLIT_DST
+            | vk::FormatFeatureFlags::SAMPLED_IMAGE_FILTER_LINEAR;
+        let blit_formats = TEXTURE_FORMATS
+            .iter()
+            .copied()
+            .filter(|&format| features(format).contains(blit_features))
+            .collect();
+        let compute_formats = TEXTURE_FORMATS
+            .iter()
+            .copied()
+            .filter(|&format| {
+                format == COMPUTE_FORMAT
+                    && features(format).contains(vk::FormatFeatureFlags::STORAGE_IMAGE)
+            })
+            .collect();
+
+        let set_layout = DescriptorLayoutBuilder::default()
+            .add_binding(0, vk::DescriptorType::SAMPLED_IMAGE)
+            .add_binding(1, vk::DescriptorType::STORAGE_IMAGE)
+            .build(device, vk::ShaderStageFlags::COMPUTE)?;
+        let layout = pipeline::create_pipeline_layout(device, &[set_layout], &[])?;
+
+        let module = pipeline::create_shader_module(device, include_shader!("mipgen"))?;
+        let pipeline = pipeline::create_compute_pipeline(device, layout, module, c"cs_main");
+        unsafe { device.destroy_shader_module(module, None) };
+        let pipeline = pipeline?;
+
+        Ok(Self {
+            blit_formats,
+            compute_formats,
+            set_layout,
+            layout,
+            pipeline,
+        })
+    }
+
+    fn method(&self, format: vk::Format) -> Option<Method> {
+        if self.blit_formats.contains(&format) {
+            Some(Method::Blit)
+        } else if self.compute_formats.contains(&format) {
+            Some(Method::Compute)
+        } else {
+            None
+        }
+    }
+
+    /// A full chain when it can be generated, otherwise just the base level
+    pub fn mip_levels(&self, format: vk::Format, extent: vk::Extent2D) -> u32 {
+        match self.method(format) {
+            Some(_) => u32::BITS - extent.width.max(extent.height).max(1).leading_zeros(),
+            None => 1,
+        }
+    }
+
+    /// Extra usage an image needs for its mips to be generated
+    pub fn usage(&self, format: vk::Format) -> vk::ImageUsageFlags {
+        match self.method(format) {
+            Some(Method::Blit) => vk::ImageUsageFlags::TRANSFER_SRC,
+            Some(Method::Compute) => vk::ImageUsageFlags::STORAGE,
+            None => vk::ImageUsageFlags::empty(),
+        }
+    }
+
+    /// Expects every level in `TransferDst` with the base level filled, and leaves them all
+    /// ready to be sampled from fragment shaders
+    pub fn generate(
+        &self,
+        ctx: &mut UploadContext,
+        image: vk::Image,
+        format: vk::Format,
+        extent: vk::Extent2D,
+        mip_levels: u32,
+    ) -> anyhow::Result<()> {
+        match self.method(format) {
+            Some(Method::Compute) if mip_levels > 1 => {
+                self.generate_compute(ctx, image, format, extent, mip_levels)
+            }
+            _ => self.generate_blit(ctx, image, format, extent, mip_levels),
+        }
+    }
+
+    fn generate_blit(
+        &self,
+        ctx: &mut UploadContext,
+        image: vk::Image,
+        format: vk::Format,
+        extent: vk::Extent2D,
+        mip_levels: u32,
+    ) -> anyhow::Result<()> {
+        let device = ctx.device;
+        ctx.immediate.submit(device, ctx.queue, |cmd| {
+            let mut barriers = Barriers::default();
+            for level in 1..mip_levels {
+                barriers
+                    .image(
+                        ImageBarrier::new(image, format, Access::TransferDst, Access::TransferSrc)
+                            .range(mip_range(level - 1, 1)),
+                    )
+                    .flush(device, cmd);
+
+                let subresource = |level| {
+                    vk::ImageSubresourceLayers::default()
+                        .aspect_mask(vk::ImageAspectFlags::COLOR)
+                        .mip_level(level)
+                        .layer_count(1)
+                };
+                let region = vk::ImageBlit2::default()
+                    .src_subresource(subresource(level - 1))
+                    .src_offsets([vk::Offset3D::default(), mip_offset(extent, level - 1)])
+                    .dst_subresource(subresource(level))
+                    .dst_offsets([vk::Offset3D::default(), mip_offset(extent, level)]);
+                let regions = [region];
+                let info = vk::BlitImageInfo2::default()
+                    .src_image(image)
+                    .src_image_layout(vk::ImageLayout::TRANSFER_SRC_OPTIMAL)
+                    .dst_image(image)
+                    .dst_image_layout(vk::ImageLayout::TRANSFER_DST_OPTIMAL)
+                    .regions(&regions)
+                    .filter(vk::Filter::LINEAR);
+                unsafe { device.cmd_blit_image2(cmd, &info) };
+            }
+
+            // Every level but the last was a blit source
+            if mip_levels > 1 {
+                barriers.image(
+                    ImageBarrier::new(image, format, Access::TransferSrc, Access::FragmentSampled)
+                        .range(mip_range(0, mip_levels - 1)),
+                );
+            }
+            barriers
+                .image(
+                    ImageBarrier::new(image, format, Access::TransferDst, Access::FragmentSampled)
+                        .range(mip_range(mip_levels - 1, 1)),
+                )
+                .flush(device, cmd);
+        })
+    }
+
+    fn generate_compute(
+        &self,
+        ctx: &mut UploadContext,
+        image: vk::Image,
+        format: vk::Format,
+        extent: vk::Extent2D,
+        mip_levels: u32,
+    ) -> anyhow::Result<()> {
+        let device = ctx.device;
+
+        let mut views = Vec::with_capacity(mip_levels as usize);
+        let descriptor_allocator = DescriptorAllocator::new(
+            device,
+            mip_levels - 1,
+            &[
+                PoolSizeRatio {
+                    ty: vk::DescriptorType::SAMPLED_IMAGE,
+                    ratio: 1.0,
+                },
+                PoolSizeRatio {
+                    ty: vk::DescriptorType::STORAGE_IMAGE,
+                    ratio: 1.0,
+                },
+            ],
+        )?;
+        let result = (|| {
+            for level in 0..mip_levels {
+                let mut info =
+                    init::imageview_create_info(format, image, vk::ImageAspectFlags::COLOR);
+                info.subresource_range.base_mip_level = level;
+                views.push(unsafe { device.create_image_view(&info, None) }?);
+            }
+
+            let mut sets = Vec::with_capacity(mip_levels as usize - 1);
+            for pair in views.windows(2) {
+                let set = descriptor_allocator.allocate(device, self.set_layout)?;
+                DescriptorWriter::default()
+                    .write_image(
+                        0,
+                        pair[0],
+                        vk::Sampler::null(),
+                        vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+                        vk::DescriptorType::SAMPLED_IMAGE,
+                    )
+                    .write_image(
+                        1,
+                        pair[1],
+                        vk::Sampler::null(),
+                        vk::ImageLayout::GENERAL,
+                        vk::DescriptorType::STORAGE_IMAGE,
+                    )
+                    .update_set(device, set);
+                sets.push(set);
+            }
+
+            ctx.immediate.submit(device, ctx.queue, |cmd| {
+                unsafe {
+                    device.cmd_bind_pipeline(cmd, vk::PipelineBindPoint::COMPUTE, self.pipeline)
+                };
+
+                let mut barriers = Barriers::default();
+                for level in 1..mip_levels {
+                    let src_prev = if level == 1 {
+                        Access::TransferDst
+                    } else {
+                        Access::ComputeWrite
+                    };
+                    barriers
+                        .image(
+                            ImageBarrier::new(image, format, src_prev, Access::ComputeSampled)
+                                .range(mip_range(level - 1, 1)),
+                        )
+                        .image(
+                            ImageBarrier::new(
+                                image,
+                                format,
+                                Access::TransferDst,
+                                Access::ComputeWrite,
+                            )
+                            .range(mip_range(level, 1))
+                            .discard(),
+                        )
+                        .flush(device, cmd);
+
+                    let width = (extent.width >> level).max(1);
+                    let height = (extent.height >> level).max(1);
+                    unsafe {
+                        device.cmd_bind_descriptor_sets(
+                            cmd,
+                            vk::PipelineBindPoint::COMPUTE,
+                            self.layout,
+                            0,
+                            &[sets[level as usize - 1]],
+                            &[],
+                        );
+                        device.cmd_dispatch(cmd, width.div_ceil(8), height.div_ceil(8), 1);
+                    }
+                }
+
+                barriers
+                    .image(
+                        ImageBarrier::new(
+                            image,
+                            format,
+                            Access::ComputeSampled,
+                            Access::FragmentSampled,
+                        )
+                        .range(mip_range(0, mip_levels - 1)),
+                    )
+                    .image(
+                        ImageBarrier::new(
+                            image,
+                            format,
+                            Access::ComputeWrite,
+                            Access::FragmentSampled,
+                        )
+                        .range(mip_range(mip_levels - 1, 1)),
+                    )
+                    .flush(device, cmd);
+            })
+        })();
+
+        for view in views {
+            unsafe { device.destroy_image_view(view, None) };
+        }
+        descriptor_allocator.destroy(device);
+        result
+    }
+
+    pub fn destroy(&self, device: &ash::Device) {
+        unsafe {
+            device.destroy_pipeline(self.pipeline, None);
+            device.destroy_pipeline_layout(self.layout, None);
+            device.destroy_descriptor_set_layout(self.set_layout, None);
+        }
+    }
+}
+
+fn mip_range(base_mip: u32, mip_count: u32) -> ImageRange {
+    ImageRange {
+        base_mip,
+        mip_count,
+        base_layer: 0,
+        layer_count: 1,
+    }
+}
+
+fn mip_offset(extent: vk::Extent2D, level: u32) -> vk::Offset3D {
+    vk::Offset3D {
+        x: (extent.width >> level).max(1) as i32,
+        y: (extent.height >> level).max(1) as i32,
+        z: 1,
+    }
+}
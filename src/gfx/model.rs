@@ -0,0 +1,264 @@
+use ash::vk;
+use glam::{Mat4, Vec4};
+use gpu_allocator::vulkan::Allocator;
+
+use crate::assets;
+
+use super::{
+    barrier::Access,
+    buffer::AllocatedBuffer,
+    descriptors::{DescriptorAllocator, DescriptorWriter, PoolSizeRatio},
+    mesh::GpuMesh,
+    texture::Texture,
+    upload::UploadContext,
+};
+
+// Upper bound of minUniformBufferOffsetAlignment, so every material can start at a multiple
+const MATERIAL_STRIDE: usize = 256;
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy, bytemuck::Pod, bytemuck::Zeroable)]
+struct MaterialConstants {
+    base_color_factor: Vec4,
+    emissive_factor: Vec4,
+    metallic_factor: f32,
+    roughness_factor: f32,
+    normal_scale: f32,
+    occlusion_strength: f32,
+    alpha_cutoff: f32,
+    _pad: [f32; 3],
+}
+
+impl From<&assets::Material> for MaterialConstants {
+    fn from(material: &assets::Material) -> Self {
+        Self {
+            base_color_factor: material.base_color_factor,
+            emissive_factor: material.emissive_factor.extend(0.0),
+            metallic_factor: material.metallic_factor,
+            roughness_factor: material.roughness_factor,
+            normal_scale: material.normal_scale,
+            occlusion_strength: material.occlusion_strength,
+            alpha_cutoff: material.alpha_cutoff,
+            _pad: [0.0; 3],
+        }
+    }
+}
+
+/// One mesh instance, rebuilt from the scene every frame
+pub struct DrawItem {
+    pub mesh: usize,
+    pub transform: Mat4,
+    /// Last frame's transform, for motion vectors
+    pub previous_transform: Mat4,
+}
+
+/// Defaults for textures a material doesn't have
+pub struct MaterialDefaults {
+    pub white: vk::ImageView,
+    pub flat_normal: vk::ImageView,
+    pub sampler: vk::Sampler,
+}
+
+/// What the mesh pass needs to pick a pipeline and bind a material
+pub struct GpuMaterial {
+    pub set: vk::DescriptorSet,
+    pub alpha_mode: assets::AlphaMode,
+    pub double_sided: bool,
+}
+
+/// Everything of an `assets::Model` the GPU needs to draw it
+pub struct GpuModel {
+    pub meshes: Vec<GpuMesh>,
+    textures: Vec<Texture>,
+    material_buffer: AllocatedBuffer,
+    // The last one is the fallback for primitives without a material
+    materials: Vec<GpuMaterial>,
+    descriptor_allocator: DescriptorAllocator,
+}
+
+impl GpuModel {
+    /// Nothing uploaded is left behind if any of it fails
+    pub fn upload(
+        ctx: &mut UploadContext,
+        model: &assets::Model,
+        material_set_layout: vk::DescriptorSetLayout,
+        defaults: &MaterialDefaults,
+    ) -> anyhow::Result<Self> {
+        let mut meshes = Vec::with_capacity(model.meshes.len());
+        let mut textures = Vec::with_capacity(model.images.len());
+        let result = upload_meshes_and_textures(ctx, model, &mut meshes, &mut textures)
+            .and_then(|()| create_materials(ctx, model, &textures, material_set_layout, defaults));
+
+        match result {
+            Ok((material_buffer, materials, descriptor_allocator)) => Ok(Self {
+                meshes,
+                textures,
+                material_buffer,
+                materials,
+                descriptor_allocator,
+            }),
+            Err(err) => {
+                for mesh in &mut meshes {
+                    mesh.destroy(ctx.device, ctx.allocator);
+                }
+                for texture in &mut textures {
+                    texture.destroy(ctx.device, ctx.allocator);
+                }
+                Err(err)
+            }
+        }
+    }
+
+    pub fn material(&self, material: Option<usize>) -> &GpuMaterial {
+        material
+            .and_then(|idx| self.materials.get(idx))
+            .or(self.materials.last())
+            .expect("the fallback material is always there")
+    }
+
+    pub fn destroy(&mut self, device: &ash::Device, allocator: &mut Allocator) {
+        for mesh in &mut self.meshes {
+            mesh.destroy(device, allocator);
+        }
+        for texture in &mut self.textures {
+            texture.destroy(device, allocator);
+        }
+        self.material_buffer.destroy(device, allocator);
+        self.descriptor_allocator.destroy(device);
+    }
+}
+
+// Pushed as they're uploaded, so whatever made it is there to destroy if a later one fails
+fn upload_meshes_and_textures(
+    ctx: &mut UploadContext,
+    model: &assets::Model,
+    meshes: &mut Vec<GpuMesh>,
+    textures: &mut Vec<Texture>,
+) -> anyhow::Result<()> {
+    for mesh in &model.meshes {
+        meshes.push(GpuMesh::upload(ctx, mesh)?);
+    }
+    for (idx, image) in model.images.iter().enumerate() {
+        textures.push(Texture::from_image(
+            ctx,
+            &format!("model texture {idx}"),
+            image,
+        )?);
+    }
+    Ok(())
+}
+
+// Every material's constants in one buffer, and a set per material. Cleans up after itself on
+// failure
+fn create_materials(
+    ctx: &mut UploadContext,
+    model: &assets::Model,
+    textures: &[Texture],
+    material_set_layout: vk::DescriptorSetLayout,
+    defaults: &MaterialDefaults,
+) -> anyhow::Result<(AllocatedBuffer, Vec<GpuMaterial>, DescriptorAllocator)> {
+    let default_material = assets::Material::default();
+    let materials: Vec<&assets::Material> = model
+        .materials
+        .iter()
+        .chain(std::iter::once(&default_material))
+        .collect();
+
+    let mut material_data = vec![0u8; materials.len() * MATERIAL_STRIDE];
+    for (material, chunk) in materials
+        .iter()
+        .zip(material_data.chunks_exact_mut(MATERIAL_STRIDE))
+    {
+        let constants = MaterialConstants::from(*material);
+        chunk[..size_of::<MaterialConstants>()].copy_from_slice(bytemuck::bytes_of(&constants));
+    }
+    let mut material_buffer = ctx.create_buffer(
+        "material buffer",
+        vk::BufferUsageFlags::UNIFORM_BUFFER,
+        &material_data,
+        Access::UniformRead,
+    )?;
+
+    let descriptor_allocator = match DescriptorAllocator::new(
+        ctx.device,
+        materials.len() as u32,
+        &[
+            PoolSizeRatio {
+                ty: vk::DescriptorType::UNIFORM_BUFFER,
+                ratio: 1.0,
+            },
+            PoolSizeRatio {
+                ty: vk::DescriptorType::SAMPLED_IMAGE,
+                ratio: 5.0,
+            },
+            PoolSizeRatio {
+                ty: vk::DescriptorType::SAMPLER,
+                ratio: 1.0,
+            },
+        ],
+    ) {
+        Ok(descriptor_allocator) => descriptor_allocator,
+        Err(err) => {
+            material_buffer.destroy(ctx.device, ctx.allocator);
+            return Err(err);
+        }
+    };
+
+    let texture_view = |texture: Option<usize>, default: vk::ImageView| {
+        texture
+            .and_then(|idx| textures.get(idx))
+            .map_or(default, |texture| texture.image.view)
+    };
+
+    let mut gpu_materials = Vec::with_capacity(materials.len());
+    for (idx, material) in materials.iter().enumerate() {
+        let set = match descriptor_allocator.allocate(ctx.device, material_set_layout) {
+            Ok(set) => set,
+            Err(err) => {
+                material_buffer.destroy(ctx.device, ctx.allocator);
+                descriptor_allocator.destroy(ctx.device);
+                return Err(err);
+            }
+        };
+        let mut writer = DescriptorWriter::default().write_buffer(
+            0,
+            material_buffer.buffer,
+            (idx * MATERIAL_STRIDE) as vk::DeviceSize,
+            size_of::<MaterialConstants>() as vk::DeviceSize,
+            vk::DescriptorType::UNIFORM_BUFFER,
+        );
+        let views = [
+            (material.base_color_texture, defaults.white),
+            (material.metallic_roughness_texture, defaults.white),
+            (material.normal_texture, defaults.flat_normal),
+            (material.occlusion_texture, defaults.white),
+            (material.emissive_texture, defaults.white),
+        ];
+        for (binding, (texture, default)) in (1..).zip(views) {
+            writer = writer.write_image(
+                binding,
+                texture_view(texture, default),
+                vk::Sampler::null(),
+                vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+                vk::DescriptorType::SAMPLED_IMAGE,
+            );
+        }
+        writer
+            .write_image(
+                6,
+                vk::ImageView::null(),
+                defaults.sampler,
+                vk::ImageLayout::UNDEFINED,
+                vk::DescriptorType::SAMPLER,
+            )
+            .update_set(ctx.device, set);
+
+        gpu_materials.push(GpuMaterial {
+            set,
+            alpha_mode: material.alpha_mode,
+            double_sided: material.double_sided,
+        });
+    }
+
+    Ok((material_buffer, gpu_materials, descriptor_allocator))
+}
@@ -0,0 +1,290 @@
+use std::ffi::CStr;
+
+use ash::vk;
+
+use super::{depth, init};
+
+// Shaders are compiled from `shaders/*.wgsl` by the build script
+macro_rules! include_shader {
+    ($name:literal) => {
+        include_bytes!(concat!(env!("OUT_DIR"), "/", $name, ".spv"))
+    };
+}
+pub(crate) use include_shader;
+
+pub fn create_shader_module(device: &ash::Device, code: &[u8]) -> anyhow::Result<vk::ShaderModule> {
+    let words = ash::util::read_spv(&mut std::io::Cursor::new(code))?;
+    let info = vk::ShaderModuleCreateInfo::default().code(&words);
+    Ok(unsafe { device.create_shader_module(&info, None) }?)
+}
+
+pub fn create_pipeline_layout(
+    device: &ash::Device,
+    set_layouts: &[vk::DescriptorSetLayout],
+    push_constant_ranges: &[vk::PushConstantRange],
+) -> anyhow::Result<vk::PipelineLayout> {
+    let info = vk::PipelineLayoutCreateInfo::default()
+        .set_layouts(set_layouts)
+        .push_constant_ranges(push_constant_ranges);
+    Ok(unsafe { device.create_pipeline_layout(&info, None) }?)
+}
+
+pub fn push_constant_range<T>(stages: vk::ShaderStageFlags) -> vk::PushConstantRange {
+    vk::PushConstantRange {
+        stage_flags: stages,
+        offset: 0,
+        size: size_of::<T>() as u32,
+    }
+}
+
+pub fn create_compute_pipeline(
+    device: &ash::Device,
+    layout: vk::PipelineLayout,
+    module: vk::ShaderModule,
+    entry: &'static CStr,
+) -> anyhow::Result<vk::Pipeline> {
+    let info = vk::ComputePipelineCreateInfo::default()
+        .stage(init::shader_stage_create_info(
+            vk::ShaderStageFlags::COMPUTE,
+            module,
+            entry,
+        ))
+        .layout(layout);
+    let pipelines =
+        unsafe { device.create_compute_pipelines(vk::PipelineCache::null(), &[info], None) }
+            .map_err(|(_, err)| err)?;
+    Ok(pipelines[0])
+}
+
+// Graphics pipelines always use dynamic rendering and dynamic viewport/scissor
+#[derive(Clone)]
+pub struct PipelineBuilder {
+    stages: Vec<(vk::ShaderStageFlags, vk::ShaderModule, &'static CStr)>,
+    topology: vk::PrimitiveTopology,
+    polygon_mode: vk::PolygonMode,
+    cull_mode: vk::CullModeFlags,
+    front_face: vk::FrontFace,
+    blend_attachment: vk::PipelineColorBlendAttachmentState,
+    first_attachment_only: bool,
+    samples: vk::SampleCountFlags,
+    color_formats: Vec<vk::Format>,
+    depth_format: vk::Format,
+    depth_test: Option<(bool, vk::CompareOp)>,
+    // Constant and slope scaled factors
+    depth_bias: Option<(f32, f32)>,
+    vertex_bindings: Vec<vk::VertexInputBindingDescription>,
+    vertex_attributes: Vec<vk::VertexInputAttributeDescription>,
+    layout: vk::PipelineLayout,
+}
+
+impl Default for PipelineBuilder {
+    fn default() -> Self {
+        Self {
+            stages: Vec::new(),
+            topology: vk::PrimitiveTopology::TRIANGLE_LIST,
+            polygon_mode: vk::PolygonMode::FILL,
+            cull_mode: vk::CullModeFlags::NONE,
+            front_face: vk::FrontFace::COUNTER_CLOCKWISE,
+            blend_attachment: vk::PipelineColorBlendAttachmentState::default()
+                .color_write_mask(vk::ColorComponentFlags::RGBA)
+                .blend_enable(false),
+            first_attachment_only: false,
+            samples: vk::SampleCountFlags::TYPE_1,
+            color_formats: Vec::new(),
+            depth_format: vk::Format::UNDEFINED,
+            depth_test: None,
+            depth_bias: None,
+            vertex_bindings: Vec::new(),
+            vertex_attributes: Vec::new(),
+            layout: vk::PipelineLayout::null(),
+        }
+    }
+}
+
+impl PipelineBuilder {
+    pub fn shaders(
+        mut self,
+        vertex: vk::ShaderModule,
+        vertex_entry: &'static CStr,
+        fragment: vk::ShaderModule,
+        fragment_entry: &'static CStr,
+    ) -> Self {
+        self.stages.clear();
+        self.stages
+            .push((vk::ShaderStageFlags::VERTEX, vertex, vertex_entry));
+        self.stages
+            .push((vk::ShaderStageFlags::FRAGMENT, fragment, fragment_entry));
+        self
+    }
+
+    /// For depth-only passes, which have nothing to shade
+    pub fn vertex_shader(mut self, module: vk::ShaderModule, entry: &'static CStr) -> Self {
+        self.stages.clear();
+        self.stages
+            .push((vk::ShaderStageFlags::VERTEX, module, entry));
+        self
+    }
+
+    pub fn layout(mut self, layout: vk::PipelineLayout) -> Self {
+        self.layout = layout;
+        self
+    }
+
+    pub fn color_attachment_format(mut self, format: vk::Format) -> Self {
+        self.color_formats = vec![format];
+        self
+    }
+
+    /// One blend state is shared by every attachment
+    pub fn color_attachment_formats(mut self, formats: &[vk::Format]) -> Self {
+        self.color_formats = formats.to_vec();
+        self
+    }
+
+    /// Leaves every attachment after the first untouched, for sharing a render pass with
+    /// pipelines that write them
+    pub fn first_attachment_only(mut self) -> Self {
+        self.first_attachment_only = true;
+        self
+    }
+
+    pub fn cull_mode(mut self, cull_mode: vk::CullModeFlags) -> Self {
+        self.cull_mode = cull_mode;
+        self
+    }
+
+    /// Straight alpha blending, the colour output isn't premultiplied
+    pub fn alpha_blending(mut self) -> Self {
+        self.blend_attachment = self
+            .blend_attachment
+            .blend_enable(true)
+            .src_color_blend_factor(vk::BlendFactor::SRC_ALPHA)
+            .dst_color_blend_factor(vk::BlendFactor::ONE_MINUS_SRC_ALPHA)
+            .color_blend_op(vk::BlendOp::ADD)
+            .src_alpha_blend_factor(vk::BlendFactor::ONE)
+            .dst_alpha_blend_factor(vk::BlendFactor::ONE_MINUS_SRC_ALPHA)
+            .alpha_blend_op(vk::BlendOp::ADD);
+        self
+    }
+
+    /// Adds the output onto what's already in the attachment
+    pub fn additive_blending(mut self) -> Self {
+        self.blend_attachment = self
+            .blend_attachment
+            .blend_enable(true)
+            .src_color_blend_factor(vk::BlendFactor::ONE)
+            .dst_color_blend_factor(vk::BlendFactor::ONE)
+            .color_blend_op(vk::BlendOp::ADD)
+            .src_alpha_blend_factor(vk::BlendFactor::ONE)
+            .dst_alpha_blend_factor(vk::BlendFactor::ZERO)
+            .alpha_blend_op(vk::BlendOp::ADD);
+        self
+    }
+
+    /// Has to match every attachment's sample count
+    pub fn samples(mut self, samples: vk::SampleCountFlags) -> Self {
+        self.samples = samples;
+        self
+    }
+
+    pub fn vertex_input(
+        mut self,
+        bindings: &[vk::VertexInputBindingDescription],
+        attributes: &[vk::VertexInputAttributeDescription],
+    ) -> Self {
+        self.vertex_bindings = bindings.to_vec();
+        self.vertex_attributes = attributes.to_vec();
+        self
+    }
+
+    /// The format always has to match the attachment, even with the test disabled
+    pub fn depth_format(mut self, format: vk::Format) -> Self {
+        self.depth_format = format;
+        self
+    }
+
+    pub fn depth_test(mut self, write: bool, compare_op: vk::CompareOp) -> Self {
+        self.depth_test = Some((write, compare_op));
+        self
+    }
+
+    /// Pushes depth away from the viewer, in units of the depth format's resolution and of the
+    /// polygon's depth slope
+    pub fn depth_bias(mut self, constant: f32, slope: f32) -> Self {
+        self.depth_bias = Some((constant, slope));
+        self
+    }
+
+    pub fn build(&self, device: &ash::Device) -> anyhow::Result<vk::Pipeline> {
+        let stages: Vec<vk::PipelineShaderStageCreateInfo> = self
+            .stages
+            .iter()
+            .map(|(stage, module, entry)| init::shader_stage_create_info(*stage, *module, entry))
+            .collect();
+
+        let vertex_input = vk::PipelineVertexInputStateCreateInfo::default()
+            .vertex_binding_descriptions(&self.vertex_bindings)
+            .vertex_attribute_descriptions(&self.vertex_attributes);
+        let input_assembly =
+            vk::PipelineInputAssemblyStateCreateInfo::default().topology(self.topology);
+        let viewport = vk::PipelineViewportStateCreateInfo::default()
+            .viewport_count(1)
+            .scissor_count(1);
+        let rasterizer = vk::PipelineRasterizationStateCreateInfo::default()
+            .polygon_mode(self.polygon_mode)
+            .cull_mode(self.cull_mode)
+            .front_face(self.front_face)
+            .depth_bias_enable(self.depth_bias.is_some())
+            .depth_bias_constant_factor(self.depth_bias.map_or(0.0, |(constant, _)| constant))
+            .depth_bias_slope_factor(self.depth_bias.map_or(0.0, |(_, slope)| slope))
+            .line_width(1.0);
+        let multisampling = vk::PipelineMultisampleStateCreateInfo::default()
+            .rasterization_samples(self.samples)
+            .min_sample_shading(1.0);
+        let (write, compare_op) = self.depth_test.unwrap_or((false, vk::CompareOp::NEVER));
+        let depth_stencil = vk::PipelineDepthStencilStateCreateInfo::default()
+            .depth_test_enable(self.depth_test.is_some())
+            .depth_write_enable(write)
+            .depth_compare_op(compare_op)
+            .min_depth_bounds(0.0)
+            .max_depth_bounds(1.0);
+
+        let mut blend_attachments = vec![self.blend_attachment; self.color_formats.len()];
+        if self.first_attachment_only {
+            for attachment in blend_attachments.iter_mut().skip(1) {
+                *attachment = attachment.color_write_mask(vk::ColorComponentFlags::empty());
+            }
+        }
+        let color_blend =
+            vk::PipelineColorBlendStateCreateInfo::default().attachments(&blend_attachments);
+
+        let dynamic_states = [vk::DynamicState::VIEWPORT, vk::DynamicState::SCISSOR];
+        let dynamic = vk::PipelineDynamicStateCreateInfo::default().dynamic_states(&dynamic_states);
+
+        let mut rendering = vk::PipelineRenderingCreateInfo::default()
+            .color_attachment_formats(&self.color_formats)
+            .depth_attachment_format(self.depth_format);
+        if depth::has_stencil(self.depth_format) {
+            rendering = rendering.stencil_attachment_format(self.depth_format);
+        }
+
+        let info = vk::GraphicsPipelineCreateInfo::default()
+            .stages(&stages)
+            .vertex_input_state(&vertex_input)
+            .input_assembly_state(&input_assembly)
+            .viewport_state(&viewport)
+            .rasterization_state(&rasterizer)
+            .multisample_state(&multisampling)
+            .depth_stencil_state(&depth_stencil)
+            .color_blend_state(&color_blend)
+            .dynamic_state(&dynamic)
+            .layout(self.layout)
+            .push_next(&mut rendering);
+
+        let pipelines =
+            unsafe { device.create_graphics_pipelines(vk::PipelineCache::null(), &[info], None) }
+                .map_err(|(_, err)| err)?;
+
+        Ok(pipelines[0])
+    }
+}
@@ -1,25 +1,283 @@
+use std::{
+    collections::HashMap,
+    mem::ManuallyDrop,
+    path::{Path, PathBuf},
+    time::Instant,
+};
+
 use ash::vk::{self, PhysicalDevice};
+use glam::{Mat4, Vec2, Vec3, Vec4};
+use gpu_allocator::{
+    vulkan::{Allocator, AllocatorCreateDesc},
+    MemoryLocation,
+};
 use winit::raw_window_handle::HasDisplayHandle;
 
-use super::{init, surface::Surface, swapchain::Swapchain, util};
+use crate::{
+    assets::{self, LightKind},
+    camera::Camera,
+    scene::{NodeId, Scene},
+};
+
+use super::{
+    atmosphere::AtmospherePass,
+    barrier::{Access, ImageRange},
+    bloom::{BloomImages, BloomPass, BloomSettings},
+    buffer::AllocatedBuffer,
+    cluster::ClusterPass,
+    deferred::{self, GBuffer, LightingPass},
+    depth::{self, DepthBuffer},
+    descriptors::{DescriptorAllocator, DescriptorLayoutBuilder, DescriptorWriter, PoolSizeRatio},
+    exposure::ExposurePass,
+    ibl::{self, Environment, IblBaker},
+    image::AllocatedImage,
+    init,
+    mesh::{self, MeshPass, MeshPhase},
+    mipmap::MipGenerator,
+    model::{DrawItem, GpuModel, MaterialDefaults},
+    pipeline::{self, include_shader, PipelineBuilder},
+    shadow::{self, Cascades, ShadowPass, ShadowSettings},
+    skybox::{SkyboxPass, SkyboxSettings},
+    ssao::{SsaoImages, SsaoPass, SsaoSettings},
+    surface::Surface,
+    swapchain::{self, Swapchain},
+    taa::{self, TaaImages, TaaPass},
+    texture::Texture,
+    tracker::ResourceTracker,
+    upload::{ImmediateSubmit, UploadContext},
+    util,
+};
 
 const FIF: usize = 2;
+// Everything is rendered into this before being encoded into the swapchain
+const DRAW_IMAGE_FORMAT: vk::Format = vk::Format::R16G16B16A16_SFLOAT;
+// Geometry writes motion vectors next to whatever else it draws
+const FORWARD_FORMATS: [vk::Format; 2] = [DRAW_IMAGE_FORMAT, taa::MOTION_FORMAT];
+const GBUFFER_PASS_FORMATS: [vk::Format; 5] = [
+    deferred::GBUFFER_FORMATS[0],
+    deferred::GBUFFER_FORMATS[1],
+    deferred::GBUFFER_FORMATS[2],
+    deferred::GBUFFER_FORMATS[3],
+    taa::MOTION_FORMAT,
+];
+// Tonemapped into before FXAA, wide enough for HDR outputs
+const LDR_IMAGE_FORMAT: vk::Format = vk::Format::R16G16B16A16_SFLOAT;
+// Point and spot lights beyond this many are ignored
+const MAX_LIGHTS: usize = 1024;
+// Lights without a range are culled where their intensity drops below this
+const LIGHT_CUTOFF: f32 = 0.01;
+// Sets allocated from the renderer's descriptor pool: the scene uniforms for each frame in
+// flight, auto-exposure's, present's draw and LDR image sets, deferred lighting's
+// G-buffer, TAA's one per history image, and SSAO's occlusion and two blur directions. Passes
+// with pools of their own aren't counted. Pool sizes are per set ratios of this, so a new set
+// has to be added here
+const SHARED_DESCRIPTOR_SETS: u32 = FIF as u32 + 1 + 2 + 1 + 2 + 3;
+// Lights the scene until an environment is loaded, the same as a constant grey-blue sky
+const DEFAULT_ENVIRONMENT: [f32; 4] = [0.1, 0.1, 0.12, 1.0];
+
+#[derive(Debug, Clone)]
+pub struct RendererConfig {
+    /// Present through an HDR10 or scRGB swapchain when the display supports one
+    pub hdr: bool,
+    /// Brightness of scene value 1.0 on HDR outputs
+    pub paper_white_nits: f32,
+    pub max_luminance_nits: f32,
+    /// Reported to HDR displays as the brightest a whole frame gets on average (MaxFALL)
+    pub max_frame_average_nits: f32,
+    pub reverse_z: bool,
+    /// Pick a depth format with a stencil aspect
+    pub stencil: bool,
+    /// Where baked environments are kept between runs, `None` bakes them every time
+    pub ibl_cache_dir: Option<PathBuf>,
+    pub shadows: ShadowSettings,
+    pub render_path: RenderPath,
+    /// Samples per pixel for the forward path, 1, 2, 4 or 8 and lowered to what the device
+    /// supports. The deferred path always renders with one
+    pub msaa_samples: u32,
+    /// Temporal anti-aliasing, on top of any multisampling
+    pub taa: bool,
+    /// FXAA on the tonemapped image, cheaper than either MSAA or TAA
+    pub fxaa: bool,
+    pub tonemapper: Tonemapper,
+    /// Expose the scene by its average luminance instead of leaving it as lit
+    pub auto_exposure: bool,
+    /// How quickly exposure follows changes in brightness, higher is faster
+    pub adaptation_speed: f32,
+    pub bloom: BloomSettings,
+    pub ssao: SsaoSettings,
+    pub skybox: SkyboxSettings,
+}
+
+impl Default for RendererConfig {
+    fn default() -> Self {
+        Self {
+            hdr: false,
+            paper_white_nits: 203.0,
+            max_luminance_nits: 1000.0,
+            max_frame_average_nits: 400.0,
+            reverse_z: true,
+            stencil: false,
+            ibl_cache_dir: Some(PathBuf::from("cache/ibl")),
+            shadows: ShadowSettings::default(),
+            render_path: RenderPath::Forward,
+            msaa_samples: 4,
+            taa: true,
+            fxaa: false,
+            tonemapper: Tonemapper::Reinhard,
+            auto_exposure: false,
+            adaptation_speed: 1.5,
+            bloom: BloomSettings::default(),
+            ssao: SsaoSettings::default(),
+            skybox: SkyboxSettings::default(),
+        }
+    }
+}
+
+/// How opaque geometry gets lit, blended geometry is always shaded forward
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum RenderPath {
+    #[default]
+    Forward,
+    /// Surfaces go to a G-buffer first, then every pixel is lit once
+    Deferred,
+}
+
+/// Curve mapping the scene's range down to the display's
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Tonemapper {
+    /// Extended Reinhard on the brightest channel, keeping hue
+    #[default]
+    Reinhard,
+    Aces,
+    Agx,
+    Uncharted2,
+}
+
+impl Tonemapper {
+    pub fn next(self) -> Self {
+        match self {
+            Self::Reinhard => Self::Aces,
+            Self::Aces => Self::Agx,
+            Self::Agx => Self::Uncharted2,
+            Self::Uncharted2 => Self::Reinhard,
+        }
+    }
+}
+
+/// Size of the ambient occlusion buffer relative to the screen
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum SsaoResolution {
+    Full,
+    #[default]
+    Half,
+    Quarter,
+}
+
+impl SsaoResolution {
+    /// Screen pixels per occlusion pixel along each axis
+    pub fn scale(self) -> u32 {
+        match self {
+            Self::Full => 1,
+            Self::Half => 2,
+            Self::Quarter => 4,
+        }
+    }
+
+    pub fn next(self) -> Self {
+        match self {
+            Self::Full => Self::Half,
+            Self::Half => Self::Quarter,
+            Self::Quarter => Self::Full,
+        }
+    }
+}
 
 pub struct Renderer {
-    entry: ash::Entry,
+    config: RendererConfig,
+    _entry: ash::Entry,
     instance: ash::Instance,
     physical_device: PhysicalDevice,
     device: ash::Device,
+    allocator: ManuallyDrop<Allocator>,
     surface: Surface,
     swapchain: Swapchain,
+    colorspace_supported: bool,
+    hdr_metadata_fns: Option<ash::ext::hdr_metadata::Device>,
+    window_extent: vk::Extent2D,
+    resize_requested: bool,
     queue: vk::Queue,
-    gfx_queue_family_idx: u32,
     frames: [FrameData; FIF],
     frame_counter: usize,
+    depth_format: vk::Format,
+    // Counts usable for both colour and depth attachments
+    supported_samples: vk::SampleCountFlags,
+    // What the targets and multisampled pipelines were created with
+    samples: vk::SampleCountFlags,
+    targets: RenderTargets,
+    tracker: ResourceTracker,
+    descriptor_allocator: DescriptorAllocator,
+    present: PresentPass,
+    immediate: ImmediateSubmit,
+    mip_generator: MipGenerator,
+    scene_set_layout: vk::DescriptorSetLayout,
+    material_set_layout: vk::DescriptorSetLayout,
+    frame_uniforms: Vec<FrameUniforms>,
+    white_texture: Texture,
+    flat_normal_texture: Texture,
+    default_sampler: vk::Sampler,
+    mesh_pass: MeshPass,
+    lighting_pass: LightingPass,
+    ibl_baker: IblBaker,
+    brdf_lut: AllocatedImage,
+    environment: Environment,
+    environment_sampler: vk::Sampler,
+    skybox_pass: SkyboxPass,
+    atmosphere_pass: AtmospherePass,
+    // Cleared until the LUTs that only depend on the atmosphere have been baked
+    atmosphere_baked: bool,
+    shadow_pass: ShadowPass,
+    // Fitted to the camera each frame
+    cascades: Cascades,
+    cluster_pass: ClusterPass,
+    taa_pass: TaaPass,
+    // Unjittered, what the history was rendered with
+    previous_view_proj: Mat4,
+    // From this frame's clip space to the previous one's
+    reprojection: Mat4,
+    // Cleared whenever the history no longer matches what's on screen
+    history_valid: bool,
+    exposure_pass: ExposurePass,
+    // Cleared while auto-exposure is off, so it doesn't adapt from a stale luminance
+    exposure_valid: bool,
+    last_draw: Option<Instant>,
+    bloom_pass: BloomPass,
+    ssao_pass: SsaoPass,
+    debug_view: DebugView,
+    // Off until an environment is loaded, the procedural atmosphere is drawn instead
+    show_skybox: bool,
+    model: Option<GpuModel>,
+    // Rebuilt from the scene every frame, kept around to reuse the allocation
+    draws: Vec<DrawItem>,
+    // Each node's transform as of the last frame, for motion vectors
+    previous_transforms: HashMap<NodeId, Mat4>,
+    lights: Vec<PunctualLight>,
+}
+
+/// Replaces shading with a visualisation of one of the renderer's intermediate results
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum DebugView {
+    #[default]
+    None,
+    /// Tints everything by which shadow cascade it falls in
+    Cascades,
+    /// Heatmap of how many lights reach each cluster
+    LightCount,
+    /// Screen-space ambient occlusion on its own, white while it's off
+    AmbientOcclusion,
 }
 
 impl Renderer {
-    pub fn new(window: &winit::window::Window) -> anyhow::Result<Self> {
+    pub fn new(window: &winit::window::Window, config: RendererConfig) -> anyhow::Result<Self> {
         let entry = unsafe { ash::Entry::load() }?;
         let app_info = vk::ApplicationInfo::default()
             .application_name(c"Vulkan Exploration")
@@ -27,9 +285,16 @@ impl Renderer {
             .engine_name(c"Vulkan Exploration Engine")
             .engine_version(vk::make_api_version(0, 0, 1, 0))
             .api_version(vk::API_VERSION_1_3);
-        let required_extensions: Vec<*const i8> =
+        let mut required_extensions: Vec<*const i8> =
             ash_window::enumerate_required_extensions(window.display_handle()?.as_raw())?.into();
 
+        // Needed for any colour space other than SRGB_NONLINEAR
+        let colorspace_supported = config.hdr
+            && init::instance_extension_supported(&entry, vk::EXT_SWAPCHAIN_COLORSPACE_NAME);
+        if colorspace_supported {
+            required_extensions.push(vk::EXT_SWAPCHAIN_COLORSPACE_NAME.as_ptr());
+        }
+
         let instance = {
             let info = vk::InstanceCreateInfo::default()
                 .application_info(&app_info)
@@ -42,11 +307,20 @@ impl Renderer {
 
         let gfx_queue_family_idx =
             init::select_queue_family(&instance, physical_device, vk::QueueFlags::GRAPHICS)?;
+        let hdr_metadata_supported = colorspace_supported
+            && init::device_extension_supported(
+                &instance,
+                physical_device,
+                vk::EXT_HDR_METADATA_NAME,
+            );
         let device = {
             let queue_info = [vk::DeviceQueueCreateInfo::default()
                 .queue_family_index(gfx_queue_family_idx)
                 .queue_priorities(&[1.0])];
-            let extension_names = [vk::KHR_SWAPCHAIN_NAME.as_ptr()];
+            let mut extension_names = vec![vk::KHR_SWAPCHAIN_NAME.as_ptr()];
+            if hdr_metadata_supported {
+                extension_names.push(vk::EXT_HDR_METADATA_NAME.as_ptr());
+            }
             let mut features_12 = vk::PhysicalDeviceVulkan12Features::default()
                 .buffer_device_address(true)
                 .descriptor_indexing(true);
@@ -62,7 +336,16 @@ impl Renderer {
             unsafe { instance.create_device(physical_device, &info, None)? }
         };
 
-        let surface = Surface::new(&entry, &instance, &window)?;
+        let mut allocator = Allocator::new(&AllocatorCreateDesc {
+            instance: instance.clone(),
+            device: device.clone(),
+            physical_device,
+            debug_settings: Default::default(),
+            buffer_device_address: true,
+            allocation_sizes: Default::default(),
+        })?;
+
+        let surface = Surface::new(&entry, &instance, window)?;
 
         let swapchain = Swapchain::new(
             &instance,
@@ -71,43 +354,577 @@ impl Renderer {
             &surface,
             window.inner_size().width,
             window.inner_size().height,
+            swapchain::PREFERRED_FORMATS,
+            if colorspace_supported {
+                swapchain::HDR_SURFACE_FORMATS
+            } else {
+                &[]
+            },
             None,
         )?;
 
+        let hdr_metadata_fns =
+            hdr_metadata_supported.then(|| ash::ext::hdr_metadata::Device::new(&instance, &device));
+        if let Some(fns) = &hdr_metadata_fns {
+            Self::set_hdr_metadata(&swapchain, fns, &config);
+        }
+
         let queue = unsafe { device.get_device_queue(gfx_queue_family_idx, 0) };
 
         let frames = Self::init_frame_data(&device, gfx_queue_family_idx)?;
 
+        let depth_format = depth::choose_depth_format(
+            &instance,
+            physical_device,
+            if config.stencil {
+                depth::DEPTH_STENCIL_FORMATS
+            } else {
+                depth::DEPTH_FORMATS
+            },
+        )?;
+        let limits = unsafe { instance.get_physical_device_properties(physical_device) }.limits;
+        let supported_samples =
+            limits.framebuffer_color_sample_counts & limits.framebuffer_depth_sample_counts;
+        let samples = sample_count(&config, supported_samples);
+        let targets = RenderTargets::new(
+            &device,
+            &mut allocator,
+            swapchain.extent,
+            depth_format,
+            config.reverse_z,
+            samples,
+            config.ssao.resolution,
+        )?;
+
+        let mut tracker = ResourceTracker::default();
+        targets.track(&mut tracker);
+        swapchain.track(&mut tracker);
+
+        let descriptor_allocator = DescriptorAllocator::new(
+            &device,
+            SHARED_DESCRIPTOR_SETS,
+            &[
+                PoolSizeRatio {
+                    ty: vk::DescriptorType::UNIFORM_BUFFER,
+                    ratio: 1.0,
+                },
+                PoolSizeRatio {
+                    ty: vk::DescriptorType::SAMPLED_IMAGE,
+                    ratio: 4.0,
+                },
+                PoolSizeRatio {
+                    ty: vk::DescriptorType::SAMPLER,
+                    ratio: 1.0,
+                },
+                PoolSizeRatio {
+                    ty: vk::DescriptorType::STORAGE_BUFFER,
+                    ratio: 1.5,
+                },
+                PoolSizeRatio {
+                    ty: vk::DescriptorType::STORAGE_IMAGE,
+                    ratio: 1.0,
+                },
+            ],
+        )?;
+
+        let exposure_pass = ExposurePass::new(&device, &mut allocator, &descriptor_allocator)?;
+        exposure_pass.write_source(&device, targets.draw.view);
+        tracker.register_buffer(exposure_pass.histogram.buffer, Access::Undefined);
+        tracker.register_buffer(exposure_pass.state.buffer, Access::Undefined);
+        let present = PresentPass::new(
+            &device,
+            &descriptor_allocator,
+            swapchain.format,
+            &exposure_pass.state,
+        )?;
+        present.write_sources(
+            &device,
+            targets.draw.view,
+            targets.ldr.view,
+            targets.bloom.result_view(),
+        );
+        let bloom_pass = BloomPass::new(&device)?;
+        bloom_pass.write_sources(&device, &targets.bloom, targets.draw.view);
+
+        let immediate = ImmediateSubmit::new(&device, gfx_queue_family_idx)?;
+        let mip_generator = MipGenerator::new(&instance, physical_device, &device)?;
+
+        // Scene uniforms, then irradiance, prefiltered radiance, the BRDF LUT, their sampler,
+        // the skybox, the sun's shadow map with its comparison sampler, the lights, how
+        // they're binned into clusters, screen-space ambient occlusion, and the atmosphere's
+        // transmittance
+        let scene_set_layout = DescriptorLayoutBuilder::default()
+            .add_binding(0, vk::DescriptorType::UNIFORM_BUFFER)
+            .add_binding(1, vk::DescriptorType::SAMPLED_IMAGE)
+            .add_binding(2, vk::DescriptorType::SAMPLED_IMAGE)
+            .add_binding(3, vk::DescriptorType::SAMPLED_IMAGE)
+            .add_binding(4, vk::DescriptorType::SAMPLER)
+            .add_binding(5, vk::DescriptorType::SAMPLED_IMAGE)
+            .add_binding(6, vk::DescriptorType::SAMPLED_IMAGE)
+            .add_binding(7, vk::DescriptorType::SAMPLER)
+            .add_binding(8, vk::DescriptorType::STORAGE_BUFFER)
+            .add_binding(9, vk::DescriptorType::STORAGE_BUFFER)
+            .add_binding(10, vk::DescriptorType::STORAGE_BUFFER)
+            .add_binding(11, vk::DescriptorType::SAMPLED_IMAGE)
+            .add_binding(12, vk::DescriptorType::SAMPLED_IMAGE)
+            .build(
+                &device,
+                vk::ShaderStageFlags::VERTEX
+                    | vk::ShaderStageFlags::FRAGMENT
+                    | vk::ShaderStageFlags::COMPUTE,
+            )?;
+        let frame_uniforms = (0..FIF)
+            .map(|_| {
+                FrameUniforms::new(
+                    &device,
+                    &mut allocator,
+                    &descriptor_allocator,
+                    scene_set_layout,
+                )
+            })
+            .collect::<anyhow::Result<Vec<_>>>()?;
+        let atmosphere_pass = AtmospherePass::new(&device, &mut allocator, scene_set_layout)?;
+        atmosphere_pass.track(&mut tracker);
+        for uniforms in &frame_uniforms {
+            uniforms.write_transmittance(&device, atmosphere_pass.transmittance.view);
+        }
+
+        let mut upload = UploadContext {
+            instance: &instance,
+            physical_device,
+            device: &device,
+            allocator: &mut allocator,
+            queue,
+            immediate: &immediate,
+            mip_generator: &mip_generator,
+        };
+        let white_texture = Texture::solid(&mut upload, "white texture", [255; 4])?;
+        // A normal pointing straight out of the surface
+        let flat_normal_texture =
+            Texture::solid(&mut upload, "flat normal texture", [128, 128, 255, 255])?;
+        let default_sampler = {
+            let info = vk::SamplerCreateInfo::default()
+                .mag_filter(vk::Filter::LINEAR)
+                .min_filter(vk::Filter::LINEAR)
+                .mipmap_mode(vk::SamplerMipmapMode::LINEAR)
+                .address_mode_u(vk::SamplerAddressMode::REPEAT)
+                .address_mode_v(vk::SamplerAddressMode::REPEAT)
+                .address_mode_w(vk::SamplerAddressMode::REPEAT)
+                .max_lod(vk::LOD_CLAMP_NONE);
+            unsafe { device.create_sampler(&info, None) }?
+        };
+
+        let ibl_baker = IblBaker::new(&device)?;
+        let brdf_lut = ibl_baker.bake_brdf_lut(&mut upload)?;
+        let environment = ibl_baker.bake(
+            &mut upload,
+            &assets::EnvironmentImage::Equirect(assets::ImageData {
+                width: 2,
+                height: 1,
+                format: assets::PixelFormat::Rgba32F,
+                pixels: bytemuck::cast_slice(&[DEFAULT_ENVIRONMENT; 2]).to_vec(),
+                mips: Vec::new(),
+            }),
+        )?;
+        let environment_sampler = {
+            let info = vk::SamplerCreateInfo::default()
+                .mag_filter(vk::Filter::LINEAR)
+                .min_filter(vk::Filter::LINEAR)
+                .mipmap_mode(vk::SamplerMipmapMode::LINEAR)
+                .address_mode_u(vk::SamplerAddressMode::CLAMP_TO_EDGE)
+                .address_mode_v(vk::SamplerAddressMode::CLAMP_TO_EDGE)
+                .address_mode_w(vk::SamplerAddressMode::CLAMP_TO_EDGE)
+                .max_lod(vk::LOD_CLAMP_NONE);
+            unsafe { device.create_sampler(&info, None) }?
+        };
+        let shadow_pass = ShadowPass::new(&instance, physical_device, &device, &mut allocator)?;
+        tracker.register_image(
+            shadow_pass.map.image,
+            shadow_pass.format,
+            1,
+            shadow::CASCADE_COUNT as u32,
+            Access::Undefined,
+        );
+        for uniforms in &frame_uniforms {
+            uniforms.write_environment(&device, &environment, &brdf_lut, environment_sampler);
+            uniforms.write_shadow_map(&device, shadow_pass.map.view, shadow_pass.sampler);
+        }
+        let cluster_pass = ClusterPass::new(&device, &mut allocator, scene_set_layout)?;
+        tracker.register_buffer(cluster_pass.counts.buffer, Access::Undefined);
+        tracker.register_buffer(cluster_pass.indices.buffer, Access::Undefined);
+        for uniforms in &frame_uniforms {
+            uniforms.write_clusters(&device, &cluster_pass);
+            uniforms.write_ambient_occlusion(&device, targets.ssao.occlusion.view);
+        }
+
+        let material_set_layout = mesh::create_material_set_layout(&device)?;
+        let mesh_pass = MeshPass::new(
+            &device,
+            [scene_set_layout, material_set_layout],
+            &FORWARD_FORMATS,
+            &GBUFFER_PASS_FORMATS,
+            depth_format,
+            targets.depth.compare_op(),
+            samples,
+        )?;
+        let lighting_pass = LightingPass::new(
+            &device,
+            &descriptor_allocator,
+            scene_set_layout,
+            material_set_layout,
+            &FORWARD_FORMATS,
+            depth_format,
+        )?;
+        lighting_pass.write_gbuffer(&device, &targets.gbuffer, &targets.depth);
+        let skybox_pass = SkyboxPass::new(
+            &device,
+            scene_set_layout,
+            atmosphere_pass.set_layout(),
+            &FORWARD_FORMATS,
+            depth_format,
+            samples,
+        )?;
+        let taa_pass = TaaPass::new(&device, &descriptor_allocator)?;
+        targets.write_taa(&device, &taa_pass);
+        let ssao_pass = SsaoPass::new(&device, &descriptor_allocator, scene_set_layout)?;
+        targets.write_ssao(&device, &ssao_pass);
+
         Ok(Self {
-            entry,
+            config,
+            _entry: entry,
             instance,
             physical_device,
             device,
+            allocator: ManuallyDrop::new(allocator),
             surface,
             swapchain,
+            colorspace_supported,
+            hdr_metadata_fns,
+            window_extent: vk::Extent2D {
+                width: window.inner_size().width,
+                height: window.inner_size().height,
+            },
+            resize_requested: false,
             queue,
-            gfx_queue_family_idx,
             frames,
             frame_counter: 0,
+            depth_format,
+            supported_samples,
+            samples,
+            targets,
+            tracker,
+            descriptor_allocator,
+            present,
+            immediate,
+            mip_generator,
+            scene_set_layout,
+            material_set_layout,
+            frame_uniforms,
+            white_texture,
+            flat_normal_texture,
+            default_sampler,
+            mesh_pass,
+            lighting_pass,
+            ibl_baker,
+            brdf_lut,
+            environment,
+            environment_sampler,
+            skybox_pass,
+            atmosphere_pass,
+            atmosphere_baked: false,
+            shadow_pass,
+            cascades: Cascades::default(),
+            cluster_pass,
+            taa_pass,
+            previous_view_proj: Mat4::IDENTITY,
+            reprojection: Mat4::IDENTITY,
+            history_valid: false,
+            exposure_pass,
+            exposure_valid: false,
+            last_draw: None,
+            bloom_pass,
+            ssao_pass,
+            debug_view: DebugView::None,
+            show_skybox: false,
+            model: None,
+            draws: Vec::new(),
+            previous_transforms: HashMap::new(),
+            lights: Vec::new(),
         })
     }
 
-    pub fn draw(&mut self) -> anyhow::Result<()> {
+    /// Uploads `model`, replacing whatever was drawn before
+    pub fn set_model(&mut self, model: &assets::Model) -> anyhow::Result<()> {
+        unsafe { self.device.device_wait_idle() }?;
+        if let Some(mut old) = self.model.take() {
+            old.destroy(&self.device, &mut self.allocator);
+        }
+
+        let gpu_model = GpuModel::upload(
+            &mut UploadContext {
+                instance: &self.instance,
+                physical_device: self.physical_device,
+                device: &self.device,
+                allocator: &mut self.allocator,
+                queue: self.queue,
+                immediate: &self.immediate,
+                mip_generator: &self.mip_generator,
+            },
+            model,
+            self.material_set_layout,
+            &MaterialDefaults {
+                white: self.white_texture.image.view,
+                flat_normal: self.flat_normal_texture.image.view,
+                sampler: self.default_sampler,
+            },
+        )?;
+        self.model = Some(gpu_model);
+        self.previous_transforms.clear();
+
+        Ok(())
+    }
+
+    /// Lights the scene with an equirectangular image, a KTX2 cubemap or a directory of six cube
+    /// faces, and shows it as the skybox. Baked results are cached when `ibl_cache_dir` is set
+    pub fn set_environment(&mut self, path: &Path) -> anyhow::Result<()> {
+        unsafe { self.device.device_wait_idle() }?;
+
+        let cache_path = self.config.ibl_cache_dir.as_deref().and_then(|dir| {
+            ibl::cache_path(dir, path)
+                .inspect_err(|err| eprintln!("Not caching the environment: {err:#}"))
+                .ok()
+        });
+        let mut ctx = UploadContext {
+            instance: &self.instance,
+            physical_device: self.physical_device,
+            device: &self.device,
+            allocator: &mut self.allocator,
+            queue: self.queue,
+            immediate: &self.immediate,
+            mip_generator: &self.mip_generator,
+        };
+        let cached = cache_path
+            .as_deref()
+            .filter(|cache_path| cache_path.exists())
+            .and_then(|cache_path| {
+                Environment::load(&mut ctx, cache_path)
+                    .inspect_err(|err| eprintln!("Ignoring {}: {err:#}", cache_path.display()))
+                    .ok()
+            });
+        let environment = match cached {
+            Some(environment) => environment,
+            None => {
+                let image = assets::load_environment(path)?;
+                let environment = self.ibl_baker.bake(&mut ctx, &image)?;
+                if let Some(cache_path) = &cache_path {
+                    if let Err(err) = environment.save(&mut ctx, cache_path) {
+                        eprintln!("Failed to cache {}: {err:#}", path.display());
+                    }
+                }
+                environment
+            }
+        };
+
+        let mut old = std::mem::replace(&mut self.environment, environment);
+        old.destroy(&self.device, &mut self.allocator);
+        for uniforms in &self.frame_uniforms {
+            uniforms.write_environment(
+                &self.device,
+                &self.environment,
+                &self.brdf_lut,
+                self.environment_sampler,
+            );
+        }
+        self.show_skybox = true;
+
+        Ok(())
+    }
+
+    /// Switches to `view`, or back to normal shading if it's already showing
+    pub fn toggle_debug_view(&mut self, view: DebugView) {
+        self.debug_view = if self.debug_view == view {
+            DebugView::None
+        } else {
+            view
+        };
+    }
+
+    /// Stays on the current path if switching fails
+    pub fn toggle_render_path(&mut self) -> anyhow::Result<RenderPath> {
+        let previous = self.config.render_path;
+        self.config.render_path = match previous {
+            RenderPath::Forward => RenderPath::Deferred,
+            RenderPath::Deferred => RenderPath::Forward,
+        };
+        if let Err(err) = self.update_samples() {
+            self.config.render_path = previous;
+            return Err(err);
+        }
+        Ok(self.config.render_path)
+    }
+
+    /// The count asked for, which may be more than is actually used
+    pub fn msaa_samples(&self) -> u32 {
+        self.config.msaa_samples
+    }
+
+    /// Returns the number of samples actually used. Keeps the current count if changing fails
+    pub fn set_msaa_samples(&mut self, samples: u32) -> anyhow::Result<u32> {
+        let previous = std::mem::replace(&mut self.config.msaa_samples, samples);
+        if let Err(err) = self.update_samples() {
+            self.config.msaa_samples = previous;
+            return Err(err);
+        }
+        Ok(self.samples.as_raw())
+    }
+
+    // Rebuilds everything multisampled when the effective count changes. Nothing is replaced
+    // unless all of it was rebuilt
+    fn update_samples(&mut self) -> anyhow::Result<()> {
+        let samples = sample_count(&self.config, self.supported_samples);
+        if samples == self.samples {
+            return Ok(());
+        }
+        unsafe { self.device.device_wait_idle() }?;
+        let mut targets = self.create_targets(samples, self.config.ssao.resolution)?;
+
+        let passes = MeshPass::new(
+            &self.device,
+            [self.scene_set_layout, self.material_set_layout],
+            &FORWARD_FORMATS,
+            &GBUFFER_PASS_FORMATS,
+            self.depth_format,
+            targets.depth.compare_op(),
+            samples,
+        )
+        .and_then(|mesh_pass| {
+            match SkyboxPass::new(
+                &self.device,
+                self.scene_set_layout,
+                self.atmosphere_pass.set_layout(),
+                &FORWARD_FORMATS,
+                self.depth_format,
+                samples,
+            ) {
+                Ok(skybox_pass) => Ok((mesh_pass, skybox_pass)),
+                Err(err) => {
+                    mesh_pass.destroy(&self.device);
+                    Err(err)
+                }
+            }
+        });
+        let (mesh_pass, skybox_pass) = match passes {
+            Ok(passes) => passes,
+            Err(err) => {
+                targets.destroy(&self.device, &mut self.allocator);
+                return Err(err);
+            }
+        };
+
+        self.samples = samples;
+        self.replace_targets(targets);
+        std::mem::replace(&mut self.mesh_pass, mesh_pass).destroy(&self.device);
+        std::mem::replace(&mut self.skybox_pass, skybox_pass).destroy(&self.device);
+        Ok(())
+    }
+
+    pub fn toggle_ssao(&mut self) -> bool {
+        self.config.ssao.enabled = !self.config.ssao.enabled;
+        self.config.ssao.enabled
+    }
+
+    /// Leaves the resolution as it was if the targets for the next one can't be created
+    pub fn cycle_ssao_resolution(&mut self) -> anyhow::Result<SsaoResolution> {
+        unsafe { self.device.device_wait_idle() }?;
+        let resolution = self.config.ssao.resolution.next();
+        let targets = self.create_targets(self.samples, resolution)?;
+        self.config.ssao.resolution = resolution;
+        self.replace_targets(targets);
+        Ok(resolution)
+    }
+
+    /// Takes effect from the next frame
+    pub fn bloom_mut(&mut self) -> &mut BloomSettings {
+        &mut self.config.bloom
+    }
+
+    /// Takes effect from the next frame
+    pub fn skybox_mut(&mut self) -> &mut SkyboxSettings {
+        &mut self.config.skybox
+    }
+
+    pub fn cycle_tonemapper(&mut self) -> Tonemapper {
+        self.config.tonemapper = self.config.tonemapper.next();
+        self.config.tonemapper
+    }
+
+    pub fn toggle_auto_exposure(&mut self) -> bool {
+        self.config.auto_exposure = !self.config.auto_exposure;
+        self.exposure_valid = false;
+        self.config.auto_exposure
+    }
+
+    pub fn toggle_fxaa(&mut self) -> bool {
+        self.config.fxaa = !self.config.fxaa;
+        self.config.fxaa
+    }
+
+    pub fn toggle_taa(&mut self) -> bool {
+        self.config.taa = !self.config.taa;
+        self.history_valid = false;
+        self.config.taa
+    }
+
+    /// Starts accumulating from scratch, for when the view jumps somewhere unrelated
+    pub fn reset_history(&mut self) {
+        self.history_valid = false;
+    }
+
+    pub fn resize(&mut self, width: u32, height: u32) {
+        self.window_extent = vk::Extent2D { width, height };
+        self.resize_requested = true;
+    }
+
+    /// Draws every mesh attached to `scene`, whose world transforms have to be up to date
+    pub fn draw(&mut self, scene: &Scene, camera: &Camera) -> anyhow::Result<()> {
+        // Nothing to present to while minimized
+        if self.window_extent.width == 0 || self.window_extent.height == 0 {
+            return Ok(());
+        }
+        if self.resize_requested {
+            self.recreate_swapchain()?;
+        }
+
         unsafe {
             self.device
                 .wait_for_fences(&[self.current_frame().render_fence], true, u64::MAX)?;
-            self.device
-                .reset_fences(&[self.current_frame().render_fence])?;
         }
 
-        let (swapchain_image_idx, _) = unsafe {
+        let acquired = unsafe {
             self.swapchain.fns.acquire_next_image(
                 self.swapchain.swapchain,
                 u64::MAX,
                 self.current_frame().swapchain_sem,
                 vk::Fence::null(),
             )
-        }?;
+        };
+        let swapchain_image_idx = match acquired {
+            Ok((idx, suboptimal)) => {
+                self.resize_requested |= suboptimal;
+                idx
+            }
+            Err(vk::Result::ERROR_OUT_OF_DATE_KHR) => {
+                self.resize_requested = true;
+                return Ok(());
+            }
+            Err(err) => return Err(err.into()),
+        };
+
+        // Only reset once work is guaranteed to be submitted, otherwise the next wait deadlocks
+        unsafe {
+            self.device
+                .reset_fences(&[self.current_frame().render_fence])?;
+        }
 
         let cmd = self.current_frame().buffer;
 
@@ -120,40 +937,195 @@ impl Renderer {
             )?;
         }
 
-        util::transition_image(
+        let draw_image = self.targets.draw.image;
+        let depth_image = self.targets.depth.image.image;
+        let swapchain_image = self.swapchain.images[swapchain_image_idx as usize];
+
+        let msaa = self
+            .targets
+            .msaa
+            .as_ref()
+            .map(|msaa| (msaa.color.image, msaa.motion.image, msaa.depth.image.image));
+
+        self.build_draw_list(scene, camera.position);
+        self.update_scene_uniforms(scene, camera)?;
+
+        let cluster_counts = self.cluster_pass.counts.buffer;
+        let cluster_indices = self.cluster_pass.indices.buffer;
+        self.tracker
+            .use_buffer(cluster_counts, Access::ComputeWrite)
+            .use_buffer(cluster_indices, Access::ComputeWrite)
+            .flush(&self.device, cmd);
+        self.cluster_pass.dispatch(
             &self.device,
             cmd,
-            self.swapchain.images[swapchain_image_idx as usize],
-            vk::ImageLayout::UNDEFINED,
-            vk::ImageLayout::GENERAL,
+            self.frame_uniforms[self.frame_counter % FIF].set,
         );
+        self.compute_atmosphere(cmd);
 
-        let flash = f32::abs(f32::sin(self.frame_counter as f32 / 120.0));
-        let clear_value = vk::ClearColorValue {
-            float32: [0.0, 0.0, flash, 1.0],
-        };
+        let shadow_map = self.shadow_pass.map.image;
+        self.tracker
+            .discard_image(shadow_map, ImageRange::ALL, Access::DepthAttachmentWrite)
+            .flush(&self.device, cmd);
+        self.shadow_pass.draw(
+            &self.device,
+            cmd,
+            &self.cascades,
+            self.model.as_ref(),
+            &self.draws,
+        );
 
-        let clear_range = init::image_subresource_range(vk::ImageAspectFlags::COLOR);
+        // Forward shading reads occlusion as it goes, so depth and normals need a prepass
+        let normal_image = self.targets.gbuffer.images[1].image;
+        if self.config.ssao.enabled && self.config.render_path == RenderPath::Forward {
+            self.tracker
+                .discard_image(depth_image, ImageRange::ALL, Access::DepthAttachmentWrite)
+                .discard_image(normal_image, ImageRange::ALL, Access::ColorAttachmentWrite)
+                .flush(&self.device, cmd);
+            self.draw_prepass(cmd);
+            self.compute_ssao(cmd);
+        }
 
-        unsafe {
-            self.device.cmd_clear_color_image(
-                cmd,
-                self.swapchain.images[swapchain_image_idx as usize],
-                vk::ImageLayout::GENERAL,
-                &clear_value,
-                &[clear_range],
-            )
+        // Geometry and the sky cover every pixel between them, so nothing drawn last frame is
+        // needed again
+        self.tracker
+            .use_image(shadow_map, ImageRange::ALL, Access::FragmentSampled)
+            .use_buffer(cluster_counts, Access::FragmentStorageRead)
+            .use_buffer(cluster_indices, Access::FragmentStorageRead);
+        let motion_image = self.targets.taa.motion.image;
+        self.tracker
+            .discard_image(draw_image, ImageRange::ALL, Access::ColorAttachmentWrite)
+            .discard_image(motion_image, ImageRange::ALL, Access::ColorAttachmentWrite);
+        if let Some((msaa_color, msaa_motion, msaa_depth)) = msaa {
+            self.tracker
+                .discard_image(msaa_color, ImageRange::ALL, Access::ColorAttachmentWrite)
+                .discard_image(msaa_motion, ImageRange::ALL, Access::ColorAttachmentWrite)
+                .discard_image(msaa_depth, ImageRange::ALL, Access::DepthAttachmentWrite)
+                .discard_image(depth_image, ImageRange::ALL, Access::DepthResolveWrite);
+        } else {
+            self.tracker
+                .discard_image(depth_image, ImageRange::ALL, Access::DepthAttachmentWrite);
+        }
+
+        match self.config.render_path {
+            RenderPath::Forward => {
+                self.tracker.flush(&self.device, cmd);
+                self.draw_geometry(cmd);
+            }
+            RenderPath::Deferred => {
+                for image in &self.targets.gbuffer.images {
+                    self.tracker.discard_image(
+                        image.image,
+                        ImageRange::ALL,
+                        Access::ColorAttachmentWrite,
+                    );
+                }
+                self.tracker.flush(&self.device, cmd);
+                self.draw_gbuffer(cmd);
+                if self.config.ssao.enabled {
+                    self.compute_ssao(cmd);
+                }
+
+                for image in &self.targets.gbuffer.images {
+                    self.tracker
+                        .use_image(image.image, ImageRange::ALL, Access::FragmentSampled);
+                }
+                // Blended geometry draws its motion over what the G-buffer pass wrote
+                self.tracker
+                    .use_image(
+                        depth_image,
+                        ImageRange::ALL,
+                        Access::DepthAttachmentReadSampled,
+                    )
+                    .use_image(motion_image, ImageRange::ALL, Access::ColorAttachmentWrite)
+                    .flush(&self.device, cmd);
+                self.draw_lighting(cmd);
+            }
+        }
+
+        if self.config.taa {
+            self.resolve_taa(cmd);
+        }
+        if self.config.bloom.enabled {
+            self.draw_bloom(cmd);
+        }
+        if self.config.auto_exposure {
+            self.update_exposure(cmd);
+        }
+
+        let push_constants = PresentPushConstants {
+            transfer: self.swapchain.transfer as u32,
+            paper_white_nits: self.config.paper_white_nits,
+            max_luminance_nits: self.config.max_luminance_nits,
+            tonemapper: self.config.tonemapper as u32,
+            auto_exposure: self.config.auto_exposure as u32,
+            bloom_intensity: if self.config.bloom.enabled {
+                self.config.bloom.intensity
+            } else {
+                0.0
+            },
         };
+        let swapchain_view = self.swapchain.views[swapchain_image_idx as usize];
+        self.tracker
+            .use_image(draw_image, ImageRange::ALL, Access::FragmentSampled);
+        if self.config.fxaa {
+            let ldr_image = self.targets.ldr.image;
+            self.tracker
+                .discard_image(ldr_image, ImageRange::ALL, Access::ColorAttachmentWrite)
+                .flush(&self.device, cmd);
+            self.present.draw(
+                &self.device,
+                cmd,
+                PresentStep::Tonemap,
+                self.targets.ldr.view,
+                self.swapchain.extent,
+                push_constants,
+            );
 
-        util::transition_image(
-            &self.device,
-            cmd,
-            self.swapchain.images[swapchain_image_idx as usize],
-            vk::ImageLayout::GENERAL,
-            vk::ImageLayout::PRESENT_SRC_KHR,
-        );
+            self.tracker
+                .use_image(ldr_image, ImageRange::ALL, Access::FragmentSampled)
+                .discard_image(
+                    swapchain_image,
+                    ImageRange::ALL,
+                    Access::ColorAttachmentWrite,
+                )
+                .flush(&self.device, cmd);
+            self.present.draw(
+                &self.device,
+                cmd,
+                PresentStep::Fxaa,
+                swapchain_view,
+                self.swapchain.extent,
+                push_constants,
+            );
+        } else {
+            self.tracker
+                .discard_image(
+                    swapchain_image,
+                    ImageRange::ALL,
+                    Access::ColorAttachmentWrite,
+                )
+                .flush(&self.device, cmd);
+            self.present.draw(
+                &self.device,
+                cmd,
+                PresentStep::Direct,
+                swapchain_view,
+                self.swapchain.extent,
+                push_constants,
+            );
+        }
 
-        unsafe { self.device.end_command_buffer(cmd) };
+        self.tracker
+            .use_image_from(
+                swapchain_image,
+                ImageRange::ALL,
+                Access::ColorAttachmentWrite,
+                Access::Present,
+            )
+            .flush(&self.device, cmd);
+
+        unsafe { self.device.end_command_buffer(cmd) }?;
 
         // Prepare the submission to the queue
         // Wait on the present_sem as that semaphore is signaled when the swapchain is ready
@@ -192,16 +1164,587 @@ impl Renderer {
             .wait_semaphores(&wait_sems)
             .image_indices(&image_indices);
 
-        unsafe {
-            self.swapchain
-                .fns
-                .queue_present(self.queue, &present_info)?;
+        let presented = unsafe { self.swapchain.fns.queue_present(self.queue, &present_info) };
+        match presented {
+            Ok(suboptimal) => self.resize_requested |= suboptimal,
+            Err(vk::Result::ERROR_OUT_OF_DATE_KHR) => self.resize_requested = true,
+            Err(err) => return Err(err.into()),
         }
 
         self.frame_counter += 1;
         Ok(())
     }
 
+    // Bakes the LUTs that only depend on the atmosphere the first time through, then the sky
+    // as seen from the camera whenever it's shown. Shading always reads the transmittance
+    fn compute_atmosphere(&mut self, cmd: vk::CommandBuffer) {
+        let atmosphere = &self.atmosphere_pass;
+        let transmittance = atmosphere.transmittance.image;
+        let multi_scattering = atmosphere.multi_scattering.image;
+        let sky_view = atmosphere.sky_view.image;
+        let scene_set = self.frame_uniforms[self.frame_counter % FIF].set;
+
+        if !self.atmosphere_baked {
+            self.tracker
+                .discard_image(transmittance, ImageRange::ALL, Access::ComputeWrite)
+                .flush(&self.device, cmd);
+            atmosphere.dispatch_transmittance(&self.device, cmd, scene_set);
+            self.tracker
+                .use_image(transmittance, ImageRange::ALL, Access::ComputeSampled)
+                .discard_image(multi_scattering, ImageRange::ALL, Access::ComputeWrite)
+                .flush(&self.device, cmd);
+            atmosphere.dispatch_multi_scattering(&self.device, cmd, scene_set);
+            self.atmosphere_baked = true;
+        }
+
+        if !self.show_skybox {
+            self.tracker
+                .use_image(transmittance, ImageRange::ALL, Access::ComputeSampled)
+                .use_image(multi_scattering, ImageRange::ALL, Access::ComputeSampled)
+                .discard_image(sky_view, ImageRange::ALL, Access::ComputeWrite)
+                .flush(&self.device, cmd);
+            atmosphere.dispatch_sky_view(&self.device, cmd, scene_set);
+            self.tracker
+                .use_image(sky_view, ImageRange::ALL, Access::FragmentSampled);
+        }
+        self.tracker
+            .use_image(transmittance, ImageRange::ALL, Access::FragmentSampled);
+    }
+
+    // Graphics passes render into the draw image with the depth buffer attached, or into
+    // multisampled copies resolved into them
+    fn draw_geometry(&self, cmd: vk::CommandBuffer) {
+        let (color_attachments, depth_attachment) = self.targets.forward_attachments();
+        let rendering_info = init::rendering_info(
+            self.swapchain.extent,
+            &color_attachments,
+            Some(&depth_attachment),
+        );
+
+        let scene_set = self.frame_uniforms[self.frame_counter % FIF].set;
+        unsafe { self.device.cmd_begin_rendering(cmd, &rendering_info) };
+        self.draw_meshes(cmd, scene_set, MeshPhase::Opaque);
+        self.draw_sky(cmd, scene_set);
+        self.draw_meshes(cmd, scene_set, MeshPhase::Blended);
+        unsafe { self.device.cmd_end_rendering(cmd) };
+    }
+
+    // Opaque and masked geometry's depth and normals, the normals into the G-buffer's
+    fn draw_prepass(&self, cmd: vk::CommandBuffer) {
+        let color_attachments = [init::color_attachment_info(
+            self.targets.gbuffer.images[1].view,
+            Some(vk::ClearValue::default()),
+            vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
+        )];
+        let depth_attachment = self.targets.depth.attachment_info(true);
+        let rendering_info = init::rendering_info(
+            self.swapchain.extent,
+            &color_attachments,
+            Some(&depth_attachment),
+        );
+
+        unsafe { self.device.cmd_begin_rendering(cmd, &rendering_info) };
+        if let Some(model) = &self.model {
+            self.mesh_pass.draw(
+                &self.device,
+                cmd,
+                self.swapchain.extent,
+                self.frame_uniforms[self.frame_counter % FIF].set,
+                model,
+                &self.draws,
+                MeshPhase::Prepass,
+            );
+        }
+        unsafe { self.device.cmd_end_rendering(cmd) };
+    }
+
+    // Occlusion from depth and normals, then blurred both ways, left ready for shading to read
+    fn compute_ssao(&mut self, cmd: vk::CommandBuffer) {
+        let depth_image = self.targets.depth.image.image;
+        let normal_image = self.targets.gbuffer.images[1].image;
+        let ssao = &self.targets.ssao;
+        let (occlusion, scratch) = (ssao.occlusion.image, ssao.scratch.image);
+        let scene_set = self.frame_uniforms[self.frame_counter % FIF].set;
+
+        self.tracker
+            .use_image(depth_image, ImageRange::ALL, Access::ComputeSampled)
+            .use_image(normal_image, ImageRange::ALL, Access::ComputeSampled)
+            .discard_image(occlusion, ImageRange::ALL, Access::ComputeWrite)
+            .flush(&self.device, cmd);
+        self.ssao_pass
+            .dispatch_occlusion(&self.device, cmd, scene_set, ssao, &self.config.ssao);
+
+        self.tracker
+            .use_image(occlusion, ImageRange::ALL, Access::ComputeSampled)
+            .discard_image(scratch, ImageRange::ALL, Access::ComputeWrite)
+            .flush(&self.device, cmd);
+        self.ssao_pass
+            .dispatch_blur(&self.device, cmd, scene_set, ssao, false);
+
+        self.tracker
+            .use_image(scratch, ImageRange::ALL, Access::ComputeSampled)
+            .discard_image(occlusion, ImageRange::ALL, Access::ComputeWrite)
+            .flush(&self.device, cmd);
+        self.ssao_pass
+            .dispatch_blur(&self.device, cmd, scene_set, ssao, true);
+
+        self.tracker
+            .use_image(occlusion, ImageRange::ALL, Access::FragmentSampled);
+    }
+
+    // Opaque and masked geometry into the G-buffer and motion vectors, leaving the draw image
+    // alone
+    fn draw_gbuffer(&self, cmd: vk::CommandBuffer) {
+        let [albedo, normal, material, emissive] = self.targets.gbuffer.attachment_infos();
+        let motion = self
+            .targets
+            .motion_attachment_info(Some(vk::ClearValue::default()));
+        let color_attachments = [albedo, normal, material, emissive, motion];
+        let depth_attachment = self.targets.depth.attachment_info(true);
+        let rendering_info = init::rendering_info(
+            self.swapchain.extent,
+            &color_attachments,
+            Some(&depth_attachment),
+        );
+
+        unsafe { self.device.cmd_begin_rendering(cmd, &rendering_info) };
+        if let Some(model) = &self.model {
+            self.mesh_pass.draw(
+                &self.device,
+                cmd,
+                self.swapchain.extent,
+                self.frame_uniforms[self.frame_counter % FIF].set,
+                model,
+                &self.draws,
+                MeshPhase::GBuffer,
+            );
+        }
+        unsafe { self.device.cmd_end_rendering(cmd) };
+    }
+
+    // The G-buffer lit, then the sky around it, then blended geometry shaded forward. Motion
+    // vectors are kept from the G-buffer pass for blended geometry to blend over
+    fn draw_lighting(&self, cmd: vk::CommandBuffer) {
+        let color_attachments = [
+            init::color_attachment_info(
+                self.targets.draw.view,
+                None,
+                vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
+            ),
+            self.targets.motion_attachment_info(None),
+        ];
+        let depth_attachment = self.targets.depth.read_only_attachment_info();
+        let rendering_info = init::rendering_info(
+            self.swapchain.extent,
+            &color_attachments,
+            Some(&depth_attachment),
+        );
+
+        let scene_set = self.frame_uniforms[self.frame_counter % FIF].set;
+        unsafe { self.device.cmd_begin_rendering(cmd, &rendering_info) };
+        self.lighting_pass
+            .draw(&self.device, cmd, self.swapchain.extent, scene_set);
+        self.draw_sky(cmd, scene_set);
+        self.draw_meshes(cmd, scene_set, MeshPhase::Blended);
+        unsafe { self.device.cmd_end_rendering(cmd) };
+    }
+
+    // The loaded environment, or the atmosphere until there is one
+    fn draw_sky(&self, cmd: vk::CommandBuffer, scene_set: vk::DescriptorSet) {
+        let extent = self.swapchain.extent;
+        if self.show_skybox {
+            self.skybox_pass.draw(&self.device, cmd, extent, scene_set);
+        } else {
+            let atmosphere_set = self.atmosphere_pass.sky_set();
+            self.skybox_pass
+                .draw_atmosphere(&self.device, cmd, extent, scene_set, atmosphere_set);
+        }
+    }
+
+    fn draw_meshes(&self, cmd: vk::CommandBuffer, scene_set: vk::DescriptorSet, phase: MeshPhase) {
+        if let Some(model) = &self.model {
+            self.mesh_pass.draw(
+                &self.device,
+                cmd,
+                self.swapchain.extent,
+                scene_set,
+                model,
+                &self.draws,
+                phase,
+            );
+        }
+    }
+
+    // Filters the draw image down the bloom chain and back up, leaving the result in mip 0
+    fn draw_bloom(&mut self, cmd: vk::CommandBuffer) {
+        let bloom = &self.targets.bloom;
+        let image = bloom.image();
+        let mip = |mip| ImageRange {
+            base_mip: mip,
+            mip_count: 1,
+            base_layer: 0,
+            layer_count: 1,
+        };
+
+        self.tracker.use_image(
+            self.targets.draw.image,
+            ImageRange::ALL,
+            Access::FragmentSampled,
+        );
+        for level in 0..bloom.mip_count() {
+            self.tracker
+                .discard_image(image, mip(level), Access::ColorAttachmentWrite)
+                .flush(&self.device, cmd);
+            self.bloom_pass
+                .downsample(&self.device, cmd, bloom, level, &self.config.bloom);
+            self.tracker
+                .use_image(image, mip(level), Access::FragmentSampled);
+        }
+        for level in (0..bloom.mip_count() - 1).rev() {
+            self.tracker
+                .use_image(image, mip(level), Access::ColorAttachmentWrite)
+                .flush(&self.device, cmd);
+            self.bloom_pass
+                .upsample(&self.device, cmd, bloom, level, &self.config.bloom);
+            self.tracker
+                .use_image(image, mip(level), Access::FragmentSampled);
+        }
+    }
+
+    // Measures the draw image's luminance and eases the exposure the present pass uses towards it
+    fn update_exposure(&mut self, cmd: vk::CommandBuffer) {
+        let now = Instant::now();
+        let dt = self
+            .last_draw
+            .map_or(0.0, |last| (now - last).as_secs_f32());
+        self.last_draw = Some(now);
+
+        let draw_image = self.targets.draw.image;
+        let histogram = self.exposure_pass.histogram.buffer;
+        let state = self.exposure_pass.state.buffer;
+        self.tracker
+            .use_buffer(histogram, Access::TransferDst)
+            .flush(&self.device, cmd);
+        self.exposure_pass.clear_histogram(&self.device, cmd);
+
+        self.tracker
+            .use_image(draw_image, ImageRange::ALL, Access::ComputeSampled)
+            .use_buffer(histogram, Access::ComputeReadWrite)
+            .flush(&self.device, cmd);
+        self.exposure_pass
+            .dispatch_histogram(&self.device, cmd, self.swapchain.extent);
+
+        self.tracker
+            .use_buffer(histogram, Access::ComputeRead)
+            .use_buffer(state, Access::ComputeReadWrite)
+            .flush(&self.device, cmd);
+        let adaptation = 1.0 - (-dt * self.config.adaptation_speed).exp();
+        self.exposure_pass
+            .dispatch_average(&self.device, cmd, adaptation, !self.exposure_valid);
+        self.exposure_valid = true;
+
+        self.tracker.use_buffer(state, Access::FragmentStorageRead);
+    }
+
+    // Blends the draw image into the history, then copies the result back for presenting
+    fn resolve_taa(&mut self, cmd: vk::CommandBuffer) {
+        let extent = self.swapchain.extent;
+        let draw_image = self.targets.draw.image;
+        let depth_image = self.targets.depth.image.image;
+        let taa = &self.targets.taa;
+        let motion = taa.motion.image;
+        // Each history image is written every other frame, reading the one written in between
+        let target = self.frame_counter % 2;
+        let history = taa.history[target].image;
+        let previous_history = taa.history[1 - target].image;
+
+        self.tracker
+            .use_image(depth_image, ImageRange::ALL, Access::ComputeSampled)
+            .use_image(draw_image, ImageRange::ALL, Access::ComputeSampled)
+            .use_image(motion, ImageRange::ALL, Access::ComputeSampled)
+            .use_image(previous_history, ImageRange::ALL, Access::ComputeSampled)
+            .discard_image(history, ImageRange::ALL, Access::ComputeWrite)
+            .flush(&self.device, cmd);
+        self.taa_pass.dispatch_resolve(
+            &self.device,
+            cmd,
+            extent,
+            target,
+            self.reprojection,
+            !self.history_valid,
+        );
+        self.history_valid = true;
+
+        self.tracker
+            .use_image(history, ImageRange::ALL, Access::TransferSrc)
+            .discard_image(draw_image, ImageRange::ALL, Access::TransferDst)
+            .flush(&self.device, cmd);
+        let subresource = vk::ImageSubresourceLayers::default()
+            .aspect_mask(vk::ImageAspectFlags::COLOR)
+            .layer_count(1);
+        let region = vk::ImageCopy::default()
+            .src_subresource(subresource)
+            .dst_subresource(subresource)
+            .extent(extent.into());
+        unsafe {
+            self.device.cmd_copy_image(
+                cmd,
+                history,
+                vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+                draw_image,
+                vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                &[region],
+            )
+        };
+    }
+
+    // Mesh indices refer to the current model, anything else is left out. Sorted back to front
+    // by node origin, so blended primitives within one mesh can still overlap wrong. Nodes that
+    // weren't drawn last frame count as not having moved
+    fn build_draw_list(&mut self, scene: &Scene, camera_position: Vec3) {
+        self.draws.clear();
+        let mesh_count = self.model.as_ref().map_or(0, |model| model.meshes.len());
+        let previous_transforms = &mut self.previous_transforms;
+        self.draws.extend(scene.nodes().filter_map(|(id, node)| {
+            let mesh = node.mesh.filter(|&mesh| mesh < mesh_count)?;
+            let transform = node.world();
+            Some(DrawItem {
+                mesh,
+                transform,
+                previous_transform: previous_transforms
+                    .insert(id, transform)
+                    .unwrap_or(transform),
+            })
+        }));
+
+        let distance = |draw: &DrawItem| {
+            draw.transform
+                .w_axis
+                .truncate()
+                .distance_squared(camera_position)
+        };
+        self.draws
+            .sort_by(|a, b| distance(b).total_cmp(&distance(a)));
+    }
+
+    fn update_scene_uniforms(&mut self, scene: &Scene, camera: &Camera) -> anyhow::Result<()> {
+        debug_assert_eq!(
+            camera.reverse_z, self.config.reverse_z,
+            "the projection has to match the depth test"
+        );
+        let extent = self.swapchain.extent;
+        let aspect = extent.width as f32 / extent.height.max(1) as f32;
+        let view = camera.view();
+        let unjittered = camera.projection(aspect);
+        // Every frame samples a different point within each pixel, for TAA to accumulate
+        let proj = if self.config.taa {
+            let jitter = taa::jitter(self.frame_counter as u64);
+            taa::jittered(unjittered, jitter, extent)
+        } else {
+            unjittered
+        };
+
+        let view_proj = proj * view;
+        let unjittered_view_proj = unjittered * view;
+        let previous_view_proj = self.previous_view_proj;
+        self.reprojection = previous_view_proj * unjittered_view_proj.inverse();
+        self.previous_view_proj = unjittered_view_proj;
+
+        // Slices grow exponentially from the near plane, which can't be at or behind the camera
+        let (near, far) = camera.projection.near_far();
+        let cluster_near = near.max(0.01);
+
+        let mut data = SceneData {
+            view,
+            proj,
+            view_proj,
+            inv_view_proj: view_proj.inverse(),
+            camera_position: camera.position.extend(1.0),
+            sun_direction: Vec4::new(0.0, -1.0, 0.0, 0.0),
+            sun_color: Vec4::ZERO,
+            light_count: 0,
+            environment_intensity: self.config.skybox.intensity,
+            debug_view: self.debug_view as u32,
+            far_depth: if self.config.reverse_z { 0.0 } else { 1.0 },
+            cascade_view_proj: [Mat4::IDENTITY; shadow::CASCADE_COUNT],
+            cascade_splits: Vec4::ZERO,
+            cascade_texel_sizes: Vec4::ZERO,
+            inv_proj: proj.inverse(),
+            screen_size: Vec2::new(extent.width as f32, extent.height as f32),
+            cluster_near,
+            cluster_far: far.max(cluster_near * 2.0),
+            ambient_occlusion: self.config.ssao.enabled as u32,
+            atmosphere: !self.show_skybox as u32,
+            _pad: [0; 2],
+            environment_rotation: Mat4::from_rotation_y(-self.config.skybox.rotation),
+            unjittered_view_proj,
+            previous_view_proj,
+        };
+
+        // Only the first directional light is used, as the sun
+        let mut has_sun = false;
+        self.lights.clear();
+        for (_, node) in scene.nodes() {
+            let Some(light) = &node.light else {
+                continue;
+            };
+            let world = node.world();
+            let direction = world
+                .transform_vector3(Vec3::NEG_Z)
+                .normalize_or(Vec3::NEG_Z);
+            let color = light.color * light.intensity;
+
+            if light.kind == LightKind::Directional {
+                if !has_sun {
+                    data.sun_direction = direction.extend(0.0);
+                    data.sun_color = color.extend(1.0);
+                    has_sun = true;
+
+                    self.cascades = Cascades::fit(camera, aspect, direction, &self.config.shadows);
+                    data.cascade_view_proj = self.cascades.view_proj;
+                    data.cascade_splits = Vec4::from_array(self.cascades.splits);
+                    data.cascade_texel_sizes = Vec4::from_array(self.cascades.texel_sizes);
+                }
+                continue;
+            }
+            if self.lights.len() == MAX_LIGHTS {
+                continue;
+            }
+
+            // A point light is a spot light whose cone never cuts anything off
+            let (spot_scale, spot_offset) = match light.kind {
+                LightKind::Spot {
+                    inner_cone_angle,
+                    outer_cone_angle,
+                } => {
+                    let cos_outer = outer_cone_angle.cos();
+                    let scale = 1.0 / (inner_cone_angle.cos() - cos_outer).max(1e-3);
+                    (scale, -cos_outer * scale)
+                }
+                _ => (0.0, 1.0),
+            };
+            // Clustering needs every light to end somewhere
+            let range = light
+                .range
+                .unwrap_or_else(|| (color.max_element() / LIGHT_CUTOFF).sqrt());
+            self.lights.push(PunctualLight {
+                position_range: world.transform_point3(Vec3::ZERO).extend(range),
+                color_spot_scale: color.extend(spot_scale),
+                direction_spot_offset: direction.extend(spot_offset),
+            });
+        }
+        data.light_count = self.lights.len() as u32;
+
+        let uniforms = &mut self.frame_uniforms[self.frame_counter % FIF];
+        uniforms
+            .lights
+            .write(0, bytemuck::cast_slice(&self.lights))?;
+        uniforms.buffer.write(0, bytemuck::bytes_of(&data))
+    }
+
+    fn recreate_swapchain(&mut self) -> anyhow::Result<()> {
+        unsafe { self.device.device_wait_idle() }?;
+
+        let swapchain = Swapchain::new(
+            &self.instance,
+            &self.device,
+            self.physical_device,
+            &self.surface,
+            self.window_extent.width,
+            self.window_extent.height,
+            swapchain::PREFERRED_FORMATS,
+            if self.colorspace_supported {
+                swapchain::HDR_SURFACE_FORMATS
+            } else {
+                &[]
+            },
+            Some(&self.swapchain),
+        )?;
+        let surface_format_changed = (swapchain.format, swapchain.color_space)
+            != (self.swapchain.format, self.swapchain.color_space);
+        self.swapchain.untrack(&mut self.tracker);
+        self.swapchain.destroy(&self.device);
+        self.swapchain = swapchain;
+        self.swapchain.track(&mut self.tracker);
+        if surface_format_changed {
+            self.present
+                .set_swapchain_format(&self.device, self.swapchain.format)?;
+        }
+        if let Some(fns) = &self.hdr_metadata_fns {
+            Self::set_hdr_metadata(&self.swapchain, fns, &self.config);
+        }
+
+        // Everything sized to the swapchain follows it
+        self.recreate_targets()?;
+
+        self.resize_requested = false;
+        Ok(())
+    }
+
+    // Expects the device to be idle. The old targets are kept if the new ones can't be created
+    fn recreate_targets(&mut self) -> anyhow::Result<()> {
+        let targets = self.create_targets(self.samples, self.config.ssao.resolution)?;
+        self.replace_targets(targets);
+        Ok(())
+    }
+
+    // Sized to the swapchain, alongside the current targets until they're replaced
+    fn create_targets(
+        &mut self,
+        samples: vk::SampleCountFlags,
+        ssao_resolution: SsaoResolution,
+    ) -> anyhow::Result<RenderTargets> {
+        RenderTargets::new(
+            &self.device,
+            &mut self.allocator,
+            self.swapchain.extent,
+            self.depth_format,
+            self.config.reverse_z,
+            samples,
+            ssao_resolution,
+        )
+    }
+
+    // Expects the device to be idle
+    fn replace_targets(&mut self, targets: RenderTargets) {
+        self.targets.untrack(&mut self.tracker);
+        self.targets.destroy(&self.device, &mut self.allocator);
+        self.targets = targets;
+        self.targets.track(&mut self.tracker);
+        self.present.write_sources(
+            &self.device,
+            self.targets.draw.view,
+            self.targets.ldr.view,
+            self.targets.bloom.result_view(),
+        );
+        self.bloom_pass
+            .write_sources(&self.device, &self.targets.bloom, self.targets.draw.view);
+        self.lighting_pass
+            .write_gbuffer(&self.device, &self.targets.gbuffer, &self.targets.depth);
+        self.targets.write_taa(&self.device, &self.taa_pass);
+        self.targets.write_ssao(&self.device, &self.ssao_pass);
+        for uniforms in &self.frame_uniforms {
+            uniforms.write_ambient_occlusion(&self.device, self.targets.ssao.occlusion.view);
+        }
+        self.exposure_pass
+            .write_source(&self.device, self.targets.draw.view);
+        self.history_valid = false;
+    }
+
+    fn set_hdr_metadata(
+        swapchain: &Swapchain,
+        fns: &ash::ext::hdr_metadata::Device,
+        config: &RendererConfig,
+    ) {
+        if swapchain.transfer.is_hdr() {
+            swapchain.set_hdr_metadata(
+                fns,
+                config.max_luminance_nits,
+                config.max_frame_average_nits,
+            );
+        }
+    }
+
     fn init_frame_data(
         device: &ash::Device,
         queue_family_idx: u32,
@@ -219,7 +1762,7 @@ impl Renderer {
         let fence_info = init::fence_create_info(vk::FenceCreateFlags::SIGNALED);
         let sem_info = init::semaphore_create_info(vk::SemaphoreCreateFlags::empty());
 
-        for i in 0..FIF {
+        for frame in &mut frames {
             let pool = unsafe { device.create_command_pool(&pool_info, None) }?;
             let buffer = unsafe {
                 device.allocate_command_buffers(&init::cmd_buffer_allocate_info(pool, 1))
@@ -228,7 +1771,7 @@ impl Renderer {
             let render_fence = unsafe { device.create_fence(&fence_info, None) }?;
             let swapchain_sem = unsafe { device.create_semaphore(&sem_info, None) }?;
             let rendering_sem = unsafe { device.create_semaphore(&sem_info, None) }?;
-            frames[i] = FrameData {
+            *frame = FrameData {
                 pool,
                 buffer,
                 swapchain_sem,
@@ -247,15 +1790,57 @@ impl Renderer {
 
 impl Drop for Renderer {
     fn drop(&mut self) {
-        unsafe { self.device.device_wait_idle() };
+        unsafe { self.device.device_wait_idle() }.unwrap();
         // NOTE: swapchain MUST be destroyed before the surface
         for frame in &self.frames {
             frame.destroy(&self.device);
         }
 
+        if let Some(model) = &mut self.model {
+            model.destroy(&self.device, &mut self.allocator);
+        }
+        self.lighting_pass.destroy(&self.device);
+        self.mesh_pass.destroy(&self.device);
+        self.skybox_pass.destroy(&self.device);
+        self.atmosphere_pass
+            .destroy(&self.device, &mut self.allocator);
+        self.shadow_pass.destroy(&self.device, &mut self.allocator);
+        self.cluster_pass.destroy(&self.device, &mut self.allocator);
+        self.taa_pass.destroy(&self.device);
+        self.exposure_pass
+            .destroy(&self.device, &mut self.allocator);
+        self.bloom_pass.destroy(&self.device);
+        self.ssao_pass.destroy(&self.device);
+        unsafe { self.device.destroy_sampler(self.environment_sampler, None) };
+        self.environment.destroy(&self.device, &mut self.allocator);
+        self.brdf_lut.destroy(&self.device, &mut self.allocator);
+        self.ibl_baker.destroy(&self.device);
+        unsafe { self.device.destroy_sampler(self.default_sampler, None) };
+        self.white_texture
+            .destroy(&self.device, &mut self.allocator);
+        self.flat_normal_texture
+            .destroy(&self.device, &mut self.allocator);
+        for uniforms in &mut self.frame_uniforms {
+            uniforms.destroy(&self.device, &mut self.allocator);
+        }
+        unsafe {
+            self.device
+                .destroy_descriptor_set_layout(self.material_set_layout, None);
+            self.device
+                .destroy_descriptor_set_layout(self.scene_set_layout, None);
+        };
+        self.mip_generator.destroy(&self.device);
+        self.immediate.destroy(&self.device);
+
+        self.present.destroy(&self.device);
+        self.descriptor_allocator.destroy(&self.device);
+        self.targets.destroy(&self.device, &mut self.allocator);
+
         self.swapchain.destroy(&self.device);
         self.surface.destroy();
 
+        // NOTE: the allocator frees its memory blocks on drop, so it has to go before the device
+        unsafe { ManuallyDrop::drop(&mut self.allocator) };
         unsafe { self.device.destroy_device(None) };
         unsafe { self.instance.destroy_instance(None) };
     }
@@ -279,3 +1864,698 @@ impl FrameData {
         }
     }
 }
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy, bytemuck::Pod, bytemuck::Zeroable)]
+struct SceneData {
+    view: Mat4,
+    proj: Mat4,
+    view_proj: Mat4,
+    inv_view_proj: Mat4,
+    camera_position: Vec4,
+    sun_direction: Vec4,
+    sun_color: Vec4,
+    light_count: u32,
+    environment_intensity: f32,
+    debug_view: u32,
+    far_depth: f32,
+    cascade_view_proj: [Mat4; shadow::CASCADE_COUNT],
+    cascade_splits: Vec4,
+    cascade_texel_sizes: Vec4,
+    inv_proj: Mat4,
+    screen_size: Vec2,
+    /// View space depths the first cluster slice starts and the last one ends at
+    cluster_near: f32,
+    cluster_far: f32,
+    ambient_occlusion: u32,
+    /// Whether the sun's light is filtered through the procedural atmosphere
+    atmosphere: u32,
+    _pad: [u32; 2],
+    /// World space directions to the environment's
+    environment_rotation: Mat4,
+    /// This frame's and the last one's, without jitter, for motion vectors
+    unjittered_view_proj: Mat4,
+    previous_view_proj: Mat4,
+}
+
+/// A point or spot light, with the spot cone's falloff as a scale and offset of the cosine
+#[repr(C)]
+#[derive(Debug, Clone, Copy, bytemuck::Pod, bytemuck::Zeroable)]
+struct PunctualLight {
+    position_range: Vec4,
+    color_spot_scale: Vec4,
+    direction_spot_offset: Vec4,
+}
+
+// Per frame in flight so the CPU never writes data the GPU is still reading
+struct FrameUniforms {
+    buffer: AllocatedBuffer,
+    lights: AllocatedBuffer,
+    set: vk::DescriptorSet,
+}
+
+impl FrameUniforms {
+    fn new(
+        device: &ash::Device,
+        allocator: &mut Allocator,
+        descriptor_allocator: &DescriptorAllocator,
+        layout: vk::DescriptorSetLayout,
+    ) -> anyhow::Result<Self> {
+        let buffer = AllocatedBuffer::new(
+            device,
+            allocator,
+            "scene uniforms",
+            size_of::<SceneData>() as vk::DeviceSize,
+            vk::BufferUsageFlags::UNIFORM_BUFFER,
+            MemoryLocation::CpuToGpu,
+        )?;
+        let lights = AllocatedBuffer::new(
+            device,
+            allocator,
+            "scene lights",
+            (MAX_LIGHTS * size_of::<PunctualLight>()) as vk::DeviceSize,
+            vk::BufferUsageFlags::STORAGE_BUFFER,
+            MemoryLocation::CpuToGpu,
+        )?;
+        let set = descriptor_allocator.allocate(device, layout)?;
+        DescriptorWriter::default()
+            .write_buffer(
+                0,
+                buffer.buffer,
+                0,
+                buffer.size,
+                vk::DescriptorType::UNIFORM_BUFFER,
+            )
+            .write_buffer(
+                8,
+                lights.buffer,
+                0,
+                lights.size,
+                vk::DescriptorType::STORAGE_BUFFER,
+            )
+            .update_set(device, set);
+
+        Ok(Self {
+            buffer,
+            lights,
+            set,
+        })
+    }
+
+    // Bindings 1 to 5, rewritten whenever the environment changes
+    fn write_environment(
+        &self,
+        device: &ash::Device,
+        environment: &Environment,
+        brdf_lut: &AllocatedImage,
+        sampler: vk::Sampler,
+    ) {
+        let sampled = |writer: DescriptorWriter, binding, view| {
+            writer.write_image(
+                binding,
+                view,
+                vk::Sampler::null(),
+                vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+                vk::DescriptorType::SAMPLED_IMAGE,
+            )
+        };
+        let writer = sampled(DescriptorWriter::default(), 1, environment.irradiance.view);
+        let writer = sampled(writer, 2, environment.prefiltered.view);
+        let writer = sampled(writer, 3, brdf_lut.view);
+        let writer = sampled(writer, 5, environment.skybox.view);
+        writer
+            .write_image(
+                4,
+                vk::ImageView::null(),
+                sampler,
+                vk::ImageLayout::UNDEFINED,
+                vk::DescriptorType::SAMPLER,
+            )
+            .update_set(device, self.set);
+    }
+
+    // Bindings 6 and 7, the map is the same for the renderer's whole lifetime
+    fn write_shadow_map(&self, device: &ash::Device, view: vk::ImageView, sampler: vk::Sampler) {
+        DescriptorWriter::default()
+            .write_image(
+                6,
+                view,
+                vk::Sampler::null(),
+                vk::ImageLayout::DEPTH_READ_ONLY_OPTIMAL,
+                vk::DescriptorType::SAMPLED_IMAGE,
+            )
+            .write_image(
+                7,
+                vk::ImageView::null(),
+                sampler,
+                vk::ImageLayout::UNDEFINED,
+                vk::DescriptorType::SAMPLER,
+            )
+            .update_set(device, self.set);
+    }
+
+    // Bindings 9 and 10, shared by every frame since they're rebuilt before being read
+    fn write_clusters(&self, device: &ash::Device, clusters: &ClusterPass) {
+        let storage = |writer: DescriptorWriter, binding, buffer: &AllocatedBuffer| {
+            writer.write_buffer(
+                binding,
+                buffer.buffer,
+                0,
+                buffer.size,
+                vk::DescriptorType::STORAGE_BUFFER,
+            )
+        };
+        let writer = storage(DescriptorWriter::default(), 9, &clusters.counts);
+        storage(writer, 10, &clusters.indices).update_set(device, self.set);
+    }
+
+    // Binding 12, written once
+    fn write_transmittance(&self, device: &ash::Device, view: vk::ImageView) {
+        DescriptorWriter::default()
+            .write_image(
+                12,
+                view,
+                vk::Sampler::null(),
+                vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+                vk::DescriptorType::SAMPLED_IMAGE,
+            )
+            .update_set(device, self.set);
+    }
+
+    // Binding 11, rewritten whenever the targets are recreated
+    fn write_ambient_occlusion(&self, device: &ash::Device, view: vk::ImageView) {
+        DescriptorWriter::default()
+            .write_image(
+                11,
+                view,
+                vk::Sampler::null(),
+                vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+                vk::DescriptorType::SAMPLED_IMAGE,
+            )
+            .update_set(device, self.set);
+    }
+
+    fn destroy(&mut self, device: &ash::Device, allocator: &mut Allocator) {
+        self.buffer.destroy(device, allocator);
+        self.lights.destroy(device, allocator);
+    }
+}
+
+// Forward rendering's highest sample count the device supports up to the one asked for
+fn sample_count(config: &RendererConfig, supported: vk::SampleCountFlags) -> vk::SampleCountFlags {
+    if config.render_path == RenderPath::Deferred {
+        return vk::SampleCountFlags::TYPE_1;
+    }
+    [
+        vk::SampleCountFlags::TYPE_8,
+        vk::SampleCountFlags::TYPE_4,
+        vk::SampleCountFlags::TYPE_2,
+    ]
+    .into_iter()
+    .find(|&samples| samples.as_raw() <= config.msaa_samples && supported.contains(samples))
+    .unwrap_or(vk::SampleCountFlags::TYPE_1)
+}
+
+// Images sized to the swapchain, recreated with it
+struct RenderTargets {
+    draw: AllocatedImage,
+    /// Single sampled, resolved into when multisampling
+    depth: DepthBuffer,
+    gbuffer: GBuffer,
+    msaa: Option<MsaaTargets>,
+    taa: TaaImages,
+    /// Tonemapped, for FXAA to read
+    ldr: AllocatedImage,
+    bloom: BloomImages,
+    ssao: SsaoImages,
+}
+
+// Rendered into in place of the draw image, motion vectors and depth buffer, then resolved into
+// them
+struct MsaaTargets {
+    color: AllocatedImage,
+    motion: AllocatedImage,
+    depth: DepthBuffer,
+}
+
+impl RenderTargets {
+    fn new(
+        device: &ash::Device,
+        allocator: &mut Allocator,
+        extent: vk::Extent2D,
+        depth_format: vk::Format,
+        reverse_z: bool,
+        samples: vk::SampleCountFlags,
+        ssao_resolution: SsaoResolution,
+    ) -> anyhow::Result<Self> {
+        let draw = AllocatedImage::new(
+            device,
+            allocator,
+            "draw image",
+            &init::image_create_info(
+                DRAW_IMAGE_FORMAT,
+                vk::ImageUsageFlags::TRANSFER_SRC
+                    | vk::ImageUsageFlags::TRANSFER_DST
+                    | vk::ImageUsageFlags::STORAGE
+                    | vk::ImageUsageFlags::COLOR_ATTACHMENT
+                    | vk::ImageUsageFlags::SAMPLED,
+                extent.into(),
+            ),
+            vk::ImageAspectFlags::COLOR,
+        )?;
+        let depth = DepthBuffer::new(
+            device,
+            allocator,
+            depth_format,
+            extent,
+            reverse_z,
+            vk::SampleCountFlags::TYPE_1,
+        )?;
+        let gbuffer = GBuffer::new(device, allocator, extent)?;
+        let taa = TaaImages::new(device, allocator, extent)?;
+        let ldr = AllocatedImage::new(
+            device,
+            allocator,
+            "ldr image",
+            &init::image_create_info(
+                LDR_IMAGE_FORMAT,
+                vk::ImageUsageFlags::COLOR_ATTACHMENT | vk::ImageUsageFlags::SAMPLED,
+                extent.into(),
+            ),
+            vk::ImageAspectFlags::COLOR,
+        )?;
+
+        let msaa = if samples == vk::SampleCountFlags::TYPE_1 {
+            None
+        } else {
+            // Geometry and the sky cover every sample, and only the resolve is kept
+            let color = AllocatedImage::new(
+                device,
+                allocator,
+                "multisampled draw image",
+                &init::image_create_info(
+                    DRAW_IMAGE_FORMAT,
+                    vk::ImageUsageFlags::COLOR_ATTACHMENT,
+                    extent.into(),
+                )
+                .samples(samples),
+                vk::ImageAspectFlags::COLOR,
+            )?;
+            let motion = AllocatedImage::new(
+                device,
+                allocator,
+                "multisampled motion image",
+                &init::image_create_info(
+                    taa::MOTION_FORMAT,
+                    vk::ImageUsageFlags::COLOR_ATTACHMENT,
+                    extent.into(),
+                )
+                .samples(samples),
+                vk::ImageAspectFlags::COLOR,
+            )?;
+            let depth =
+                DepthBuffer::new(device, allocator, depth_format, extent, reverse_z, samples)?;
+            Some(MsaaTargets {
+                color,
+                motion,
+                depth,
+            })
+        };
+
+        let bloom = BloomImages::new(device, allocator, extent)?;
+        let ssao = SsaoImages::new(device, allocator, extent, ssao_resolution)?;
+
+        Ok(Self {
+            draw,
+            depth,
+            gbuffer,
+            msaa,
+            taa,
+            ldr,
+            bloom,
+            ssao,
+        })
+    }
+
+    /// Motion vectors as a colour attachment, cleared to no motion when `clear` is set
+    fn motion_attachment_info(
+        &self,
+        clear: Option<vk::ClearValue>,
+    ) -> vk::RenderingAttachmentInfo<'static> {
+        init::color_attachment_info(
+            self.taa.motion.view,
+            clear,
+            vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
+        )
+    }
+
+    /// Colour, motion vector and depth attachments for forward rendering, motion and depth
+    /// cleared
+    fn forward_attachments(
+        &self,
+    ) -> (
+        [vk::RenderingAttachmentInfo<'static>; 2],
+        vk::RenderingAttachmentInfo<'static>,
+    ) {
+        let Some(msaa) = &self.msaa else {
+            let color = init::color_attachment_info(
+                self.draw.view,
+                None,
+                vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
+            );
+            let motion = self.motion_attachment_info(Some(vk::ClearValue::default()));
+            return ([color, motion], self.depth.attachment_info(true));
+        };
+
+        // Only the resolved results are kept
+        let resolved = |view, clear, resolve_view| {
+            init::color_attachment_info(view, clear, vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL)
+                .store_op(vk::AttachmentStoreOp::DONT_CARE)
+                .resolve_mode(vk::ResolveModeFlags::AVERAGE)
+                .resolve_image_view(resolve_view)
+                .resolve_image_layout(vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL)
+        };
+        let color = resolved(msaa.color.view, None, self.draw.view);
+        let motion = resolved(
+            msaa.motion.view,
+            Some(vk::ClearValue::default()),
+            self.taa.motion.view,
+        );
+        // Averaging depth isn't meaningful, and sample zero is the one mode always supported
+        let depth = msaa
+            .depth
+            .attachment_info(true)
+            .store_op(vk::AttachmentStoreOp::DONT_CARE)
+            .resolve_mode(vk::ResolveModeFlags::SAMPLE_ZERO)
+            .resolve_image_view(self.depth.image.view)
+            .resolve_image_layout(self.depth.layout());
+        ([color, motion], depth)
+    }
+
+    fn write_taa(&self, device: &ash::Device, taa_pass: &TaaPass) {
+        taa_pass.write_images(
+            device,
+            &self.taa,
+            self.draw.view,
+            self.depth.sampled_view,
+            self.depth.read_only_layout(),
+        );
+    }
+
+    fn write_ssao(&self, device: &ash::Device, ssao_pass: &SsaoPass) {
+        ssao_pass.write_images(
+            device,
+            &self.ssao,
+            self.depth.sampled_view,
+            self.depth.read_only_layout(),
+            self.gbuffer.images[1].view,
+        );
+    }
+
+    fn track(&self, tracker: &mut ResourceTracker) {
+        tracker.register_image(self.draw.image, DRAW_IMAGE_FORMAT, 1, 1, Access::Undefined);
+        tracker.register_image(
+            self.depth.image.image,
+            self.depth.format,
+            1,
+            1,
+            Access::Undefined,
+        );
+        self.gbuffer.track(tracker);
+        self.taa.track(tracker);
+        tracker.register_image(self.ldr.image, LDR_IMAGE_FORMAT, 1, 1, Access::Undefined);
+        self.bloom.track(tracker);
+        self.ssao.track(tracker);
+        if let Some(msaa) = &self.msaa {
+            tracker.register_image(msaa.color.image, DRAW_IMAGE_FORMAT, 1, 1, Access::Undefined);
+            let motion = msaa.motion.image;
+            tracker.register_image(motion, taa::MOTION_FORMAT, 1, 1, Access::Undefined);
+            tracker.register_image(
+                msaa.depth.image.image,
+                msaa.depth.format,
+                1,
+                1,
+                Access::Undefined,
+            );
+        }
+    }
+
+    fn untrack(&self, tracker: &mut ResourceTracker) {
+        tracker.forget_image(self.draw.image);
+        tracker.forget_image(self.depth.image.image);
+        self.gbuffer.untrack(tracker);
+        self.taa.untrack(tracker);
+        tracker.forget_image(self.ldr.image);
+        self.bloom.untrack(tracker);
+        self.ssao.untrack(tracker);
+        if let Some(msaa) = &self.msaa {
+            tracker.forget_image(msaa.color.image);
+            tracker.forget_image(msaa.motion.image);
+            tracker.forget_image(msaa.depth.image.image);
+        }
+    }
+
+    fn destroy(&mut self, device: &ash::Device, allocator: &mut Allocator) {
+        if let Some(msaa) = &mut self.msaa {
+            msaa.color.destroy(device, allocator);
+            msaa.motion.destroy(device, allocator);
+            msaa.depth.destroy(device, allocator);
+        }
+        self.ssao.destroy(device, allocator);
+        self.bloom.destroy(device, allocator);
+        self.ldr.destroy(device, allocator);
+        self.taa.destroy(device, allocator);
+        self.gbuffer.destroy(device, allocator);
+        self.depth.destroy(device, allocator);
+        self.draw.destroy(device, allocator);
+    }
+}
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy, bytemuck::Pod, bytemuck::Zeroable)]
+struct PresentPushConstants {
+    transfer: u32,
+    paper_white_nits: f32,
+    max_luminance_nits: f32,
+    tonemapper: u32,
+    auto_exposure: u32,
+    bloom_intensity: f32,
+}
+
+// Which of the present pass's pipelines to draw with
+#[derive(Debug, Clone, Copy)]
+enum PresentStep {
+    /// Draw image tonemapped and encoded into the swapchain
+    Direct,
+    /// Draw image tonemapped into the LDR image
+    Tonemap,
+    /// LDR image anti-aliased and encoded into the swapchain
+    Fxaa,
+}
+
+// Final fullscreen pass encoding the draw image into the swapchain, optionally split in two
+// around FXAA
+struct PresentPass {
+    set_layout: vk::DescriptorSetLayout,
+    set: vk::DescriptorSet,
+    ldr_set: vk::DescriptorSet,
+    sampler: vk::Sampler,
+    layout: vk::PipelineLayout,
+    pipeline: vk::Pipeline,
+    tonemap_pipeline: vk::Pipeline,
+    fxaa_pipeline: vk::Pipeline,
+}
+
+impl PresentPass {
+    fn new(
+        device: &ash::Device,
+        descriptor_allocator: &DescriptorAllocator,
+        swapchain_format: vk::Format,
+        exposure: &AllocatedBuffer,
+    ) -> anyhow::Result<Self> {
+        let set_layout = DescriptorLayoutBuilder::default()
+            .add_binding(0, vk::DescriptorType::SAMPLED_IMAGE)
+            .add_binding(1, vk::DescriptorType::SAMPLER)
+            .add_binding(2, vk::DescriptorType::STORAGE_BUFFER)
+            .add_binding(3, vk::DescriptorType::SAMPLED_IMAGE)
+            .build(device, vk::ShaderStageFlags::FRAGMENT)?;
+        let set = descriptor_allocator.allocate(device, set_layout)?;
+        let ldr_set = descriptor_allocator.allocate(device, set_layout)?;
+        for set in [set, ldr_set] {
+            DescriptorWriter::default()
+                .write_buffer(
+                    2,
+                    exposure.buffer,
+                    0,
+                    exposure.size,
+                    vk::DescriptorType::STORAGE_BUFFER,
+                )
+                .update_set(device, set);
+        }
+
+        let sampler_info = vk::SamplerCreateInfo::default()
+            .mag_filter(vk::Filter::LINEAR)
+            .min_filter(vk::Filter::LINEAR)
+            .address_mode_u(vk::SamplerAddressMode::CLAMP_TO_EDGE)
+            .address_mode_v(vk::SamplerAddressMode::CLAMP_TO_EDGE)
+            .address_mode_w(vk::SamplerAddressMode::CLAMP_TO_EDGE);
+        let sampler = unsafe { device.create_sampler(&sampler_info, None) }?;
+
+        let layout = pipeline::create_pipeline_layout(
+            device,
+            &[set_layout],
+            &[pipeline::push_constant_range::<PresentPushConstants>(
+                vk::ShaderStageFlags::FRAGMENT,
+            )],
+        )?;
+
+        let [pipeline, tonemap_pipeline, fxaa_pipeline] =
+            Self::create_pipelines(device, layout, swapchain_format)?;
+
+        Ok(Self {
+            set_layout,
+            set,
+            ldr_set,
+            sampler,
+            layout,
+            pipeline,
+            tonemap_pipeline,
+            fxaa_pipeline,
+        })
+    }
+
+    // Two of them write straight to the swapchain, so they're built for its format
+    fn create_pipelines(
+        device: &ash::Device,
+        layout: vk::PipelineLayout,
+        swapchain_format: vk::Format,
+    ) -> anyhow::Result<[vk::Pipeline; 3]> {
+        let module = pipeline::create_shader_module(device, include_shader!("present"))?;
+        let build = |fragment_entry, format| {
+            PipelineBuilder::default()
+                .shaders(module, c"vs_main", module, fragment_entry)
+                .layout(layout)
+                .color_attachment_format(format)
+                .build(device)
+        };
+        let pipeline = build(c"fs_main", swapchain_format);
+        let tonemap_pipeline = build(c"fs_tonemap", LDR_IMAGE_FORMAT);
+        let fxaa_pipeline = build(c"fs_fxaa", swapchain_format);
+        unsafe { device.destroy_shader_module(module, None) };
+
+        Ok([pipeline?, tonemap_pipeline?, fxaa_pipeline?])
+    }
+
+    /// Rebuilds the pipelines after the swapchain was recreated with another surface format.
+    /// Expects the device to be idle
+    fn set_swapchain_format(
+        &mut self,
+        device: &ash::Device,
+        swapchain_format: vk::Format,
+    ) -> anyhow::Result<()> {
+        let [pipeline, tonemap_pipeline, fxaa_pipeline] =
+            Self::create_pipelines(device, self.layout, swapchain_format)?;
+        self.destroy_pipelines(device);
+        self.pipeline = pipeline;
+        self.tonemap_pipeline = tonemap_pipeline;
+        self.fxaa_pipeline = fxaa_pipeline;
+        Ok(())
+    }
+
+    // The LDR set's bloom is never read, it's only there to keep the set complete
+    fn write_sources(
+        &self,
+        device: &ash::Device,
+        draw: vk::ImageView,
+        ldr: vk::ImageView,
+        bloom: vk::ImageView,
+    ) {
+        for (set, view) in [(self.set, draw), (self.ldr_set, ldr)] {
+            DescriptorWriter::default()
+                .write_image(
+                    0,
+                    view,
+                    vk::Sampler::null(),
+                    vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+                    vk::DescriptorType::SAMPLED_IMAGE,
+                )
+                .write_image(
+                    3,
+                    bloom,
+                    vk::Sampler::null(),
+                    vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+                    vk::DescriptorType::SAMPLED_IMAGE,
+                )
+                .write_image(
+                    1,
+                    vk::ImageView::null(),
+                    self.sampler,
+                    vk::ImageLayout::UNDEFINED,
+                    vk::DescriptorType::SAMPLER,
+                )
+                .update_set(device, set);
+        }
+    }
+
+    fn draw(
+        &self,
+        device: &ash::Device,
+        cmd: vk::CommandBuffer,
+        step: PresentStep,
+        target: vk::ImageView,
+        extent: vk::Extent2D,
+        push_constants: PresentPushConstants,
+    ) {
+        let (pipeline, set) = match step {
+            PresentStep::Direct => (self.pipeline, self.set),
+            PresentStep::Tonemap => (self.tonemap_pipeline, self.set),
+            PresentStep::Fxaa => (self.fxaa_pipeline, self.ldr_set),
+        };
+        let color_attachments = [init::color_attachment_info(
+            target,
+            None,
+            vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
+        )];
+        let rendering_info = init::rendering_info(extent, &color_attachments, None);
+
+        unsafe {
+            device.cmd_begin_rendering(cmd, &rendering_info);
+            device.cmd_bind_pipeline(cmd, vk::PipelineBindPoint::GRAPHICS, pipeline);
+            device.cmd_bind_descriptor_sets(
+                cmd,
+                vk::PipelineBindPoint::GRAPHICS,
+                self.layout,
+                0,
+                &[set],
+                &[],
+            );
+            device.cmd_push_constants(
+                cmd,
+                self.layout,
+                vk::ShaderStageFlags::FRAGMENT,
+                0,
+                bytemuck::bytes_of(&push_constants),
+            );
+            util::set_viewport_and_scissor(device, cmd, extent);
+            device.cmd_draw(cmd, 3, 1, 0, 0);
+            device.cmd_end_rendering(cmd);
+        }
+    }
+
+    fn destroy_pipelines(&self, device: &ash::Device) {
+        unsafe {
+            device.destroy_pipeline(self.pipeline, None);
+            device.destroy_pipeline(self.tonemap_pipeline, None);
+            device.destroy_pipeline(self.fxaa_pipeline, None);
+        }
+    }
+
+    fn destroy(&self, device: &ash::Device) {
+        self.destroy_pipelines(device);
+        unsafe {
+            device.destroy_pipeline_layout(self.layout, None);
+            device.destroy_sampler(self.sampler, None);
+            device.destroy_descriptor_set_layout(self.set_layout, None);
+        }
+    }
+}
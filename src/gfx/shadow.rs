@@ -0,0 +1,355 @@
+// Cascaded shadow maps for the sun
+use ash::vk::{self, PhysicalDevice};
+use glam::{Mat4, Vec3, Vec3Swizzles};
+use gpu_allocator::vulkan::Allocator;
+
+use crate::{
+    assets::{self, AlphaMode},
+    camera::{Camera, Projection},
+};
+
+use super::{
+    depth,
+    image::AllocatedImage,
+    init,
+    model::{DrawItem, GpuModel},
+    pipeline::{self, include_shader, PipelineBuilder},
+    util,
+};
+
+/// Has to match mesh.wgsl
+pub const CASCADE_COUNT: usize = 4;
+const SHADOW_MAP_SIZE: u32 = 2048;
+// Only precision matters, no stencil
+const SHADOW_FORMATS: &[vk::Format] = &[vk::Format::D32_SFLOAT, vk::Format::D16_UNORM];
+// Casters this far beyond a cascade towards the sun still land in its map
+const CASTER_MARGIN: f32 = 100.0;
+
+/// How the view distance covered by shadows is divided between cascades
+#[derive(Debug, Clone, Copy)]
+pub struct ShadowSettings {
+    /// Shadows end this far from the camera, or at its far plane if that's closer
+    pub distance: f32,
+    /// 0 splits evenly, 1 logarithmically, which gives nearby cascades more resolution
+    pub split_lambda: f32,
+}
+
+impl Default for ShadowSettings {
+    fn default() -> Self {
+        Self {
+            distance: 100.0,
+            split_lambda: 0.75,
+        }
+    }
+}
+
+/// One frame's light space projections
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Cascades {
+    pub view_proj: [Mat4; CASCADE_COUNT],
+    /// View space depth each cascade ends at
+    pub splits: [f32; CASCADE_COUNT],
+    /// World space size of a shadow map texel
+    pub texel_sizes: [f32; CASCADE_COUNT],
+}
+
+impl Cascades {
+    /// Fits a cascade around each slice of the camera's frustum, shining along `direction`
+    pub fn fit(camera: &Camera, aspect: f32, direction: Vec3, settings: &ShadowSettings) -> Self {
+        let (near, far, half_height) = match camera.projection {
+            Projection::Perspective { fov_y, near, far } => {
+                (near, far, HalfHeight::Slope((fov_y * 0.5).tan()))
+            }
+            Projection::Orthographic { height, near, far } => {
+                (near, far, HalfHeight::Constant(height * 0.5))
+            }
+        };
+        // Logarithmic splits need a positive near plane, orthographic ones can go behind
+        let near = near.max(0.01);
+        let far = far.min(settings.distance).max(near);
+
+        let view_to_world = camera.view().inverse();
+        let up = if direction.y.abs() > 0.99 {
+            Vec3::Z
+        } else {
+            Vec3::Y
+        };
+        let light_view = Mat4::look_to_rh(Vec3::ZERO, direction, up);
+
+        let mut cascades = Self::default();
+        let mut slice_near = near;
+        for i in 0..CASCADE_COUNT {
+            let t = (i + 1) as f32 / CASCADE_COUNT as f32;
+            let log = near * (far / near).powf(t);
+            let uniform = near + (far - near) * t;
+            let slice_far = uniform + (log - uniform) * settings.split_lambda;
+
+            // The sphere around the slice stays the same size however the camera turns, so
+            // the texel grid doesn't change scale from frame to frame
+            let corners = [slice_near, slice_far].into_iter().flat_map(|depth| {
+                let half_height = half_height.at(depth);
+                let half_width = half_height * aspect;
+                [(-1.0, -1.0), (1.0, -1.0), (-1.0, 1.0), (1.0, 1.0)].map(|(x, y)| {
+                    view_to_world.transform_point3(Vec3::new(
+                        x * half_width,
+                        y * half_height,
+                        -depth,
+                    ))
+                })
+            });
+            let corners: Vec<Vec3> = corners.collect();
+            let center = corners.iter().sum::<Vec3>() / corners.len() as f32;
+            let radius = corners
+                .iter()
+                .map(|corner| corner.distance(center))
+                .fold(0.0, f32::max);
+            // Rounded up so float noise doesn't change it either
+            let radius = (radius * 16.0).ceil() / 16.0;
+
+            // Moving the camera only ever moves the projection by whole texels, which keeps
+            // shadow edges from shimmering
+            let texel_size = 2.0 * radius / SHADOW_MAP_SIZE as f32;
+            let light_center = light_view.transform_point3(center);
+            let snapped = (light_center.xy() / texel_size).floor() * texel_size;
+
+            let proj = Mat4::orthographic_rh(
+                snapped.x - radius,
+                snapped.x + radius,
+                snapped.y - radius,
+                snapped.y + radius,
+                -light_center.z - radius - CASTER_MARGIN,
+                -light_center.z + radius,
+            );
+            cascades.view_proj[i] = proj * light_view;
+            cascades.splits[i] = slice_far;
+            cascades.texel_sizes[i] = texel_size;
+            slice_near = slice_far;
+        }
+
+        cascades
+    }
+}
+
+#[derive(Clone, Copy)]
+enum HalfHeight {
+    /// Grows with depth, as for a perspective projection
+    Slope(f32),
+    Constant(f32),
+}
+
+impl HalfHeight {
+    fn at(self, depth: f32) -> f32 {
+        match self {
+            Self::Slope(slope) => slope * depth,
+            Self::Constant(half_height) => half_height,
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy, bytemuck::Pod, bytemuck::Zeroable)]
+struct ShadowPushConstants {
+    light_view_proj: Mat4,
+    model: Mat4,
+}
+
+// Renders opaque and masked geometry into one layer of the shadow map per cascade. Masked
+// materials cast shadows as if they were opaque, blended ones cast none
+pub struct ShadowPass {
+    pub map: AllocatedImage,
+    pub format: vk::Format,
+    layer_views: Vec<vk::ImageView>,
+    /// Compares against the map, with anything outside it lit
+    pub sampler: vk::Sampler,
+    layout: vk::PipelineLayout,
+    pipeline: vk::Pipeline,
+}
+
+impl ShadowPass {
+    pub fn new(
+        instance: &ash::Instance,
+        physical_device: PhysicalDevice,
+        device: &ash::Device,
+        allocator: &mut Allocator,
+    ) -> anyhow::Result<Self> {
+        let format = depth::choose_depth_format(instance, physical_device, SHADOW_FORMATS)?;
+        let map = AllocatedImage::new(
+            device,
+            allocator,
+            "shadow map",
+            &init::image_create_info(
+                format,
+                vk::ImageUsageFlags::DEPTH_STENCIL_ATTACHMENT | vk::ImageUsageFlags::SAMPLED,
+                vk::Extent3D {
+                    width: SHADOW_MAP_SIZE,
+                    height: SHADOW_MAP_SIZE,
+                    depth: 1,
+                },
+            )
+            .array_layers(CASCADE_COUNT as u32),
+            vk::ImageAspectFlags::DEPTH,
+        )?;
+
+        let mut layer_views = Vec::with_capacity(CASCADE_COUNT);
+        for layer in 0..CASCADE_COUNT as u32 {
+            let mut info =
+                init::imageview_create_info(format, map.image, vk::ImageAspectFlags::DEPTH);
+            info.subresource_range.base_array_layer = layer;
+            layer_views.push(unsafe { device.create_image_view(&info, None) }?);
+        }
+
+        let sampler_info = vk::SamplerCreateInfo::default()
+            .mag_filter(vk::Filter::LINEAR)
+            .min_filter(vk::Filter::LINEAR)
+            .address_mode_u(vk::SamplerAddressMode::CLAMP_TO_BORDER)
+            .address_mode_v(vk::SamplerAddressMode::CLAMP_TO_BORDER)
+            .address_mode_w(vk::SamplerAddressMode::CLAMP_TO_EDGE)
+            .border_color(vk::BorderColor::FLOAT_OPAQUE_WHITE)
+            .compare_enable(true)
+            .compare_op(vk::CompareOp::LESS_OR_EQUAL);
+        let sampler = unsafe { device.create_sampler(&sampler_info, None) }?;
+
+        let layout = pipeline::create_pipeline_layout(
+            device,
+            &[],
+            &[pipeline::push_constant_range::<ShadowPushConstants>(
+                vk::ShaderStageFlags::VERTEX,
+            )],
+        )?;
+
+        let bindings = [vk::VertexInputBindingDescription {
+            binding: 0,
+            stride: size_of::<assets::Vertex>() as u32,
+            input_rate: vk::VertexInputRate::VERTEX,
+        }];
+        let attributes = [vk::VertexInputAttributeDescription {
+            location: 0,
+            binding: 0,
+            format: vk::Format::R32G32B32_SFLOAT,
+            offset: std::mem::offset_of!(assets::Vertex, position) as u32,
+        }];
+
+        let module = pipeline::create_shader_module(device, include_shader!("shadow"))?;
+        // Casters are drawn double sided, the bias alone keeps surfaces from shadowing
+        // themselves
+        let pipeline = PipelineBuilder::default()
+            .vertex_shader(module, c"vs_main")
+            .layout(layout)
+            .vertex_input(&bindings, &attributes)
+            .depth_format(format)
+            .depth_test(true, vk::CompareOp::LESS_OR_EQUAL)
+            .depth_bias(1.0, 1.5)
+            .build(device);
+        unsafe { device.destroy_shader_module(module, None) };
+
+        Ok(Self {
+            map,
+            format,
+            layer_views,
+            sampler,
+            layout,
+            pipeline: pipeline?,
+        })
+    }
+
+    /// Expects the map ready to be used as a depth attachment
+    pub fn draw(
+        &self,
+        device: &ash::Device,
+        cmd: vk::CommandBuffer,
+        cascades: &Cascades,
+        model: Option<&GpuModel>,
+        draws: &[DrawItem],
+    ) {
+        let extent = vk::Extent2D {
+            width: SHADOW_MAP_SIZE,
+            height: SHADOW_MAP_SIZE,
+        };
+        let clear = vk::ClearValue {
+            depth_stencil: vk::ClearDepthStencilValue {
+                depth: 1.0,
+                stencil: 0,
+            },
+        };
+
+        for (view, light_view_proj) in self.layer_views.iter().zip(cascades.view_proj) {
+            let depth_attachment = init::depth_attachment_info(
+                *view,
+                Some(clear),
+                vk::ImageLayout::DEPTH_ATTACHMENT_OPTIMAL,
+            );
+            let rendering_info = init::rendering_info(extent, &[], Some(&depth_attachment));
+
+            unsafe {
+                device.cmd_begin_rendering(cmd, &rendering_info);
+                device.cmd_bind_pipeline(cmd, vk::PipelineBindPoint::GRAPHICS, self.pipeline);
+                util::set_viewport_and_scissor(device, cmd, extent);
+            }
+            if let Some(model) = model {
+                self.draw_casters(device, cmd, light_view_proj, model, draws);
+            }
+            unsafe { device.cmd_end_rendering(cmd) };
+        }
+    }
+
+    fn draw_casters(
+        &self,
+        device: &ash::Device,
+        cmd: vk::CommandBuffer,
+        light_view_proj: Mat4,
+        model: &GpuModel,
+        draws: &[DrawItem],
+    ) {
+        for draw in draws {
+            let mesh = &model.meshes[draw.mesh];
+            let push_constants = ShadowPushConstants {
+                light_view_proj,
+                model: draw.transform,
+            };
+            unsafe {
+                device.cmd_push_constants(
+                    cmd,
+                    self.layout,
+                    vk::ShaderStageFlags::VERTEX,
+                    0,
+                    bytemuck::bytes_of(&push_constants),
+                );
+                device.cmd_bind_vertex_buffers(cmd, 0, &[mesh.vertex_buffer.buffer], &[0]);
+                device.cmd_bind_index_buffer(
+                    cmd,
+                    mesh.index_buffer.buffer,
+                    0,
+                    vk::IndexType::UINT32,
+                );
+            }
+            // Blended surfaces let light through, so they cast nothing
+            for primitive in &mesh.primitives {
+                if model.material(primitive.material).alpha_mode == AlphaMode::Blend {
+                    continue;
+                }
+                unsafe {
+                    device.cmd_draw_indexed(
+                        cmd,
+                        primitive.index_count,
+                        1,
+                        primitive.first_index,
+                        0,
+                        0,
+                    )
+                };
+            }
+        }
+    }
+
+    pub fn destroy(&mut self, device: &ash::Device, allocator: &mut Allocator) {
+        unsafe {
+            device.destroy_pipeline(self.pipeline, None);
+            device.destroy_pipeline_layout(self.layout, None);
+            device.destroy_sampler(self.sampler, None);
+            for &view in &self.layer_views {
+                device.destroy_image_view(view, None);
+            }
+        }
+        self.map.destroy(device, allocator);
+    }
+}
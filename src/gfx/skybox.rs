@@ -0,0 +1,142 @@
+use ash::vk;
+
+use super::{
+    pipeline::{self, include_shader, PipelineBuilder},
+    util,
+};
+
+#[derive(Debug, Clone, Copy)]
+pub struct SkyboxSettings {
+    /// Radians the environment is turned around the vertical axis, lighting included
+    pub rotation: f32,
+    /// Scales all light coming from the environment, not just the skybox
+    pub intensity: f32,
+}
+
+impl Default for SkyboxSettings {
+    fn default() -> Self {
+        Self {
+            rotation: 0.0,
+            intensity: 1.0,
+        }
+    }
+}
+
+// Fullscreen pass drawing the environment or the procedural atmosphere behind everything. It's
+// drawn at the far plane after opaque geometry, so only pixels nothing covered pass the depth
+// test
+pub struct SkyboxPass {
+    layout: vk::PipelineLayout,
+    pipeline: vk::Pipeline,
+    atmosphere_layout: vk::PipelineLayout,
+    atmosphere_pipeline: vk::Pipeline,
+}
+
+impl SkyboxPass {
+    pub fn new(
+        device: &ash::Device,
+        scene_set_layout: vk::DescriptorSetLayout,
+        atmosphere_set_layout: vk::DescriptorSetLayout,
+        color_formats: &[vk::Format],
+        depth_format: vk::Format,
+        samples: vk::SampleCountFlags,
+    ) -> anyhow::Result<Self> {
+        let layout = pipeline::create_pipeline_layout(device, &[scene_set_layout], &[])?;
+        let atmosphere_layout = pipeline::create_pipeline_layout(
+            device,
+            &[scene_set_layout, atmosphere_set_layout],
+            &[],
+        )?;
+
+        let builder = PipelineBuilder::default()
+            .color_attachment_formats(color_formats)
+            .first_attachment_only()
+            .depth_format(depth_format)
+            .depth_test(false, vk::CompareOp::EQUAL)
+            .samples(samples);
+        let module = pipeline::create_shader_module(device, include_shader!("skybox"))?;
+        let pipeline = builder
+            .clone()
+            .shaders(module, c"vs_main", module, c"fs_main")
+            .layout(layout)
+            .build(device);
+        unsafe { device.destroy_shader_module(module, None) };
+        let module = pipeline::create_shader_module(device, include_shader!("atmosphere"))?;
+        let atmosphere_pipeline = builder
+            .shaders(module, c"vs_sky", module, c"fs_sky")
+            .layout(atmosphere_layout)
+            .build(device);
+        unsafe { device.destroy_shader_module(module, None) };
+
+        Ok(Self {
+            layout,
+            pipeline: pipeline?,
+            atmosphere_layout,
+            atmosphere_pipeline: atmosphere_pipeline?,
+        })
+    }
+
+    /// The environment cubemap
+    pub fn draw(
+        &self,
+        device: &ash::Device,
+        cmd: vk::CommandBuffer,
+        extent: vk::Extent2D,
+        scene_set: vk::DescriptorSet,
+    ) {
+        let (pipeline, layout) = (self.pipeline, self.layout);
+        Self::draw_with(device, cmd, extent, pipeline, layout, &[scene_set]);
+    }
+
+    /// The sky looked up in the atmosphere's sky-view LUT
+    pub fn draw_atmosphere(
+        &self,
+        device: &ash::Device,
+        cmd: vk::CommandBuffer,
+        extent: vk::Extent2D,
+        scene_set: vk::DescriptorSet,
+        atmosphere_set: vk::DescriptorSet,
+    ) {
+        let (pipeline, layout) = (self.atmosphere_pipeline, self.atmosphere_layout);
+        Self::draw_with(
+            device,
+            cmd,
+            extent,
+            pipeline,
+            layout,
+            &[scene_set, atmosphere_set],
+        );
+    }
+
+    fn draw_with(
+        device: &ash::Device,
+        cmd: vk::CommandBuffer,
+        extent: vk::Extent2D,
+        pipeline: vk::Pipeline,
+        layout: vk::PipelineLayout,
+        sets: &[vk::DescriptorSet],
+    ) {
+        unsafe {
+            device.cmd_bind_pipeline(cmd, vk::PipelineBindPoint::GRAPHICS, pipeline);
+            device.cmd_bind_descriptor_sets(
+                cmd,
+                vk::PipelineBindPoint::GRAPHICS,
+                layout,
+                0,
+                sets,
+                &[],
+            );
+            util::set_viewport_and_scissor(device, cmd, extent);
+            device.cmd_draw(cmd, 3, 1, 0, 0);
+        }
+    }
+
+    pub fn destroy(&self, device: &ash::Device) {
+        unsafe {
+            device.destroy_pipeline(self.pipeline, None);
+            device.destroy_pipeline(self.atmosphere_pipeline, None);
+            device.destroy_pipeline_layout(self.layout, None);
+            device.destroy_pipeline_layout(self.atmosphere_layout, None);
+        }
+    }
+}
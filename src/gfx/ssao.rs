@@ -0,0 +1,301 @@
+// Screen-space ambient occlusion from depth and the G-buffer's normals, darkening the ambient
+// light in creases and corners that the environment can't reach
+use ash::vk;
+use gpu_allocator::vulkan::Allocator;
+
+use super::{
+    barrier::Access,
+    descriptors::{DescriptorAllocator, DescriptorLayoutBuilder, DescriptorWriter},
+    image::AllocatedImage,
+    init,
+    pipeline::{self, include_shader},
+    renderer::SsaoResolution,
+    tracker::ResourceTracker,
+};
+
+/// Occlusion and view depth, the depth keeps the blur from crossing edges. Has to match
+/// ssao.wgsl
+const FORMAT: vk::Format = vk::Format::R16G16_SFLOAT;
+
+#[derive(Debug, Clone, Copy)]
+pub struct SsaoSettings {
+    pub enabled: bool,
+    pub resolution: SsaoResolution,
+    /// World space distance occluders are looked for within
+    pub radius: f32,
+    /// Higher darkens partially occluded areas more
+    pub power: f32,
+}
+
+impl Default for SsaoSettings {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            resolution: SsaoResolution::Half,
+            radius: 0.5,
+            power: 1.5,
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy, bytemuck::Pod, bytemuck::Zeroable)]
+struct SsaoPushConstants {
+    radius: f32,
+    power: f32,
+    scale: u32,
+    direction: u32,
+}
+
+// Sized to the swapchain divided by the resolution's scale, recreated with it
+pub struct SsaoImages {
+    /// Where the finished occlusion ends up, after both blurs
+    pub occlusion: AllocatedImage,
+    /// Holds the horizontally blurred occlusion in between
+    pub scratch: AllocatedImage,
+    scale: u32,
+    extent: vk::Extent2D,
+}
+
+impl SsaoImages {
+    pub fn new(
+        device: &ash::Device,
+        allocator: &mut Allocator,
+        extent: vk::Extent2D,
+        resolution: SsaoResolution,
+    ) -> anyhow::Result<Self> {
+        let scale = resolution.scale();
+        let extent = vk::Extent2D {
+            width: extent.width.div_ceil(scale),
+            height: extent.height.div_ceil(scale),
+        };
+        let image = |allocator: &mut Allocator, name| {
+            AllocatedImage::new(
+                device,
+                allocator,
+                name,
+                &init::image_create_info(
+                    FORMAT,
+                    vk::ImageUsageFlags::STORAGE | vk::ImageUsageFlags::SAMPLED,
+                    extent.into(),
+                ),
+                vk::ImageAspectFlags::COLOR,
+            )
+        };
+        let mut occlusion = image(allocator, "ambient occlusion")?;
+        let scratch = match image(allocator, "ambient occlusion scratch") {
+            Ok(image) => image,
+            Err(err) => {
+                occlusion.destroy(device, allocator);
+                return Err(err);
+            }
+        };
+
+        Ok(Self {
+            occlusion,
+            scratch,
+            scale,
+            extent,
+        })
+    }
+
+    pub fn track(&self, tracker: &mut ResourceTracker) {
+        for image in [&self.occlusion, &self.scratch] {
+            tracker.register_image(image.image, FORMAT, 1, 1, Access::Undefined);
+        }
+    }
+
+    pub fn untrack(&self, tracker: &mut ResourceTracker) {
+        for image in [&self.occlusion, &self.scratch] {
+            tracker.forget_image(image.image);
+        }
+    }
+
+    pub fn destroy(&mut self, device: &ash::Device, allocator: &mut Allocator) {
+        self.occlusion.destroy(device, allocator);
+        self.scratch.destroy(device, allocator);
+    }
+}
+
+pub struct SsaoPass {
+    set_layout: vk::DescriptorSetLayout,
+    /// Occlusion into `occlusion`, blurred across into `scratch`, then down back into it
+    sets: [vk::DescriptorSet; 3],
+    layout: vk::PipelineLayout,
+    ssao_pipeline: vk::Pipeline,
+    blur_pipeline: vk::Pipeline,
+}
+
+impl SsaoPass {
+    pub fn new(
+        device: &ash::Device,
+        descriptor_allocator: &DescriptorAllocator,
+        scene_set_layout: vk::DescriptorSetLayout,
+    ) -> anyhow::Result<Self> {
+        let set_layout = DescriptorLayoutBuilder::default()
+            .add_binding(0, vk::DescriptorType::SAMPLED_IMAGE)
+            .add_binding(1, vk::DescriptorType::SAMPLED_IMAGE)
+            .add_binding(2, vk::DescriptorType::SAMPLED_IMAGE)
+            .add_binding(3, vk::DescriptorType::STORAGE_IMAGE)
+            .build(device, vk::ShaderStageFlags::COMPUTE)?;
+        let sets = [
+            descriptor_allocator.allocate(device, set_layout)?,
+            descriptor_allocator.allocate(device, set_layout)?,
+            descriptor_allocator.allocate(device, set_layout)?,
+        ];
+
+        let layout = pipeline::create_pipeline_layout(
+            device,
+            &[scene_set_layout, set_layout],
+            &[pipeline::push_constant_range::<SsaoPushConstants>(
+                vk::ShaderStageFlags::COMPUTE,
+            )],
+        )?;
+        let module = pipeline::create_shader_module(device, include_shader!("ssao"))?;
+        let ssao_pipeline = pipeline::create_compute_pipeline(device, layout, module, c"cs_ssao");
+        let blur_pipeline = pipeline::create_compute_pipeline(device, layout, module, c"cs_blur");
+        unsafe { device.destroy_shader_module(module, None) };
+
+        Ok(Self {
+            set_layout,
+            sets,
+            layout,
+            ssao_pipeline: ssao_pipeline?,
+            blur_pipeline: blur_pipeline?,
+        })
+    }
+
+    /// Has to be called again whenever the images are recreated. `depth_layout` is the one
+    /// depth is sampled in
+    pub fn write_images(
+        &self,
+        device: &ash::Device,
+        images: &SsaoImages,
+        depth: vk::ImageView,
+        depth_layout: vk::ImageLayout,
+        normals: vk::ImageView,
+    ) {
+        // The first set's source is never read, it's only there to keep the set complete
+        let (occlusion, scratch) = (images.occlusion.view, images.scratch.view);
+        let targets = [
+            (scratch, occlusion),
+            (occlusion, scratch),
+            (scratch, occlusion),
+        ];
+        for (&set, (src, dst)) in self.sets.iter().zip(targets) {
+            let sampled = |writer: DescriptorWriter, binding, view, layout| {
+                writer.write_image(
+                    binding,
+                    view,
+                    vk::Sampler::null(),
+                    layout,
+                    vk::DescriptorType::SAMPLED_IMAGE,
+                )
+            };
+            let read_only = vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL;
+
+            let writer = sampled(DescriptorWriter::default(), 0, depth, depth_layout);
+            let writer = sampled(writer, 1, normals, read_only);
+            let writer = sampled(writer, 2, src, read_only);
+            writer
+                .write_image(
+                    3,
+                    dst,
+                    vk::Sampler::null(),
+                    vk::ImageLayout::GENERAL,
+                    vk::DescriptorType::STORAGE_IMAGE,
+                )
+                .update_set(device, set);
+        }
+    }
+
+    /// Expects depth and normals sampled and the occlusion image ready for compute writes
+    pub fn dispatch_occlusion(
+        &self,
+        device: &ash::Device,
+        cmd: vk::CommandBuffer,
+        scene_set: vk::DescriptorSet,
+        images: &SsaoImages,
+        settings: &SsaoSettings,
+    ) {
+        let push_constants = SsaoPushConstants {
+            radius: settings.radius,
+            power: settings.power,
+            scale: images.scale,
+            direction: 0,
+        };
+        let pipeline = self.ssao_pipeline;
+        self.dispatch(device, cmd, pipeline, 0, scene_set, images, push_constants);
+    }
+
+    /// Horizontally from the occlusion image into the scratch one, or vertically back. Expects
+    /// the source sampled and the other ready for compute writes
+    pub fn dispatch_blur(
+        &self,
+        device: &ash::Device,
+        cmd: vk::CommandBuffer,
+        scene_set: vk::DescriptorSet,
+        images: &SsaoImages,
+        vertical: bool,
+    ) {
+        let push_constants = SsaoPushConstants {
+            radius: 0.0,
+            power: 0.0,
+            scale: images.scale,
+            direction: vertical as u32,
+        };
+        let set = 1 + vertical as usize;
+        let pipeline = self.blur_pipeline;
+        self.dispatch(
+            device,
+            cmd,
+            pipeline,
+            set,
+            scene_set,
+            images,
+            push_constants,
+        );
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn dispatch(
+        &self,
+        device: &ash::Device,
+        cmd: vk::CommandBuffer,
+        pipeline: vk::Pipeline,
+        set: usize,
+        scene_set: vk::DescriptorSet,
+        images: &SsaoImages,
+        push_constants: SsaoPushConstants,
+    ) {
+        let extent = images.extent;
+        unsafe {
+            device.cmd_bind_pipeline(cmd, vk::PipelineBindPoint::COMPUTE, pipeline);
+            device.cmd_bind_descriptor_sets(
+                cmd,
+                vk::PipelineBindPoint::COMPUTE,
+                self.layout,
+                0,
+                &[scene_set, self.sets[set]],
+                &[],
+            );
+            device.cmd_push_constants(
+                cmd,
+                self.layout,
+                vk::ShaderStageFlags::COMPUTE,
+                0,
+                bytemuck::bytes_of(&push_constants),
+            );
+            device.cmd_dispatch(cmd, extent.width.div_ceil(8), extent.height.div_ceil(8), 1);
+        }
+    }
+
+    pub fn destroy(&self, device: &ash::Device) {
+        unsafe {
+            device.destroy_pipeline(self.ssao_pipeline, None);
+            device.destroy_pipeline(self.blur_pipeline, None);
+            device.destroy_pipeline_layout(self.layout, None);
+            device.destroy_descriptor_set_layout(self.set_layout, None);
+        }
+    }
+}
@@ -11,11 +11,11 @@ impl Surface {
         instance: &ash::Instance,
         window: &winit::window::Window,
     ) -> anyhow::Result<Self> {
-        let fns = ash::khr::surface::Instance::new(&entry, &instance);
+        let fns = ash::khr::surface::Instance::new(entry, instance);
         let surface = unsafe {
             ash_window::create_surface(
-                &entry,
-                &instance,
+                entry,
+                instance,
                 window.display_handle()?.as_raw(),
                 window.window_handle()?.as_raw(),
                 None,
@@ -60,4 +60,3 @@ impl Surface {
         unsafe { self.fns.destroy_surface(self.surface, None) };
     }
 }
-
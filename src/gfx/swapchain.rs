@@ -1,15 +1,131 @@
-use super::surface::Surface;
+use super::{barrier::Access, surface::Surface, tracker::ResourceTracker};
 use ash::vk::{self, PhysicalDevice};
 
+// Ranked from most to least preferred. sRGB formats come first so the hardware does the
+// encoding, then 10-bit UNORM, then plain 8-bit UNORM where the present pass encodes itself.
+pub const PREFERRED_FORMATS: &[vk::Format] = &[
+    vk::Format::B8G8R8A8_SRGB,
+    vk::Format::R8G8B8A8_SRGB,
+    vk::Format::A8B8G8R8_SRGB_PACK32,
+    vk::Format::A2B10G10R10_UNORM_PACK32,
+    vk::Format::A2R10G10B10_UNORM_PACK32,
+    vk::Format::B8G8R8A8_UNORM,
+    vk::Format::R8G8B8A8_UNORM,
+];
+
+// Tried before PREFERRED_FORMATS when HDR output is requested and the instance has
+// VK_EXT_swapchain_colorspace
+pub const HDR_SURFACE_FORMATS: &[vk::SurfaceFormatKHR] = &[
+    vk::SurfaceFormatKHR {
+        format: vk::Format::A2B10G10R10_UNORM_PACK32,
+        color_space: vk::ColorSpaceKHR::HDR10_ST2084_EXT,
+    },
+    vk::SurfaceFormatKHR {
+        format: vk::Format::A2R10G10B10_UNORM_PACK32,
+        color_space: vk::ColorSpaceKHR::HDR10_ST2084_EXT,
+    },
+    vk::SurfaceFormatKHR {
+        format: vk::Format::R16G16B16A16_SFLOAT,
+        color_space: vk::ColorSpaceKHR::EXTENDED_SRGB_LINEAR_EXT,
+    },
+];
+
+/// Picks the first entry of `preferences` the surface supports in the sRGB colour space,
+/// falling back to any other format in that colour space. Formats in any other colour space
+/// are never picked, the present pass wouldn't know how to encode for them.
+pub fn choose_surface_format(
+    supported: &[vk::SurfaceFormatKHR],
+    preferences: &[vk::Format],
+) -> Option<vk::SurfaceFormatKHR> {
+    let srgb_space = vk::ColorSpaceKHR::SRGB_NONLINEAR;
+
+    // A single UNDEFINED entry means the surface has no preference at all
+    if let [only] = supported {
+        if only.format == vk::Format::UNDEFINED {
+            return Some(vk::SurfaceFormatKHR {
+                format: preferences
+                    .first()
+                    .copied()
+                    .unwrap_or(vk::Format::B8G8R8A8_SRGB),
+                color_space: srgb_space,
+            });
+        }
+    }
+
+    preferences
+        .iter()
+        .find_map(|&format| {
+            supported
+                .iter()
+                .find(|surface_format| {
+                    surface_format.format == format && surface_format.color_space == srgb_space
+                })
+                .copied()
+        })
+        .or_else(|| {
+            supported
+                .iter()
+                .find(|surface_format| surface_format.color_space == srgb_space)
+                .copied()
+        })
+}
+
+pub fn is_srgb_format(format: vk::Format) -> bool {
+    matches!(
+        format,
+        vk::Format::B8G8R8A8_SRGB
+            | vk::Format::R8G8B8A8_SRGB
+            | vk::Format::A8B8G8R8_SRGB_PACK32
+            | vk::Format::R8G8B8_SRGB
+            | vk::Format::B8G8R8_SRGB
+    )
+}
+
+/// How the final pass has to encode linear colour for the swapchain image
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u32)]
+pub enum OutputTransfer {
+    /// `_SRGB` format, the hardware applies the sRGB curve on write
+    Hardware = 0,
+    /// `_UNORM` format presented as sRGB, the shader has to apply the curve
+    Srgb = 1,
+    /// HDR10: Rec.2020 primaries encoded with the ST2084 (PQ) curve
+    Pq = 2,
+    /// scRGB: linear Rec.709 primaries where 1.0 is 80 nits
+    ScRgb = 3,
+}
+
+impl OutputTransfer {
+    /// Follows the colour space, which decides how the display interprets the values written
+    pub fn new(surface_format: vk::SurfaceFormatKHR) -> Option<Self> {
+        match surface_format.color_space {
+            vk::ColorSpaceKHR::SRGB_NONLINEAR if is_srgb_format(surface_format.format) => {
+                Some(Self::Hardware)
+            }
+            vk::ColorSpaceKHR::SRGB_NONLINEAR => Some(Self::Srgb),
+            vk::ColorSpaceKHR::HDR10_ST2084_EXT => Some(Self::Pq),
+            vk::ColorSpaceKHR::EXTENDED_SRGB_LINEAR_EXT => Some(Self::ScRgb),
+            _ => None,
+        }
+    }
+
+    pub fn is_hdr(self) -> bool {
+        matches!(self, Self::Pq | Self::ScRgb)
+    }
+}
+
 pub struct Swapchain {
     pub fns: ash::khr::swapchain::Device,
     pub swapchain: vk::SwapchainKHR,
     pub images: Vec<vk::Image>,
     pub views: Vec<vk::ImageView>,
     pub format: vk::Format,
+    pub color_space: vk::ColorSpaceKHR,
+    pub transfer: OutputTransfer,
     pub extent: vk::Extent2D,
 }
 impl Swapchain {
+    #[allow(clippy::too_many_arguments)]
     pub fn new(
         instance: &ash::Instance,
         device: &ash::Device,
@@ -17,37 +133,43 @@ impl Swapchain {
         surface: &Surface,
         width: u32,
         height: u32,
-        old_swapchain: Option<Self>,
+        preferred_formats: &[vk::Format],
+        hdr_formats: &[vk::SurfaceFormatKHR],
+        old_swapchain: Option<&Self>,
     ) -> anyhow::Result<Self> {
         let fns = ash::khr::swapchain::Device::new(instance, device);
-        let extent = vk::Extent2D { width, height };
         let caps = surface.get_caps(physical_device)?;
-        let format = {
-            let surface_format = surface
-                .supported_formats(physical_device)?
-                .into_iter()
-                .find(|surface_format| {
-                    surface_format.format == vk::Format::B8G8R8A8_UNORM
-                        && surface_format.color_space == vk::ColorSpaceKHR::SRGB_NONLINEAR
-                });
-
-            if surface_format.is_none() {
-                return Err(anyhow::anyhow!("Desired Surface format not found!"));
+        let extent = if caps.current_extent.width != u32::MAX {
+            caps.current_extent
+        } else {
+            vk::Extent2D {
+                width: width.clamp(caps.min_image_extent.width, caps.max_image_extent.width),
+                height: height.clamp(caps.min_image_extent.height, caps.max_image_extent.height),
             }
-
-            surface_format.unwrap().format
         };
 
+        let supported_formats = surface.supported_formats(physical_device)?;
+        let surface_format = hdr_formats
+            .iter()
+            .find(|hdr_format| supported_formats.contains(hdr_format))
+            .copied()
+            .or_else(|| choose_surface_format(&supported_formats, preferred_formats))
+            .ok_or(anyhow::anyhow!(
+                "Surface does not report any formats in the sRGB colour space!"
+            ))?;
+        let format = surface_format.format;
+        let transfer = OutputTransfer::new(surface_format).ok_or(anyhow::anyhow!(
+            "Surface format {surface_format:?} has no known output encoding"
+        ))?;
+
         let max_images = match caps.max_image_count {
             0 => u32::MAX,
             x => x,
         };
-        let min_images = (caps.min_image_count).max(max_images);
+        let min_images = (caps.min_image_count + 1).min(max_images);
         let mailbox_supported: bool = surface
             .supported_present_modes(physical_device)?
-            .iter()
-            .find(|&mode| *mode == vk::PresentModeKHR::MAILBOX)
-            .is_some();
+            .contains(&vk::PresentModeKHR::MAILBOX);
         let present_mode = if mailbox_supported {
             vk::PresentModeKHR::MAILBOX
         } else {
@@ -58,7 +180,7 @@ impl Swapchain {
             .surface(surface.surface)
             .min_image_count(min_images)
             .image_format(format)
-            .image_color_space(vk::ColorSpaceKHR::SRGB_NONLINEAR)
+            .image_color_space(surface_format.color_space)
             .image_array_layers(1)
             .image_sharing_mode(vk::SharingMode::EXCLUSIVE)
             .pre_transform(vk::SurfaceTransformFlagsKHR::IDENTITY)
@@ -106,10 +228,48 @@ impl Swapchain {
             images,
             views,
             format,
+            color_space: surface_format.color_space,
+            transfer,
             extent,
         })
     }
 
+    // Tells the display what the content was mastered for, only meaningful for HDR outputs
+    pub fn set_hdr_metadata(
+        &self,
+        fns: &ash::ext::hdr_metadata::Device,
+        max_luminance: f32,
+        max_frame_average: f32,
+    ) {
+        // Rec.2020 primaries with a D65 white point
+        let metadata = vk::HdrMetadataEXT::default()
+            .display_primary_red(vk::XYColorEXT { x: 0.708, y: 0.292 })
+            .display_primary_green(vk::XYColorEXT { x: 0.170, y: 0.797 })
+            .display_primary_blue(vk::XYColorEXT { x: 0.131, y: 0.046 })
+            .white_point(vk::XYColorEXT {
+                x: 0.3127,
+                y: 0.3290,
+            })
+            .max_luminance(max_luminance)
+            .min_luminance(0.001)
+            .max_content_light_level(max_luminance)
+            .max_frame_average_light_level(max_frame_average);
+
+        unsafe { fns.set_hdr_metadata(&[self.swapchain], &[metadata]) };
+    }
+
+    pub fn track(&self, tracker: &mut ResourceTracker) {
+        for image in &self.images {
+            tracker.register_image(*image, self.format, 1, 1, Access::Present);
+        }
+    }
+
+    pub fn untrack(&self, tracker: &mut ResourceTracker) {
+        for image in &self.images {
+            tracker.forget_image(*image);
+        }
+    }
+
     pub fn destroy(&self, device: &ash::Device) {
         self.views
             .iter()
@@ -0,0 +1,283 @@
+// Temporal anti-aliasing, resolving each jittered frame against a reprojected history
+use ash::vk;
+use glam::{Mat4, Vec2};
+use gpu_allocator::vulkan::Allocator;
+
+use super::{
+    barrier::Access,
+    descriptors::{DescriptorAllocator, DescriptorLayoutBuilder, DescriptorWriter},
+    image::AllocatedImage,
+    init,
+    pipeline::{self, include_shader},
+    tracker::ResourceTracker,
+};
+
+/// History format, has to match taa.wgsl's output
+pub const FORMAT: vk::Format = vk::Format::R16G16B16A16_SFLOAT;
+/// Motion in UV and the pixel's coverage, written by geometry as an extra colour attachment
+pub const MOTION_FORMAT: vk::Format = vk::Format::R16G16B16A16_SFLOAT;
+// How much of the history survives each frame
+const FEEDBACK: f32 = 0.9;
+// Jitter repeats after this many frames
+const JITTER_PHASES: u32 = 8;
+
+/// Sub-pixel offset for a frame, in pixels from the pixel's centre
+pub fn jitter(frame: u64) -> Vec2 {
+    let index = (frame % JITTER_PHASES as u64) as u32 + 1;
+    Vec2::new(halton(index, 2), halton(index, 3)) - 0.5
+}
+
+fn halton(mut index: u32, base: u32) -> f32 {
+    let mut result = 0.0;
+    let mut fraction = 1.0;
+    while index > 0 {
+        fraction /= base as f32;
+        result += fraction * (index % base) as f32;
+        index /= base;
+    }
+    result
+}
+
+/// Shifts a projection by `jitter` pixels, without changing depth
+pub fn jittered(proj: Mat4, jitter: Vec2, extent: vk::Extent2D) -> Mat4 {
+    let offset = jitter * 2.0 / Vec2::new(extent.width as f32, extent.height as f32);
+    Mat4::from_translation(offset.extend(0.0)) * proj
+}
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy, bytemuck::Pod, bytemuck::Zeroable)]
+struct TaaPushConstants {
+    reprojection: Mat4,
+    reset: u32,
+    feedback: f32,
+    _pad: [u32; 2],
+}
+
+// Sized to the swapchain, recreated with it
+pub struct TaaImages {
+    /// Written every other frame each, one holds the last result while the other gets the next
+    pub history: [AllocatedImage; 2],
+    pub motion: AllocatedImage,
+}
+
+impl TaaImages {
+    pub fn new(
+        device: &ash::Device,
+        allocator: &mut Allocator,
+        extent: vk::Extent2D,
+    ) -> anyhow::Result<Self> {
+        let image = |allocator: &mut Allocator, name, format, usage| {
+            AllocatedImage::new(
+                device,
+                allocator,
+                name,
+                &init::image_create_info(
+                    format,
+                    vk::ImageUsageFlags::SAMPLED | usage,
+                    extent.into(),
+                ),
+                vk::ImageAspectFlags::COLOR,
+            )
+        };
+        // Copied into the draw image once resolved
+        let history_usage = vk::ImageUsageFlags::STORAGE | vk::ImageUsageFlags::TRANSFER_SRC;
+        let mut first = image(allocator, "taa history", FORMAT, history_usage)?;
+        let mut second = match image(allocator, "taa history", FORMAT, history_usage) {
+            Ok(image) => image,
+            Err(err) => {
+                first.destroy(device, allocator);
+                return Err(err);
+            }
+        };
+        let motion = match image(
+            allocator,
+            "motion vectors",
+            MOTION_FORMAT,
+            vk::ImageUsageFlags::COLOR_ATTACHMENT,
+        ) {
+            Ok(image) => image,
+            Err(err) => {
+                first.destroy(device, allocator);
+                second.destroy(device, allocator);
+                return Err(err);
+            }
+        };
+
+        Ok(Self {
+            history: [first, second],
+            motion,
+        })
+    }
+
+    pub fn track(&self, tracker: &mut ResourceTracker) {
+        for image in &self.history {
+            tracker.register_image(image.image, FORMAT, 1, 1, Access::Undefined);
+        }
+        tracker.register_image(self.motion.image, MOTION_FORMAT, 1, 1, Access::Undefined);
+    }
+
+    pub fn untrack(&self, tracker: &mut ResourceTracker) {
+        for image in self.history.iter().chain([&self.motion]) {
+            tracker.forget_image(image.image);
+        }
+    }
+
+    pub fn destroy(&mut self, device: &ash::Device, allocator: &mut Allocator) {
+        for image in &mut self.history {
+            image.destroy(device, allocator);
+        }
+        self.motion.destroy(device, allocator);
+    }
+}
+
+pub struct TaaPass {
+    set_layout: vk::DescriptorSetLayout,
+    /// One per history image written, reading the other
+    sets: [vk::DescriptorSet; 2],
+    sampler: vk::Sampler,
+    layout: vk::PipelineLayout,
+    pipeline: vk::Pipeline,
+}
+
+impl TaaPass {
+    pub fn new(
+        device: &ash::Device,
+        descriptor_allocator: &DescriptorAllocator,
+    ) -> anyhow::Result<Self> {
+        let set_layout = DescriptorLayoutBuilder::default()
+            .add_binding(0, vk::DescriptorType::SAMPLED_IMAGE)
+            .add_binding(1, vk::DescriptorType::SAMPLED_IMAGE)
+            .add_binding(2, vk::DescriptorType::SAMPLED_IMAGE)
+            .add_binding(3, vk::DescriptorType::STORAGE_IMAGE)
+            .add_binding(4, vk::DescriptorType::SAMPLER)
+            .add_binding(5, vk::DescriptorType::SAMPLED_IMAGE)
+            .build(device, vk::ShaderStageFlags::COMPUTE)?;
+        let sets = [
+            descriptor_allocator.allocate(device, set_layout)?,
+            descriptor_allocator.allocate(device, set_layout)?,
+        ];
+
+        let sampler_info = vk::SamplerCreateInfo::default()
+            .mag_filter(vk::Filter::LINEAR)
+            .min_filter(vk::Filter::LINEAR)
+            .address_mode_u(vk::SamplerAddressMode::CLAMP_TO_EDGE)
+            .address_mode_v(vk::SamplerAddressMode::CLAMP_TO_EDGE)
+            .address_mode_w(vk::SamplerAddressMode::CLAMP_TO_EDGE);
+        let sampler = unsafe { device.create_sampler(&sampler_info, None) }?;
+
+        let layout = pipeline::create_pipeline_layout(
+            device,
+            &[set_layout],
+            &[pipeline::push_constant_range::<TaaPushConstants>(
+                vk::ShaderStageFlags::COMPUTE,
+            )],
+        )?;
+        let module = pipeline::create_shader_module(device, include_shader!("taa"))?;
+        let pipeline = pipeline::create_compute_pipeline(device, layout, module, c"cs_resolve");
+        unsafe { device.destroy_shader_module(module, None) };
+
+        Ok(Self {
+            set_layout,
+            sets,
+            sampler,
+            layout,
+            pipeline: pipeline?,
+        })
+    }
+
+    /// Has to be called again whenever the images are recreated. `depth_layout` is the one
+    /// depth is sampled in
+    pub fn write_images(
+        &self,
+        device: &ash::Device,
+        images: &TaaImages,
+        current: vk::ImageView,
+        depth: vk::ImageView,
+        depth_layout: vk::ImageLayout,
+    ) {
+        for (i, &set) in self.sets.iter().enumerate() {
+            let sampled = |writer: DescriptorWriter, binding, view, layout| {
+                writer.write_image(
+                    binding,
+                    view,
+                    vk::Sampler::null(),
+                    layout,
+                    vk::DescriptorType::SAMPLED_IMAGE,
+                )
+            };
+            let storage = |writer: DescriptorWriter, binding, view| {
+                writer.write_image(
+                    binding,
+                    view,
+                    vk::Sampler::null(),
+                    vk::ImageLayout::GENERAL,
+                    vk::DescriptorType::STORAGE_IMAGE,
+                )
+            };
+            let read_only = vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL;
+
+            let writer = sampled(DescriptorWriter::default(), 0, current, read_only);
+            let writer = sampled(writer, 1, depth, depth_layout);
+            let writer = sampled(writer, 2, images.history[1 - i].view, read_only);
+            let writer = storage(writer, 3, images.history[i].view);
+            let writer = sampled(writer, 5, images.motion.view, read_only);
+            writer
+                .write_image(
+                    4,
+                    vk::ImageView::null(),
+                    self.sampler,
+                    vk::ImageLayout::UNDEFINED,
+                    vk::DescriptorType::SAMPLER,
+                )
+                .update_set(device, set);
+        }
+    }
+
+    /// Expects the current image, depth, motion and the history not being written sampled,
+    /// and `history[target]` ready for compute writes. `reprojection` moves whatever geometry
+    /// didn't cover from this frame's clip space to the last one's
+    pub fn dispatch_resolve(
+        &self,
+        device: &ash::Device,
+        cmd: vk::CommandBuffer,
+        extent: vk::Extent2D,
+        target: usize,
+        reprojection: Mat4,
+        reset: bool,
+    ) {
+        let push_constants = TaaPushConstants {
+            reprojection,
+            reset: reset as u32,
+            feedback: FEEDBACK,
+            _pad: [0; 2],
+        };
+        unsafe {
+            device.cmd_bind_pipeline(cmd, vk::PipelineBindPoint::COMPUTE, self.pipeline);
+            device.cmd_bind_descriptor_sets(
+                cmd,
+                vk::PipelineBindPoint::COMPUTE,
+                self.layout,
+                0,
+                &[self.sets[target]],
+                &[],
+            );
+            device.cmd_push_constants(
+                cmd,
+                self.layout,
+                vk::ShaderStageFlags::COMPUTE,
+                0,
+                bytemuck::bytes_of(&push_constants),
+            );
+            device.cmd_dispatch(cmd, extent.width.div_ceil(8), extent.height.div_ceil(8), 1);
+        }
+    }
+
+    pub fn destroy(&self, device: &ash::Device) {
+        unsafe {
+            device.destroy_pipeline(self.pipeline, None);
+            device.destroy_pipeline_layout(self.layout, None);
+            device.destroy_sampler(self.sampler, None);
+            device.destroy_descriptor_set_layout(self.set_layout, None);
+        }
+    }
+}
@@ -0,0 +1,182 @@
+use ash::vk;
+use gpu_allocator::vulkan::Allocator;
+
+use crate::assets::{ImageData, PixelFormat};
+
+use super::{
+    barrier::{Access, Barriers, ImageBarrier},
+    image::AllocatedImage,
+    init,
+    upload::UploadContext,
+};
+
+pub struct Texture {
+    pub image: AllocatedImage,
+}
+
+impl Texture {
+    /// Uploads `data` with the mips it comes with, or generates them on the GPU
+    pub fn from_image(
+        ctx: &mut UploadContext,
+        name: &str,
+        data: &ImageData,
+    ) -> anyhow::Result<Self> {
+        let can_sample = |format| {
+            let features = unsafe {
+                ctx.instance
+                    .get_physical_device_format_properties(ctx.physical_device, format)
+                    .optimal_tiling_features
+            };
+            features.contains(vk::FormatFeatureFlags::SAMPLED_IMAGE)
+        };
+        // Basis Universal data and formats the device can't sample get converted first
+        let transcoded = data
+            .transcode(can_sample)
+            .map_err(|err| err.context(format!("Failed to transcode {name}")))?;
+        let data = transcoded.as_ref().unwrap_or(data);
+
+        let format = match data.format {
+            PixelFormat::Rgba8 { srgb: true } => vk::Format::R8G8B8A8_SRGB,
+            PixelFormat::Rgba8 { srgb: false } => vk::Format::R8G8B8A8_UNORM,
+            PixelFormat::Rgba32F => vk::Format::R32G32B32A32_SFLOAT,
+            PixelFormat::Native(format) => format,
+            PixelFormat::Basis { .. } => unreachable!("Basis Universal data is always transcoded"),
+        };
+        if !can_sample(format) {
+            return Err(anyhow::anyhow!(
+                "{name} is {format:?}, which this device can't sample from"
+            ));
+        }
+
+        let extent = vk::Extent2D {
+            width: data.width,
+            height: data.height,
+        };
+        let mip_generator = ctx.mip_generator;
+        let generate_mips = data.mips.is_empty();
+        let (mip_levels, usage) = if generate_mips {
+            (
+                mip_generator.mip_levels(format, extent),
+                mip_generator.usage(format),
+            )
+        } else {
+            (1 + data.mips.len() as u32, vk::ImageUsageFlags::empty())
+        };
+
+        let mut image = AllocatedImage::new(
+            ctx.device,
+            ctx.allocator,
+            name,
+            &init::image_create_info(
+                format,
+                vk::ImageUsageFlags::SAMPLED | vk::ImageUsageFlags::TRANSFER_DST | usage,
+                extent.into(),
+            )
+            .mip_levels(mip_levels),
+            vk::ImageAspectFlags::COLOR,
+        )?;
+
+        // Every level the file has goes into one staging buffer, one copy region each
+        let levels: Vec<&[u8]> = std::iter::once(&data.pixels)
+            .chain(&data.mips)
+            .map(Vec::as_slice)
+            .collect();
+        let mut regions = Vec::with_capacity(levels.len());
+        let mut offset = 0;
+        for (level, bytes) in levels.iter().enumerate() {
+            let level = level as u32;
+            regions.push(
+                vk::BufferImageCopy::default()
+                    .buffer_offset(offset as vk::DeviceSize)
+                    .image_subresource(
+                        vk::ImageSubresourceLayers::default()
+                            .aspect_mask(vk::ImageAspectFlags::COLOR)
+                            .mip_level(level)
+                            .layer_count(1),
+                    )
+                    .image_extent(vk::Extent3D {
+                        width: (extent.width >> level).max(1),
+                        height: (extent.height >> level).max(1),
+                        depth: 1,
+                    }),
+            );
+            // Offsets have to be a multiple of the texel block size, 16 covers all of them
+            offset = (offset + bytes.len()).next_multiple_of(16);
+        }
+        let mut staging_data = vec![0; offset];
+        for (region, bytes) in regions.iter().zip(&levels) {
+            let start = region.buffer_offset as usize;
+            staging_data[start..start + bytes.len()].copy_from_slice(bytes);
+        }
+        let mut staging = ctx.staging_buffer(&staging_data)?;
+
+        let device = ctx.device;
+        let result = ctx
+            .immediate
+            .submit(device, ctx.queue, |cmd| {
+                let mut barriers = Barriers::default();
+                barriers
+                    .image(ImageBarrier::new(
+                        image.image,
+                        format,
+                        Access::Undefined,
+                        Access::TransferDst,
+                    ))
+                    .flush(device, cmd);
+
+                unsafe {
+                    device.cmd_copy_buffer_to_image(
+                        cmd,
+                        staging.buffer,
+                        image.image,
+                        vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                        &regions,
+                    )
+                };
+
+                if !generate_mips {
+                    barriers
+                        .image(ImageBarrier::new(
+                            image.image,
+                            format,
+                            Access::TransferDst,
+                            Access::FragmentSampled,
+                        ))
+                        .flush(device, cmd);
+                }
+            })
+            .and_then(|()| {
+                if generate_mips {
+                    mip_generator.generate(ctx, image.image, format, extent, mip_levels)
+                } else {
+                    Ok(())
+                }
+            });
+        staging.destroy(ctx.device, ctx.allocator);
+        if let Err(err) = result {
+            image.destroy(ctx.device, ctx.allocator);
+            return Err(err);
+        }
+
+        Ok(Self { image })
+    }
+
+    /// A single linear texel, for filling in textures a material doesn't have
+    pub fn solid(ctx: &mut UploadContext, name: &str, rgba: [u8; 4]) -> anyhow::Result<Self> {
+        Self::from_image(
+            ctx,
+            name,
+            &ImageData {
+                width: 1,
+                height: 1,
+                format: PixelFormat::Rgba8 { srgb: false },
+                pixels: rgba.to_vec(),
+                mips: Vec::new(),
+            },
+        )
+    }
+
+    pub fn destroy(&mut self, device: &ash::Device, allocator: &mut Allocator) {
+        self.image.destroy(device, allocator);
+    }
+}
@@ -0,0 +1,521 @@
+use std::collections::HashMap;
+
+use ash::vk;
+
+use super::barrier::{aspect_mask_for_format, Access, AccessInfo, Barriers, ImageRange, Scope};
+
+/// What a subresource or buffer went through since it was last written
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct State {
+    /// Only kept for `use_image_from` to check against
+    last: Access,
+    layout: vk::ImageLayout,
+    /// The last write, or layout transition, which every later access has to wait for
+    write: Scope,
+    /// Accesses already made to wait for the write. Reads within these need no barrier, and
+    /// the next write or transition waits for all of them
+    reads: Scope,
+}
+
+/// Source and destination of the barrier an access needs
+struct Dependency {
+    src: Scope,
+    dst: Scope,
+    old_layout: vk::ImageLayout,
+}
+
+impl State {
+    fn new(initial: Access, info: AccessInfo) -> Self {
+        Self {
+            last: initial,
+            layout: info.layout,
+            write: if initial.is_write() {
+                info.scope()
+            } else {
+                info.scope().execution_only()
+            },
+            reads: Scope::default(),
+        }
+    }
+
+    /// Moves on to `next`, returning the barrier it needs first if it isn't covered yet
+    fn advance(&mut self, next: Access, info: AccessInfo, discard: bool) -> Option<Dependency> {
+        let dst = info.scope();
+        let dependency = if next.is_write() || discard || info.layout != self.layout {
+            // Writes and layout transitions have to wait for every access since the last write
+            let dependency = Dependency {
+                src: Scope {
+                    stage: self.write.stage | self.reads.stage,
+                    access: self.write.access,
+                },
+                dst,
+                old_layout: if discard {
+                    vk::ImageLayout::UNDEFINED
+                } else {
+                    self.layout
+                },
+            };
+            if next.is_write() {
+                self.write = dst;
+                self.reads = Scope::default();
+            } else {
+                // Readers in other stages have to chain after the transition
+                self.write = dst.execution_only();
+                self.reads = dst;
+            }
+            Some(dependency)
+        } else if self.reads.contains(dst) {
+            None
+        } else {
+            // Visibility is per stage, so the barrier covers every reader so far as well
+            self.reads = self.reads | dst;
+            Some(Dependency {
+                src: self.write,
+                dst: self.reads,
+                old_layout: self.layout,
+            })
+        };
+
+        self.last = next;
+        self.layout = info.layout;
+        dependency
+    }
+}
+
+struct TrackedImage {
+    format: vk::Format,
+    mip_levels: u32,
+    array_layers: u32,
+    // Every subresource, indexed by `layer * mip_levels + mip`
+    states: Vec<State>,
+}
+
+impl TrackedImage {
+    fn resolve(&self, range: ImageRange) -> ImageRange {
+        let mip_count = if range.mip_count == vk::REMAINING_MIP_LEVELS {
+            self.mip_levels - range.base_mip
+        } else {
+            range.mip_count
+        };
+        let layer_count = if range.layer_count == vk::REMAINING_ARRAY_LAYERS {
+            self.array_layers - range.base_layer
+        } else {
+            range.layer_count
+        };
+
+        ImageRange {
+            mip_count,
+            layer_count,
+            ..range
+        }
+    }
+}
+
+/// Remembers the last write of every tracked image subresource and buffer and what has read it
+/// since, so passes only say how they are about to use a resource and the minimal barriers are
+/// emitted for them.
+///
+/// State carries over between command buffers since they all go to the same queue in order.
+#[derive(Default)]
+pub struct ResourceTracker {
+    images: HashMap<vk::Image, TrackedImage>,
+    buffers: HashMap<vk::Buffer, State>,
+    barriers: Barriers,
+}
+
+impl ResourceTracker {
+    pub fn register_image(
+        &mut self,
+        image: vk::Image,
+        format: vk::Format,
+        mip_levels: u32,
+        array_layers: u32,
+        initial: Access,
+    ) {
+        let state = State::new(initial, initial.info(aspect_mask_for_format(format)));
+        self.images.insert(
+            image,
+            TrackedImage {
+                format,
+                mip_levels,
+                array_layers,
+                states: vec![state; (mip_levels * array_layers) as usize],
+            },
+        );
+    }
+
+    pub fn forget_image(&mut self, image: vk::Image) {
+        self.images.remove(&image);
+    }
+
+    pub fn register_buffer(&mut self, buffer: vk::Buffer, initial: Access) {
+        self.buffers
+            .insert(buffer, State::new(initial, buffer_info(initial)));
+    }
+
+    /// Queues whatever barriers are needed before `range` of `image` is used as `next`
+    pub fn use_image(&mut self, image: vk::Image, range: ImageRange, next: Access) -> &mut Self {
+        self.transition_image(image, range, None, next, false)
+    }
+
+    /// Like `use_image`, but the previous contents don't have to be preserved
+    pub fn discard_image(
+        &mut self,
+        image: vk::Image,
+        range: ImageRange,
+        next: Access,
+    ) -> &mut Self {
+        self.transition_image(image, range, None, next, true)
+    }
+
+    /// Like `use_image`, but debug builds check that the whole range was last used as `expected`
+    pub fn use_image_from(
+        &mut self,
+        image: vk::Image,
+        range: ImageRange,
+        expected: Access,
+        next: Access,
+    ) -> &mut Self {
+        self.transition_image(image, range, Some(expected), next, false)
+    }
+
+    fn transition_image(
+        &mut self,
+        image: vk::Image,
+        range: ImageRange,
+        expected: Option<Access>,
+        next: Access,
+        discard: bool,
+    ) -> &mut Self {
+        let Some(tracked) = self.images.get_mut(&image) else {
+            debug_assert!(false, "Image {image:?} is not tracked");
+            return self;
+        };
+        let range = tracked.resolve(range);
+        let aspect_mask = aspect_mask_for_format(tracked.format);
+        let info = next.info(aspect_mask);
+
+        // Runs of mips in the same state share a barrier, and the barrier queue merges the
+        // matching runs of neighbouring layers
+        for layer in range.base_layer..range.base_layer + range.layer_count {
+            let mut mip = range.base_mip;
+            while mip < range.base_mip + range.mip_count {
+                let idx = (layer * tracked.mip_levels + mip) as usize;
+                let mut state = tracked.states[idx];
+
+                let mut run = 1;
+                while mip + run < range.base_mip + range.mip_count
+                    && tracked.states[idx + run as usize] == state
+                {
+                    run += 1;
+                }
+
+                if let Some(expected) = expected {
+                    debug_assert_eq!(
+                        state.last, expected,
+                        "Image {image:?} mip {mip} layer {layer} was declared to be in {expected:?}"
+                    );
+                }
+
+                if let Some(dependency) = state.advance(next, info, discard) {
+                    self.barriers.image_dependency(
+                        image,
+                        aspect_mask,
+                        ImageRange {
+                            base_mip: mip,
+                            mip_count: run,
+                            base_layer: layer,
+                            layer_count: 1,
+                        },
+                        dependency.src,
+                        dependency.dst,
+                        dependency.old_layout,
+                        info.layout,
+                    );
+                }
+
+                tracked.states[idx..idx + run as usize].fill(state);
+                mip += run;
+            }
+        }
+
+        self
+    }
+
+    pub fn use_buffer(&mut self, buffer: vk::Buffer, next: Access) -> &mut Self {
+        let Some(state) = self.buffers.get_mut(&buffer) else {
+            debug_assert!(false, "Buffer {buffer:?} is not tracked");
+            return self;
+        };
+
+        if let Some(dependency) = state.advance(next, buffer_info(next), false) {
+            self.barriers
+                .buffer_dependency(buffer, dependency.src, dependency.dst);
+        }
+        self
+    }
+
+    /// Records every queued barrier with a single `cmd_pipeline_barrier2`
+    pub fn flush(&mut self, device: &ash::Device, cmd: vk::CommandBuffer) {
+        self.barriers.flush(device, cmd);
+    }
+}
+
+// Buffers have no layouts to transition between
+fn buffer_info(access: Access) -> AccessInfo {
+    AccessInfo {
+        layout: vk::ImageLayout::UNDEFINED,
+        ..access.info(vk::ImageAspectFlags::empty())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ash::vk::Handle;
+
+    use super::*;
+
+    const COLOR: vk::Format = vk::Format::R8G8B8A8_UNORM;
+
+    // What `flush` would record, without a device to record it with
+    fn take_images(tracker: &mut ResourceTracker) -> Vec<vk::ImageMemoryBarrier2<'static>> {
+        std::mem::take(&mut tracker.barriers)
+            .queued_images()
+            .to_vec()
+    }
+
+    fn take_buffers(tracker: &mut ResourceTracker) -> Vec<vk::BufferMemoryBarrier2<'static>> {
+        std::mem::take(&mut tracker.barriers)
+            .queued_buffers()
+            .to_vec()
+    }
+
+    fn tracker_with_image(mip_levels: u32, array_layers: u32) -> (ResourceTracker, vk::Image) {
+        let image = vk::Image::from_raw(1);
+        let mut tracker = ResourceTracker::default();
+        tracker.register_image(image, COLOR, mip_levels, array_layers, Access::Undefined);
+        (tracker, image)
+    }
+
+    fn covered(barrier: &vk::ImageMemoryBarrier2) -> (u32, u32, u32, u32) {
+        let range = barrier.subresource_range;
+        (
+            range.base_mip_level,
+            range.level_count,
+            range.base_array_layer,
+            range.layer_count,
+        )
+    }
+
+    #[test]
+    fn write_then_read() {
+        let (mut tracker, image) = tracker_with_image(1, 1);
+        tracker.discard_image(image, ImageRange::ALL, Access::ColorAttachmentWrite);
+        let [discard] = take_images(&mut tracker)[..] else {
+            panic!("expected one barrier");
+        };
+        assert_eq!(discard.old_layout, vk::ImageLayout::UNDEFINED);
+        assert_eq!(
+            discard.new_layout,
+            vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL
+        );
+
+        tracker.use_image(image, ImageRange::ALL, Access::FragmentSampled);
+        let [read] = take_images(&mut tracker)[..] else {
+            panic!("expected one barrier");
+        };
+        assert_eq!(
+            read.src_stage_mask,
+            vk::PipelineStageFlags2::COLOR_ATTACHMENT_OUTPUT
+        );
+        assert!(read
+            .src_access_mask
+            .contains(vk::AccessFlags2::COLOR_ATTACHMENT_WRITE));
+        assert_eq!(read.dst_access_mask, vk::AccessFlags2::SHADER_SAMPLED_READ);
+        assert_eq!(read.old_layout, vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL);
+        assert_eq!(read.new_layout, vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL);
+    }
+
+    #[test]
+    fn read_then_read() {
+        let (mut tracker, image) = tracker_with_image(1, 1);
+        tracker
+            .discard_image(image, ImageRange::ALL, Access::ComputeWrite)
+            .use_image(image, ImageRange::ALL, Access::FragmentSampled);
+        take_images(&mut tracker);
+
+        // Already visible to fragment shaders
+        tracker.use_image(image, ImageRange::ALL, Access::FragmentSampled);
+        assert!(take_images(&mut tracker).is_empty());
+
+        // Compute hasn't been made to wait yet, and has to wait on the transition
+        tracker.use_image(image, ImageRange::ALL, Access::ComputeSampled);
+        let [read] = take_images(&mut tracker)[..] else {
+            panic!("expected one barrier");
+        };
+        assert_eq!(
+            read.src_stage_mask,
+            vk::PipelineStageFlags2::FRAGMENT_SHADER
+        );
+        assert!(read
+            .dst_stage_mask
+            .contains(vk::PipelineStageFlags2::COMPUTE_SHADER));
+        assert_eq!(read.old_layout, read.new_layout);
+
+        tracker
+            .use_image(image, ImageRange::ALL, Access::ComputeSampled)
+            .use_image(image, ImageRange::ALL, Access::FragmentSampled);
+        assert!(take_images(&mut tracker).is_empty());
+    }
+
+    #[test]
+    fn read_then_write_waits_for_every_reader() {
+        let (mut tracker, image) = tracker_with_image(1, 1);
+        tracker
+            .discard_image(image, ImageRange::ALL, Access::ComputeWrite)
+            .use_image(image, ImageRange::ALL, Access::FragmentSampled)
+            .use_image(image, ImageRange::ALL, Access::ComputeSampled);
+        take_images(&mut tracker);
+
+        tracker.use_image(image, ImageRange::ALL, Access::ComputeWrite);
+        let [write] = take_images(&mut tracker)[..] else {
+            panic!("expected one barrier");
+        };
+        assert_eq!(
+            write.src_stage_mask,
+            vk::PipelineStageFlags2::FRAGMENT_SHADER | vk::PipelineStageFlags2::COMPUTE_SHADER
+        );
+        assert_eq!(write.src_access_mask, vk::AccessFlags2::NONE);
+        assert_eq!(
+            write.dst_access_mask,
+            vk::AccessFlags2::SHADER_STORAGE_WRITE
+        );
+        assert_eq!(write.old_layout, vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL);
+        assert_eq!(write.new_layout, vk::ImageLayout::GENERAL);
+    }
+
+    #[test]
+    fn buffer_reads_after_a_write() {
+        let buffer = vk::Buffer::from_raw(1);
+        let mut tracker = ResourceTracker::default();
+        tracker.register_buffer(buffer, Access::Undefined);
+        tracker.use_buffer(buffer, Access::ComputeWrite);
+        take_buffers(&mut tracker);
+
+        tracker.use_buffer(buffer, Access::FragmentStorageRead);
+        let [read] = take_buffers(&mut tracker)[..] else {
+            panic!("expected one barrier");
+        };
+        assert_eq!(read.src_access_mask, vk::AccessFlags2::SHADER_STORAGE_WRITE);
+        assert_eq!(
+            read.dst_stage_mask,
+            vk::PipelineStageFlags2::FRAGMENT_SHADER
+        );
+
+        tracker.use_buffer(buffer, Access::FragmentStorageRead);
+        assert!(take_buffers(&mut tracker).is_empty());
+    }
+
+    #[test]
+    fn split_subresources_merge_back() {
+        let (mut tracker, image) = tracker_with_image(4, 2);
+        tracker.discard_image(image, ImageRange::ALL, Access::TransferDst);
+        let barriers = take_images(&mut tracker);
+        assert_eq!(
+            barriers.iter().map(covered).collect::<Vec<_>>(),
+            [(0, 4, 0, 2)]
+        );
+
+        // Mip 0 of every layer is read while the rest are still being written
+        let first_mip = ImageRange {
+            base_mip: 0,
+            mip_count: 1,
+            base_layer: 0,
+            layer_count: vk::REMAINING_ARRAY_LAYERS,
+        };
+        tracker.use_image(image, first_mip, Access::TransferSrc);
+        let barriers = take_images(&mut tracker);
+        assert_eq!(
+            barriers.iter().map(covered).collect::<Vec<_>>(),
+            [(0, 1, 0, 2)]
+        );
+
+        // Each run of mips comes from its own layout, but every layer shares them
+        tracker.use_image(image, ImageRange::ALL, Access::FragmentSampled);
+        let barriers = take_images(&mut tracker);
+        assert_eq!(
+            barriers.iter().map(covered).collect::<Vec<_>>(),
+            [(0, 1, 0, 2), (1, 3, 0, 2)]
+        );
+        assert_eq!(
+            barriers[0].old_layout,
+            vk::ImageLayout::TRANSFER_SRC_OPTIMAL
+        );
+        assert_eq!(
+            barriers[1].old_layout,
+            vk::ImageLayout::TRANSFER_DST_OPTIMAL
+        );
+
+        // Back in the same state everywhere, so one barrier covers the whole image again
+        tracker.use_image(image, ImageRange::ALL, Access::ComputeWrite);
+        let barriers = take_images(&mut tracker);
+        assert_eq!(
+            barriers.iter().map(covered).collect::<Vec<_>>(),
+            [(0, 4, 0, 2)]
+        );
+    }
+
+    #[test]
+    fn single_layer_leaves_the_rest_alone() {
+        let (mut tracker, image) = tracker_with_image(1, 6);
+        tracker.discard_image(image, ImageRange::ALL, Access::ComputeWrite);
+        take_images(&mut tracker);
+
+        let layer = ImageRange {
+            base_mip: 0,
+            mip_count: 1,
+            base_layer: 3,
+            layer_count: 1,
+        };
+        tracker.use_image(image, layer, Access::FragmentSampled);
+        let barriers = take_images(&mut tracker);
+        assert_eq!(
+            barriers.iter().map(covered).collect::<Vec<_>>(),
+            [(0, 1, 3, 1)]
+        );
+
+        // Only the other layers still need making visible
+        tracker.use_image(image, ImageRange::ALL, Access::FragmentSampled);
+        let barriers = take_images(&mut tracker);
+        assert_eq!(
+            barriers.iter().map(covered).collect::<Vec<_>>(),
+            [(0, 1, 0, 3), (0, 1, 4, 2)]
+        );
+    }
+
+    #[test]
+    fn registering_again_resets_the_state() {
+        let (mut tracker, image) = tracker_with_image(1, 1);
+        tracker.discard_image(image, ImageRange::ALL, Access::ComputeWrite);
+        take_images(&mut tracker);
+
+        tracker.forget_image(image);
+        assert!(!tracker.images.contains_key(&image));
+        tracker.register_image(image, COLOR, 1, 1, Access::Undefined);
+        tracker.use_image(image, ImageRange::ALL, Access::FragmentSampled);
+        let [read] = take_images(&mut tracker)[..] else {
+            panic!("expected one barrier");
+        };
+        assert_eq!(read.old_layout, vk::ImageLayout::UNDEFINED);
+        assert_eq!(read.src_access_mask, vk::AccessFlags2::NONE);
+    }
+
+    #[test]
+    #[cfg(debug_assertions)]
+    #[should_panic(expected = "is not tracked")]
+    fn forgotten_images_are_not_tracked() {
+        let (mut tracker, image) = tracker_with_image(1, 1);
+        tracker.forget_image(image);
+        tracker.use_image(image, ImageRange::ALL, Access::FragmentSampled);
+    }
+}
@@ -0,0 +1,133 @@
+use ash::vk;
+use gpu_allocator::{vulkan::Allocator, MemoryLocation};
+
+use super::{
+    barrier::{Access, Barriers},
+    buffer::AllocatedBuffer,
+    init,
+    mipmap::MipGenerator,
+};
+
+// Records and submits one-off command buffers, blocking until the GPU is done with them
+pub struct ImmediateSubmit {
+    pool: vk::CommandPool,
+    cmd: vk::CommandBuffer,
+    fence: vk::Fence,
+}
+
+impl ImmediateSubmit {
+    pub fn new(device: &ash::Device, queue_family_idx: u32) -> anyhow::Result<Self> {
+        let pool_info = init::cmd_pool_create_info(
+            queue_family_idx,
+            vk::CommandPoolCreateFlags::RESET_COMMAND_BUFFER,
+        );
+        let pool = unsafe { device.create_command_pool(&pool_info, None) }?;
+        let cmd =
+            unsafe { device.allocate_command_buffers(&init::cmd_buffer_allocate_info(pool, 1)) }?
+                [0];
+        let fence = unsafe {
+            device.create_fence(
+                &init::fence_create_info(vk::FenceCreateFlags::empty()),
+                None,
+            )
+        }?;
+
+        Ok(Self { pool, cmd, fence })
+    }
+
+    pub fn submit(
+        &self,
+        device: &ash::Device,
+        queue: vk::Queue,
+        record: impl FnOnce(vk::CommandBuffer),
+    ) -> anyhow::Result<()> {
+        unsafe {
+            device.reset_command_buffer(self.cmd, vk::CommandBufferResetFlags::empty())?;
+            device.begin_command_buffer(
+                self.cmd,
+                &init::cmd_buffer_begin_info(vk::CommandBufferUsageFlags::ONE_TIME_SUBMIT),
+            )?;
+        }
+
+        record(self.cmd);
+
+        unsafe {
+            device.end_command_buffer(self.cmd)?;
+            let cmd_info = init::cmd_buffer_submit_info(self.cmd);
+            let submit_info = init::submit_info(&cmd_info, None, None);
+            device.queue_submit2(queue, &[submit_info], self.fence)?;
+            device.wait_for_fences(&[self.fence], true, u64::MAX)?;
+            device.reset_fences(&[self.fence])?;
+        }
+
+        Ok(())
+    }
+
+    pub fn destroy(&self, device: &ash::Device) {
+        unsafe {
+            device.destroy_fence(self.fence, None);
+            device.destroy_command_pool(self.pool, None);
+        }
+    }
+}
+
+/// Everything needed to get data into device local memory
+pub struct UploadContext<'a> {
+    pub instance: &'a ash::Instance,
+    pub physical_device: vk::PhysicalDevice,
+    pub device: &'a ash::Device,
+    pub allocator: &'a mut Allocator,
+    pub queue: vk::Queue,
+    pub immediate: &'a ImmediateSubmit,
+    pub mip_generator: &'a MipGenerator,
+}
+
+impl UploadContext<'_> {
+    pub fn staging_buffer(&mut self, data: &[u8]) -> anyhow::Result<AllocatedBuffer> {
+        let mut staging = AllocatedBuffer::new(
+            self.device,
+            self.allocator,
+            "staging buffer",
+            data.len().max(1) as vk::DeviceSize,
+            vk::BufferUsageFlags::TRANSFER_SRC,
+            MemoryLocation::CpuToGpu,
+        )?;
+        staging.write(0, data)?;
+        Ok(staging)
+    }
+
+    /// Creates a device local buffer filled with `data`
+    pub fn create_buffer(
+        &mut self,
+        name: &str,
+        usage: vk::BufferUsageFlags,
+        data: &[u8],
+        next: Access,
+    ) -> anyhow::Result<AllocatedBuffer> {
+        let size = data.len().max(1) as vk::DeviceSize;
+        let buffer = AllocatedBuffer::new(
+            self.device,
+            self.allocator,
+            name,
+            size,
+            usage | vk::BufferUsageFlags::TRANSFER_DST,
+            MemoryLocation::GpuOnly,
+        )?;
+        let mut staging = self.staging_buffer(data)?;
+
+        let device = self.device;
+        let result = self.immediate.submit(device, self.queue, |cmd| {
+            if !data.is_empty() {
+                let region = vk::BufferCopy::default().size(data.len() as vk::DeviceSize);
+                unsafe { device.cmd_copy_buffer(cmd, staging.buffer, buffer.buffer, &[region]) };
+            }
+            Barriers::default()
+                .buffer(buffer.buffer, 0, vk::WHOLE_SIZE, Access::TransferDst, next)
+                .flush(device, cmd);
+        });
+        staging.destroy(self.device, self.allocator);
+        result?;
+
+        Ok(buffer)
+    }
+}
@@ -1,29 +1,25 @@
 use ash::vk;
 
-use super::init;
-pub fn transition_image(
+pub fn set_viewport_and_scissor(
     device: &ash::Device,
     cmd: vk::CommandBuffer,
-    image: vk::Image,
-    current_layout: vk::ImageLayout,
-    new_layout: vk::ImageLayout,
+    extent: vk::Extent2D,
 ) {
-    let aspect_mask = if new_layout == vk::ImageLayout::DEPTH_ATTACHMENT_OPTIMAL {
-        vk::ImageAspectFlags::DEPTH
-    } else {
-        vk::ImageAspectFlags::COLOR
+    let viewport = vk::Viewport {
+        x: 0.0,
+        y: 0.0,
+        width: extent.width as f32,
+        height: extent.height as f32,
+        min_depth: 0.0,
+        max_depth: 1.0,
+    };
+    let scissor = vk::Rect2D {
+        offset: vk::Offset2D::default(),
+        extent,
     };
 
-    let image_barriers = [vk::ImageMemoryBarrier2::default()
-        .src_stage_mask(vk::PipelineStageFlags2::ALL_COMMANDS)
-        .src_access_mask(vk::AccessFlags2::MEMORY_WRITE)
-        .dst_stage_mask(vk::PipelineStageFlags2::ALL_COMMANDS)
-        .dst_access_mask(vk::AccessFlags2::MEMORY_WRITE | vk::AccessFlags2::MEMORY_READ)
-        .old_layout(current_layout)
-        .new_layout(new_layout)
-        .subresource_range(init::image_subresource_range(aspect_mask))
-        .image(image)];
-
-    let dep_info = vk::DependencyInfo::default().image_memory_barriers(&image_barriers);
-    unsafe { device.cmd_pipeline_barrier2(cmd, &dep_info) };
+    unsafe {
+        device.cmd_set_viewport(cmd, 0, &[viewport]);
+        device.cmd_set_scissor(cmd, 0, &[scissor]);
+    }
 }
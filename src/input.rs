@@ -0,0 +1,253 @@
+// Raw input state, and the bindings that turn it into actions and axes
+use std::{
+    collections::{HashMap, HashSet},
+    path::Path,
+};
+
+use anyhow::Context;
+use glam::Vec2;
+use serde::Deserialize;
+use winit::{
+    event::{DeviceEvent, ElementState, KeyEvent, MouseButton, MouseScrollDelta, WindowEvent},
+    keyboard::{KeyCode, PhysicalKey},
+};
+
+const DEFAULT_BINDINGS: &str = include_str!("../bindings.toml");
+
+// Roughly how many pixels one notch of a line based wheel scrolls
+const PIXELS_PER_LINE: f32 = 40.0;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Button {
+    Key(KeyCode),
+    Mouse(MouseButton),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Action {
+    Exit,
+    ToggleController,
+    ToggleProjection,
+    /// Cycles through the cameras in the scene, then back to the free camera
+    NextCamera,
+    ToggleRenderPath,
+    /// Steps through 1, 2, 4 and 8 samples per pixel
+    CycleMsaa,
+    ToggleTaa,
+    ToggleFxaa,
+    /// Steps through Reinhard, ACES, AgX and Uncharted 2
+    CycleTonemapper,
+    ToggleAutoExposure,
+    ToggleBloom,
+    ToggleSsao,
+    /// Steps through full, half and quarter resolution ambient occlusion
+    CycleSsaoResolution,
+    /// Tints the scene by shadow cascade
+    ToggleCascadeDebug,
+    /// Shows how many lights reach each cluster as a heatmap
+    ToggleLightHeatmap,
+    /// Shows screen-space ambient occlusion on its own
+    ToggleOcclusionDebug,
+    /// Keeps the sun moving across the sky, through day and night
+    ToggleDayCycle,
+    Look,
+    Orbit,
+    Boost,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Axis {
+    Forward,
+    Right,
+    Up,
+    /// Turns the sun around the vertical axis
+    Sun,
+    /// Raises or lowers the sun
+    SunElevation,
+    BloomIntensity,
+    BloomThreshold,
+    /// Widens or narrows the bloom's upsampling filter
+    BloomRadius,
+    /// Turns the environment around the vertical axis
+    SkyboxRotation,
+    SkyboxIntensity,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct AxisBinding {
+    #[serde(default)]
+    positive: Vec<Button>,
+    #[serde(default)]
+    negative: Vec<Button>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Bindings {
+    #[serde(default)]
+    actions: HashMap<Action, Vec<Button>>,
+    #[serde(default)]
+    axes: HashMap<Axis, AxisBinding>,
+}
+
+impl Default for Bindings {
+    fn default() -> Self {
+        toml::from_str(DEFAULT_BINDINGS).expect("default bindings are valid")
+    }
+}
+
+impl Bindings {
+    /// Layers the bindings in `path` over the defaults
+    pub fn load(path: &Path) -> anyhow::Result<Self> {
+        let text = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read {}", path.display()))?;
+        let overrides: Self =
+            toml::from_str(&text).with_context(|| format!("Failed to parse {}", path.display()))?;
+
+        let mut bindings = Self::default();
+        bindings.actions.extend(overrides.actions);
+        bindings.axes.extend(overrides.axes);
+        Ok(bindings)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ButtonState {
+    Up,
+    /// Went down since the last frame
+    Pressed,
+    Held,
+    /// Went up since the last frame
+    Released,
+}
+
+impl ButtonState {
+    pub fn is_down(self) -> bool {
+        matches!(self, Self::Pressed | Self::Held)
+    }
+
+    pub fn just_pressed(self) -> bool {
+        self == Self::Pressed
+    }
+}
+
+/// Collects events as they arrive, read once per frame and then cleared with `end_frame`
+#[derive(Debug, Default)]
+pub struct Input {
+    bindings: Bindings,
+    held: HashSet<Button>,
+    pressed: HashSet<Button>,
+    released: HashSet<Button>,
+    mouse_delta: Vec2,
+    scroll_delta: f32,
+}
+
+impl Input {
+    pub fn new(bindings: Bindings) -> Self {
+        Self {
+            bindings,
+            ..Default::default()
+        }
+    }
+
+    pub fn window_event(&mut self, event: &WindowEvent) {
+        match *event {
+            WindowEvent::KeyboardInput {
+                event:
+                    KeyEvent {
+                        physical_key: PhysicalKey::Code(key),
+                        state,
+                        repeat: false,
+                        ..
+                    },
+                ..
+            } => self.button(Button::Key(key), state),
+            WindowEvent::MouseInput { state, button, .. } => {
+                self.button(Button::Mouse(button), state)
+            }
+            WindowEvent::MouseWheel { delta, .. } => {
+                self.scroll_delta += match delta {
+                    MouseScrollDelta::LineDelta(_, y) => y,
+                    MouseScrollDelta::PixelDelta(pos) => pos.y as f32 / PIXELS_PER_LINE,
+                };
+            }
+            // Release events never arrive for whatever was held while focus was lost
+            WindowEvent::Focused(false) => {
+                self.released.extend(self.held.drain());
+            }
+            _ => {}
+        }
+    }
+
+    // Raw motion keeps coming while the cursor is locked in place
+    pub fn device_event(&mut self, event: &DeviceEvent) {
+        if let DeviceEvent::MouseMotion { delta } = *event {
+            self.mouse_delta += Vec2::new(delta.0 as f32, delta.1 as f32);
+        }
+    }
+
+    fn button(&mut self, button: Button, state: ElementState) {
+        match state {
+            ElementState::Pressed => {
+                if self.held.insert(button) {
+                    self.pressed.insert(button);
+                }
+            }
+            ElementState::Released => {
+                if self.held.remove(&button) {
+                    self.released.insert(button);
+                }
+            }
+        }
+    }
+
+    // A tap shorter than a frame still counts as a press
+    pub fn action(&self, action: Action) -> ButtonState {
+        let buttons = self
+            .bindings
+            .actions
+            .get(&action)
+            .map_or(&[][..], Vec::as_slice);
+        let any = |set: &HashSet<Button>| buttons.iter().any(|button| set.contains(button));
+
+        if any(&self.pressed) {
+            ButtonState::Pressed
+        } else if any(&self.held) {
+            ButtonState::Held
+        } else if any(&self.released) {
+            ButtonState::Released
+        } else {
+            ButtonState::Up
+        }
+    }
+
+    /// -1 to 1, opposing bindings cancel out
+    pub fn axis(&self, axis: Axis) -> f32 {
+        let Some(binding) = self.bindings.axes.get(&axis) else {
+            return 0.0;
+        };
+        let any_held = |buttons: &[Button]| buttons.iter().any(|button| self.held.contains(button));
+        any_held(&binding.positive) as i32 as f32 - any_held(&binding.negative) as i32 as f32
+    }
+
+    /// Mouse movement since the last frame, in pixels
+    pub fn mouse_delta(&self) -> Vec2 {
+        self.mouse_delta
+    }
+
+    /// Scroll wheel movement since the last frame, in lines
+    pub fn scroll_delta(&self) -> f32 {
+        self.scroll_delta
+    }
+
+    pub fn end_frame(&mut self) {
+        self.pressed.clear();
+        self.released.clear();
+        self.mouse_delta = Vec2::ZERO;
+        self.scroll_delta = 0.0;
+    }
+}
@@ -1,13 +1,148 @@
+use std::path::PathBuf;
+
 use app::App;
 use winit::{event_loop::EventLoop, window::WindowAttributes};
 
 mod app;
+mod assets;
+mod camera;
 mod gfx;
+mod input;
+mod scene;
+
+/// Everything that can be set from the command line
+struct Options {
+    renderer: gfx::RendererConfig,
+    model_path: Option<PathBuf>,
+    environment_path: Option<PathBuf>,
+    load_options: assets::LoadOptions,
+    bindings_path: Option<PathBuf>,
+}
+
+impl Options {
+    /// Unknown flags and values that don't parse are reported and otherwise ignored
+    fn parse(args: impl Iterator<Item = String>) -> Self {
+        let mut options = Self {
+            renderer: gfx::RendererConfig::default(),
+            model_path: None,
+            environment_path: None,
+            load_options: assets::LoadOptions {
+                normals: assets::Normals::Smooth,
+            },
+            bindings_path: None,
+        };
+        let renderer = &mut options.renderer;
+
+        for arg in args {
+            let Some(flag) = arg.strip_prefix("--") else {
+                // The first argument that isn't a flag is a model to show
+                if options.model_path.is_none() {
+                    options.model_path = Some(PathBuf::from(arg));
+                }
+                continue;
+            };
+            let (name, value) = match flag.split_once('=') {
+                Some((name, value)) => (name, Some(value)),
+                None => (flag, None),
+            };
+
+            match (name, value) {
+                ("hdr", None) => renderer.hdr = true,
+                ("deferred", None) => renderer.render_path = gfx::RenderPath::Deferred,
+                ("no-taa", None) => renderer.taa = false,
+                ("fxaa", None) => renderer.fxaa = true,
+                ("auto-exposure", None) => renderer.auto_exposure = true,
+                ("no-bloom", None) => renderer.bloom.enabled = false,
+                ("no-ssao", None) => renderer.ssao.enabled = false,
+                ("no-ibl-cache", None) => renderer.ibl_cache_dir = None,
+                ("flat-normals", None) => options.load_options.normals = assets::Normals::Flat,
+                ("msaa", Some(value)) => parse_value(&arg, value, &mut renderer.msaa_samples),
+                ("adaptation-speed", Some(value)) => {
+                    parse_value(&arg, value, &mut renderer.adaptation_speed)
+                }
+                ("ssao-radius", Some(value)) => parse_value(&arg, value, &mut renderer.ssao.radius),
+                ("skybox-rotation", Some(value)) => {
+                    let mut degrees = renderer.skybox.rotation.to_degrees();
+                    parse_value(&arg, value, &mut degrees);
+                    renderer.skybox.rotation = degrees.to_radians();
+                }
+                ("skybox-intensity", Some(value)) => {
+                    parse_value(&arg, value, &mut renderer.skybox.intensity)
+                }
+                ("tonemapper", Some(value)) => {
+                    renderer.tonemapper = match value.to_ascii_lowercase().as_str() {
+                        "reinhard" => gfx::Tonemapper::Reinhard,
+                        "aces" => gfx::Tonemapper::Aces,
+                        "agx" => gfx::Tonemapper::Agx,
+                        "uncharted2" => gfx::Tonemapper::Uncharted2,
+                        _ => {
+                            eprintln!(
+                                "Unknown tonemapper {value}, using {:?}",
+                                renderer.tonemapper
+                            );
+                            renderer.tonemapper
+                        }
+                    }
+                }
+                ("ssao-resolution", Some(value)) => {
+                    renderer.ssao.resolution = match value.to_ascii_lowercase().as_str() {
+                        "full" => gfx::SsaoResolution::Full,
+                        "half" => gfx::SsaoResolution::Half,
+                        "quarter" => gfx::SsaoResolution::Quarter,
+                        _ => {
+                            eprintln!(
+                                "Unknown SSAO resolution {value}, using {:?}",
+                                renderer.ssao.resolution
+                            );
+                            renderer.ssao.resolution
+                        }
+                    }
+                }
+                ("environment", Some(value)) => {
+                    options.environment_path = Some(PathBuf::from(value))
+                }
+                ("bindings", Some(value)) => options.bindings_path = Some(PathBuf::from(value)),
+                _ => eprintln!("Ignoring unknown argument {arg}"),
+            }
+        }
+
+        options
+    }
+}
+
+// Leaves `target` alone when `value` doesn't parse
+fn parse_value<T: std::str::FromStr>(arg: &str, value: &str, target: &mut T) {
+    match value.parse() {
+        Ok(value) => *target = value,
+        Err(_) => eprintln!("Ignoring {arg}, {value} isn't a valid value"),
+    }
+}
 
 fn main() -> anyhow::Result<()> {
     let event_loop = EventLoop::new()?;
 
-    let mut app = App::new(WindowAttributes::default());
+    let options = Options::parse(std::env::args().skip(1));
+
+    // Bad bindings fall back to the defaults rather than keeping the app from starting
+    let bindings_path = options
+        .bindings_path
+        .or_else(|| Some(PathBuf::from("bindings.toml")).filter(|path| path.exists()));
+    let bindings = match bindings_path {
+        Some(path) => input::Bindings::load(&path).unwrap_or_else(|err| {
+            eprintln!("{err:#}");
+            input::Bindings::default()
+        }),
+        None => input::Bindings::default(),
+    };
+
+    let mut app = App::new(
+        WindowAttributes::default(),
+        options.renderer,
+        options.model_path,
+        options.environment_path,
+        options.load_options,
+        bindings,
+    );
     event_loop.run_app(&mut app)?;
 
     Ok(())
@@ -0,0 +1,155 @@
+// Scene graph: a hierarchy of transforms with meshes, lights and cameras hanging off it
+use glam::{Mat4, Quat, Vec3};
+
+use crate::{
+    assets::{self, Light},
+    camera::Projection,
+};
+
+/// Translation, rotation and scale, applied in reverse order
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Transform {
+    pub translation: Vec3,
+    pub rotation: Quat,
+    pub scale: Vec3,
+}
+
+impl Transform {
+    pub const IDENTITY: Self = Self {
+        translation: Vec3::ZERO,
+        rotation: Quat::IDENTITY,
+        scale: Vec3::ONE,
+    };
+
+    /// Any shear in `matrix` is lost
+    pub fn from_matrix(matrix: Mat4) -> Self {
+        let (scale, rotation, translation) = matrix.to_scale_rotation_translation();
+        Self {
+            translation,
+            rotation,
+            scale,
+        }
+    }
+
+    pub fn matrix(&self) -> Mat4 {
+        Mat4::from_scale_rotation_translation(self.scale, self.rotation, self.translation)
+    }
+}
+
+impl Default for Transform {
+    fn default() -> Self {
+        Self::IDENTITY
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct NodeId(usize);
+
+pub struct Node {
+    local: Transform,
+    world: Mat4,
+    // The local transform changed since the world matrix was last computed
+    dirty: bool,
+    children: Vec<NodeId>,
+    /// Index into the meshes of the renderer's current model
+    pub mesh: Option<usize>,
+    pub light: Option<Light>,
+    pub camera: Option<Projection>,
+}
+
+impl Node {
+    pub fn local(&self) -> &Transform {
+        &self.local
+    }
+
+    /// As of the last `Scene::update_transforms`
+    pub fn world(&self) -> Mat4 {
+        self.world
+    }
+}
+
+#[derive(Default)]
+pub struct Scene {
+    nodes: Vec<Node>,
+    roots: Vec<NodeId>,
+}
+
+impl Scene {
+    pub fn add_node(&mut self, parent: Option<NodeId>, local: Transform) -> NodeId {
+        let id = NodeId(self.nodes.len());
+        self.nodes.push(Node {
+            local,
+            world: Mat4::IDENTITY,
+            dirty: true,
+            children: Vec::new(),
+            mesh: None,
+            light: None,
+            camera: None,
+        });
+        match parent {
+            Some(parent) => self.nodes[parent.0].children.push(id),
+            None => self.roots.push(id),
+        }
+        id
+    }
+
+    pub fn node(&self, id: NodeId) -> &Node {
+        &self.nodes[id.0]
+    }
+
+    pub fn node_mut(&mut self, id: NodeId) -> &mut Node {
+        &mut self.nodes[id.0]
+    }
+
+    pub fn set_local(&mut self, id: NodeId, local: Transform) {
+        let node = &mut self.nodes[id.0];
+        node.local = local;
+        node.dirty = true;
+    }
+
+    pub fn nodes(&self) -> impl Iterator<Item = (NodeId, &Node)> {
+        self.nodes
+            .iter()
+            .enumerate()
+            .map(|(idx, node)| (NodeId(idx), node))
+    }
+
+    pub fn clear(&mut self) {
+        self.nodes.clear();
+        self.roots.clear();
+    }
+
+    /// Adds the node hierarchy of `model` at the root of the scene
+    pub fn instantiate(&mut self, model: &assets::Model) {
+        let mut stack: Vec<(usize, Option<NodeId>)> =
+            model.roots.iter().map(|&root| (root, None)).collect();
+        while let Some((idx, parent)) = stack.pop() {
+            let source = &model.nodes[idx];
+            let id = self.add_node(parent, Transform::from_matrix(source.transform));
+            let node = self.node_mut(id);
+            node.mesh = source.mesh;
+            node.light = source.light;
+            node.camera = source.camera;
+            stack.extend(source.children.iter().map(|&child| (child, Some(id))));
+        }
+    }
+
+    /// Recomputes the world matrices of dirty nodes and everything below them
+    pub fn update_transforms(&mut self) {
+        let mut stack: Vec<(NodeId, Mat4, bool)> = self
+            .roots
+            .iter()
+            .map(|&root| (root, Mat4::IDENTITY, false))
+            .collect();
+        while let Some((id, parent_world, parent_changed)) = stack.pop() {
+            let node = &mut self.nodes[id.0];
+            let changed = parent_changed || node.dirty;
+            if changed {
+                node.world = parent_world * node.local.matrix();
+                node.dirty = false;
+            }
+            let world = node.world;
+            stack.extend(node.children.iter().map(|&child| (child, world, changed)));
+        }
+    }
+}